@@ -0,0 +1,205 @@
+use std::{fmt, net, str};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use listen::Datagram;
+use rate::Rate;
+
+/// How often each group's rate is measured and compared.
+pub const ALERT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A rate a group's traffic should stay below or above; going past it for
+/// long enough raises an alert.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    /// Traffic above the rate, as in a multicast storm.
+    Above(Rate),
+    /// Traffic below the rate, as in a dead or failing feed.
+    Below(Rate),
+}
+
+impl Threshold {
+    fn rate(&self) -> Rate {
+        match *self {
+            Threshold::Above(rate) | Threshold::Below(rate) => rate,
+        }
+    }
+
+    /// The measured rate in this threshold's unit, packets or bits per
+    /// second.
+    fn measure(&self, packets: u64, bytes: u64, period: Duration) -> f64 {
+        let secs = period.as_secs_f64().max(1e-9);
+        match self.rate() {
+            Rate::Packets(_) => packets as f64 / secs,
+            Rate::Bits(_) => bytes as f64 * 8.0 / secs,
+        }
+    }
+
+    fn crossed(&self, measured: f64) -> bool {
+        let limit = match self.rate() {
+            Rate::Packets(limit) | Rate::Bits(limit) => limit,
+        };
+        match *self {
+            Threshold::Above(_) => measured > limit,
+            Threshold::Below(_) => measured < limit,
+        }
+    }
+}
+
+impl str::FromStr for Threshold {
+    type Err = String;
+
+    /// Parses `above:RATE` or `below:RATE`, as in `above:20000pps` or
+    /// `below:2mbit`.
+    fn from_str(s: &str) -> Result<Threshold, String> {
+        let invalid = || format!("invalid threshold {}: expected above:RATE or below:RATE, as in above:20000pps", s);
+        let mut parts = s.splitn(2, ':');
+        let direction = parts.next().unwrap_or("");
+        let rate = parts.next().ok_or_else(invalid)?.parse()?;
+        match direction {
+            "above" => Ok(Threshold::Above(rate)),
+            "below" => Ok(Threshold::Below(rate)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Threshold::Above(rate) => write!(f, "above {}", rate),
+            Threshold::Below(rate) => write!(f, "below {}", rate),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertKind {
+    /// The group's rate has been past the threshold for the sustain time.
+    Raised,
+    /// The rate of a group that was alerting is back within the threshold.
+    Cleared,
+}
+
+/// A group crossing a threshold for long enough, or coming back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub group: net::SocketAddr,
+    pub threshold: Threshold,
+    /// The rate over the last interval, in the threshold's unit.
+    pub measured: f64,
+    /// How long the rate has been past the threshold, or was before
+    /// clearing.
+    pub sustained: Duration,
+}
+
+impl Alert {
+    /// The measured rate with the threshold's unit.
+    pub fn measured_rate(&self) -> String {
+        match self.threshold.rate() {
+            Rate::Packets(_) => format!("{:.1} pps", self.measured),
+            Rate::Bits(_) => format!("{:.3} Mbit/s", self.measured / 1e6),
+        }
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            AlertKind::Raised => {
+                write!(f,
+                       "{} at {}, {} for {:.0} s",
+                       self.group,
+                       self.measured_rate(),
+                       self.threshold,
+                       self.sustained.as_secs_f64())
+            }
+            AlertKind::Cleared => {
+                write!(f,
+                       "{} back at {}, no longer {} after {:.0} s",
+                       self.group,
+                       self.measured_rate(),
+                       self.threshold,
+                       self.sustained.as_secs_f64())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct GroupState {
+    packets: u64,
+    bytes: u64,
+    /// When each threshold was first crossed in the current run, and
+    /// whether an alert was raised for it.
+    crossed: Vec<Option<(Instant, bool)>>,
+}
+
+/// Compares the traffic rate of each group against thresholds once per
+/// `ALERT_INTERVAL`, alerting when a group stays past one for the sustain
+/// time and clearing when it comes back.
+#[derive(Debug)]
+pub struct Alerts {
+    thresholds: Vec<Threshold>,
+    sustain: Duration,
+    groups: HashMap<net::SocketAddr, GroupState>,
+    last_check: Instant,
+}
+
+impl Alerts {
+    pub fn new(thresholds: &[Threshold], sustain: Duration) -> Alerts {
+        Alerts {
+            thresholds: thresholds.to_vec(),
+            sustain,
+            groups: HashMap::new(),
+            last_check: Instant::now(),
+        }
+    }
+
+    pub fn record(&mut self, datagram: &Datagram) {
+        let state = self.groups.entry(datagram.group).or_default();
+        state.packets += 1;
+        state.bytes += datagram.size as u64;
+    }
+
+    /// Whether the rates are due to be compared.
+    pub fn due(&self) -> bool {
+        self.last_check.elapsed() >= ALERT_INTERVAL
+    }
+
+    /// Compares the rate of each of `groups` since the last check, which
+    /// counts silent groups as well, and starts a new interval.
+    pub fn check(&mut self, groups: &[net::SocketAddr]) -> Vec<Alert> {
+        let now = Instant::now();
+        let period = now - self.last_check;
+        self.last_check = now;
+        self.groups.retain(|group, _| groups.contains(group));
+        let mut alerts = Vec::new();
+        for &group in groups {
+            let state = self.groups.entry(group).or_default();
+            state.crossed.resize(self.thresholds.len(), None);
+            for (threshold, crossed) in self.thresholds.iter().zip(&mut state.crossed) {
+                let measured = threshold.measure(state.packets, state.bytes, period);
+                let alert = |kind, since: Instant| {
+                    Alert { kind, group, threshold: *threshold, measured, sustained: now - since }
+                };
+                if threshold.crossed(measured) {
+                    // the interval that crossed counts towards the sustain time
+                    let (since, raised) = crossed.get_or_insert((now - period, false));
+                    if !*raised && now - *since >= self.sustain {
+                        *raised = true;
+                        alerts.push(alert(AlertKind::Raised, *since));
+                    }
+                } else if let Some((since, raised)) = crossed.take() {
+                    if raised {
+                        alerts.push(alert(AlertKind::Cleared, since));
+                    }
+                }
+            }
+            state.packets = 0;
+            state.bytes = 0;
+        }
+        alerts
+    }
+}
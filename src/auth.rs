@@ -0,0 +1,272 @@
+use std::fmt;
+
+/// Bytes of HMAC-SHA256 kept as the tag, truncated as RFC 4868 does.
+pub const TAG_LEN: usize = 16;
+
+const BLOCK_LEN: usize = 64;
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// FIPS 180-4 SHA-256, fed in pieces.
+#[derive(Clone)]
+struct Sha256 {
+    state: [u32; 8],
+    block: [u8; BLOCK_LEN],
+    filled: usize,
+    len: u64,
+}
+
+impl Sha256 {
+    fn new() -> Sha256 {
+        Sha256 {
+            state: INITIAL_STATE,
+            block: [0; BLOCK_LEN],
+            filled: 0,
+            len: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let take = (BLOCK_LEN - self.filled).min(data.len());
+            self.block[self.filled..self.filled + take].copy_from_slice(&data[..take]);
+            self.filled += take;
+            data = &data[take..];
+            if self.filled == BLOCK_LEN {
+                self.compress();
+                self.filled = 0;
+            }
+        }
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, word) in self.block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(ROUND_CONSTANTS[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        let bits = self.len * 8;
+        self.update(&[0x80]);
+        while self.filled != BLOCK_LEN - 8 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut digest = [0u8; 32];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
+
+/// The SHA-256 digest of `data`.
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hash = Sha256::new();
+    hash.update(data);
+    hash.finish()
+}
+
+/// A shared secret that senders tag their datagrams with and listeners
+/// check the tags against: the first `TAG_LEN` bytes of the payload's
+/// HMAC-SHA256 (RFC 2104), appended to the payload.
+#[derive(Clone)]
+pub struct Key {
+    /// The hash states after the inner and outer padded keys.
+    inner: Sha256,
+    outer: Sha256,
+}
+
+impl Key {
+    pub fn new(secret: &[u8]) -> Key {
+        let mut key = [0u8; BLOCK_LEN];
+        if secret.len() > BLOCK_LEN {
+            key[..32].copy_from_slice(&sha256(secret));
+        } else {
+            key[..secret.len()].copy_from_slice(secret);
+        }
+        let mut inner = Sha256::new();
+        inner.update(&key.map(|b| b ^ 0x36));
+        let mut outer = Sha256::new();
+        outer.update(&key.map(|b| b ^ 0x5c));
+        Key { inner, outer }
+    }
+
+    /// The full HMAC-SHA256 of `data`.
+    fn hmac(&self, data: &[u8]) -> [u8; 32] {
+        let mut inner = self.inner.clone();
+        inner.update(data);
+        let mut outer = self.outer.clone();
+        outer.update(&inner.finish());
+        outer.finish()
+    }
+
+    fn tag(&self, data: &[u8]) -> [u8; TAG_LEN] {
+        let mut tag = [0u8; TAG_LEN];
+        tag.copy_from_slice(&self.hmac(data)[..TAG_LEN]);
+        tag
+    }
+
+    /// Appends the tag of `datagram` to it.
+    pub fn sign(&self, datagram: &mut Vec<u8>) {
+        let tag = self.tag(datagram);
+        datagram.extend_from_slice(&tag);
+    }
+
+    /// Overwrites the last `TAG_LEN` bytes of `datagram`, which must be at
+    /// least that long, with the tag of the rest, keeping its size.
+    pub fn sign_in_place(&self, datagram: &mut [u8]) {
+        let payload = datagram.len() - TAG_LEN;
+        let tag = self.tag(&datagram[..payload]);
+        datagram[payload..].copy_from_slice(&tag);
+    }
+
+    /// The payload of `datagram` without its tag, or `None` if the tag is
+    /// missing or wrong.
+    pub fn verify<'a>(&self, datagram: &'a [u8]) -> Option<&'a [u8]> {
+        let payload = datagram.len().checked_sub(TAG_LEN)?;
+        let tag = self.tag(&datagram[..payload]);
+        // compared in full every time, so timing doesn't give away how much
+        // of a forged tag was right
+        let diff = tag.iter().zip(&datagram[payload..]).fold(0, |diff, (a, b)| diff | (a ^ b));
+        if diff == 0 { Some(&datagram[..payload]) } else { None }
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // FIPS 180-2, appendix B, and the digest of nothing
+    #[test]
+    fn sha256_vectors() {
+        assert_eq!(sha256(b"").to_vec(), hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"));
+        assert_eq!(sha256(b"abc").to_vec(),
+                   hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"));
+        assert_eq!(sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq").to_vec(),
+                   hex("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"));
+        assert_eq!(sha256(&vec![b'a'; 1_000_000]).to_vec(),
+                   hex("cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"));
+    }
+
+    #[test]
+    fn sha256_in_pieces() {
+        let data: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        for split in &[0, 1, 55, 56, 63, 64, 65, 999] {
+            let mut hash = Sha256::new();
+            hash.update(&data[..*split]);
+            hash.update(&data[*split..]);
+            assert_eq!(hash.finish(), sha256(&data));
+        }
+    }
+
+    // RFC 4231 test cases 1-4 and 6
+    #[test]
+    fn hmac_vectors() {
+        let cases: &[(Vec<u8>, Vec<u8>, &str)] = &[
+            (vec![0x0b; 20], b"Hi There".to_vec(), "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"),
+            (b"Jefe".to_vec(),
+             b"what do ya want for nothing?".to_vec(),
+             "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+            (vec![0xaa; 20], vec![0xdd; 50], "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"),
+            ((1..=25).collect(), vec![0xcd; 50], "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b"),
+            (vec![0xaa; 131],
+             b"Test Using Larger Than Block-Size Key - Hash Key First".to_vec(),
+             "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"),
+        ];
+        for (key, data, mac) in cases {
+            let key = Key::new(key);
+            assert_eq!(key.hmac(data).to_vec(), hex(mac));
+            let mut datagram = data.clone();
+            key.sign(&mut datagram);
+            assert_eq!(datagram[data.len()..], hex(mac)[..TAG_LEN]);
+            assert_eq!(key.verify(&datagram), Some(&data[..]));
+        }
+    }
+
+    // RFC 4231 test case 5, whose MAC is truncated to our tag length
+    #[test]
+    fn truncated_tag() {
+        let mut datagram = b"Test With Truncation".to_vec();
+        Key::new(&[0x0c; 20]).sign(&mut datagram);
+        assert_eq!(datagram[20..], hex("a3b6167473100ee06e0c796c2955552b")[..]);
+    }
+
+    #[test]
+    fn sign_in_place() {
+        let key = Key::new(b"secret");
+        let mut datagram = b"PING 1 padding to be overwritten".to_vec();
+        key.sign_in_place(&mut datagram);
+        assert_eq!(key.verify(&datagram), Some(&b"PING 1 padding t"[..]));
+    }
+
+    #[test]
+    fn verify_failures() {
+        let key = Key::new(b"secret");
+        let mut datagram = b"payload".to_vec();
+        key.sign(&mut datagram);
+        for bit in 0..8 * TAG_LEN {
+            let mut flipped = datagram.clone();
+            flipped[7 + bit / 8] ^= 1 << (bit % 8);
+            assert_eq!(key.verify(&flipped), None);
+        }
+        let mut flipped = datagram.clone();
+        flipped[0] ^= 1;
+        assert_eq!(key.verify(&flipped), None);
+        assert_eq!(key.verify(&datagram[..datagram.len() - 1]), None);
+        assert_eq!(key.verify(&datagram[..TAG_LEN - 1]), None);
+        assert_eq!(key.verify(b""), None);
+        assert_eq!(Key::new(b"secret2").verify(&datagram), None);
+        assert_eq!(Key::new(b"").verify(&datagram), None);
+    }
+}
@@ -0,0 +1,114 @@
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+use std::{io, mem};
+
+use libc;
+
+use iface;
+use sockopt;
+
+pub const ETH_P_IP: u16 = 0x0800;
+pub const ETH_P_IPV6: u16 = 0x86dd;
+
+/// A packet as captured, from its network header on.
+#[derive(Debug)]
+pub struct Frame<'a> {
+    pub data: &'a [u8],
+    /// The ethertype, `ETH_P_IP` or `ETH_P_IPV6` for the packets of use.
+    pub protocol: u16,
+    pub interface: u32,
+    /// Whether this host sent it.
+    pub outgoing: bool,
+}
+
+/// A packet socket capturing what a classic BPF filter lets through, on
+/// one interface or all of them, which takes `CAP_NET_RAW`. Interfaces are
+/// put in all-multicast mode so that traffic to groups this host hasn't
+/// joined gets through.
+#[derive(Debug)]
+pub struct Capture {
+    fd: OwnedFd,
+}
+
+impl AsRawFd for Capture {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl Capture {
+    /// Captures on the interface with index `interface`, or all of them.
+    pub fn new(interface: Option<u32>, filter: &mut [libc::sock_filter]) -> io::Result<Capture> {
+        // no protocol until the filter is in place
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let capture = Capture { fd: unsafe { OwnedFd::from_raw_fd(fd) } };
+        let program = libc::sock_fprog { len: filter.len() as u16, filter: filter.as_mut_ptr() };
+        sockopt::set(&capture, libc::SOL_SOCKET, libc::SO_ATTACH_FILTER, &program)?;
+        let interfaces = match interface {
+            Some(index) => vec![index],
+            None => iface::interfaces()?.iter().filter(|i| i.up && i.multicast).map(|i| i.index).collect(),
+        };
+        for index in interfaces {
+            let mreq = libc::packet_mreq {
+                mr_ifindex: index as libc::c_int,
+                mr_type: libc::PACKET_MR_ALLMULTI as libc::c_ushort,
+                mr_alen: 0,
+                mr_address: [0; 8],
+            };
+            sockopt::set(&capture, libc::SOL_PACKET, libc::PACKET_ADD_MEMBERSHIP, &mreq)?;
+        }
+        let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as libc::c_ushort;
+        addr.sll_protocol = (libc::ETH_P_ALL as u16).to_be();
+        addr.sll_ifindex = interface.unwrap_or(0) as libc::c_int;
+        let ret = unsafe {
+            libc::bind(fd,
+                       &addr as *const _ as *const libc::sockaddr,
+                       mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t)
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(capture)
+    }
+
+    pub fn timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = timeout.unwrap_or_default();
+        let timeval = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: libc::suseconds_t::from(timeout.subsec_micros()),
+        };
+        sockopt::set(self, libc::SOL_SOCKET, libc::SO_RCVTIMEO, &timeval)
+    }
+
+    /// Waits for the next packet, read into `buf`; fails with `TimedOut`
+    /// when the timeout passes first.
+    pub fn recv<'a>(&self, buf: &'a mut [u8]) -> io::Result<Frame<'a>> {
+        let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        let mut addr_len = mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+        let len = unsafe {
+            libc::recvfrom(self.as_raw_fd(),
+                           buf.as_mut_ptr() as *mut libc::c_void,
+                           buf.len(),
+                           0,
+                           &mut addr as *mut _ as *mut libc::sockaddr,
+                           &mut addr_len)
+        };
+        if len == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::WouldBlock {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "no packet received"));
+            }
+            return Err(err);
+        }
+        Ok(Frame {
+            data: &buf[..len as usize],
+            protocol: u16::from_be(addr.sll_protocol),
+            interface: addr.sll_ifindex as u32,
+            outgoing: addr.sll_pkttype == libc::PACKET_OUTGOING,
+        })
+    }
+}
@@ -0,0 +1,321 @@
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::process;
+
+use auth;
+use fec::Fec;
+
+/// Starts every carousel datagram, followed by its kind and the session.
+const MAGIC: &[u8] = b"MCT";
+const MANIFEST: u8 = 0;
+const SYMBOL: u8 = 1;
+const NACK: u8 = 2;
+
+/// Magic, kind and session.
+const PREFIX_LEN: usize = 8;
+/// What a symbol datagram adds to its symbol: the prefix, the block and the
+/// symbol's index in it.
+pub const SYMBOL_HEADER_LEN: usize = PREFIX_LEN + 6;
+
+/// Symbol size that keeps datagrams clear of IP fragmentation on Ethernet.
+pub const DEFAULT_SYMBOL_SIZE: usize = 1400 - SYMBOL_HEADER_LEN;
+
+/// Most blocks one NACK asks for, keeping it within a datagram.
+pub const MAX_NACK_BLOCKS: usize = 256;
+
+/// What a receiver needs to know about the file before putting it back
+/// together, sent now and then among the symbols.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub session: u32,
+    pub name: String,
+    pub size: u64,
+    pub symbol_size: usize,
+    /// Data symbols per block, all but the last of which are full.
+    pub block_len: usize,
+    /// Parity symbols added to each block.
+    pub parity: usize,
+    pub digest: [u8; 32],
+}
+
+impl Manifest {
+    pub fn blocks(&self) -> u32 {
+        let block_bytes = (self.symbol_size * self.block_len) as u64;
+        self.size.div_ceil(block_bytes) as u32
+    }
+
+    /// Data symbols in `block`, fewer in the last one.
+    fn data_symbols(&self, block: u32) -> usize {
+        let start = block as u64 * (self.symbol_size * self.block_len) as u64;
+        let rest = self.size - start;
+        rest.div_ceil(self.symbol_size as u64).min(self.block_len as u64) as usize
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut packet = prefix(MANIFEST, self.session);
+        packet.extend_from_slice(&self.size.to_be_bytes());
+        packet.extend_from_slice(&(self.symbol_size as u16).to_be_bytes());
+        packet.push((self.block_len - 1) as u8);
+        packet.push(self.parity as u8);
+        packet.extend_from_slice(&self.digest);
+        packet.extend_from_slice(self.name.as_bytes());
+        packet
+    }
+
+    fn decode(session: u32, body: &[u8]) -> Option<Manifest> {
+        if body.len() < 44 {
+            return None;
+        }
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&body[12..44]);
+        let manifest = Manifest {
+            session,
+            size: u64::from_be_bytes(<[u8; 8]>::try_from(&body[..8]).ok()?),
+            symbol_size: u16::from_be_bytes([body[8], body[9]]) as usize,
+            block_len: body[10] as usize + 1,
+            parity: body[11] as usize,
+            digest,
+            name: String::from_utf8_lossy(&body[44..]).into_owned(),
+        };
+        let sane = manifest.symbol_size > 0 && manifest.block_len + manifest.parity <= 256 &&
+                   manifest.size.div_ceil((manifest.symbol_size * manifest.block_len) as u64) <= u32::MAX as u64;
+        if sane { Some(manifest) } else { None }
+    }
+}
+
+/// A datagram of the carousel protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet<'a> {
+    Manifest(Manifest),
+    /// Symbol `index` of `block`: a data symbol, zero-padded in the last
+    /// block, or a parity symbol after those.
+    Symbol {
+        session: u32,
+        block: u32,
+        index: usize,
+        symbol: &'a [u8],
+    },
+    /// A receiver asking, unicast, for these blocks again, or for the
+    /// manifest if it names none.
+    Nack {
+        session: u32,
+        blocks: Vec<u32>,
+    },
+}
+
+impl<'a> Packet<'a> {
+    /// Parses a datagram, `None` if it isn't one of ours.
+    pub fn parse(datagram: &'a [u8]) -> Option<Packet<'a>> {
+        if datagram.len() < PREFIX_LEN || !datagram.starts_with(MAGIC) {
+            return None;
+        }
+        let session = u32::from_be_bytes([datagram[4], datagram[5], datagram[6], datagram[7]]);
+        let body = &datagram[PREFIX_LEN..];
+        match datagram[3] {
+            MANIFEST => Manifest::decode(session, body).map(Packet::Manifest),
+            SYMBOL if body.len() >= 6 => {
+                Some(Packet::Symbol {
+                    session,
+                    block: u32::from_be_bytes([body[0], body[1], body[2], body[3]]),
+                    index: u16::from_be_bytes([body[4], body[5]]) as usize,
+                    symbol: &body[6..],
+                })
+            }
+            NACK => {
+                let blocks = body.chunks_exact(4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]])).collect();
+                Some(Packet::Nack { session, blocks })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn prefix(kind: u8, session: u32) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(PREFIX_LEN);
+    packet.extend_from_slice(MAGIC);
+    packet.push(kind);
+    packet.extend_from_slice(&session.to_be_bytes());
+    packet
+}
+
+/// A NACK for `blocks`, of which at most `MAX_NACK_BLOCKS` are named.
+pub fn nack(session: u32, blocks: &[u32]) -> Vec<u8> {
+    let mut packet = prefix(NACK, session);
+    for block in blocks.iter().take(MAX_NACK_BLOCKS) {
+        packet.extend_from_slice(&block.to_be_bytes());
+    }
+    packet
+}
+
+/// A file cut into blocks of symbols, each with its parity symbols, ready
+/// to be sent round and round.
+#[derive(Debug)]
+pub struct Carousel {
+    manifest: Manifest,
+    data: Vec<u8>,
+    /// The parity symbols of each block.
+    parity: Vec<Vec<Vec<u8>>>,
+}
+
+impl Carousel {
+    /// Codes `data` in blocks of `block_len` symbols of `symbol_size` bytes
+    /// with `parity` more per block, to be sent as `name`.
+    pub fn new(name: &str, data: Vec<u8>, symbol_size: usize, block_len: usize, parity: usize)
+               -> Result<Carousel, String> {
+        if symbol_size == 0 || symbol_size > u16::MAX as usize {
+            return Err(format!("invalid symbol size {}: expected 1 to {} bytes", symbol_size, u16::MAX));
+        }
+        if block_len == 0 || block_len + parity > 256 {
+            return Err(format!("can't code blocks of {} symbols with {} parity symbols: expected at most 256 \
+                                in all", block_len, parity));
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let manifest = Manifest {
+            session: now.subsec_nanos() ^ (now.as_secs() as u32).rotate_left(16) ^ process::id(),
+            name: name.to_owned(),
+            size: data.len() as u64,
+            symbol_size,
+            block_len,
+            parity,
+            digest: auth::sha256(&data),
+        };
+        if manifest.size.div_ceil((symbol_size * block_len) as u64) > u32::MAX as u64 {
+            return Err(format!("{} bytes is too large to send in blocks of {} symbols", data.len(), block_len));
+        }
+        let mut carousel = Carousel { manifest, data, parity: Vec::new() };
+        for block in 0..carousel.manifest.blocks() {
+            let symbols = carousel.data_symbols(block);
+            let fec = Fec::new(symbols.len(), parity)?;
+            carousel.parity.push(fec.encode(&symbols));
+        }
+        Ok(carousel)
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// The data symbols of `block`, the last of them padded.
+    fn data_symbols(&self, block: u32) -> Vec<Vec<u8>> {
+        let size = self.manifest.symbol_size;
+        let start = block as usize * size * self.manifest.block_len;
+        (0..self.manifest.data_symbols(block)).map(|i| {
+            let from = start + i * size;
+            let mut symbol = self.data[from..(from + size).min(self.data.len())].to_vec();
+            symbol.resize(size, 0);
+            symbol
+        }).collect()
+    }
+
+    pub fn manifest_packet(&self) -> Vec<u8> {
+        self.manifest.encode()
+    }
+
+    /// The datagrams of `block`, data symbols first.
+    pub fn block_packets(&self, block: u32) -> Vec<Vec<u8>> {
+        let symbols = self.data_symbols(block);
+        symbols.iter().chain(&self.parity[block as usize]).enumerate().map(|(index, symbol)| {
+            let mut packet = prefix(SYMBOL, self.manifest.session);
+            packet.extend_from_slice(&block.to_be_bytes());
+            packet.extend_from_slice(&(index as u16).to_be_bytes());
+            packet.extend_from_slice(symbol);
+            packet
+        }).collect()
+    }
+}
+
+/// The symbols of a block received so far, until it can be decoded.
+#[derive(Debug)]
+enum Block {
+    Partial(Vec<(usize, Vec<u8>)>),
+    Done(Vec<u8>),
+}
+
+/// Puts a file sent by a `Carousel` back together.
+#[derive(Debug)]
+pub struct Receiver {
+    manifest: Manifest,
+    blocks: Vec<Block>,
+    done: u32,
+    /// The block the last symbol was from.
+    latest: Option<u32>,
+}
+
+impl Receiver {
+    pub fn new(manifest: Manifest) -> Receiver {
+        let blocks = (0..manifest.blocks()).map(|_| Block::Partial(Vec::new())).collect();
+        Receiver { manifest, blocks, done: 0, latest: None }
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Blocks decoded so far.
+    pub fn done(&self) -> u32 {
+        self.done
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.done == self.manifest.blocks()
+    }
+
+    /// Adds symbol `index` of `block`, decoding the block once there are
+    /// enough.
+    pub fn add(&mut self, block: u32, index: usize, symbol: &[u8]) -> Result<(), String> {
+        let data_symbols = match self.blocks.get(block as usize) {
+            Some(_) => self.manifest.data_symbols(block),
+            None => return Err(format!("block {} of a file in {}", block, self.manifest.blocks())),
+        };
+        if index >= data_symbols + self.manifest.parity || symbol.len() != self.manifest.symbol_size {
+            return Err(format!("symbol {} of {} bytes doesn't fit block {}", index, symbol.len(), block));
+        }
+        self.latest = Some(block);
+        let received = match self.blocks[block as usize] {
+            Block::Done(_) => return Ok(()),
+            Block::Partial(ref mut received) => received,
+        };
+        if received.iter().any(|&(seen, _)| seen == index) {
+            return Ok(());
+        }
+        received.push((index, symbol.to_vec()));
+        if received.len() < data_symbols {
+            return Ok(());
+        }
+        let fec = Fec::new(data_symbols, self.manifest.parity)?;
+        let symbols: Vec<(usize, &[u8])> = received.iter().map(|&(index, ref symbol)| (index, &symbol[..])).collect();
+        let data = fec.decode(&symbols).ok_or_else(|| format!("block {} doesn't decode", block))?;
+        self.blocks[block as usize] = Block::Done(data.concat());
+        self.done += 1;
+        Ok(())
+    }
+
+    /// The blocks still missing, up to `MAX_NACK_BLOCKS` of them. Unless
+    /// `all`, only those the carousel has already gone past this time round.
+    pub fn missing(&self, all: bool) -> Vec<u32> {
+        let passed = match (all, self.latest) {
+            (true, _) => self.manifest.blocks(),
+            (false, Some(latest)) => latest,
+            (false, None) => 0,
+        };
+        (0..passed).filter(|&block| matches!(self.blocks[block as usize], Block::Partial(_)))
+                   .take(MAX_NACK_BLOCKS)
+                   .collect()
+    }
+
+    /// The file, once complete and checked against the manifest's digest.
+    pub fn finish(self) -> Result<Vec<u8>, String> {
+        let mut file = Vec::with_capacity(self.manifest.size as usize);
+        for block in self.blocks {
+            match block {
+                Block::Done(data) => file.extend_from_slice(&data),
+                Block::Partial(_) => return Err("the file is incomplete".to_owned()),
+            }
+        }
+        file.truncate(self.manifest.size as usize);
+        if auth::sha256(&file) != self.manifest.digest {
+            return Err("the file doesn't match its SHA-256 digest".to_owned());
+        }
+        Ok(file)
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+
+/// Starts every chat datagram, versioning the format.
+const PREFIX: &str = "mcchat1";
+
+/// What chat participants send each other. Each datagram is one line of
+/// text, `mcchat1 ID KIND NICK [TEXT]`, where ID is a random number that
+/// tells participants apart, and so lets them recognize their own messages
+/// when they come back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Join { id: u32, nick: String },
+    Leave { id: u32, nick: String },
+    Say { id: u32, nick: String, text: String },
+}
+
+impl Message {
+    pub fn id(&self) -> u32 {
+        match *self {
+            Message::Join { id, .. } | Message::Leave { id, .. } | Message::Say { id, .. } => id,
+        }
+    }
+
+    pub fn parse(datagram: &[u8]) -> Option<Message> {
+        let line = String::from_utf8_lossy(datagram);
+        let mut parts = line.trim_end_matches(['\r', '\n']).splitn(5, ' ');
+        if parts.next() != Some(PREFIX) {
+            return None;
+        }
+        let id = u32::from_str_radix(parts.next()?, 16).ok()?;
+        let kind = parts.next()?;
+        let nick = parts.next().filter(|nick| !nick.is_empty())?.to_owned();
+        match kind {
+            "join" => Some(Message::Join { id, nick }),
+            "leave" => Some(Message::Leave { id, nick }),
+            "say" => Some(Message::Say { id, nick, text: parts.next().unwrap_or("").to_owned() }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Message::Join { id, ref nick } => write!(f, "{} {:08x} join {}", PREFIX, id, nick),
+            Message::Leave { id, ref nick } => write!(f, "{} {:08x} leave {}", PREFIX, id, nick),
+            Message::Say { id, ref nick, ref text } => write!(f, "{} {:08x} say {} {}", PREFIX, id, nick, text),
+        }
+    }
+}
+
+/// Whether `nick` can be sent as one: non-empty, with no spaces or control
+/// characters.
+pub fn valid_nick(nick: &str) -> bool {
+    !nick.is_empty() && !nick.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// `text` with control characters, such as terminal escapes, replaced so
+/// that printing it can't mess with the terminal.
+pub fn printable(text: &str) -> String {
+    text.chars().map(|c| if c.is_control() { '?' } else { c }).collect()
+}
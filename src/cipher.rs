@@ -0,0 +1,346 @@
+use std::{fmt, fs, io};
+use std::io::prelude::*;
+
+use auth;
+
+pub const NONCE_LEN: usize = 12;
+pub const TAG_LEN: usize = 16;
+
+/// What sealing adds to a payload: the nonce before it and the tag after.
+pub const OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+
+/// The ChaCha20 block function (RFC 8439 section 2.3).
+fn chacha20_block(key: &[u32; 8], counter: u32, nonce: &[u32; 3]) -> [u8; 64] {
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+    state[4..12].copy_from_slice(key);
+    state[12] = counter;
+    state[13..].copy_from_slice(nonce);
+    let mut working = state;
+    for _ in 0..10 {
+        for &(a, b, c, d) in &[(0, 4, 8, 12), (1, 5, 9, 13), (2, 6, 10, 14), (3, 7, 11, 15),
+                               (0, 5, 10, 15), (1, 6, 11, 12), (2, 7, 8, 13), (3, 4, 9, 14)] {
+            working[a] = working[a].wrapping_add(working[b]);
+            working[d] = (working[d] ^ working[a]).rotate_left(16);
+            working[c] = working[c].wrapping_add(working[d]);
+            working[b] = (working[b] ^ working[c]).rotate_left(12);
+            working[a] = working[a].wrapping_add(working[b]);
+            working[d] = (working[d] ^ working[a]).rotate_left(8);
+            working[c] = working[c].wrapping_add(working[d]);
+            working[b] = (working[b] ^ working[c]).rotate_left(7);
+        }
+    }
+    let mut block = [0u8; 64];
+    for (i, chunk) in block.chunks_mut(4).enumerate() {
+        chunk.copy_from_slice(&working[i].wrapping_add(state[i]).to_le_bytes());
+    }
+    block
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// The Poly1305 one-time authenticator (RFC 8439 section 2.5), in 26-bit
+/// limbs.
+struct Poly1305 {
+    r: [u32; 5],
+    h: [u32; 5],
+    pad: [u32; 4],
+}
+
+impl Poly1305 {
+    fn new(key: &[u8]) -> Poly1305 {
+        Poly1305 {
+            r: [
+                le32(&key[0..]) & 0x3ffffff,
+                (le32(&key[3..]) >> 2) & 0x3ffff03,
+                (le32(&key[6..]) >> 4) & 0x3ffc0ff,
+                (le32(&key[9..]) >> 6) & 0x3f03fff,
+                (le32(&key[12..]) >> 8) & 0x00fffff,
+            ],
+            h: [0; 5],
+            pad: [le32(&key[16..]), le32(&key[20..]), le32(&key[24..]), le32(&key[28..])],
+        }
+    }
+
+    /// Adds `data`, zero-padded to a multiple of 16 bytes as the AEAD
+    /// construction pads its parts.
+    fn update_padded(&mut self, data: &[u8]) {
+        for chunk in data.chunks(16) {
+            let mut block = [0u8; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.block(&block, 1 << 24);
+        }
+    }
+
+    /// Adds a 16 byte block, `hibit` being the bit above it in the top limb:
+    /// set for all but a short final block, which brings its own.
+    fn block(&mut self, m: &[u8; 16], hibit: u32) {
+        const MASK: u32 = 0x3ffffff;
+        let [r0, r1, r2, r3, r4] = self.r.map(u64::from);
+        let (s1, s2, s3, s4) = (r1 * 5, r2 * 5, r3 * 5, r4 * 5);
+        let h = &mut self.h;
+        h[0] += le32(&m[0..]) & MASK;
+        h[1] += (le32(&m[3..]) >> 2) & MASK;
+        h[2] += (le32(&m[6..]) >> 4) & MASK;
+        h[3] += (le32(&m[9..]) >> 6) & MASK;
+        h[4] += (le32(&m[12..]) >> 8) | hibit;
+        let [h0, h1, h2, h3, h4] = h.map(u64::from);
+        let d0 = h0 * r0 + h1 * s4 + h2 * s3 + h3 * s2 + h4 * s1;
+        let mut d1 = h0 * r1 + h1 * r0 + h2 * s4 + h3 * s3 + h4 * s2;
+        let mut d2 = h0 * r2 + h1 * r1 + h2 * r0 + h3 * s4 + h4 * s3;
+        let mut d3 = h0 * r3 + h1 * r2 + h2 * r1 + h3 * r0 + h4 * s4;
+        let mut d4 = h0 * r4 + h1 * r3 + h2 * r2 + h3 * r1 + h4 * r0;
+        d1 += d0 >> 26;
+        d2 += d1 >> 26;
+        d3 += d2 >> 26;
+        d4 += d3 >> 26;
+        let mask = u64::from(MASK);
+        let d0 = (d0 & mask) + (d4 >> 26) * 5;
+        h[0] = (d0 & mask) as u32;
+        h[1] = ((d1 & mask) + (d0 >> 26)) as u32;
+        h[2] = (d2 & mask) as u32;
+        h[3] = (d3 & mask) as u32;
+        h[4] = (d4 & mask) as u32;
+    }
+
+    fn finish(self) -> [u8; TAG_LEN] {
+        const MASK: u32 = 0x3ffffff;
+        let mut h = self.h;
+        // fully carry h
+        for i in 1..5 {
+            h[i] += h[i - 1] >> 26;
+            h[i - 1] &= MASK;
+        }
+        h[0] += (h[4] >> 26) * 5;
+        h[4] &= MASK;
+        h[1] += h[0] >> 26;
+        h[0] &= MASK;
+        // h - p, kept if it doesn't go negative
+        let mut g = [0u32; 5];
+        let mut carry = 5;
+        for i in 0..5 {
+            g[i] = h[i] + carry;
+            carry = g[i] >> 26;
+            g[i] &= MASK;
+        }
+        g[4] = g[4].wrapping_sub(1 << 26).wrapping_add(carry << 26);
+        let keep_g = (g[4] >> 31).wrapping_sub(1);
+        for i in 0..5 {
+            h[i] = (h[i] & !keep_g) | (g[i] & keep_g);
+        }
+        let words = [
+            h[0] | (h[1] << 26),
+            (h[1] >> 6) | (h[2] << 20),
+            (h[2] >> 12) | (h[3] << 14),
+            (h[3] >> 18) | (h[4] << 8),
+        ];
+        let mut tag = [0u8; TAG_LEN];
+        let mut carry = 0u64;
+        for i in 0..4 {
+            let sum = u64::from(words[i]) + u64::from(self.pad[i]) + carry;
+            tag[i * 4..i * 4 + 4].copy_from_slice(&(sum as u32).to_le_bytes());
+            carry = sum >> 32;
+        }
+        tag
+    }
+}
+
+/// ChaCha20-Poly1305 (RFC 8439) under a key derived from a pre-shared
+/// secret. A sealed payload is a random nonce, the ciphertext and the tag.
+#[derive(Clone)]
+pub struct Cipher {
+    key: [u32; 8],
+}
+
+impl Cipher {
+    /// The cipher for `secret`, keyed with its SHA-256 digest.
+    pub fn new(secret: &[u8]) -> Cipher {
+        Cipher::with_key(&auth::sha256(secret))
+    }
+
+    fn with_key(bytes: &[u8; 32]) -> Cipher {
+        let mut key = [0u32; 8];
+        for (word, bytes) in key.iter_mut().zip(bytes.chunks(4)) {
+            *word = le32(bytes);
+        }
+        Cipher { key }
+    }
+
+    /// XORs `data` with the key stream from block 1 on, and returns the tag
+    /// of `aad` and the ciphertext, which is `data` before or after that.
+    fn apply(&self, nonce: &[u8], aad: &[u8], data: &mut [u8], encrypting: bool) -> [u8; TAG_LEN] {
+        let nonce = [le32(&nonce[0..]), le32(&nonce[4..]), le32(&nonce[8..])];
+        let mut poly = Poly1305::new(&chacha20_block(&self.key, 0, &nonce)[..32]);
+        poly.update_padded(aad);
+        if !encrypting {
+            poly.update_padded(data);
+        }
+        for (counter, chunk) in data.chunks_mut(64).enumerate() {
+            let stream = chacha20_block(&self.key, counter as u32 + 1, &nonce);
+            for (byte, key) in chunk.iter_mut().zip(stream.iter()) {
+                *byte ^= key;
+            }
+        }
+        if encrypting {
+            poly.update_padded(data);
+        }
+        let mut lengths = [0u8; 16];
+        lengths[..8].copy_from_slice(&(aad.len() as u64).to_le_bytes());
+        lengths[8..].copy_from_slice(&(data.len() as u64).to_le_bytes());
+        poly.update_padded(&lengths);
+        poly.finish()
+    }
+
+    /// Encrypts `plaintext` under `nonce`, which must never be used twice
+    /// with the same key.
+    pub fn seal(&self, nonce: &[u8; NONCE_LEN], plaintext: &[u8]) -> Vec<u8> {
+        self.seal_with(nonce, &[], plaintext)
+    }
+
+    /// `seal`, with the tag also covering `aad`, which isn't sent.
+    fn seal_with(&self, nonce: &[u8; NONCE_LEN], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let mut sealed = Vec::with_capacity(plaintext.len() + OVERHEAD);
+        sealed.extend_from_slice(nonce);
+        sealed.extend_from_slice(plaintext);
+        let tag = self.apply(nonce, aad, &mut sealed[NONCE_LEN..], true);
+        sealed.extend_from_slice(&tag);
+        sealed
+    }
+
+    /// The plaintext of a sealed payload, or `None` if it was sealed with
+    /// another key or altered on the way.
+    pub fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        self.open_with(&[], sealed)
+    }
+
+    fn open_with(&self, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        let len = sealed.len().checked_sub(OVERHEAD)?;
+        let (nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(len);
+        let mut plaintext = ciphertext.to_vec();
+        let expected = self.apply(nonce, aad, &mut plaintext, false);
+        let diff = expected.iter().zip(tag).fold(0, |diff, (a, b)| diff | (a ^ b));
+        if diff == 0 { Some(plaintext) } else { None }
+    }
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Cipher(..)")
+    }
+}
+
+/// Random nonces, read from /dev/urandom.
+#[derive(Debug)]
+pub struct Nonces {
+    random: io::BufReader<fs::File>,
+}
+
+impl Nonces {
+    pub fn new() -> io::Result<Nonces> {
+        Ok(Nonces { random: io::BufReader::new(fs::File::open("/dev/urandom")?) })
+    }
+
+    pub fn generate(&mut self) -> io::Result<[u8; NONCE_LEN]> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.random.read_exact(&mut nonce)?;
+        Ok(nonce)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // RFC 8439 section 2.5.2
+    #[test]
+    fn poly1305_vector() {
+        let key = hex("85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b");
+        let message = b"Cryptographic Forum Research Group";
+        let mut poly = Poly1305::new(&key);
+        for chunk in message.chunks(16) {
+            let mut block = [0u8; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            if chunk.len() == 16 {
+                poly.block(&block, 1 << 24);
+            } else {
+                block[chunk.len()] = 1;
+                poly.block(&block, 0);
+            }
+        }
+        assert_eq!(poly.finish().to_vec(), hex("a8061dc1305136c6c22b8baf0c0127a9"));
+    }
+
+    // RFC 8439 section 2.8.2
+    #[test]
+    fn aead_vector() {
+        let key: Vec<u8> = (0x80..0xa0).collect();
+        let cipher = Cipher::with_key(&<[u8; 32]>::try_from(&key[..]).unwrap());
+        let nonce = <[u8; NONCE_LEN]>::try_from(&hex("070000004041424344454647")[..]).unwrap();
+        let aad = hex("50515253c0c1c2c3c4c5c6c7");
+        let plaintext = &b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for \
+                           the future, sunscreen would be it."[..];
+        let ciphertext = hex("d31a8d34648e60db7b86afbc53ef7ec2 a4aded51296e08fea9e2b5a736ee62d6
+                              3dbea45e8ca9671282fafb69da92728b 1a71de0a9e060b2905d6a5b67ecd3b36
+                              92ddbd7f2d778b8c9803aee328091b58 fab324e4fad675945585808b4831d7bc
+                              3ff4def08e4b7a9de576d26586cec64b 6116");
+        let tag = hex("1ae10b594f09e26a7e902ecbd0600691");
+        let sealed = cipher.seal_with(&nonce, &aad, plaintext);
+        assert_eq!(sealed[..NONCE_LEN], nonce);
+        assert_eq!(sealed[NONCE_LEN..sealed.len() - TAG_LEN], ciphertext[..]);
+        assert_eq!(sealed[sealed.len() - TAG_LEN..], tag[..]);
+        assert_eq!(cipher.open_with(&aad, &sealed).as_deref(), Some(plaintext));
+    }
+
+    #[test]
+    fn round_trip() {
+        let cipher = Cipher::new(b"secret");
+        let nonce = [7u8; NONCE_LEN];
+        for len in &[0, 1, 15, 16, 17, 63, 64, 65, 1400] {
+            let plaintext: Vec<u8> = (0..*len).map(|i| i as u8).collect();
+            let sealed = cipher.seal(&nonce, &plaintext);
+            assert_eq!(sealed.len(), len + OVERHEAD);
+            assert_eq!(cipher.open(&sealed), Some(plaintext));
+        }
+    }
+
+    #[test]
+    fn tampering_rejected() {
+        let cipher = Cipher::new(b"secret");
+        let aad = b"header";
+        let sealed = cipher.seal_with(&[7u8; NONCE_LEN], aad, b"attack at dawn");
+        for i in 0..sealed.len() {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 0x80;
+            assert_eq!(cipher.open_with(aad, &tampered), None, "byte {} flipped", i);
+        }
+        assert_eq!(cipher.open_with(b"heade", &sealed), None);
+        assert_eq!(cipher.open_with(b"Header", &sealed), None);
+        assert_eq!(cipher.open_with(b"", &sealed), None);
+        assert_eq!(Cipher::new(b"secret2").open_with(aad, &sealed), None);
+        let mut extended = sealed.clone();
+        extended.push(0);
+        assert_eq!(cipher.open_with(aad, &extended), None);
+    }
+
+    #[test]
+    fn too_short() {
+        let cipher = Cipher::new(b"secret");
+        let sealed = cipher.seal(&[7u8; NONCE_LEN], b"");
+        assert_eq!(sealed.len(), OVERHEAD);
+        assert_eq!(cipher.open(&sealed), Some(Vec::new()));
+        for len in 0..OVERHEAD {
+            assert_eq!(cipher.open(&sealed[..len]), None);
+            assert_eq!(cipher.open(&vec![0; len]), None);
+        }
+    }
+}
@@ -0,0 +1,1504 @@
+use std::net;
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{ArgAction, Args, Parser, Subcommand};
+
+use mccat::{chat, dns, dscp, tag, Framing, ReplyPath};
+use mccat::alert::Threshold;
+use mccat::auth::Key;
+use mccat::cipher::Cipher;
+use mccat::compress::Codec;
+use mccat::config::Group;
+use mccat::exec::Trigger;
+use mccat::filter::{Cidr, PayloadPattern};
+use mccat::iface::ScopedAddr;
+use mccat::impair::Delay;
+use mccat::log::LogFormat;
+use mccat::loss::FieldSequence;
+use mccat::merge::Sequence;
+use mccat::output::{CsvColumn, Decode, OutputFormat, PayloadEncoding, Template};
+use mccat::rate::Rate;
+use mccat::relay::Direction;
+use mccat::resolve::{self, Host};
+use mccat::rotate::Compression;
+use mccat::timestamp::TimestampFormat;
+
+#[derive(Parser)]
+#[command(name = "mccat", version, about = "Listen, send and ping on multicast groups")]
+pub struct Cli {
+    /// Show more diagnostics on stderr: -v for debug, -vv for trace
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Show fewer diagnostics on stderr: -q for only warnings and errors, -qq for only errors
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "verbose")]
+    pub quiet: u8,
+
+    /// How diagnostics on stderr are written
+    #[arg(long, value_name = "text|json", default_value = "text")]
+    pub log_format: LogFormat,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Join one or more groups and print the datagrams received
+    Listen(Box<ListenArgs>),
+    /// Send stdin or a file to a group
+    Send(SendArgs),
+    /// Probe a group and report the listeners that answer
+    Ping(PingArgs),
+    /// Join a group and answer ping probes, printing nothing else
+    Respond(RespondArgs),
+    /// Ask once who is joined to a group and list the hosts that answer
+    Census(CensusArgs),
+    /// Send stamped test traffic for `listen --measure` to analyse
+    Generate(GenerateArgs),
+    /// List interfaces with their addresses and joined groups
+    Interfaces,
+    /// List the names that can be given in place of ADDRESS PORT: well-known groups and those in
+    /// ~/.config/mccat/profiles.toml
+    Profiles,
+    /// Resend the multicast datagrams in a pcap or pcapng capture
+    Replay(ReplayArgs),
+    /// Decode the announcements of a discovery protocol
+    #[command(subcommand)]
+    Discover(DiscoverCommand),
+    /// Make groups available to unicast clients
+    #[command(subcommand)]
+    Relay(RelayCommand),
+    /// Forward datagrams between a group and a unicast UDP peer
+    Bridge(BridgeArgs),
+    /// Join a group on one interface and resend it on another
+    Forward(ForwardArgs),
+    /// Send a file round and round to a group, with FEC, for any number of pull receivers
+    Push(PushFileArgs),
+    /// Receive a file sent with push and write it out once complete
+    Pull(PullFileArgs),
+    /// Chat on a group with everyone else running chat on it
+    Chat(ChatArgs),
+    /// Join each group in a prefix for a moment and report those that carry traffic
+    Scan(ScanArgs),
+    /// Join groups and hold the memberships, printing nothing, until interrupted
+    Join(JoinArgs),
+    /// Measure how long joining each group takes to bring the first datagram, or keyframe
+    Zap(ZapArgs),
+    /// Join a main and a backup group carrying the same stream and put out one seamless stream, as SMPTE
+    /// 2022-7 receivers do
+    Merge(MergeArgs),
+    /// Watch the messages hosts and routers exchange to manage group membership
+    #[command(subcommand)]
+    Observe(ObserveCommand),
+    /// Load switches and routers to find their limits
+    #[command(subcommand)]
+    Stress(StressCommand),
+    /// Send IGMP/MLD queries on a link with no multicast router, so snooping switches keep forwarding; needs
+    /// CAP_NET_RAW
+    Querier(QuerierArgs),
+    /// Send a command to a listen instance started with --control
+    Ctl(CtlArgs),
+}
+
+#[derive(Subcommand)]
+pub enum DiscoverCommand {
+    /// Show sessions announced with SAP/SDP
+    Sap(SapArgs),
+    /// Query multicast DNS and show the hosts and services that answer
+    Mdns(MdnsArgs),
+    /// Search for UPnP devices with SSDP and show their announcements
+    Ssdp(SsdpArgs),
+}
+
+#[derive(Subcommand)]
+pub enum RelayCommand {
+    /// Serve groups over HTTP, udpxy style, e.g. GET /udp/239.1.1.1:1234
+    Http(HttpRelayArgs),
+}
+
+#[derive(Subcommand)]
+pub enum ObserveCommand {
+    /// Print the IGMP and MLD joins, leaves and queries on the link, and querier elections; needs CAP_NET_RAW
+    Igmp(IgmpArgs),
+    /// Join the PTP (IEEE 1588) group and report the grandmasters, domains and message rates; binding ports 319
+    /// and 320 needs root
+    Ptp(PtpArgs),
+    /// Print the OSPF, RIP, PIM, VRRP and HSRP hellos and advertisements on the link, showing which routers
+    /// are talking; needs CAP_NET_RAW
+    Routing(RoutingArgs),
+}
+
+#[derive(Subcommand)]
+pub enum StressCommand {
+    /// Join and leave many groups at a steady rate, churning IGMP snooping tables and multicast routing state
+    Joins(StressJoinsArgs),
+    /// Join as many groups as the host allows, then optionally check that traffic flows on all of them
+    Groups(StressGroupsArgs),
+}
+
+/// Parses a number of events per second or minute, as in `100/s` or
+/// `30/m`; a bare number is per second.
+pub fn parse_per_second(value: &str) -> Result<f64, String> {
+    let (number, unit) = if let Some(number) = value.strip_suffix("/s") {
+        (number, 1.0)
+    } else if let Some(number) = value.strip_suffix("/m") {
+        (number, 60.0)
+    } else {
+        (value, 1.0)
+    };
+    match number.parse::<f64>() {
+        Ok(count) if count > 0.0 && count.is_finite() => Ok(count / unit),
+        _ => Err(format!("invalid rate {}: expected a positive number per second or minute, e.g. 100/s", value)),
+    }
+}
+
+/// Parses a port number or a service name from /etc/services, e.g. ntp.
+pub fn parse_port(value: &str) -> Result<u16, String> {
+    value.parse().ok().or_else(|| resolve::service_port(value)).ok_or_else(|| {
+        format!("invalid port {}: expected a number or a service name from /etc/services", value)
+    })
+}
+
+/// How many ports a list or range may expand to, as each takes a socket.
+const MAX_PORTS: usize = 1024;
+
+/// Parses a comma-separated list of ports, service names and ranges, e.g.
+/// `5000-5004` or `5004,5006,rtsp`.
+pub fn parse_ports(value: &str) -> Result<Vec<u16>, String> {
+    let mut ports = Vec::new();
+    for item in value.split(',') {
+        let range = item.split_once('-').filter(|(first, _)| first.parse::<u16>().is_ok());
+        match range {
+            Some((first, last)) => {
+                let (first, last) = (parse_port(first)?, parse_port(last)?);
+                if first > last {
+                    return Err(format!("invalid port range {}: {} is above {}", item, first, last));
+                }
+                ports.extend(first..=last);
+            }
+            None => ports.push(parse_port(item)?),
+        }
+        if ports.len() > MAX_PORTS {
+            return Err(format!("too many ports in {}: at most {}", value, MAX_PORTS));
+        }
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    Ok(ports)
+}
+
+/// Parses `GROUP:PORT`, taking the zone of a scoped IPv6 group, as in
+/// `[ff02::1%eth0]:5000`, for the interface to join on.
+pub fn parse_group(value: &str) -> Result<Group, String> {
+    let (addr, port) = ScopedAddr::parse_with_port(value)?;
+    Ok(Group {
+        addr: (addr.addr, port).into(),
+        interface: addr.zone,
+        source: None,
+    })
+}
+
+/// Parses seconds, optionally with an ms, s, m or h suffix.
+pub fn parse_seconds(value: &str) -> Result<Duration, String> {
+    let (number, unit) = if let Some(number) = value.strip_suffix("ms") {
+        (number, 1e-3)
+    } else if let Some(number) = value.strip_suffix('s') {
+        (number, 1.0)
+    } else if let Some(number) = value.strip_suffix('m') {
+        (number, 60.0)
+    } else if let Some(number) = value.strip_suffix('h') {
+        (number, 3600.0)
+    } else {
+        (value, 1.0)
+    };
+    match number.parse::<f64>() {
+        Ok(secs) if secs > 0.0 && secs.is_finite() => Ok(Duration::from_secs_f64(secs * unit)),
+        _ => Err(format!("invalid duration {}: expected a positive number of seconds", value)),
+    }
+}
+
+pub fn parse_on_off(value: &str) -> Result<bool, String> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("expected on or off, got {}", value)),
+    }
+}
+
+/// Parses an inclusive TTL range such as 1..16.
+pub fn parse_ttl_range(value: &str) -> Result<(u8, u8), String> {
+    let (first, last) = value.split_once("..").ok_or_else(|| format!("expected FIRST..LAST, got {}", value))?;
+    let first: u8 = first.parse().map_err(|_| format!("invalid TTL {}", first))?;
+    let last: u8 = last.parse().map_err(|_| format!("invalid TTL {}", last))?;
+    if first == 0 || first > last {
+        return Err(format!("expected 1 <= FIRST <= LAST, got {}", value));
+    }
+    Ok((first, last))
+}
+
+/// Most datagrams per `recvmmsg` / `sendmmsg` call (the kernel's UIO_MAXIOV).
+const MAX_BATCH: usize = 1024;
+
+pub fn parse_batch(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(size) if (1..=MAX_BATCH).contains(&size) => Ok(size),
+        _ => Err(format!("expected 1 to {} datagrams, got {}", MAX_BATCH, value)),
+    }
+}
+
+/// A byte string given as one argument; clap would take a bare `Vec<u8>`
+/// as a list of arguments.
+pub type Bytes = Vec<u8>;
+
+/// Parses a probe size, which must fit in one UDP datagram.
+pub fn parse_probe_size(value: &str) -> Result<usize, String> {
+    match parse_size(value)? {
+        size if size <= mccat::MAX_PROBE_SIZE => Ok(size),
+        _ => Err(format!("expected at most {} bytes, got {}", mccat::MAX_PROBE_SIZE, value)),
+    }
+}
+
+/// Parses a FIRST:LAST:STEP range of probe sizes such as 64:1500:64.
+pub fn parse_size_range(value: &str) -> Result<(usize, usize, usize), String> {
+    let parts: Vec<_> = value.split(':').collect();
+    if parts.len() != 3 {
+        return Err(format!("expected FIRST:LAST:STEP, got {}", value));
+    }
+    let (first, last, step) = (parse_probe_size(parts[0])?, parse_probe_size(parts[1])?, parse_size(parts[2])?);
+    if first == 0 || first > last || step == 0 {
+        return Err(format!("expected 0 < FIRST <= LAST and STEP > 0, got {}", value));
+    }
+    Ok((first, last, step))
+}
+
+/// Parses pairs of hex digits such as "deadbeef" or "de:ad:be:ef".
+pub fn parse_hex_bytes(value: &str) -> Result<Vec<u8>, String> {
+    mccat::filter::parse_hex(value).ok_or_else(|| format!("invalid hex {}: expected pairs of hex digits", value))
+}
+
+/// Makes an HMAC key of a shared secret, which must not be empty.
+pub fn parse_key(value: &str) -> Result<Key, String> {
+    if value.is_empty() {
+        return Err("the key must not be empty".to_owned());
+    }
+    Ok(Key::new(value.as_bytes()))
+}
+
+/// Parses a chat nickname, which can't contain spaces.
+pub fn parse_nick(value: &str) -> Result<String, String> {
+    if !chat::valid_nick(value) {
+        return Err(format!("invalid nickname {:?}: expected no spaces or control characters", value));
+    }
+    Ok(value.to_owned())
+}
+
+pub fn parse_tag(value: &str) -> Result<String, String> {
+    if !tag::valid(value) {
+        return Err(format!("invalid tag {:?}: expected 1 to {} letters, digits, dots, dashes or underscores",
+                           value, tag::MAX_LEN));
+    }
+    Ok(value.to_owned())
+}
+
+/// Makes a cipher keyed with a pre-shared secret, which must not be empty.
+pub fn parse_cipher(value: &str) -> Result<Cipher, String> {
+    if value.is_empty() {
+        return Err("the secret must not be empty".to_owned());
+    }
+    Ok(Cipher::new(value.as_bytes()))
+}
+
+/// Parses a byte given in decimal or, with a 0x prefix, hex.
+pub fn parse_tos(value: &str) -> Result<u8, String> {
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("invalid TOS {}: expected 0-255 or 0x00-0xff", value))
+}
+
+pub fn parse_speed(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!("invalid speed {}: expected a positive factor", value)),
+    }
+}
+
+/// Parses a byte count with an optional K, M or G (binary) suffix.
+pub fn parse_size(value: &str) -> Result<usize, String> {
+    let invalid = || format!("invalid size {}: expected bytes with optional K, M or G suffix", value);
+    let (digits, multiplier) = match value.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&value[..value.len() - 1], 1 << 10),
+        Some('M') => (&value[..value.len() - 1], 1 << 20),
+        Some('G') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    let size: usize = digits.parse().map_err(|_| invalid())?;
+    size.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+#[derive(Args)]
+pub struct BufferArgs {
+    /// Socket receive buffer size (SO_RCVBUF), e.g. 8M
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub rcvbuf: Option<usize>,
+
+    /// Socket send buffer size (SO_SNDBUF)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub sndbuf: Option<usize>,
+}
+
+/// Parses a percentage such as 1% or 0.5%, returning it as a fraction.
+pub fn parse_percent(value: &str) -> Result<f64, String> {
+    match value.trim_end_matches('%').parse::<f64>() {
+        Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(percent / 100.0),
+        _ => Err(format!("invalid percentage {}: expected 0% to 100%", value)),
+    }
+}
+
+/// Impairments for `forward` and `bridge` to put datagrams through.
+#[derive(Args)]
+pub struct ImpairArgs {
+    /// Drop this share of datagrams, e.g. 1%
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percent)]
+    pub drop: Option<f64>,
+
+    /// Hold datagrams back for this long, optionally give or take some jitter, e.g. 20ms or 20ms±5ms
+    #[arg(long, value_name = "DELAY")]
+    pub delay: Option<Delay>,
+
+    /// Send this share of datagrams twice, e.g. 0.1%
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percent)]
+    pub duplicate: Option<f64>,
+
+    /// Let the next datagram overtake this share of datagrams, e.g. 0.5%
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percent)]
+    pub reorder: Option<f64>,
+
+    /// Seed the impairments so that a run can be repeated exactly
+    #[arg(long, value_name = "N")]
+    pub seed: Option<u64>,
+}
+
+/// Resolves a HOST:PORT, taking the first address.
+pub fn parse_host_port(value: &str) -> Result<net::SocketAddr, String> {
+    let mut addrs = value.to_socket_addrs().map_err(|e| format!("invalid address {}: {}", value, e))?;
+    addrs.next().ok_or_else(|| format!("invalid address {}: no addresses found", value))
+}
+
+#[derive(Args)]
+pub struct PushArgs {
+    /// Push statistics to a StatsD agent at HOST:PORT over UDP
+    #[arg(long, value_name = "HOST:PORT", value_parser = parse_host_port)]
+    pub statsd: Option<net::SocketAddr>,
+
+    /// Push statistics to an InfluxDB UDP listener at HOST:PORT in line protocol
+    #[arg(long, value_name = "HOST:PORT", value_parser = parse_host_port)]
+    pub influx: Option<net::SocketAddr>,
+
+    /// Seconds between pushes to --statsd or --influx
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "10")]
+    pub push_interval: Duration,
+}
+
+#[derive(Args)]
+pub struct DaemonArgs {
+    /// Detach from the terminal and run in the background, logging to syslog
+    #[arg(long)]
+    pub daemon: bool,
+
+    /// Write the daemon's process ID to this file, removed on exit
+    #[arg(long, value_name = "PATH", requires = "daemon")]
+    pub pidfile: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct InterfaceArgs {
+    /// Interface to use, given as a name, index or address
+    #[arg(short = 'I', long)]
+    pub interface: Option<String>,
+}
+
+#[derive(Args)]
+pub struct FamilyArgs {
+    /// Resolve group names to IPv4 addresses only
+    #[arg(short = '4', long, conflicts_with = "ipv6")]
+    pub ipv4: bool,
+
+    /// Resolve group names to IPv6 addresses only
+    #[arg(short = '6', long)]
+    pub ipv6: bool,
+}
+
+#[derive(Args)]
+pub struct SenderArgs {
+    /// Interface to use, given as a name, index or address; send, generate and ping take several, sending
+    /// every datagram out of each, as over redundant paths
+    #[arg(short = 'I', long = "interface", value_name = "INTERFACE")]
+    pub interfaces: Vec<String>,
+
+    #[command(flatten)]
+    pub family: FamilyArgs,
+
+    #[command(flatten)]
+    pub buffers: BufferArgs,
+
+    /// Multicast TTL / hop limit
+    #[arg(short, long, default_value_t = mccat::DEFAULT_TTL)]
+    pub ttl: u8,
+
+    /// Whether listeners on this host receive our own traffic
+    #[arg(long, value_name = "on|off", value_parser = parse_on_off, default_value = "on", action = ArgAction::Set)]
+    pub loopback: bool,
+
+    /// Mark datagrams with this DSCP, e.g. EF, AF41 or 46
+    #[arg(long, value_parser = dscp::parse, conflicts_with = "tos")]
+    pub dscp: Option<u8>,
+
+    /// Set the whole TOS / traffic class byte, e.g. 0xb8
+    #[arg(long, value_parser = parse_tos)]
+    pub tos: Option<u8>,
+}
+
+#[derive(Args)]
+pub struct ListenArgs {
+    /// Group address and port pairs to join, or profile names such as mdns (see mccat profiles); a
+    /// zone, as in ff02::1%eth0, picks the interface, and the port may be a list or range, as in
+    /// 5004,5006 or 5000-5004
+    #[arg(value_name = "ADDRESS PORT | PROFILE")]
+    pub endpoints: Vec<String>,
+
+    /// Additional group to join, as address:port
+    #[arg(short, long = "group", value_name = "GROUP:PORT", value_parser = parse_group)]
+    pub groups: Vec<Group>,
+
+    /// Also join the well-known counterpart of each group in the other address family
+    #[arg(long)]
+    pub dual_stack: bool,
+
+    /// Only receive traffic from this sender (source-specific multicast)
+    #[arg(short, long)]
+    pub source: Option<net::IpAddr>,
+
+    /// Only accept traffic from senders in this address or prefix; repeatable
+    #[arg(long, value_name = "CIDR")]
+    pub allow_source: Vec<Cidr>,
+
+    /// Drop traffic from senders in this address or prefix; repeatable
+    #[arg(long, value_name = "CIDR")]
+    pub block_source: Vec<Cidr>,
+
+    /// Have the kernel apply the source lists (MCAST_MSFILTER); single addresses only
+    #[arg(long)]
+    pub kernel_filter: bool,
+
+    /// Only accept datagrams whose payload contains this text, hex:BYTES or
+    /// re:REGEX; repeatable, any may match
+    #[arg(short = 'm', long = "match", value_name = "PATTERN")]
+    pub patterns: Vec<PayloadPattern>,
+
+    /// Accept only datagrams that match none of the --match patterns
+    #[arg(long, requires = "patterns")]
+    pub invert_match: bool,
+
+    #[command(flatten)]
+    pub interface: InterfaceArgs,
+
+    #[command(flatten)]
+    pub family: FamilyArgs,
+
+    /// Set SO_REUSEADDR so several receivers can share the group port
+    #[arg(long, value_name = "on|off", value_parser = parse_on_off, default_value = "on", action = ArgAction::Set)]
+    pub reuse: bool,
+
+    /// Also set SO_REUSEPORT
+    #[arg(long)]
+    pub reuse_port: bool,
+
+    /// Answer ping probes unicast to the prober or multicast to the group
+    #[arg(long, value_name = "unicast|multicast", default_value = "unicast")]
+    pub reply: ReplyPath,
+
+    #[command(flatten)]
+    pub buffers: BufferArgs,
+
+    /// Largest datagram received without truncation
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value_t = mccat::DEFAULT_BUFFER_SIZE)]
+    pub buffer_size: usize,
+
+    /// Receive up to this many datagrams per system call (recvmmsg on Linux)
+    #[arg(long, value_name = "COUNT", value_parser = parse_batch, default_value = "1")]
+    pub batch: usize,
+
+    /// Report throughput, loss and jitter of `mccat generate` streams every second
+    /// instead of printing datagrams
+    #[arg(long, conflicts_with_all = ["output", "format", "binary", "hex", "detect_loss", "daemon"])]
+    pub measure: bool,
+
+    /// With --measure, also report percentiles of one-way delay from the send times in the datagrams
+    #[arg(long, requires = "measure")]
+    pub one_way_delay: bool,
+
+    /// Take the sender's clock to be synced with ours (e.g. by NTP or PTP), so that one-way delay is absolute
+    /// rather than relative to the fastest datagram
+    #[arg(long, requires = "one_way_delay")]
+    pub synced_clocks: bool,
+
+    /// Output format: text, binary, hex, json or csv
+    #[arg(short, long, value_name = "FORMAT", conflicts_with_all = ["binary", "hex"])]
+    pub output: Option<OutputFormat>,
+
+    /// Print each datagram using a template such as '{time} {src_ip} {len} {payload}'
+    #[arg(long, value_name = "TEMPLATE", conflicts_with_all = ["output", "binary", "hex"])]
+    pub format: Option<Template>,
+
+    /// CSV columns: time, src, src_port, group, port, dst, interface, ttl, dscp, len, payload
+    #[arg(long, value_name = "COLUMNS", value_delimiter = ',',
+          default_value = "time,src,src_port,group,port,dst,interface,len,payload")]
+    pub columns: Vec<CsvColumn>,
+
+    /// Payload representation in structured output: utf8 or base64
+    #[arg(long, value_name = "ENCODING", default_value = "utf8")]
+    pub payload_encoding: PayloadEncoding,
+
+    /// Decode payloads as rtp or mpegts, logging a per-PID table now and
+    /// then for mpegts. Text output shows RTP headers, or nothing for
+    /// mpegts; other output and --output-dir get the payload without the
+    /// RTP header
+    #[arg(long, value_name = "PROTOCOL", conflicts_with = "measure")]
+    pub decode: Option<Decode>,
+
+    /// Decoder program, or WebAssembly module run with wasmtime, to turn each payload into a line of text such as
+    /// JSON; it gets SRC GROUP HEX-PAYLOAD lines on stdin and answers each with the decoded line
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["decode", "measure"])]
+    pub decoder: Option<PathBuf>,
+
+    /// RTP timestamp clock rate for jitter (default: from the payload type, else 90000)
+    #[arg(long, value_name = "HZ", requires = "decode")]
+    pub clock_rate: Option<u32>,
+
+    /// Write payloads verbatim to stdout (same as --output binary)
+    #[arg(short, long, conflicts_with = "hex")]
+    pub binary: bool,
+
+    /// Precede each binary payload with its length as a 32-bit big-endian integer
+    #[arg(long)]
+    pub length_prefix: bool,
+
+    /// Print each datagram as a hexdump (same as --output hex)
+    #[arg(long)]
+    pub hex: bool,
+
+    /// Append each group's payloads to its own file in this directory, e.g. 239.1.1.1_1234.bin,
+    /// instead of printing them
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["output", "format", "binary", "hex", "measure"])]
+    pub output_dir: Option<PathBuf>,
+
+    /// Append output to this file instead of printing it
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["output_dir", "config", "measure"])]
+    pub output_file: Option<PathBuf>,
+
+    /// Start a new output file when the current one reaches this size
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub rotate_size: Option<usize>,
+
+    /// Start a new output file after this many seconds
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub rotate_interval: Option<Duration>,
+
+    /// Compress each rotated output file with gzip or zstd once the next one is started
+    #[arg(long, value_name = "gzip|zstd")]
+    pub compress: Option<Compression>,
+
+    /// Show the TTL and DSCP of each datagram
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Prefix datagrams with the receive time: iso8601, epoch, relative or delta
+    #[arg(short = 'T', long, value_name = "FORMAT")]
+    pub timestamp: Option<TimestampFormat>,
+
+    /// Take receive times from the NIC's clock rather than the kernel's, for NICs with hardware stamping
+    /// enabled; only right where that clock is kept in step with the system's, e.g. by phc2sys
+    #[arg(long)]
+    pub hardware_timestamps: bool,
+
+    /// Report missing, duplicated and reordered packets on stderr
+    #[arg(long)]
+    pub detect_loss: bool,
+
+    /// Read sequence numbers from a big-endian payload field instead of PING probes, for --detect-loss and
+    /// --dedup
+    #[arg(long, value_name = "OFFSET:WIDTH")]
+    pub seq_field: Option<FieldSequence>,
+
+    /// Report datagrams that repeat one of the recent ones on the group, by payload or --seq-field, as
+    /// multicast loops and double forwarding cause
+    #[arg(long)]
+    pub dedup: bool,
+
+    /// How many recent datagrams --dedup compares each one with
+    #[arg(long, value_name = "COUNT", value_parser = clap::value_parser!(u32).range(1..), default_value = "1024",
+          requires = "dedup")]
+    pub dedup_window: u32,
+
+    /// Leave out the duplicates --dedup finds, as if they never arrived
+    #[arg(long, requires = "dedup")]
+    pub drop_duplicates: bool,
+
+    /// Print packet rates, inter-arrival gaps and jitter to stderr on exit
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Also print the statistics every this many seconds
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, requires = "stats")]
+    pub stats_interval: Option<Duration>,
+
+    /// Print packets, bytes, rate and sender count to stderr every this many seconds
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub report_interval: Option<Duration>,
+
+    /// Show a live dashboard of rates, jitter, loss and recent datagrams instead of printing them; p pauses,
+    /// / filters, the arrow keys and x show a datagram in hex and q quits
+    #[arg(long, conflicts_with_all = ["daemon", "decode", "measure"])]
+    pub tui: bool,
+
+    /// Serve Prometheus metrics (packets, bytes, jitter, loss, per group and per sender) on
+    /// http://ADDRESS:PORT/metrics
+    #[arg(long, value_name = "ADDRESS:PORT", conflicts_with = "measure")]
+    pub metrics_listen: Option<net::SocketAddr>,
+
+    #[command(flatten)]
+    pub push: PushArgs,
+
+    #[command(flatten)]
+    pub daemon: DaemonArgs,
+
+    /// Accept commands (join, leave, groups, stats, output, shutdown) on this Unix socket, e.g.
+    /// /run/mccat.sock, for mccat ctl
+    #[arg(long, value_name = "PATH", conflicts_with = "measure")]
+    pub control: Option<PathBuf>,
+
+    /// Join the groups in this TOML file and apply its output and filters, re-reading it on SIGHUP to
+    /// join and leave groups as they change
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["measure", "kernel_filter"])]
+    pub config: Option<PathBuf>,
+
+    /// Count traffic per sender, listing senders with each report and the top talkers on exit
+    #[arg(long)]
+    pub per_source: bool,
+
+    /// Take off the tags that send and generate --tag put on datagrams, counting traffic per tag with each
+    /// report and on exit, and with --output-dir writing each tag's datagrams to a file of its own
+    #[arg(long)]
+    pub by_tag: bool,
+
+    /// With --by-tag and --output-dir, the most tags given a file of their own; datagrams with any other tag
+    /// go to their group's file, so that senders can't have files opened without end
+    #[arg(long, value_name = "COUNT", default_value_t = mccat::output::DEFAULT_MAX_TAG_FILES, requires = "by_tag")]
+    pub max_tag_files: usize,
+
+    /// Exit after receiving this many datagrams
+    #[arg(short, long)]
+    pub count: Option<u64>,
+
+    /// Exit after this many seconds
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub duration: Option<Duration>,
+
+    /// Warn on stderr when no datagram has arrived for this long
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub idle_timeout: Option<Duration>,
+
+    /// Exit with status 4 instead of warning when the idle timeout expires
+    #[arg(long, requires = "idle_timeout")]
+    pub expect_traffic: bool,
+
+    /// Alert when a group's traffic stays above or below a rate, as above:20000pps for a storm or
+    /// below:2mbit for a failing feed; several can be given separated by commas
+    #[arg(long, value_name = "THRESHOLD", value_delimiter = ',')]
+    pub alert_rate: Vec<Threshold>,
+
+    /// How long a group must stay past an --alert-rate threshold before alerting
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "5")]
+    pub alert_after: Duration,
+
+    /// Exit with status 5 on the first alert instead of carrying on
+    #[arg(long, requires = "alert_rate")]
+    pub alert_exit: bool,
+
+    /// Run this shell command for each datagram, with the payload on stdin and MCCAT_SRC, MCCAT_SRC_PORT,
+    /// MCCAT_GROUP, MCCAT_PORT, MCCAT_SIZE and MCCAT_TIME in its environment
+    #[arg(long, value_name = "COMMAND", conflicts_with = "measure")]
+    pub exec: Option<String>,
+
+    /// When to run --exec: packet, down (the idle timeout expired, with MCCAT_GROUPS set), up (traffic
+    /// resumed), alert (an --alert-rate threshold was crossed, with MCCAT_GROUP, MCCAT_PORT, MCCAT_RATE and
+    /// MCCAT_THRESHOLD set) or clear (the rate came back); several can be given separated by commas
+    #[arg(long, value_name = "EVENTS", value_delimiter = ',', default_value = "packet", requires = "exec")]
+    pub exec_on: Vec<Trigger>,
+
+    /// Check and remove the HMAC-SHA256 tag that send, ping and generate --key end datagrams with, warning
+    /// of datagrams whose tag is bad or missing
+    #[arg(long, value_name = "SECRET", value_parser = parse_key)]
+    pub key: Option<Key>,
+
+    /// Decrypt datagrams that send --encrypt encrypted with this pre-shared secret, dropping those that
+    /// don't decrypt
+    #[arg(long, value_name = "SECRET", value_parser = parse_cipher)]
+    pub encrypt: Option<Cipher>,
+
+    /// Restore datagrams compressed by send or forward --compress; others are left as they are
+    #[arg(long)]
+    pub decompress: bool,
+
+    /// Take off the cycle headers send --carousel puts on datagrams, reporting where each cycle starts and
+    /// which datagrams of it never arrived; others are left as they are
+    #[arg(long)]
+    pub carousel: bool,
+
+    /// Reassemble messages that send --frame mccat split into fragments
+    #[arg(long, value_name = "mccat", value_parser = ["mccat"])]
+    pub frame: Option<String>,
+
+    /// Program to pass each datagram to, as a line of SRC GROUP HEX-PAYLOAD on its stdin, which answers
+    /// each with a line saying pass, drop, rewrite HEX-PAYLOAD or note TEXT
+    #[arg(long, value_name = "PATH", conflicts_with = "measure")]
+    pub script: Option<PathBuf>,
+
+    /// Also record received datagrams to a pcapng file
+    #[arg(long, value_name = "PATH")]
+    pub write_pcap: Option<PathBuf>,
+
+    /// Start a new pcapng file when the current one reaches this size
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, requires = "write_pcap")]
+    pub pcap_rotate_size: Option<usize>,
+
+    /// Start a new pcapng file after this many seconds
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, requires = "write_pcap")]
+    pub pcap_rotate_interval: Option<Duration>,
+}
+
+#[derive(Args)]
+pub struct SendArgs {
+    pub address: Host,
+    #[arg(value_parser = parse_port)]
+    pub port: u16,
+
+    #[command(flatten)]
+    pub sender: SenderArgs,
+
+    /// Read from this file instead of stdin
+    #[arg(short, long, value_name = "PATH")]
+    pub file: Option<PathBuf>,
+
+    /// Send the lines appended to this file as they come, like tail -F, following it when it is rotated
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["file", "carousel"])]
+    pub follow: Option<PathBuf>,
+
+    /// Cut the input into datagrams by line, size:N byte chunks or whole input, or send each line as a
+    /// message split into fragments of up to N bytes (default 1400) for listen --frame mccat to reassemble
+    /// (default: one datagram per read, minus a trailing newline)
+    #[arg(long, value_name = "line|size:N|whole|mccat[:N]")]
+    pub frame: Option<Framing>,
+
+    /// Limit the send rate, e.g. 500pps or 10mbit
+    #[arg(long)]
+    pub rate: Option<Rate>,
+
+    /// Largest chunk of input sent as one datagram
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value_t = mccat::DEFAULT_BUFFER_SIZE)]
+    pub buffer_size: usize,
+
+    /// Announce the stream with SAP/SDP under this session name while sending
+    #[arg(long, value_name = "NAME")]
+    pub announce_sap: Option<String>,
+
+    /// RTP payload type to announce; without it the stream is announced as raw UDP
+    #[arg(long, value_name = "PT", value_parser = clap::value_parser!(u8).range(0..=127), requires = "announce_sap")]
+    pub payload_type: Option<u8>,
+
+    /// Seconds between SAP announcements
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "5", requires = "announce_sap")]
+    pub sap_interval: Duration,
+
+    /// Put a header naming this sender instance in front of each datagram, for listen --by-tag to count
+    /// and file datagrams by
+    #[arg(long, value_name = "ID", value_parser = parse_tag)]
+    pub tag: Option<String>,
+
+    /// Append an HMAC-SHA256 tag made with this shared secret to each datagram, for listen --key to check
+    #[arg(long, value_name = "SECRET", value_parser = parse_key)]
+    pub key: Option<Key>,
+
+    /// Encrypt each datagram with ChaCha20-Poly1305 under this pre-shared secret, for listen --encrypt to
+    /// decrypt
+    #[arg(long, value_name = "SECRET", value_parser = parse_cipher)]
+    pub encrypt: Option<Cipher>,
+
+    /// Compress datagrams that get smaller for it, for listen --decompress to restore; lz4 is the only codec,
+    /// there is no zstd
+    #[arg(long, value_name = "lz4")]
+    pub compress: Option<Codec>,
+
+    /// Read the whole input, then send it over and over for receivers that join late, each datagram behind a
+    /// 15 byte header: "MCC", then the cycle, the datagram's index in it and the datagrams per cycle as u32s
+    #[arg(long, conflicts_with = "announce_sap")]
+    pub carousel: bool,
+
+    /// Seconds from the start of one --carousel cycle to the next
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "10", requires = "carousel")]
+    pub interval: Duration,
+}
+
+#[derive(Args)]
+pub struct PingArgs {
+    pub address: Host,
+    #[arg(value_parser = parse_port)]
+    pub port: u16,
+
+    #[command(flatten)]
+    pub sender: SenderArgs,
+
+    /// Stop after sending this many probes
+    #[arg(short, long)]
+    pub count: Option<u64>,
+
+    /// Seconds between probes
+    #[arg(short, long, value_parser = parse_seconds, default_value = "0.25")]
+    pub interval: Duration,
+
+    /// Stop after this many seconds
+    #[arg(short = 'w', long, value_parser = parse_seconds)]
+    pub deadline: Option<Duration>,
+
+    /// Only succeed if every probe was answered
+    #[arg(long)]
+    pub expect_all: bool,
+
+    /// Send each probe as soon as the last is answered, at least every 10 ms, showing
+    /// a dot per unanswered probe
+    #[arg(short, long, conflicts_with_all = ["interval", "adaptive"])]
+    pub flood: bool,
+
+    /// Send each probe as soon as the last is answered, at most --interval apart
+    #[arg(short = 'A', long)]
+    pub adaptive: bool,
+
+    /// Pad probes to this many bytes, e.g. 1472 to fill a 1500 byte MTU
+    #[arg(short, long, value_parser = parse_probe_size, group = "padding")]
+    pub size: Option<usize>,
+
+    /// Pad probes to each size in turn, e.g. 64:1500:64, and report the largest each responder answers
+    #[arg(long, value_name = "FIRST:LAST:STEP", value_parser = parse_size_range, group = "padding",
+          conflicts_with_all = ["count", "ttl_sweep"])]
+    pub sweep_size: Option<(usize, usize, usize)>,
+
+    /// Hex bytes to pad probes with, checked in replies (default: 00 01 02 ...)
+    #[arg(short, long, value_name = "HEX", value_parser = parse_hex_bytes, requires = "padding")]
+    pub pattern: Option<Bytes>,
+
+    /// Where responders send their replies; with multicast, also join the group to hear them
+    #[arg(long, value_name = "unicast|multicast", default_value = "unicast")]
+    pub reply: ReplyPath,
+
+    /// Probe with each TTL in turn, e.g. 1..16, and report the hop count at which responders answer
+    #[arg(long, value_name = "FIRST..LAST", value_parser = parse_ttl_range, conflicts_with = "count")]
+    pub ttl_sweep: Option<(u8, u8)>,
+
+    /// End each probe with an HMAC-SHA256 tag made with this shared secret, for listen --key to check
+    #[arg(long, value_name = "SECRET", value_parser = parse_key)]
+    pub key: Option<Key>,
+
+    #[command(flatten)]
+    pub push: PushArgs,
+}
+
+#[derive(Args)]
+pub struct RespondArgs {
+    pub address: Host,
+    #[arg(value_parser = parse_port)]
+    pub port: u16,
+
+    /// Only answer probes from this sender (source-specific multicast)
+    #[arg(short, long)]
+    pub source: Option<net::IpAddr>,
+
+    /// Send replies unicast to the prober or multicast to the group
+    #[arg(long, value_name = "unicast|multicast", default_value = "unicast")]
+    pub reply: ReplyPath,
+
+    #[command(flatten)]
+    pub sender: SenderArgs,
+}
+
+#[derive(Args)]
+pub struct CensusArgs {
+    pub address: Host,
+    #[arg(value_parser = parse_port)]
+    pub port: u16,
+
+    #[command(flatten)]
+    pub sender: SenderArgs,
+
+    /// Seconds to collect replies for
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "2")]
+    pub window: Duration,
+}
+
+#[derive(Args)]
+pub struct GenerateArgs {
+    pub address: Host,
+    #[arg(value_parser = parse_port)]
+    pub port: u16,
+
+    #[command(flatten)]
+    pub sender: SenderArgs,
+
+    /// Datagram size, including the 20 byte sequence and timestamp header
+    #[arg(short, long, value_parser = parse_size, default_value = "1000")]
+    pub size: usize,
+
+    /// Send rate, e.g. 500pps or 10mbit
+    #[arg(short, long, default_value = "1mbit")]
+    pub rate: Rate,
+
+    /// Stop after this many seconds
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "10")]
+    pub duration: Duration,
+
+    /// Stop after sending this many datagrams
+    #[arg(short, long)]
+    pub count: Option<u64>,
+
+    /// Send up to this many datagrams per system call (sendmmsg on Linux)
+    #[arg(long, value_name = "COUNT", value_parser = parse_batch, default_value = "1")]
+    pub batch: usize,
+
+    /// Put a header naming this sender instance in front of each datagram, for listen --by-tag to count
+    /// and file datagrams by
+    #[arg(long, value_name = "ID", value_parser = parse_tag)]
+    pub tag: Option<String>,
+
+    /// End each datagram with an HMAC-SHA256 tag made with this shared secret, in place of the last 16 bytes
+    /// of padding, for listen --key to check
+    #[arg(long, value_name = "SECRET", value_parser = parse_key)]
+    pub key: Option<Key>,
+}
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    /// Capture file to read
+    pub file: PathBuf,
+    pub address: Host,
+    #[arg(value_parser = parse_port)]
+    pub port: u16,
+
+    #[command(flatten)]
+    pub sender: SenderArgs,
+
+    /// Scale the captured inter-packet gaps, e.g. 2 to replay twice as fast
+    #[arg(long, value_parser = parse_speed, default_value = "1")]
+    pub speed: f64,
+
+    /// Limit the send rate, e.g. 500pps or 10mbit
+    #[arg(long)]
+    pub rate: Option<Rate>,
+
+    /// Start over at the end of the capture until interrupted
+    #[arg(long = "loop")]
+    pub repeat: bool,
+
+    /// Send up to this many datagrams per system call (sendmmsg on Linux)
+    #[arg(long, value_name = "COUNT", value_parser = parse_batch, default_value = "1")]
+    pub batch: usize,
+}
+
+#[derive(Args)]
+pub struct SapArgs {
+    #[command(flatten)]
+    pub interface: InterfaceArgs,
+
+    /// Save each announced session description as an .sdp file in this directory
+    #[arg(long, value_name = "DIR")]
+    pub sdp_dir: Option<PathBuf>,
+
+    /// Keep an M3U playlist of the announced streams at this path
+    #[arg(long, value_name = "PATH")]
+    pub m3u: Option<PathBuf>,
+
+    /// Stop after this many seconds
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub duration: Option<Duration>,
+}
+
+#[derive(Args)]
+pub struct MdnsArgs {
+    /// Names to query (default: the DNS-SD service type list, following each type found)
+    #[arg(value_name = "NAME")]
+    pub names: Vec<String>,
+
+    /// Record types to ask for: A, AAAA, PTR, SRV, TXT or ANY; repeatable
+    #[arg(short = 't', long = "type", value_name = "TYPE", value_parser = dns::parse_type, default_value = "PTR")]
+    pub types: Vec<u16>,
+
+    /// Only listen to the queries and answers of others
+    #[arg(long)]
+    pub passive: bool,
+
+    #[command(flatten)]
+    pub interface: InterfaceArgs,
+
+    /// Stop after this many seconds (default: 3, or until interrupted with --passive)
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub duration: Option<Duration>,
+}
+
+#[derive(Args)]
+pub struct SsdpArgs {
+    /// Search target, e.g. upnp:rootdevice or urn:schemas-upnp-org:device:MediaServer:1
+    #[arg(long = "st", value_name = "TARGET", default_value = mccat::ssdp::ALL)]
+    pub target: String,
+
+    /// Longest delay, in seconds, devices may wait before answering
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=5), default_value = "2")]
+    pub mx: u8,
+
+    /// Don't search; only show NOTIFY announcements and the searches of others
+    #[arg(long)]
+    pub passive: bool,
+
+    /// Print each message as a JSON object per line
+    #[arg(long)]
+    pub json: bool,
+
+    #[command(flatten)]
+    pub interface: InterfaceArgs,
+
+    /// Stop after this many seconds (default: MX + 1, or until interrupted with --passive)
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub duration: Option<Duration>,
+}
+
+#[derive(Args)]
+pub struct HttpRelayArgs {
+    /// Address to accept HTTP clients on
+    #[arg(long, value_name = "ADDRESS", default_value = "0.0.0.0")]
+    pub bind: net::IpAddr,
+
+    /// TCP port to accept HTTP clients on
+    #[arg(short, long, default_value_t = mccat::relay::DEFAULT_HTTP_PORT)]
+    pub port: u16,
+
+    /// Turn away clients beyond this many
+    #[arg(long, value_name = "COUNT")]
+    pub max_clients: Option<usize>,
+
+    #[command(flatten)]
+    pub interface: InterfaceArgs,
+
+    #[command(flatten)]
+    pub daemon: DaemonArgs,
+}
+
+#[derive(Args)]
+pub struct BridgeArgs {
+    pub address: Host,
+    #[arg(value_parser = parse_port)]
+    pub port: u16,
+
+    /// Unicast endpoint to exchange datagrams with (default: whoever last sent to --bind)
+    #[arg(long, value_name = "ADDRESS:PORT")]
+    pub peer: Option<net::SocketAddr>,
+
+    /// Local address for the unicast side (default: an ephemeral port)
+    #[arg(long, value_name = "ADDRESS:PORT")]
+    pub bind: Option<net::SocketAddr>,
+
+    /// Which way to forward: both, to-unicast or to-multicast
+    #[arg(long, default_value = "both")]
+    pub direction: Direction,
+
+    #[command(flatten)]
+    pub impair: ImpairArgs,
+
+    #[command(flatten)]
+    pub sender: SenderArgs,
+
+    #[command(flatten)]
+    pub daemon: DaemonArgs,
+}
+
+#[derive(Args)]
+pub struct ForwardArgs {
+    pub address: Host,
+    #[arg(value_parser = parse_port)]
+    pub port: u16,
+
+    /// Interface to join the group on, given as a name, index or address
+    #[arg(long, value_name = "INTERFACE")]
+    pub from_interface: Option<String>,
+
+    /// Only forward traffic from this sender (source-specific multicast)
+    #[arg(short, long)]
+    pub source: Option<net::IpAddr>,
+
+    /// Group to resend to (default: the same group); -I picks its interface
+    #[arg(long, value_name = "GROUP:PORT")]
+    pub to: Option<net::SocketAddr>,
+
+    /// Program to pass each datagram to, as a line of SRC GROUP HEX-PAYLOAD on its stdin, which answers
+    /// each with a line saying pass, drop, rewrite HEX-PAYLOAD or note TEXT
+    #[arg(long, value_name = "PATH")]
+    pub script: Option<PathBuf>,
+
+    /// Restore datagrams compressed by send or forward --compress before forwarding them
+    #[arg(long)]
+    pub decompress: bool,
+
+    /// Compress datagrams that get smaller for it before forwarding them, e.g. over a slow link; lz4 is the
+    /// only codec, there is no zstd
+    #[arg(long, value_name = "lz4")]
+    pub compress: Option<Codec>,
+
+    #[command(flatten)]
+    pub impair: ImpairArgs,
+
+    #[command(flatten)]
+    pub sender: SenderArgs,
+}
+
+#[derive(Args)]
+pub struct PushFileArgs {
+    pub address: Host,
+    #[arg(value_parser = parse_port)]
+    pub port: u16,
+
+    /// File to send
+    pub file: PathBuf,
+
+    #[command(flatten)]
+    pub sender: SenderArgs,
+
+    /// Send rate, e.g. 500pps or 10mbit
+    #[arg(short, long, default_value = "10mbit")]
+    pub rate: Rate,
+
+    /// Stop after sending the whole file this many times (default: until interrupted)
+    #[arg(long)]
+    pub rounds: Option<u64>,
+
+    /// File bytes per datagram
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value_t = mccat::carousel::DEFAULT_SYMBOL_SIZE)]
+    pub symbol_size: usize,
+
+    /// Datagrams of the file per FEC block
+    #[arg(long, value_name = "N", default_value = "32")]
+    pub block: usize,
+
+    /// Parity datagrams added to each block, any as many of which can stand in for lost ones
+    #[arg(long, value_name = "N", default_value = "8")]
+    pub parity: usize,
+
+    /// Resend the blocks that pull --nack receivers ask for, and after the last round keep doing so until
+    /// they have been quiet for --linger
+    #[arg(long)]
+    pub nack: bool,
+
+    /// Seconds without requests after which a --nack sender stops
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "3", requires = "nack")]
+    pub linger: Duration,
+}
+
+#[derive(Args)]
+pub struct PullFileArgs {
+    pub address: Host,
+    #[arg(value_parser = parse_port)]
+    pub port: u16,
+
+    /// Where to write the file, - for stdout (default: the name it was sent under, in the current directory)
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Only receive from this sender (source-specific multicast)
+    #[arg(short, long)]
+    pub source: Option<net::IpAddr>,
+
+    /// Ask the sender, unicast, for blocks that couldn't be decoded rather than waiting for the next round
+    #[arg(long)]
+    pub nack: bool,
+
+    /// Give up after this many seconds
+    #[arg(short = 'w', long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub deadline: Option<Duration>,
+
+    #[command(flatten)]
+    pub sender: SenderArgs,
+}
+
+#[derive(Args)]
+pub struct ChatArgs {
+    pub address: Host,
+    #[arg(value_parser = parse_port)]
+    pub port: u16,
+
+    /// Name to chat under (default: $USER)
+    #[arg(short, long, value_parser = parse_nick)]
+    pub nick: Option<String>,
+
+    #[command(flatten)]
+    pub sender: SenderArgs,
+}
+
+#[derive(Args)]
+pub struct ScanArgs {
+    /// Groups to scan, e.g. 239.1.1.0/24
+    #[arg(value_name = "PREFIX")]
+    pub prefix: Cidr,
+
+    /// Ports to scan each group on, a list or range, as in 5000,5004 or 5000-5010
+    #[arg(value_name = "PORTS")]
+    pub ports: String,
+
+    #[command(flatten)]
+    pub interface: InterfaceArgs,
+
+    /// Seconds to stay joined to each group
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "1")]
+    pub dwell: Duration,
+
+    /// Groups joined at a time
+    #[arg(short, long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..=256), default_value = "16")]
+    pub parallel: u16,
+}
+
+#[derive(Args)]
+pub struct JoinArgs {
+    /// Groups to join, IPv6 ones optionally zoned with the interface, as in ff02::1234%eth0
+    #[arg(value_name = "GROUP", required = true)]
+    pub groups: Vec<ScopedAddr>,
+
+    #[command(flatten)]
+    pub interface: InterfaceArgs,
+
+    /// Only join traffic from this sender (source-specific multicast)
+    #[arg(short, long)]
+    pub source: Option<net::IpAddr>,
+
+    /// Leave and join again every this many seconds, making the host report the memberships afresh
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub rejoin: Option<Duration>,
+
+    #[command(flatten)]
+    pub daemon: DaemonArgs,
+}
+
+#[derive(Args)]
+pub struct StressJoinsArgs {
+    /// Groups to join, in order and round again, as a prefix such as 239.10.0.0/16
+    #[arg(short, long, value_name = "PREFIX")]
+    pub groups: Cidr,
+
+    /// Joins per second or minute, e.g. 100/s or 30/m
+    #[arg(short, long, value_parser = parse_per_second, default_value = "100/s")]
+    pub rate: f64,
+
+    /// Seconds each membership is held before leaving
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "10")]
+    pub hold: Duration,
+
+    /// Stop after this many seconds (default: until interrupted)
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub duration: Option<Duration>,
+
+    #[command(flatten)]
+    pub interface: InterfaceArgs,
+}
+
+#[derive(Args)]
+pub struct StressGroupsArgs {
+    /// Groups to join, in order, as a prefix such as 239.10.0.0/16
+    #[arg(short, long, value_name = "PREFIX")]
+    pub groups: Cidr,
+
+    /// Port to receive the groups' traffic on
+    #[arg(short, long, value_parser = parse_port)]
+    pub port: u16,
+
+    /// Join at most this many groups (default: the whole prefix, or until the host refuses)
+    #[arg(short, long)]
+    pub max: Option<u64>,
+
+    /// Hold the memberships this many seconds, counting the datagrams received on each group
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub hold: Option<Duration>,
+
+    #[command(flatten)]
+    pub interface: InterfaceArgs,
+}
+
+#[derive(Args)]
+pub struct ZapArgs {
+    /// Groups to join one after the other, as address:port
+    #[arg(value_name = "GROUP:PORT", value_parser = parse_group, required = true)]
+    pub groups: Vec<Group>,
+
+    #[command(flatten)]
+    pub interface: InterfaceArgs,
+
+    /// Only join traffic from this sender (source-specific multicast)
+    #[arg(short, long)]
+    pub source: Option<net::IpAddr>,
+
+    /// Also wait for the first MPEG-TS random access point, where a decoder can start showing the channel
+    #[arg(long)]
+    pub mpegts: bool,
+
+    /// Zap through the groups this many times
+    #[arg(short, long, default_value = "1")]
+    pub count: u32,
+
+    /// Seconds to wait for traffic before giving up on a group
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "5")]
+    pub timeout: Duration,
+
+    /// Seconds between leaving one group and joining the next, for the network to stop sending it
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "1")]
+    pub interval: Duration,
+}
+
+#[derive(Args)]
+pub struct MergeArgs {
+    /// The main leg, as address:port
+    #[arg(value_name = "MAIN", value_parser = parse_group)]
+    pub main: Group,
+
+    /// The backup leg, as address:port; may be the main leg's group on another interface
+    #[arg(value_name = "BACKUP", value_parser = parse_group)]
+    pub backup: Group,
+
+    /// Interface to join the main leg on, given as a name, index or address
+    #[arg(long, value_name = "INTERFACE")]
+    pub main_interface: Option<String>,
+
+    /// Interface to join the backup leg on
+    #[arg(long, value_name = "INTERFACE")]
+    pub backup_interface: Option<String>,
+
+    /// Only take the main leg from this sender (source-specific multicast)
+    #[arg(long, value_name = "SOURCE")]
+    pub main_source: Option<net::IpAddr>,
+
+    /// Only take the backup leg from this sender
+    #[arg(long, value_name = "SOURCE")]
+    pub backup_source: Option<net::IpAddr>,
+
+    /// Sequence number that lines the legs up: rtp, generate, ping, or a big-endian payload field as
+    /// OFFSET:WIDTH
+    #[arg(long, default_value = "rtp")]
+    pub sequence: Sequence,
+
+    /// Largest delay expected between the legs; a packet missing from one leg waits this long for the other
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "50ms")]
+    pub max_skew: Duration,
+
+    /// Where to write the merged stream, - for stdout
+    #[arg(short, long, value_name = "PATH", default_value = "-")]
+    pub output: PathBuf,
+
+    /// Write the RTP payloads only, e.g. an MPEG-TS a player can take, rather than whole datagrams
+    #[arg(long)]
+    pub payload: bool,
+
+    /// Stop after this many seconds
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub duration: Option<Duration>,
+
+    #[command(flatten)]
+    pub buffers: BufferArgs,
+}
+
+#[derive(Args)]
+pub struct IgmpArgs {
+    /// Interface to watch, given as a name, index or address (default: all of them)
+    #[arg(short = 'I', long)]
+    pub interface: Option<String>,
+
+    /// Stop after this many seconds
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub duration: Option<Duration>,
+}
+
+#[derive(Args)]
+pub struct RoutingArgs {
+    /// Interface to watch, given as a name, index or address (default: all of them)
+    #[arg(short = 'I', long)]
+    pub interface: Option<String>,
+
+    /// Stop after this many seconds
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub duration: Option<Duration>,
+}
+
+#[derive(Args)]
+pub struct PtpArgs {
+    #[command(flatten)]
+    pub interface: InterfaceArgs,
+
+    /// Group the PTP messages go to, e.g. ff0e::181 for PTP over IPv6
+    #[arg(short, long, default_value = "224.0.1.129")]
+    pub group: net::IpAddr,
+
+    /// Only watch this PTP domain
+    #[arg(long)]
+    pub domain: Option<u8>,
+
+    /// Print every message as well as the ports and grandmasters
+    #[arg(short, long)]
+    pub messages: bool,
+
+    /// Seconds between reports of each port's message rates
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "10")]
+    pub report_interval: Duration,
+
+    /// Stop after this many seconds
+    #[arg(short, long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub duration: Option<Duration>,
+}
+
+#[derive(Args)]
+pub struct QuerierArgs {
+    /// Interface to query on, given as a name, index or address
+    #[arg(short = 'I', long)]
+    pub interface: String,
+
+    /// Only query for IPv4 groups, with IGMP
+    #[arg(short = '4', long, conflicts_with = "ipv6")]
+    pub ipv4: bool,
+
+    /// Only query for IPv6 groups, with MLD
+    #[arg(short = '6', long)]
+    pub ipv6: bool,
+
+    /// Seconds between general queries
+    #[arg(short = 'i', long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "125")]
+    pub interval: Duration,
+
+    /// Seconds hosts have to answer a general query
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "10")]
+    pub max_response: Duration,
+}
+
+#[derive(Args)]
+pub struct CtlArgs {
+    /// Control socket of the running instance
+    #[arg(short, long, value_name = "PATH")]
+    pub socket: PathBuf,
+
+    /// join GROUP:PORT, leave GROUP:PORT, groups, stats, output PATH (- for stdout) or shutdown
+    #[arg(required = true, num_args = 1..)]
+    pub command: Vec<String>,
+}
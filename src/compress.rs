@@ -0,0 +1,305 @@
+use std::convert::TryFrom;
+use std::str;
+
+/// Marks a compressed datagram, followed by the codec and the original
+/// length as a big-endian u16.
+const MAGIC: &[u8] = b"MCZ";
+pub const HEADER_LEN: usize = 6;
+
+/// The shortest match LZ4 encodes.
+const MIN_MATCH: usize = 4;
+/// No match may start within this many bytes of the end of the input.
+const MATCH_LIMIT: usize = 12;
+/// The last bytes of the input are always literals.
+const LAST_LITERALS: usize = 5;
+const MAX_OFFSET: usize = 65535;
+const HASH_BITS: u32 = 12;
+
+/// How datagrams are compressed. Only LZ4 is implemented: zstd would need
+/// either a dependency or a decoder many times the size of this module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// The LZ4 block format, fast enough to keep up with any link.
+    Lz4,
+}
+
+impl Codec {
+    fn id(self) -> u8 {
+        match self {
+            Codec::Lz4 => 1,
+        }
+    }
+}
+
+impl str::FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Codec, String> {
+        match s {
+            "lz4" => Ok(Codec::Lz4),
+            "zstd" => Err("zstd is not supported: expected lz4".to_owned()),
+            _ => Err(format!("unknown codec {}: expected lz4", s)),
+        }
+    }
+}
+
+/// `data` compressed with `codec` behind the header, or as it is if that
+/// wouldn't make it smaller. Fails if `data` is too long for the header,
+/// as it couldn't be told apart from a frame if sent as it is.
+pub fn compress(codec: Codec, data: &[u8]) -> Result<Vec<u8>, String> {
+    let len = u16::try_from(data.len())
+        .map_err(|_| format!("can't compress {} bytes: at most {} fit the header", data.len(), u16::MAX))?;
+    let mut frame = Vec::with_capacity(data.len());
+    frame.extend_from_slice(MAGIC);
+    frame.push(codec.id());
+    frame.extend_from_slice(&len.to_be_bytes());
+    match codec {
+        Codec::Lz4 => lz4_compress(data, &mut frame),
+    }
+    // raw data that happens to look like a frame is framed regardless
+    if frame.len() >= data.len() && !data.starts_with(MAGIC) {
+        return Ok(data.to_vec());
+    }
+    Ok(frame)
+}
+
+/// The original of a datagram from `compress`, or `None` if it wasn't
+/// compressed.
+pub fn decompress(datagram: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    if datagram.len() < HEADER_LEN || !datagram.starts_with(MAGIC) {
+        return Ok(None);
+    }
+    let len = u16::from_be_bytes([datagram[4], datagram[5]]) as usize;
+    let data = match datagram[3] {
+        1 => lz4_decompress(&datagram[HEADER_LEN..], len)?,
+        id => return Err(format!("unknown codec {}", id)),
+    };
+    Ok(Some(data))
+}
+
+fn hash(data: &[u8], at: usize) -> usize {
+    let word = u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+    (word.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Writes a length nibble's overflow as runs of 255 and a final byte.
+fn push_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn push_sequence(out: &mut Vec<u8>, literals: &[u8], offset: usize, match_len: usize) {
+    let literal_nibble = literals.len().min(15);
+    let match_nibble = if offset > 0 { (match_len - MIN_MATCH).min(15) } else { 0 };
+    out.push((literal_nibble << 4 | match_nibble) as u8);
+    if literal_nibble == 15 {
+        push_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if offset > 0 {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_nibble == 15 {
+            push_length(out, match_len - MIN_MATCH - 15);
+        }
+    }
+}
+
+/// Greedy LZ4 block compression, finding matches through a hash of the
+/// next four bytes.
+fn lz4_compress(data: &[u8], out: &mut Vec<u8>) {
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut literal_start = 0;
+    let mut pos = 0;
+    while data.len() >= MATCH_LIMIT && pos + MATCH_LIMIT <= data.len() {
+        let slot = hash(data, pos);
+        let candidate = table[slot];
+        table[slot] = pos;
+        let found = candidate != usize::MAX && pos - candidate <= MAX_OFFSET &&
+                    data[candidate..candidate + MIN_MATCH] == data[pos..pos + MIN_MATCH];
+        if !found {
+            pos += 1;
+            continue;
+        }
+        let limit = data.len() - LAST_LITERALS;
+        let mut len = MIN_MATCH;
+        while pos + len < limit && data[candidate + len] == data[pos + len] {
+            len += 1;
+        }
+        push_sequence(out, &data[literal_start..pos], pos - candidate, len);
+        pos += len;
+        literal_start = pos;
+    }
+    push_sequence(out, &data[literal_start..], 0, 0);
+}
+
+fn read_length(block: &[u8], pos: &mut usize, nibble: usize) -> Result<usize, String> {
+    let mut len = nibble;
+    if nibble == 15 {
+        loop {
+            let byte = *block.get(*pos).ok_or("truncated length")?;
+            *pos += 1;
+            len += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
+
+fn lz4_decompress(block: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(len);
+    let mut pos = 0;
+    loop {
+        let token = *block.get(pos).ok_or("truncated sequence")? as usize;
+        pos += 1;
+        let literals = read_length(block, &mut pos, token >> 4)?;
+        let end = pos.checked_add(literals).filter(|&end| end <= block.len()).ok_or("truncated literals")?;
+        out.extend_from_slice(&block[pos..end]);
+        pos = end;
+        if pos == block.len() {
+            break;
+        }
+        let offset = match block.get(pos..pos + 2) {
+            Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]) as usize,
+            None => return Err("truncated offset".to_owned()),
+        };
+        pos += 2;
+        if offset == 0 || offset > out.len() {
+            return Err("match offset out of range".to_owned());
+        }
+        let match_len = read_length(block, &mut pos, token & 15)? + MIN_MATCH;
+        if out.len() + match_len > len {
+            return Err("longer than its header says".to_owned());
+        }
+        // byte by byte, as a match may overlap what it copies
+        let start = out.len() - offset;
+        for i in 0..match_len {
+            let byte = out[start + i];
+            out.push(byte);
+        }
+    }
+    if out.len() != len {
+        return Err(format!("{} bytes where the header says {}", out.len(), len));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lz4_round_trip(data: &[u8]) -> Vec<u8> {
+        let mut block = Vec::new();
+        lz4_compress(data, &mut block);
+        assert_eq!(lz4_decompress(&block, data.len()).unwrap(), data);
+        block
+    }
+
+    /// Bytes from a xorshift generator, which LZ4 finds nothing in.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545f491u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(lz4_round_trip(b""), [0]);
+        assert_eq!(compress(Codec::Lz4, b"").unwrap(), b"");
+        assert_eq!(decompress(b"").unwrap(), None);
+    }
+
+    #[test]
+    fn incompressible() {
+        let data = noise(1400);
+        lz4_round_trip(&data);
+        assert_eq!(compress(Codec::Lz4, &data).unwrap(), data);
+        assert_eq!(decompress(&data).unwrap(), None);
+    }
+
+    #[test]
+    fn long_runs() {
+        for &len in &[12, 13, 19, 20, 270, 271, 1400, 65535] {
+            let data = vec![b'a'; len];
+            lz4_round_trip(&data);
+            let frame = compress(Codec::Lz4, &data).unwrap();
+            assert!(frame.len() < data.len() || len < 20, "{} bytes to {}", len, frame.len());
+            assert_eq!(decompress(&frame).unwrap().unwrap_or(frame), data);
+        }
+    }
+
+    #[test]
+    fn overlapping_matches() {
+        let data: Vec<u8> = b"abc".iter().cycle().take(1000).cloned().chain(noise(300)).collect();
+        let block = lz4_round_trip(&data);
+        assert!(block.len() < 400);
+        let mut data = b"0123456789".to_vec();
+        data.extend(noise(20));
+        data.extend_from_within(..10);
+        data.extend(b"0123456789".repeat(30));
+        lz4_round_trip(&data);
+    }
+
+    #[test]
+    fn raw_data_like_a_frame() {
+        let data = b"MCZ\x01\x00\x10 raw, not compressed".to_vec();
+        let frame = compress(Codec::Lz4, &data).unwrap();
+        assert_ne!(frame, data);
+        assert_eq!(decompress(&frame).unwrap(), Some(data));
+    }
+
+    #[test]
+    fn too_long() {
+        assert!(compress(Codec::Lz4, &vec![0; 65536]).is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        let cases: &[(&[u8], usize)] = &[
+            (b"", 0),
+            // literals beyond the end
+            (b"\x50abc", 5),
+            (b"\xf0", 20),
+            (b"\xf0\xff", 300),
+            // offset missing or cut short
+            (b"\x14abcd", 8),
+            (b"\x14abcd\x01", 8),
+            // offset zero, past the start, or before any output
+            (b"\x14abcd\x00\x00", 8),
+            (b"\x14abcd\x05\x00", 8),
+            (b"\x04\x01\x00", 4),
+            // a match longer than the header says
+            (b"\x1fa\x01\x00\xff\xff\xff\xff\x10", 10),
+            // match length cut short
+            (b"\x1fa\x01\x00\xff", 300),
+            // shorter than the header says
+            (b"\x30abc", 4),
+        ];
+        for &(block, len) in cases {
+            assert!(lz4_decompress(block, len).is_err(), "{:?}", block);
+        }
+        assert!(decompress(b"MCZ\x02\x00\x00\x00").is_err());
+        assert!(decompress(b"MCZ\x01\x00\x04\x14abcd\xff\xff").is_err());
+    }
+
+    #[test]
+    fn arbitrary_input_never_panics() {
+        let noise = noise(4096);
+        for start in 0..256 {
+            for len in 0..32 {
+                let _ = lz4_decompress(&noise[start..start + len], 64);
+                let _ = lz4_decompress(&noise[start..start + len], 65535);
+            }
+        }
+    }
+}
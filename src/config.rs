@@ -0,0 +1,599 @@
+use std::{fs, net};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+
+use filter::{Cidr, PayloadPattern};
+use iface::ScopedAddr;
+
+/// A value in a config file. Config files are written in the part of TOML
+/// they need: strings, integers, booleans, arrays of them, and arrays of
+/// tables such as `[[group]]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match *self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean(_) => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+/// Keys with their values and the lines they were set on.
+type Table = BTreeMap<String, (Value, usize)>;
+
+/// The keys under a `[[name]]` header.
+struct Section {
+    name: String,
+    /// The line of the header.
+    line: usize,
+    table: Table,
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn line(&self) -> usize {
+        self.text[..self.pos].matches('\n').count() + 1
+    }
+
+    fn error<S: AsRef<str>>(&self, msg: S) -> String {
+        format!("line {}: {}", self.line(), msg.as_ref())
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.rest().starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), String> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected {}", s)))
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(' ') | Some('\t') = self.peek() {
+            self.bump();
+        }
+    }
+
+    /// Skips whitespace, line breaks and comments, as between entries or
+    /// inside arrays.
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') | Some('\r') | Some('\n') => {
+                    self.bump();
+                }
+                Some('#') => {
+                    let end = self.rest().find('\n').unwrap_or(self.rest().len());
+                    self.pos += end;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_space();
+        if self.peek() == Some('#') {
+            let end = self.rest().find('\n').unwrap_or(self.rest().len());
+            self.pos += end;
+        }
+        self.eat("\r");
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.bump();
+                Ok(())
+            }
+            Some(_) => Err(self.error("expected the end of the line")),
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(quote @ '"') | Some(quote @ '\'') => self.string(quote),
+            _ => {
+                let len = self.rest()
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                    .unwrap_or(self.rest().len());
+                if len == 0 {
+                    return Err(self.error("expected a key"));
+                }
+                let key = self.rest()[..len].to_owned();
+                self.pos += len;
+                Ok(key)
+            }
+        }
+    }
+
+    /// A basic ("...") string with escapes, or a literal ('...') one.
+    fn string(&mut self, quote: char) -> Result<String, String> {
+        self.bump();
+        let mut s = String::new();
+        loop {
+            let c = match self.peek() {
+                None | Some('\n') => return Err(self.error("unterminated string")),
+                Some(c) => c,
+            };
+            self.bump();
+            match c {
+                c if c == quote => return Ok(s),
+                '\\' if quote == '"' => {
+                    let c = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') => {
+                            let rest = self.rest();
+                            let len = rest.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(rest.len()).min(4);
+                            let hex = &rest[..len];
+                            let c = u32::from_str_radix(hex, 16).ok().and_then(char::from_u32).filter(|_| len == 4);
+                            let c = c.ok_or_else(|| self.error(format!("invalid escape \\u{}", hex)))?;
+                            self.pos += len;
+                            c
+                        }
+                        _ => return Err(self.error("invalid escape in string")),
+                    };
+                    s.push(c);
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some(quote @ '"') | Some(quote @ '\'') => self.string(quote).map(Value::String),
+            Some('[') => {
+                self.bump();
+                let mut values = Vec::new();
+                loop {
+                    self.skip_blank();
+                    if self.eat("]") {
+                        return Ok(Value::Array(values));
+                    }
+                    values.push(self.value()?);
+                    self.skip_blank();
+                    if !self.eat(",") {
+                        self.skip_blank();
+                        self.expect("]")?;
+                        return Ok(Value::Array(values));
+                    }
+                }
+            }
+            _ if self.eat("true") => Ok(Value::Boolean(true)),
+            _ if self.eat("false") => Ok(Value::Boolean(false)),
+            _ => {
+                let len = self.rest()
+                    .find(|c: char| !(c.is_ascii_digit() || c == '_' || c == '+' || c == '-'))
+                    .unwrap_or(self.rest().len());
+                let number = &self.rest()[..len];
+                match number.replace('_', "").parse() {
+                    Ok(number) if len > 0 => {
+                        self.pos += len;
+                        Ok(Value::Integer(number))
+                    }
+                    _ => Err(self.error("expected a string, integer, boolean or array")),
+                }
+            }
+        }
+    }
+
+    /// The top-level table and the sections that follow it, in order.
+    fn document(&mut self) -> Result<(Table, Vec<Section>), String> {
+        let mut root = Table::new();
+        let mut sections: Vec<Section> = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.peek().is_none() {
+                return Ok((root, sections));
+            }
+            if self.eat("[[") {
+                let line = self.line();
+                self.skip_space();
+                let name = self.key()?;
+                self.skip_space();
+                self.expect("]]")?;
+                self.end_of_line()?;
+                sections.push(Section {
+                    name,
+                    line,
+                    table: Table::new(),
+                });
+                continue;
+            }
+            if self.peek() == Some('[') {
+                return Err(self.error("only arrays of tables, e.g. [[group]], are supported"));
+            }
+            let line = self.line();
+            let key = self.key()?;
+            self.skip_space();
+            self.expect("=")?;
+            self.skip_space();
+            let value = self.value()?;
+            self.end_of_line()?;
+            let table = match sections.last_mut() {
+                Some(section) => &mut section.table,
+                None => &mut root,
+            };
+            if table.insert(key.clone(), (value, line)).is_some() {
+                return Err(format!("line {}: {} is set twice", line, key));
+            }
+        }
+    }
+}
+
+/// Takes typed values out of a table, so that whatever is left over can be
+/// reported as unknown.
+struct Fields {
+    table: Table,
+}
+
+impl Fields {
+    fn take(&mut self, key: &str) -> Option<(Value, usize)> {
+        self.table.remove(key)
+    }
+
+    fn string(&mut self, key: &str) -> Result<Option<(String, usize)>, String> {
+        match self.take(key) {
+            Some((Value::String(s), line)) => Ok(Some((s, line))),
+            Some((value, line)) => Err(format!("line {}: {} must be a string, not {}", line, key, value.kind())),
+            None => Ok(None),
+        }
+    }
+
+    fn integer(&mut self, key: &str) -> Result<Option<(i64, usize)>, String> {
+        match self.take(key) {
+            Some((Value::Integer(n), line)) => Ok(Some((n, line))),
+            Some((value, line)) => Err(format!("line {}: {} must be an integer, not {}", line, key, value.kind())),
+            None => Ok(None),
+        }
+    }
+
+    fn boolean(&mut self, key: &str) -> Result<bool, String> {
+        match self.take(key) {
+            Some((Value::Boolean(b), _)) => Ok(b),
+            Some((value, line)) => Err(format!("line {}: {} must be true or false, not {}", line, key, value.kind())),
+            None => Ok(false),
+        }
+    }
+
+    /// An array of strings, each parsed as a `T`.
+    fn list<T, F>(&mut self, key: &str, parse: F) -> Result<Vec<T>, String>
+        where F: Fn(&str) -> Result<T, String>
+    {
+        let (values, line) = match self.take(key) {
+            Some((Value::Array(values), line)) => (values, line),
+            Some((value, line)) => {
+                return Err(format!("line {}: {} must be an array of strings, not {}", line, key, value.kind()))
+            }
+            None => return Ok(Vec::new()),
+        };
+        values.iter()
+            .map(|value| match *value {
+                Value::String(ref s) => parse(s).map_err(|err| format!("line {}: {}", line, err)),
+                ref value => {
+                    Err(format!("line {}: {} must be an array of strings, not of {}", line, key, value.kind()))
+                }
+            })
+            .collect()
+    }
+
+    fn finish(self, section: &str) -> Result<(), String> {
+        match self.table.into_iter().next() {
+            Some((key, (_, line))) => Err(format!("line {}: unknown key {} in {}", line, key, section)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A group to join, with the interface and sender to join it on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    pub addr: net::SocketAddr,
+    pub interface: Option<String>,
+    /// Join source-specific, taking traffic from this sender only.
+    pub source: Option<net::IpAddr>,
+}
+
+/// What `listen --config` reads: the groups to join and what to do with
+/// their traffic, e.g.
+///
+/// ```toml
+/// interface = "eth1"
+/// output = "/var/log/mccat/channels.log"
+/// block_source = ["10.9.0.0/16"]
+///
+/// [[group]]
+/// address = "239.1.1.1"
+/// port = 5000
+///
+/// [[group]]
+/// address = "232.1.1.2"
+/// port = 5000
+/// source = "10.1.1.1"
+/// interface = "eth2"
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub groups: Vec<Group>,
+    /// Where to write the datagrams, or stdout if `None`.
+    pub output: Option<PathBuf>,
+    pub allow_source: Vec<Cidr>,
+    pub block_source: Vec<Cidr>,
+    /// As `--match`.
+    pub patterns: Vec<PayloadPattern>,
+    pub invert_match: bool,
+}
+
+impl Config {
+    /// Reads the config file at `path`. A relative output path is taken
+    /// relative to the file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| format!("can't read {}: {}", path.display(), err))?;
+        let mut config = Config::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+        if let (Some(output), Some(dir)) = (config.output.as_mut(), path.parent()) {
+            *output = dir.join(&*output);
+        }
+        Ok(config)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let (root, sections) = Parser { text, pos: 0 }.document()?;
+        let mut root = Fields { table: root };
+        let interface = root.string("interface")?.map(|(interface, _)| interface);
+        let mut config = Config {
+            groups: Vec::new(),
+            output: root.string("output")?.filter(|(path, _)| path != "-").map(|(path, _)| PathBuf::from(path)),
+            allow_source: root.list("allow_source", |s| s.parse())?,
+            block_source: root.list("block_source", |s| s.parse())?,
+            patterns: root.list("match", |s| s.parse())?,
+            invert_match: root.boolean("invert_match")?,
+        };
+        root.finish("the top level")?;
+        for section in sections {
+            if section.name != "group" {
+                return Err(format!("line {}: unknown section [[{}]]: expected [[group]]", section.line, section.name));
+            }
+            let group = group(Fields { table: section.table }, "group", section.line, interface.as_ref())?;
+            if config.groups.iter().any(|other| other.addr == group.addr) {
+                return Err(format!("group {} is listed twice", group.addr));
+            }
+            config.groups.push(group);
+        }
+        Ok(config)
+    }
+}
+
+/// Parses a profiles file, whose `[[profile]]` sections each name a group
+/// to use in place of its address and port, e.g.
+///
+/// ```toml
+/// [[profile]]
+/// name = "my-iptv-ch5"
+/// address = "239.10.0.5"
+/// port = 1234
+/// interface = "eth1"
+/// ```
+pub fn profiles(text: &str) -> Result<Vec<(String, Group)>, String> {
+    let (root, sections) = Parser { text, pos: 0 }.document()?;
+    Fields { table: root }.finish("the top level")?;
+    let mut profiles: Vec<(String, Group)> = Vec::new();
+    for section in sections {
+        if section.name != "profile" {
+            return Err(format!("line {}: unknown section [[{}]]: expected [[profile]]", section.line, section.name));
+        }
+        let mut fields = Fields { table: section.table };
+        let name = match fields.string("name")? {
+            Some((name, _)) => name,
+            None => return Err(format!("line {}: [[profile]] without a name", section.line)),
+        };
+        if profiles.iter().any(|(other, _)| *other == name) {
+            return Err(format!("profile {} is defined twice", name));
+        }
+        profiles.push((name, group(fields, "profile", section.line, None)?));
+    }
+    Ok(profiles)
+}
+
+/// A group under a `[[section]]` header on `line`, by default on
+/// `interface`.
+fn group(mut fields: Fields, section: &str, line: usize, interface: Option<&String>) -> Result<Group, String> {
+    let (address, zone) = match fields.string("address")? {
+        Some((address, line)) => {
+            match address.parse::<ScopedAddr>() {
+                Ok(ScopedAddr { addr, zone }) if addr.is_multicast() => (addr, zone.map(|zone| (zone, line))),
+                _ => return Err(format!("line {}: invalid group address {}", line, address)),
+            }
+        }
+        None => return Err(format!("line {}: [[{}]] without an address", line, section)),
+    };
+    let port = match fields.integer("port")? {
+        Some((port, line)) => {
+            match u16::try_from(port) {
+                Ok(port) if port > 0 => port,
+                _ => return Err(format!("line {}: invalid port {}", line, port)),
+            }
+        }
+        None => return Err(format!("line {}: [[{}]] {} without a port", line, section, address)),
+    };
+    let source = match fields.string("source")? {
+        Some((source, line)) => {
+            Some(source.parse().map_err(|_| format!("line {}: invalid source address {}", line, source))?)
+        }
+        None => None,
+    };
+    let interface = match (zone, fields.string("interface")?) {
+        (Some((_, line)), Some(_)) => {
+            return Err(format!("line {}: give the interface either as a zone of the address or as interface", line))
+        }
+        (Some((zone, _)), None) | (None, Some((zone, _))) => Some(zone),
+        (None, None) => interface.cloned(),
+    };
+    let group = Group {
+        addr: (address, port).into(),
+        interface,
+        source,
+    };
+    fields.finish(&format!("[[{}]]", section))?;
+    Ok(group)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    const EXAMPLE: &str = r#"interface = "eth1"
+output = "/var/log/mccat/channels.log"
+block_source = ["10.9.0.0/16"]
+
+[[group]]
+address = "239.1.1.1"
+port = 5000
+
+[[group]]
+address = "232.1.1.2"
+port = 5000
+source = "10.1.1.1"
+interface = "eth2"
+"#;
+
+    fn in_group(text: &str) -> Result<Config, String> {
+        Config::parse(&format!("[[group]]\n{}\n", text))
+    }
+
+    #[test]
+    fn documented_example() {
+        let config = Config::parse(EXAMPLE).unwrap();
+        assert_eq!(config.output, Some(PathBuf::from("/var/log/mccat/channels.log")));
+        assert_eq!(config.block_source, vec!["10.9.0.0/16".parse().unwrap()]);
+        assert!(config.allow_source.is_empty());
+        assert!(!config.invert_match);
+        assert_eq!(config.groups,
+                   vec![Group {
+                            addr: "239.1.1.1:5000".parse().unwrap(),
+                            interface: Some("eth1".to_owned()),
+                            source: None,
+                        },
+                        Group {
+                            addr: "232.1.1.2:5000".parse().unwrap(),
+                            interface: Some("eth2".to_owned()),
+                            source: Some("10.1.1.1".parse().unwrap()),
+                        }]);
+    }
+
+    #[test]
+    fn profiles_example() {
+        let text = "# channels\n[[profile]]\nname = \"ch5\"  # five\naddress = '239.10.0.5'\nport = 1_234\n";
+        let parsed = profiles(text).unwrap();
+        assert_eq!(parsed[0].0, "ch5");
+        assert_eq!(parsed[0].1.addr, "239.10.0.5:1234".parse().unwrap());
+        assert_eq!(profiles("[[profile]]\naddress = \"239.10.0.5\"\nport = 1\n").unwrap_err(),
+                   "line 1: [[profile]] without a name");
+        assert_eq!(profiles("[[group]]\n").unwrap_err(), "line 1: unknown section [[group]]: expected [[profile]]");
+    }
+
+    #[test]
+    fn duplicates() {
+        assert_eq!(Config::parse("output = \"a\"\noutput = \"b\"\n").unwrap_err(), "line 2: output is set twice");
+        assert_eq!(in_group("address = \"239.1.1.1\"\nport = 1\n[[group]]\naddress = \"239.1.1.1\"\nport = 1")
+                       .unwrap_err(),
+                   "group 239.1.1.1:1 is listed twice");
+    }
+
+    #[test]
+    fn unknown_keys_and_sections() {
+        assert_eq!(Config::parse("colour = true\n").unwrap_err(), "line 1: unknown key colour in the top level");
+        assert_eq!(in_group("address = \"239.1.1.1\"\nport = 1\nttl = 4").unwrap_err(),
+                   "line 4: unknown key ttl in [[group]]");
+        assert_eq!(Config::parse("[[channel]]\n").unwrap_err(),
+                   "line 1: unknown section [[channel]]: expected [[group]]");
+        assert_eq!(Config::parse("[group]\n").unwrap_err(),
+                   "line 1: only arrays of tables, e.g. [[group]], are supported");
+        assert_eq!(Config::parse("output = 5\n").unwrap_err(), "line 1: output must be a string, not an integer");
+    }
+
+    #[test]
+    fn invalid_groups() {
+        assert_eq!(in_group("address = \"10.1.1.1\"\nport = 5000").unwrap_err(),
+                   "line 2: invalid group address 10.1.1.1");
+        assert_eq!(in_group("address = \"239.1.1.1\"\nport = 0").unwrap_err(), "line 3: invalid port 0");
+        assert_eq!(in_group("address = \"239.1.1.1\"\nport = 65536").unwrap_err(), "line 3: invalid port 65536");
+        assert_eq!(in_group("address = \"239.1.1.1\"").unwrap_err(),
+                   "line 1: [[group]] 239.1.1.1 without a port");
+        assert_eq!(in_group("address = \"ff02::1%eth0\"\nport = 5000\ninterface = \"eth1\"").unwrap_err(),
+                   "line 2: give the interface either as a zone of the address or as interface");
+        let config = in_group("address = \"ff02::1%eth0\"\nport = 5000").unwrap();
+        assert_eq!(config.groups[0].interface, Some("eth0".to_owned()));
+    }
+
+    #[test]
+    fn strings() {
+        let config = Config::parse("output = \"caf\\u00e9\\t\\\"x\\\"\"\n").unwrap();
+        assert_eq!(config.output, Some(PathBuf::from("café\t\"x\"")));
+        // no escapes in literal strings
+        let config = Config::parse("output = 'C:\\logs'\n").unwrap();
+        assert_eq!(config.output, Some(PathBuf::from("C:\\logs")));
+        assert_eq!(Config::parse("output = \"\\ud800\"\n").unwrap_err(), "line 1: invalid escape \\ud800");
+        assert_eq!(Config::parse("output = \"\\u12\"\n").unwrap_err(), "line 1: invalid escape \\u12");
+        assert_eq!(Config::parse("output = \"\\q\"\n").unwrap_err(), "line 1: invalid escape in string");
+        assert_eq!(Config::parse("output = \"open\n").unwrap_err(), "line 1: unterminated string");
+        assert_eq!(Config::parse("output = \"open").unwrap_err(), "line 1: unterminated string");
+        assert_eq!(Config::parse("output = \"a\" \"b\"\n").unwrap_err(), "line 1: expected the end of the line");
+    }
+
+    #[test]
+    fn output_relative_to_the_file() {
+        let dir = env::temp_dir().join(format!("mccat-config-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("listen.toml");
+        fs::write(&path, "output = \"channels.log\"\n").unwrap();
+        assert_eq!(Config::load(&path).unwrap().output, Some(dir.join("channels.log")));
+        fs::write(&path, "output = \"/var/log/channels.log\"\n").unwrap();
+        assert_eq!(Config::load(&path).unwrap().output, Some(PathBuf::from("/var/log/channels.log")));
+        // stdout
+        fs::write(&path, "output = \"-\"\n").unwrap();
+        assert_eq!(Config::load(&path).unwrap().output, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
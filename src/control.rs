@@ -0,0 +1,151 @@
+use std::{fs, io, net, str};
+use std::io::prelude::*;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long a control client gets to send its command.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_COMMAND: u64 = 4096;
+
+/// What a running instance can be told over its control socket, one per
+/// line, e.g. `join 239.1.1.1:5000`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Join(net::SocketAddr),
+    Leave(net::SocketAddr),
+    /// List the groups joined.
+    Groups,
+    Stats,
+    /// Write output to a file from now on, or back to stdout if `None`.
+    Output(Option<PathBuf>),
+    Shutdown,
+}
+
+impl str::FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Command, String> {
+        let mut words = s.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let arg = words.next();
+        if words.next().is_some() {
+            return Err(format!("too many arguments to {}", command));
+        }
+        let group = |arg: Option<&str>| match arg.map(str::parse::<net::SocketAddr>) {
+            Some(Ok(group)) if group.ip().is_multicast() => Ok(group),
+            Some(_) => Err(format!("invalid group {}: expected ADDRESS:PORT", arg.unwrap_or_default())),
+            None => Err(format!("{} needs a group, e.g. {} 239.1.1.1:5000", command, command)),
+        };
+        match (command, arg) {
+            ("join", arg) => group(arg).map(Command::Join),
+            ("leave", arg) => group(arg).map(Command::Leave),
+            ("groups", None) => Ok(Command::Groups),
+            ("stats", None) => Ok(Command::Stats),
+            ("output", Some("-")) => Ok(Command::Output(None)),
+            ("output", Some(path)) => Ok(Command::Output(Some(PathBuf::from(path)))),
+            ("output", None) => Err("output needs a path, or - for stdout".to_owned()),
+            ("shutdown", None) => Ok(Command::Shutdown),
+            ("groups", Some(_)) | ("stats", Some(_)) | ("shutdown", Some(_)) => {
+                Err(format!("{} takes no arguments", command))
+            }
+            _ => {
+                Err(format!("unknown command {}: expected join, leave, groups, stats, output or shutdown",
+                            command))
+            }
+        }
+    }
+}
+
+/// A command from a control client, awaiting the reply.
+#[derive(Debug)]
+pub struct Request {
+    stream: UnixStream,
+    line: String,
+    pub command: Result<Command, String>,
+}
+
+impl Request {
+    /// The command as sent, for logging.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Answers with `body` and a final `ok` line, or an `error` line.
+    pub fn reply(mut self, result: Result<String, String>) -> io::Result<()> {
+        let reply = match result {
+            Ok(body) if body.is_empty() => "ok\n".to_owned(),
+            Ok(body) => format!("{}\nok\n", body.trim_end()),
+            Err(err) => format!("error {}\n", err),
+        };
+        self.stream.write_all(reply.as_bytes())
+    }
+}
+
+/// The control socket of a running instance, removed when dropped.
+#[derive(Debug)]
+pub struct Control {
+    server: UnixListener,
+    path: PathBuf,
+}
+
+impl Control {
+    /// Listens on `path`, replacing a socket left over by an instance that
+    /// is no longer running.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Control> {
+        let path = path.as_ref();
+        let server = match UnixListener::bind(path) {
+            Err(ref err) if err.kind() == io::ErrorKind::AddrInUse && UnixStream::connect(path).is_err() => {
+                fs::remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            result => result?,
+        };
+        server.set_nonblocking(true)?;
+        Ok(Control {
+            server,
+            path: path.to_owned(),
+        })
+    }
+
+    /// The next waiting command, without blocking when there is none.
+    pub fn accept(&self) -> io::Result<Option<Request>> {
+        let stream = match self.server.accept() {
+            Ok((stream, _)) => stream,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut line = String::new();
+        io::BufReader::new((&stream).take(MAX_COMMAND)).read_line(&mut line)?;
+        let line = line.trim().to_owned();
+        Ok(Some(Request {
+            stream,
+            command: line.parse(),
+            line,
+        }))
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Sends `command` to the instance listening on `path` and returns the
+/// body of its reply, or the error it gave.
+pub fn send<P: AsRef<Path>>(path: P, command: &str) -> io::Result<Result<String, String>> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.write_all(format!("{}\n", command).as_bytes())?;
+    stream.shutdown(net::Shutdown::Write)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    let mut lines: Vec<_> = reply.lines().collect();
+    match lines.pop() {
+        Some("ok") => Ok(Ok(lines.join("\n"))),
+        Some(last) if last.starts_with("error ") => Ok(Err(last["error ".len()..].to_owned())),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "incomplete reply")),
+    }
+}
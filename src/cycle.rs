@@ -0,0 +1,221 @@
+use std::{fmt, net};
+use std::collections::{BTreeSet, HashMap};
+use std::collections::hash_map::Entry;
+
+use listen::Datagram;
+
+/// Marks a carousel datagram, followed by the cycle, the datagram's index
+/// in it and the datagrams per cycle, each a big-endian u32.
+const MAGIC: &[u8] = b"MCC";
+pub const HEADER_LEN: usize = 15;
+
+/// Where a datagram sent by `send --carousel` belongs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub cycle: u32,
+    pub index: u32,
+    /// Datagrams per cycle.
+    pub count: u32,
+}
+
+/// The header `position` puts in front of a payload.
+pub fn header(position: Position) -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    header.extend_from_slice(&position.cycle.to_be_bytes());
+    header.extend_from_slice(&position.index.to_be_bytes());
+    header.extend_from_slice(&position.count.to_be_bytes());
+    header
+}
+
+/// The position and the payload behind it, if `data` starts with a cycle
+/// header.
+pub fn split(data: &[u8]) -> Option<(Position, &[u8])> {
+    let rest = data.strip_prefix(MAGIC)?;
+    let field = |i: usize| rest.get(i * 4..i * 4 + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+    let position = Position { cycle: field(0)?, index: field(1)?, count: field(2)? };
+    if position.index >= position.count {
+        return None;
+    }
+    Some((position, &data[HEADER_LEN..]))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CycleEvent {
+    /// The first datagram of a cycle arrived, whether or not it was the
+    /// one at index 0.
+    Started { cycle: u32, count: u32 },
+    /// The datagrams of `cycle` at `indexes` never arrived.
+    Missing { cycle: u32, indexes: Vec<u32> },
+}
+
+impl fmt::Display for CycleEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CycleEvent::Started { cycle, count } => write!(f, "cycle {} started, {} datagrams", cycle, count),
+            CycleEvent::Missing { cycle, ref indexes } => {
+                write!(f, "cycle {} missing {} datagrams: {}", cycle, indexes.len(), runs(indexes))
+            }
+        }
+    }
+}
+
+/// `indexes`, ascending, with runs shortened to "first-last".
+fn runs(indexes: &[u32]) -> String {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for &index in indexes {
+        match runs.last_mut() {
+            Some(&mut (_, ref mut last)) if *last + 1 == index => *last = index,
+            _ => runs.push((index, index)),
+        }
+    }
+    let runs: Vec<_> = runs.iter()
+        .map(|&(first, last)| if first == last { first.to_string() } else { format!("{}-{}", first, last) })
+        .collect();
+    runs.join(", ")
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CycleCounts {
+    pub cycles: u64,
+    pub missing: u64,
+}
+
+impl fmt::Display for CycleCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} cycles, {} datagrams missing", self.cycles, self.missing)
+    }
+}
+
+/// The cycle one sender is in and what of it has arrived.
+#[derive(Debug)]
+struct Stream {
+    cycle: u32,
+    count: u32,
+    /// The index the cycle was joined at; those before it weren't missed.
+    joined: u32,
+    seen: BTreeSet<u32>,
+    counts: CycleCounts,
+}
+
+impl Stream {
+    fn new(position: Position) -> Stream {
+        Stream {
+            cycle: position.cycle,
+            count: position.count,
+            joined: position.index,
+            seen: Some(position.index).into_iter().collect(),
+            counts: CycleCounts { cycles: 1, missing: 0 },
+        }
+    }
+
+    fn track(&mut self, position: Position) -> Vec<CycleEvent> {
+        if position.cycle == self.cycle {
+            self.seen.insert(position.index);
+            return Vec::new();
+        }
+        // a straggler from the cycle before
+        if self.cycle.wrapping_sub(position.cycle) == 1 {
+            return Vec::new();
+        }
+        let mut events = Vec::new();
+        let indexes: Vec<_> = (self.joined..self.count).filter(|index| !self.seen.contains(index)).collect();
+        if !indexes.is_empty() {
+            self.counts.missing += indexes.len() as u64;
+            events.push(CycleEvent::Missing { cycle: self.cycle, indexes });
+        }
+        events.push(CycleEvent::Started { cycle: position.cycle, count: position.count });
+        self.cycle = position.cycle;
+        self.count = position.count;
+        self.joined = 0;
+        self.seen.clear();
+        self.seen.insert(position.index);
+        self.counts.cycles += 1;
+        events
+    }
+}
+
+/// Follows the cycles of each sender and group, reporting where cycles
+/// start and which datagrams of the last one never arrived.
+#[derive(Debug, Default)]
+pub struct CycleTracker {
+    streams: HashMap<(net::SocketAddr, net::SocketAddr), Stream>,
+}
+
+impl CycleTracker {
+    pub fn new() -> CycleTracker {
+        CycleTracker::default()
+    }
+
+    /// Records the datagram at `position`, returning what it shows about
+    /// the sender's cycles.
+    pub fn track(&mut self, datagram: &Datagram, position: Position) -> Vec<CycleEvent> {
+        match self.streams.entry((datagram.src, datagram.group)) {
+            Entry::Occupied(mut entry) => entry.get_mut().track(position),
+            Entry::Vacant(entry) => {
+                entry.insert(Stream::new(position));
+                vec![CycleEvent::Started { cycle: position.cycle, count: position.count }]
+            }
+        }
+    }
+
+    /// Counts per (source, group) stream.
+    pub fn sources(&self) -> Vec<(net::SocketAddr, net::SocketAddr, CycleCounts)> {
+        let mut sources: Vec<_> = self.streams
+            .iter()
+            .map(|(&(src, group), stream)| (src, group, stream.counts))
+            .collect();
+        sources.sort_by_key(|&(src, group, _)| (src, group));
+        sources
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    fn datagram() -> Datagram {
+        Datagram {
+            data: Vec::new(),
+            size: 0,
+            src: "192.0.2.1:4000".parse().unwrap(),
+            group: "239.1.2.3:5000".parse().unwrap(),
+            dst: None,
+            ifindex: None,
+            ttl: None,
+            tos: None,
+            drops: None,
+            received: SystemTime::now(),
+            tag: None,
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let position = Position { cycle: 7, index: 2, count: 3 };
+        let mut data = header(position);
+        assert_eq!(data.len(), HEADER_LEN);
+        data.extend_from_slice(b"payload");
+        assert_eq!(split(&data), Some((position, &b"payload"[..])));
+        assert_eq!(split(&data[..HEADER_LEN - 1]), None);
+        assert_eq!(split(&header(Position { cycle: 7, index: 3, count: 3 })), None);
+    }
+
+    #[test]
+    fn reports_missing_after_the_join() {
+        let mut tracker = CycleTracker::new();
+        let at = |cycle, index| Position { cycle, index, count: 6 };
+        assert_eq!(tracker.track(&datagram(), at(4, 2)), vec![CycleEvent::Started { cycle: 4, count: 6 }]);
+        assert!(tracker.track(&datagram(), at(4, 4)).is_empty());
+        assert_eq!(tracker.track(&datagram(), at(5, 1)),
+                   vec![CycleEvent::Missing { cycle: 4, indexes: vec![3, 5] },
+                        CycleEvent::Started { cycle: 5, count: 6 }]);
+        // a straggler from cycle 4
+        assert!(tracker.track(&datagram(), at(4, 5)).is_empty());
+        let events = tracker.track(&datagram(), at(6, 0));
+        assert_eq!(events[0].to_string(), "cycle 5 missing 5 datagrams: 0, 2-5");
+        let counts = tracker.sources()[0].2;
+        assert_eq!(counts, CycleCounts { cycles: 3, missing: 7 });
+    }
+}
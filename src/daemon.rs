@@ -0,0 +1,61 @@
+use std::{env, fs, io, process};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use libc;
+
+/// A file holding the daemon's process ID, removed when dropped so a
+/// clean shutdown leaves no stale pidfile behind.
+#[derive(Debug)]
+pub struct Pidfile {
+    path: PathBuf,
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Forks, letting the parent exit at once.
+fn fork() -> io::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+/// Detaches from the terminal to run in the background.
+///
+/// Forks twice around `setsid` so the daemon leads no session and can't
+/// acquire a controlling terminal again, then moves to `/` and points
+/// stdin, stdout and stderr at `/dev/null`. Must be called before any
+/// threads are started, as only the calling thread survives a fork.
+pub fn daemonize(pidfile: Option<&Path>) -> io::Result<Option<Pidfile>> {
+    // resolved before moving to /
+    let pidfile = match pidfile {
+        Some(path) => Some(env::current_dir()?.join(path)),
+        None => None,
+    };
+    fork()?;
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    fork()?;
+    let pidfile = match pidfile {
+        Some(path) => {
+            fs::write(&path, format!("{}\n", process::id()))?;
+            Some(Pidfile { path })
+        }
+        None => None,
+    };
+    env::set_current_dir("/")?;
+    let null = fs::OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    for fd in 0..3 {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(pidfile)
+}
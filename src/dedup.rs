@@ -0,0 +1,73 @@
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net;
+use std::time::SystemTime;
+
+use listen::Datagram;
+use loss::SequenceExtractor;
+
+/// Recognizes datagrams that arrive on a group more than once, as
+/// multicast loops and double forwarding make them do: the same payload,
+/// or sequence number, as one of the last `window` datagrams, from any
+/// source.
+pub struct Dedup {
+    window: usize,
+    extractor: Option<Box<dyn SequenceExtractor>>,
+    /// Keys of the datagrams in the window, oldest first.
+    order: VecDeque<u64>,
+    /// Where and when each datagram in the window first came from.
+    seen: HashMap<u64, (net::SocketAddr, SystemTime)>,
+    duplicates: u64,
+}
+
+impl Dedup {
+    pub fn new(window: usize) -> Dedup {
+        Dedup {
+            window: window.max(1),
+            extractor: None,
+            order: VecDeque::new(),
+            seen: HashMap::new(),
+            duplicates: 0,
+        }
+    }
+
+    /// Tells datagrams apart by the sequence number `extractor` finds in
+    /// them, falling back to the payload where there is none. As each
+    /// sender numbers its own, these only match from the same source.
+    pub fn sequence(&mut self, extractor: Box<dyn SequenceExtractor>) -> &mut Dedup {
+        self.extractor = Some(extractor);
+        self
+    }
+
+    fn key(&self, datagram: &Datagram) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        datagram.group.hash(&mut hasher);
+        match self.extractor.as_ref().and_then(|extractor| extractor.extract(&datagram.data)) {
+            Some(seq) => (datagram.src, seq).hash(&mut hasher),
+            None => datagram.data.hash(&mut hasher),
+        }
+        hasher.finish()
+    }
+
+    /// The source and arrival time of the earlier copy, if `datagram` is a
+    /// duplicate; otherwise it joins the window.
+    pub fn check(&mut self, datagram: &Datagram) -> Option<(net::SocketAddr, SystemTime)> {
+        let key = self.key(datagram);
+        if let Some(&first) = self.seen.get(&key) {
+            self.duplicates += 1;
+            return Some(first);
+        }
+        self.seen.insert(key, (datagram.src, datagram.received));
+        self.order.push_back(key);
+        if self.order.len() > self.window {
+            let oldest = self.order.pop_front().expect("a datagram");
+            self.seen.remove(&oldest);
+        }
+        None
+    }
+
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+}
@@ -0,0 +1,380 @@
+use std::{fmt, net};
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_ANY: u16 = 255;
+
+const CLASS_IN: u16 = 1;
+
+/// DNS-SD meta-query that lists the service types on the link.
+pub const SERVICES: &str = "_services._dns-sd._udp.local";
+
+/// Parses a record type name such as "PTR" or "aaaa".
+pub fn parse_type(s: &str) -> Result<u16, String> {
+    match s.to_ascii_uppercase().as_str() {
+        "A" => Ok(TYPE_A),
+        "PTR" => Ok(TYPE_PTR),
+        "TXT" => Ok(TYPE_TXT),
+        "AAAA" => Ok(TYPE_AAAA),
+        "SRV" => Ok(TYPE_SRV),
+        "ANY" => Ok(TYPE_ANY),
+        _ => Err(format!("unknown record type {}: expected A, AAAA, PTR, SRV, TXT or ANY", s)),
+    }
+}
+
+pub fn type_name(rtype: u16) -> String {
+    match rtype {
+        TYPE_A => "A".to_owned(),
+        TYPE_PTR => "PTR".to_owned(),
+        TYPE_TXT => "TXT".to_owned(),
+        TYPE_AAAA => "AAAA".to_owned(),
+        TYPE_SRV => "SRV".to_owned(),
+        TYPE_ANY => "ANY".to_owned(),
+        _ => format!("TYPE{}", rtype),
+    }
+}
+
+/// Encodes a query for `name` with one question per type.
+pub fn query(id: u16, name: &str, types: &[u16]) -> Vec<u8> {
+    let mut msg = Vec::new();
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&[0, 0]); // flags: standard query
+    msg.extend_from_slice(&(types.len() as u16).to_be_bytes());
+    msg.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    for &rtype in types {
+        for label in name.trim_end_matches('.').split('.').filter(|label| !label.is_empty()) {
+            let label = &label.as_bytes()[..label.len().min(63)];
+            msg.push(label.len() as u8);
+            msg.extend_from_slice(label);
+        }
+        msg.push(0);
+        msg.extend_from_slice(&rtype.to_be_bytes());
+        msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+    msg
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RecordData {
+    A(net::Ipv4Addr),
+    Aaaa(net::Ipv6Addr),
+    Ptr(String),
+    Srv { priority: u16, weight: u16, port: u16, target: String },
+    Txt(Vec<String>),
+    Other(Vec<u8>),
+}
+
+/// A resource record from the answer, authority or additional section.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Record {
+    pub name: String,
+    pub rtype: u16,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {} ", self.name, self.ttl, type_name(self.rtype))?;
+        match self.data {
+            RecordData::A(addr) => write!(f, "{}", addr),
+            RecordData::Aaaa(addr) => write!(f, "{}", addr),
+            RecordData::Ptr(ref name) => write!(f, "{}", name),
+            RecordData::Srv { priority, weight, port, ref target } => {
+                write!(f, "{} {} {} {}", priority, weight, port, target)
+            }
+            RecordData::Txt(ref strings) => {
+                let quoted: Vec<_> = strings.iter().map(|s| format!("{:?}", s)).collect();
+                write!(f, "{}", quoted.join(" "))
+            }
+            RecordData::Other(ref data) => write!(f, "({} bytes)", data.len()),
+        }
+    }
+}
+
+/// A decoded DNS message.
+#[derive(Debug, Clone, Default)]
+pub struct Message {
+    pub id: u16,
+    pub response: bool,
+    /// Names asked about in the question section.
+    pub questions: Vec<(String, u16)>,
+    /// Records from all three record sections.
+    pub records: Vec<Record>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+fn truncated() -> String {
+    "truncated DNS message".to_owned()
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or_else(truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a possibly compressed name.
+    fn name(&mut self) -> Result<String, String> {
+        let mut labels = Vec::new();
+        let mut pos = self.pos;
+        let mut jumped = false;
+        // bounds the work a pointer loop can cause
+        for _ in 0..128 {
+            let len = *self.data.get(pos).ok_or_else(truncated)? as usize;
+            match len {
+                0 => {
+                    if !jumped {
+                        self.pos = pos + 1;
+                    }
+                    return Ok(if labels.is_empty() { ".".to_owned() } else { labels.join(".") });
+                }
+                _ if len & 0xc0 == 0xc0 => {
+                    let low = *self.data.get(pos + 1).ok_or_else(truncated)? as usize;
+                    if !jumped {
+                        self.pos = pos + 2;
+                    }
+                    jumped = true;
+                    pos = (len & 0x3f) << 8 | low;
+                }
+                _ => {
+                    let label = self.data.get(pos + 1..pos + 1 + len).ok_or_else(truncated)?;
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    pos += 1 + len;
+                }
+            }
+        }
+        Err("DNS name compression loop".to_owned())
+    }
+
+    fn record(&mut self) -> Result<Record, String> {
+        let name = self.name()?;
+        let rtype = self.u16()?;
+        // class, with the mDNS cache flush bit
+        self.u16()?;
+        let ttl = self.u32()?;
+        let len = self.u16()? as usize;
+        let end = self.pos + len;
+        if end > self.data.len() {
+            return Err(truncated());
+        }
+        let data = match rtype {
+            TYPE_A if len == 4 => {
+                let b = self.bytes(4)?;
+                RecordData::A(net::Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+            }
+            TYPE_AAAA if len == 16 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(self.bytes(16)?);
+                RecordData::Aaaa(octets.into())
+            }
+            TYPE_PTR => RecordData::Ptr(self.name()?),
+            TYPE_SRV => {
+                RecordData::Srv {
+                    priority: self.u16()?,
+                    weight: self.u16()?,
+                    port: self.u16()?,
+                    target: self.name()?,
+                }
+            }
+            TYPE_TXT => {
+                let mut strings = Vec::new();
+                while self.pos < end {
+                    let len = self.bytes(1)?[0] as usize;
+                    strings.push(String::from_utf8_lossy(self.bytes(len)?).into_owned());
+                }
+                RecordData::Txt(strings)
+            }
+            _ => RecordData::Other(self.bytes(len)?.to_vec()),
+        };
+        self.pos = end;
+        Ok(Record { name, rtype, ttl, data })
+    }
+}
+
+impl Message {
+    pub fn parse(data: &[u8]) -> Result<Message, String> {
+        let mut reader = Reader { data, pos: 0 };
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let questions = reader.u16()?;
+        let records = reader.u16()? as usize + reader.u16()? as usize + reader.u16()? as usize;
+        let mut msg = Message {
+            id,
+            response: flags & 0x8000 != 0,
+            ..Message::default()
+        };
+        for _ in 0..questions {
+            let name = reader.name()?;
+            let qtype = reader.u16()?;
+            // class, with the mDNS unicast response bit
+            reader.u16()?;
+            msg.questions.push((name, qtype));
+        }
+        for _ in 0..records {
+            msg.records.push(reader.record()?);
+        }
+        Ok(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(answers: u16) -> Vec<u8> {
+        let mut msg = vec![0, 0, 0x84, 0];
+        for count in &[0, answers, 0, 0] {
+            msg.extend_from_slice(&u16::to_be_bytes(*count));
+        }
+        msg
+    }
+
+    fn labels(msg: &mut Vec<u8>, name: &str) {
+        for label in name.split('.') {
+            msg.push(label.len() as u8);
+            msg.extend_from_slice(label.as_bytes());
+        }
+    }
+
+    fn pointer(msg: &mut Vec<u8>, offset: usize) {
+        msg.extend_from_slice(&(0xc000 | offset as u16).to_be_bytes());
+    }
+
+    fn fixed(msg: &mut Vec<u8>, rtype: u16, class: u16, ttl: u32, len: usize) {
+        msg.extend_from_slice(&rtype.to_be_bytes());
+        msg.extend_from_slice(&class.to_be_bytes());
+        msg.extend_from_slice(&ttl.to_be_bytes());
+        msg.extend_from_slice(&(len as u16).to_be_bytes());
+    }
+
+    /// An mDNS answer to a PTR query for _http._tcp.local, names
+    /// compressed the way responders do.
+    fn response() -> Vec<u8> {
+        let mut msg = header(4);
+        let service = msg.len();
+        labels(&mut msg, "_http._tcp.local");
+        msg.push(0);
+        let local = service + "_http._tcp.".len();
+        fixed(&mut msg, TYPE_PTR, 1, 4500, 6);
+        let instance = msg.len();
+        labels(&mut msg, "web");
+        pointer(&mut msg, service);
+        pointer(&mut msg, instance);
+        fixed(&mut msg, TYPE_SRV, 0x8001, 120, 13);
+        msg.extend_from_slice(&[0, 0, 0, 5, 0x1f, 0x90]);
+        let host = msg.len();
+        labels(&mut msg, "host");
+        pointer(&mut msg, local);
+        pointer(&mut msg, instance);
+        fixed(&mut msg, TYPE_TXT, 0x8001, 4500, 12);
+        labels(&mut msg, "path=/x");
+        labels(&mut msg, "a=b");
+        pointer(&mut msg, host);
+        fixed(&mut msg, TYPE_A, 0x8001, 120, 4);
+        msg.extend_from_slice(&[192, 0, 2, 7]);
+        msg
+    }
+
+    #[test]
+    fn real_response() {
+        let msg = Message::parse(&response()).unwrap();
+        assert!(msg.response);
+        assert!(msg.questions.is_empty());
+        let records: Vec<_> = msg.records.iter().map(Record::to_string).collect();
+        assert_eq!(records,
+                   vec!["_http._tcp.local 4500 PTR web._http._tcp.local",
+                        "web._http._tcp.local 120 SRV 0 5 8080 host.local",
+                        "web._http._tcp.local 4500 TXT \"path=/x\" \"a=b\"",
+                        "host.local 120 A 192.0.2.7"]);
+    }
+
+    #[test]
+    fn compression_pointer() {
+        let mut msg = query(7, "_ipp._tcp.local", &[TYPE_PTR]);
+        msg[2] = 0x84;
+        msg[7] = 1;
+        // the answer's name points back at the question's
+        pointer(&mut msg, 12);
+        fixed(&mut msg, TYPE_PTR, 1, 10, 2);
+        pointer(&mut msg, 12 + "_ipp.".len());
+        let msg = Message::parse(&msg).unwrap();
+        assert_eq!(msg.id, 7);
+        assert_eq!(msg.questions, vec![("_ipp._tcp.local".to_owned(), TYPE_PTR)]);
+        assert_eq!(msg.records[0].name, "_ipp._tcp.local");
+        assert_eq!(msg.records[0].data, RecordData::Ptr("_tcp.local".to_owned()));
+    }
+
+    #[test]
+    fn pointer_loop() {
+        let mut msg = header(1);
+        // a pointer to itself
+        pointer(&mut msg, 12);
+        fixed(&mut msg, TYPE_A, 1, 10, 4);
+        msg.extend_from_slice(&[192, 0, 2, 7]);
+        assert_eq!(Message::parse(&msg).unwrap_err(), "DNS name compression loop");
+
+        // a label, then a pointer back to it
+        let mut msg = header(1);
+        labels(&mut msg, "a");
+        pointer(&mut msg, 12);
+        fixed(&mut msg, TYPE_A, 1, 10, 4);
+        msg.extend_from_slice(&[192, 0, 2, 7]);
+        assert_eq!(Message::parse(&msg).unwrap_err(), "DNS name compression loop");
+    }
+
+    #[test]
+    fn truncated_record() {
+        let msg = response();
+        for len in 12..msg.len() {
+            assert_eq!(Message::parse(&msg[..len]).unwrap_err(), truncated(), "cut at {}", len);
+        }
+        // data running past the end
+        let mut msg = header(1);
+        labels(&mut msg, "host");
+        msg.push(0);
+        fixed(&mut msg, TYPE_A, 1, 10, 8);
+        msg.extend_from_slice(&[192, 0, 2, 7]);
+        assert_eq!(Message::parse(&msg).unwrap_err(), truncated());
+        // TXT strings running past the record's end
+        let mut msg = header(1);
+        msg.push(0);
+        fixed(&mut msg, TYPE_TXT, 1, 10, 2);
+        msg.extend_from_slice(&[5, b'a']);
+        assert_eq!(Message::parse(&msg).unwrap_err(), truncated());
+    }
+
+    #[test]
+    fn truncated_name() {
+        // a label longer than what is left
+        let mut msg = header(1);
+        msg.extend_from_slice(&[9, b'h', b'o', b's', b't']);
+        assert_eq!(Message::parse(&msg).unwrap_err(), truncated());
+        // a pointer without its second byte
+        let mut msg = header(1);
+        msg.push(0xc0);
+        assert_eq!(Message::parse(&msg).unwrap_err(), truncated());
+        // a pointer past the end
+        let mut msg = header(1);
+        pointer(&mut msg, 200);
+        assert_eq!(Message::parse(&msg).unwrap_err(), truncated());
+    }
+}
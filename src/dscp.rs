@@ -0,0 +1,66 @@
+use std::fmt;
+
+/// Differentiated services code points with standard names.
+const NAMES: &[(&str, u8)] = &[
+    ("CS0", 0),
+    ("CS1", 8),
+    ("AF11", 10),
+    ("AF12", 12),
+    ("AF13", 14),
+    ("CS2", 16),
+    ("AF21", 18),
+    ("AF22", 20),
+    ("AF23", 22),
+    ("CS3", 24),
+    ("AF31", 26),
+    ("AF32", 28),
+    ("AF33", 30),
+    ("CS4", 32),
+    ("AF41", 34),
+    ("AF42", 36),
+    ("AF43", 38),
+    ("CS5", 40),
+    ("VA", 44),
+    ("EF", 46),
+    ("CS6", 48),
+    ("CS7", 56),
+];
+
+/// The standard name of a DSCP value, e.g. "EF" for 46.
+pub fn name(dscp: u8) -> Option<&'static str> {
+    NAMES.iter().find(|&&(_, value)| value == dscp).map(|&(name, _)| name)
+}
+
+/// Parses a DSCP name such as "AF41" (case-insensitive) or a number from 0
+/// to 63.
+pub fn parse(s: &str) -> Result<u8, String> {
+    if let Some(&(_, value)) = NAMES.iter().find(|&&(name, _)| name.eq_ignore_ascii_case(s)) {
+        return Ok(value);
+    }
+    match s.parse::<u8>() {
+        Ok(value) if value < 64 => Ok(value),
+        _ => Err(format!("invalid DSCP {}: expected a name such as EF or AF41, or 0-63", s)),
+    }
+}
+
+/// The DSCP field of a TOS / traffic class byte.
+pub fn from_tos(tos: u8) -> u8 {
+    tos >> 2
+}
+
+/// The TOS / traffic class byte carrying `dscp`, with ECN bits clear.
+pub fn to_tos(dscp: u8) -> u8 {
+    dscp << 2
+}
+
+/// Displays a DSCP value by name where it has one.
+pub struct Dscp(pub u8);
+
+impl fmt::Display for Dscp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match name(self.0) {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use libc;
+
+/// Names a registered socket in the events returned by `Poll::wait`.
+pub type Token = usize;
+
+/// What a registered socket is waited on for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interest {
+    Readable,
+    Writable,
+    Both,
+}
+
+impl Interest {
+    fn events(self) -> libc::c_short {
+        match self {
+            Interest::Readable => libc::POLLIN,
+            Interest::Writable => libc::POLLOUT,
+            Interest::Both => libc::POLLIN | libc::POLLOUT,
+        }
+    }
+}
+
+/// A socket that became ready.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub token: Token,
+    /// Data, end of stream or an error is waiting to be read.
+    pub readable: bool,
+    pub writable: bool,
+}
+
+/// Waits on many sockets at once with `poll(2)`, so one thread can serve
+/// them all. Sockets are borrowed only while registering; the caller keeps
+/// them open until deregistered.
+#[derive(Debug, Default)]
+pub struct Poll {
+    fds: Vec<libc::pollfd>,
+    tokens: Vec<Token>,
+}
+
+impl Poll {
+    pub fn new() -> Poll {
+        Poll::default()
+    }
+
+    pub fn register<S: AsRawFd>(&mut self, sock: &S, token: Token, interest: Interest) {
+        self.deregister(token);
+        self.fds.push(pollfd(sock.as_raw_fd(), interest));
+        self.tokens.push(token);
+    }
+
+    /// Changes what a registered socket is waited on for.
+    pub fn reregister(&mut self, token: Token, interest: Interest) {
+        if let Some(i) = self.position(token) {
+            self.fds[i].events = interest.events();
+        }
+    }
+
+    pub fn deregister(&mut self, token: Token) {
+        if let Some(i) = self.position(token) {
+            self.fds.swap_remove(i);
+            self.tokens.swap_remove(i);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.fds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+
+    fn position(&self, token: Token) -> Option<usize> {
+        self.tokens.iter().position(|&t| t == token)
+    }
+
+    /// Waits until a socket is ready, returning nothing on timeout and
+    /// failing with `Interrupted` when a signal arrives.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Vec<Event>> {
+        let millis = match timeout {
+            // round up so a short timeout doesn't spin
+            Some(timeout) => (timeout.as_micros().div_ceil(1000)).min(libc::c_int::MAX as u128) as libc::c_int,
+            None => -1,
+        };
+        for fd in &mut self.fds {
+            fd.revents = 0;
+        }
+        if unsafe { libc::poll(self.fds.as_mut_ptr(), self.fds.len() as libc::nfds_t, millis) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let failed = libc::POLLHUP | libc::POLLERR | libc::POLLNVAL;
+        Ok(self.fds
+            .iter()
+            .zip(&self.tokens)
+            .filter(|&(fd, _)| fd.revents != 0)
+            .map(|(fd, &token)| {
+                Event {
+                    token,
+                    readable: fd.revents & (libc::POLLIN | failed) != 0,
+                    writable: fd.revents & (libc::POLLOUT | failed) != 0,
+                }
+            })
+            .collect())
+    }
+}
+
+fn pollfd(fd: RawFd, interest: Interest) -> libc::pollfd {
+    libc::pollfd {
+        fd,
+        events: interest.events(),
+        revents: 0,
+    }
+}
+
+/// Periodic work, like a stats report, run from an event loop.
+#[derive(Debug, Clone, Copy)]
+pub struct Timer {
+    period: Duration,
+    next: Instant,
+}
+
+impl Timer {
+    pub fn every(period: Duration) -> Timer {
+        Timer {
+            period,
+            next: Instant::now() + period,
+        }
+    }
+
+    /// Time left until the timer fires, for `Poll::wait`.
+    pub fn remaining(&self) -> Duration {
+        self.next.saturating_duration_since(Instant::now())
+    }
+
+    /// Whether the timer is due, rescheduling it if so.
+    pub fn fired(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next {
+            return false;
+        }
+        self.next += self.period;
+        if self.next < now {
+            // skip the ticks missed while busy
+            self.next = now + self.period;
+        }
+        true
+    }
+}
@@ -0,0 +1,182 @@
+use std::{io, str};
+use std::io::prelude::*;
+use std::process::{Child, Command, ExitStatus, Stdio};
+
+use alert::{Alert, AlertKind};
+use listen::Datagram;
+use timestamp;
+
+/// Commands left running before further datagrams are skipped rather than
+/// forking without bound.
+const MAX_RUNNING: usize = 64;
+
+/// What `--exec` runs its command on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// Every datagram that passes the filters.
+    Packet,
+    /// Traffic stopping for the idle timeout.
+    Down,
+    /// Traffic resuming after the idle timeout.
+    Up,
+    /// A group's rate staying past an alert threshold.
+    Alert,
+    /// A group's rate coming back within the threshold it alerted on.
+    Clear,
+}
+
+impl Trigger {
+    fn name(self) -> &'static str {
+        match self {
+            Trigger::Packet => "packet",
+            Trigger::Down => "down",
+            Trigger::Up => "up",
+            Trigger::Alert => "alert",
+            Trigger::Clear => "clear",
+        }
+    }
+}
+
+impl str::FromStr for Trigger {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Trigger, String> {
+        match s {
+            "packet" => Ok(Trigger::Packet),
+            "down" => Ok(Trigger::Down),
+            "up" => Ok(Trigger::Up),
+            "alert" => Ok(Trigger::Alert),
+            "clear" => Ok(Trigger::Clear),
+            _ => Err(format!("unknown event {}: expected packet, down, up, alert or clear", s)),
+        }
+    }
+}
+
+/// A shell command run for datagrams or events, with details in `MCCAT_*`
+/// environment variables and a datagram's payload on stdin. Commands run
+/// alongside the listener; call `reap` now and then to collect them.
+#[derive(Debug)]
+pub struct Exec {
+    command: String,
+    triggers: Vec<Trigger>,
+    running: Vec<Child>,
+    skipped: u64,
+}
+
+impl Exec {
+    pub fn new(command: &str, triggers: &[Trigger]) -> Exec {
+        Exec {
+            command: command.to_owned(),
+            triggers: triggers.to_vec(),
+            running: Vec::new(),
+            skipped: 0,
+        }
+    }
+
+    pub fn runs_on(&self, trigger: Trigger) -> bool {
+        self.triggers.contains(&trigger)
+    }
+
+    /// How many times the command was not run because too many were still
+    /// running.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    fn spawn(&mut self, trigger: Trigger, env: &[(&str, String)], stdin: &[u8]) -> io::Result<()> {
+        if !self.runs_on(trigger) {
+            return Ok(());
+        }
+        if self.running.len() >= MAX_RUNNING {
+            self.skipped += 1;
+            return Ok(());
+        }
+        let mut command = Command::new("/bin/sh");
+        command.arg("-c").arg(&self.command).env("MCCAT_EVENT", trigger.name());
+        for &(name, ref value) in env {
+            command.env(name, value);
+        }
+        let mut child = command.stdin(Stdio::piped()).spawn()?;
+        let mut pipe = child.stdin.take().expect("piped stdin");
+        // a datagram fits in the pipe buffer; a command that doesn't read it
+        // just closes the pipe
+        match pipe.write_all(stdin) {
+            Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+            result => result?,
+        }
+        self.running.push(child);
+        Ok(())
+    }
+
+    /// Runs the command for `datagram`, if it runs on packets.
+    pub fn datagram(&mut self, datagram: &Datagram) -> io::Result<()> {
+        let mut env = vec![
+            ("MCCAT_SRC", datagram.src.ip().to_string()),
+            ("MCCAT_SRC_PORT", datagram.src.port().to_string()),
+            ("MCCAT_GROUP", datagram.group.ip().to_string()),
+            ("MCCAT_PORT", datagram.group.port().to_string()),
+            ("MCCAT_SIZE", datagram.size.to_string()),
+            ("MCCAT_TIME", timestamp::iso8601(datagram.received)),
+        ];
+        if let Some(dst) = datagram.dst {
+            env.push(("MCCAT_DST", dst.to_string()));
+        }
+        if let Some(ttl) = datagram.ttl {
+            env.push(("MCCAT_TTL", ttl.to_string()));
+        }
+        self.spawn(Trigger::Packet, &env, &datagram.data)
+    }
+
+    /// Runs the command for traffic on `groups` stopping or resuming, if it
+    /// runs on that event.
+    pub fn event(&mut self, trigger: Trigger, groups: &[String]) -> io::Result<()> {
+        self.spawn(trigger, &[("MCCAT_GROUPS", groups.join(" "))], &[])
+    }
+
+    /// Runs the command for a group's rate alerting or clearing, if it runs
+    /// on that event.
+    pub fn alert(&mut self, alert: &Alert) -> io::Result<()> {
+        let trigger = match alert.kind {
+            AlertKind::Raised => Trigger::Alert,
+            AlertKind::Cleared => Trigger::Clear,
+        };
+        let env = [("MCCAT_GROUP", alert.group.ip().to_string()),
+                   ("MCCAT_PORT", alert.group.port().to_string()),
+                   ("MCCAT_RATE", alert.measured_rate()),
+                   ("MCCAT_THRESHOLD", alert.threshold.to_string())];
+        self.spawn(trigger, &env, &[])
+    }
+
+    /// Collects the commands that have finished, returning the exit status
+    /// of those that failed.
+    pub fn reap(&mut self) -> io::Result<Vec<ExitStatus>> {
+        let mut failed = Vec::new();
+        let mut result = Ok(());
+        self.running.retain_mut(|child| match child.try_wait() {
+            Ok(Some(status)) => {
+                if !status.success() {
+                    failed.push(status);
+                }
+                false
+            }
+            Ok(None) => true,
+            Err(err) => {
+                result = Err(err);
+                true
+            }
+        });
+        result.map(|()| failed)
+    }
+
+    /// Waits for the commands still running, as on exit.
+    pub fn wait(&mut self) -> io::Result<Vec<ExitStatus>> {
+        let mut failed = Vec::new();
+        for mut child in self.running.drain(..) {
+            let status = child.wait()?;
+            if !status.success() {
+                failed.push(status);
+            }
+        }
+        Ok(failed)
+    }
+}
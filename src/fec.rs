@@ -0,0 +1,221 @@
+/// Reduces products in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1.
+const POLYNOMIAL: u16 = 0x11d;
+
+/// A systematic Reed-Solomon erasure code over GF(2^8): a block of `data`
+/// equal-sized symbols is sent as they are plus `parity` symbols, and any
+/// `data` of them restore the block. Parity symbols are rows of a Cauchy
+/// matrix, any square submatrix of which can be inverted.
+#[derive(Debug, Clone)]
+pub struct Fec {
+    data: usize,
+    parity: usize,
+    exp: [u8; 510],
+    log: [u8; 256],
+}
+
+impl Fec {
+    /// A code for `data` symbols and `parity` more, which between them can
+    /// number at most 256.
+    pub fn new(data: usize, parity: usize) -> Result<Fec, String> {
+        if data == 0 || data + parity > 256 {
+            return Err(format!("can't code {} data and {} parity symbols: expected at least one and at most 256 \
+                                in all", data, parity));
+        }
+        let mut exp = [0u8; 510];
+        let mut log = [0u8; 256];
+        let mut x = 1u16;
+        for i in 0..255 {
+            exp[i] = x as u8;
+            exp[i + 255] = x as u8;
+            log[x as usize] = i as u8;
+            x <<= 1;
+            if x & 0x100 != 0 {
+                x ^= POLYNOMIAL;
+            }
+        }
+        Ok(Fec { data, parity, exp, log })
+    }
+
+    fn mul(&self, a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        self.exp[self.log[a as usize] as usize + self.log[b as usize] as usize]
+    }
+
+    fn inv(&self, a: u8) -> u8 {
+        self.exp[255 - self.log[a as usize] as usize]
+    }
+
+    /// The coefficient of data symbol `col` in parity symbol `row`,
+    /// 1 / (x + y) with the x and y drawn from disjoint sets.
+    fn coefficient(&self, row: usize, col: usize) -> u8 {
+        self.inv(((self.data + row) ^ col) as u8)
+    }
+
+    /// Adds `coefficient` times `symbol` to `sum`.
+    fn mul_add(&self, sum: &mut [u8], coefficient: u8, symbol: &[u8]) {
+        if coefficient == 0 {
+            return;
+        }
+        let log = self.log[coefficient as usize] as usize;
+        for (sum, &byte) in sum.iter_mut().zip(symbol) {
+            if byte != 0 {
+                *sum ^= self.exp[log + self.log[byte as usize] as usize];
+            }
+        }
+    }
+
+    /// The parity symbols of a block of `data` symbols of equal size.
+    pub fn encode<S: AsRef<[u8]>>(&self, symbols: &[S]) -> Vec<Vec<u8>> {
+        assert_eq!(symbols.len(), self.data, "a block of data symbols");
+        let size = symbols[0].as_ref().len();
+        (0..self.parity).map(|row| {
+            let mut parity = vec![0u8; size];
+            for (col, symbol) in symbols.iter().enumerate() {
+                self.mul_add(&mut parity, self.coefficient(row, col), symbol.as_ref());
+            }
+            parity
+        }).collect()
+    }
+
+    /// The data symbols of a block from any `data` of its symbols, given
+    /// with their index: data symbols first, then parity. `None` if there
+    /// are too few distinct ones.
+    pub fn decode(&self, received: &[(usize, &[u8])]) -> Option<Vec<Vec<u8>>> {
+        let mut rows: Vec<(usize, &[u8])> = Vec::with_capacity(self.data);
+        for &(index, symbol) in received {
+            if index < self.data + self.parity && rows.iter().all(|&(seen, _)| seen != index) {
+                rows.push((index, symbol));
+                if rows.len() == self.data {
+                    break;
+                }
+            }
+        }
+        if rows.len() < self.data {
+            return None;
+        }
+        let size = rows[0].1.len();
+        let mut symbols: Vec<Option<Vec<u8>>> = vec![None; self.data];
+        for &(index, symbol) in &rows {
+            if index < self.data {
+                symbols[index] = Some(symbol.to_vec());
+            }
+        }
+        if symbols.iter().all(Option::is_some) {
+            return Some(symbols.into_iter().flatten().collect());
+        }
+        // invert the matrix that made the received symbols from the data
+        let mut matrix: Vec<Vec<u8>> = rows.iter().map(|&(index, _)| {
+            (0..self.data).map(|col| {
+                if index < self.data {
+                    (index == col) as u8
+                } else {
+                    self.coefficient(index - self.data, col)
+                }
+            }).collect()
+        }).collect();
+        let mut inverse: Vec<Vec<u8>> = (0..self.data).map(|row| {
+            (0..self.data).map(|col| (row == col) as u8).collect()
+        }).collect();
+        for col in 0..self.data {
+            let pivot = (col..self.data).find(|&row| matrix[row][col] != 0)?;
+            matrix.swap(col, pivot);
+            inverse.swap(col, pivot);
+            let scale = self.inv(matrix[col][col]);
+            for i in 0..self.data {
+                matrix[col][i] = self.mul(matrix[col][i], scale);
+                inverse[col][i] = self.mul(inverse[col][i], scale);
+            }
+            for row in 0..self.data {
+                let factor = matrix[row][col];
+                if row == col || factor == 0 {
+                    continue;
+                }
+                for i in 0..self.data {
+                    matrix[row][i] ^= self.mul(factor, matrix[col][i]);
+                    inverse[row][i] ^= self.mul(factor, inverse[col][i]);
+                }
+            }
+        }
+        for (index, symbol) in symbols.iter_mut().enumerate() {
+            if symbol.is_none() {
+                let mut restored = vec![0u8; size];
+                for (&coefficient, &(_, received)) in inverse[index].iter().zip(&rows) {
+                    self.mul_add(&mut restored, coefficient, received);
+                }
+                *symbol = Some(restored);
+            }
+        }
+        Some(symbols.into_iter().flatten().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(data: usize, size: usize) -> Vec<Vec<u8>> {
+        (0..data).map(|i| (0..size).map(|j| (i * 31 + j * 7 + i * j) as u8).collect()).collect()
+    }
+
+    /// Every symbol of the block, data then parity.
+    fn symbols(fec: &Fec, data: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        data.iter().cloned().chain(fec.encode(data)).collect()
+    }
+
+    #[test]
+    fn every_loss_up_to_parity() {
+        for &(data, parity) in &[(1, 1), (1, 3), (2, 2), (4, 2), (5, 3), (8, 4), (6, 0)] {
+            let fec = Fec::new(data, parity).unwrap();
+            let original = block(data, 40);
+            let all = symbols(&fec, &original);
+            for lost in 0u32..1 << (data + parity) {
+                let received: Vec<_> = all.iter()
+                    .enumerate()
+                    .filter(|&(index, _)| lost & 1 << index == 0)
+                    .map(|(index, symbol)| (index, &symbol[..]))
+                    .collect();
+                let decoded = fec.decode(&received);
+                if lost.count_ones() as usize <= parity {
+                    assert_eq!(decoded.as_ref(), Some(&original), "{}+{} losing {:b}", data, parity, lost);
+                    // in any order
+                    let reversed: Vec<_> = received.iter().rev().cloned().collect();
+                    assert_eq!(fec.decode(&reversed).as_ref(), Some(&original));
+                } else {
+                    assert_eq!(decoded, None, "{}+{} losing {:b}", data, parity, lost);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn largest_code() {
+        let fec = Fec::new(200, 56).unwrap();
+        let original = block(200, 8);
+        let all = symbols(&fec, &original);
+        // the last 56 data symbols lost, the parity standing in for them
+        let received: Vec<_> = all.iter().enumerate().skip(56).map(|(index, symbol)| (index, &symbol[..])).collect();
+        assert_eq!(fec.decode(&received), Some(original));
+        assert_eq!(fec.decode(&received[1..]), None);
+    }
+
+    #[test]
+    fn duplicates_and_strays_dont_count() {
+        let fec = Fec::new(3, 2).unwrap();
+        let original = block(3, 16);
+        let all = symbols(&fec, &original);
+        let received = [(0, &all[0][..]), (0, &all[0][..]), (4, &all[4][..]), (5, &all[4][..]), (200, &all[1][..])];
+        assert_eq!(fec.decode(&received), None);
+        assert_eq!(fec.decode(&[]), None);
+        let received = [(0, &all[0][..]), (4, &all[4][..]), (4, &all[4][..]), (3, &all[3][..])];
+        assert_eq!(fec.decode(&received), Some(original));
+    }
+
+    #[test]
+    fn sizes() {
+        assert!(Fec::new(0, 1).is_err());
+        assert!(Fec::new(200, 57).is_err());
+        assert!(Fec::new(256, 0).is_ok());
+    }
+}
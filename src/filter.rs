@@ -0,0 +1,193 @@
+use std::{fmt, io, net, str};
+
+use regex::bytes::Regex;
+
+/// An address prefix such as 10.0.0.0/8 or 2001:db8::/32. A bare address is
+/// a prefix of full length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    pub addr: net::IpAddr,
+    pub prefix: u8,
+}
+
+fn max_prefix(addr: &net::IpAddr) -> u8 {
+    if addr.is_ipv4() { 32 } else { 128 }
+}
+
+fn bits(addr: &net::IpAddr) -> u128 {
+    match *addr {
+        net::IpAddr::V4(addr) => u128::from(u32::from(addr)),
+        net::IpAddr::V6(addr) => u128::from(addr),
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, addr: &net::IpAddr) -> bool {
+        if addr.is_ipv4() != self.addr.is_ipv4() {
+            return false;
+        }
+        let width = u32::from(max_prefix(addr));
+        let host_bits = width - u32::from(self.prefix);
+        (bits(addr) ^ bits(&self.addr)).checked_shr(host_bits).unwrap_or(0) == 0
+    }
+
+    /// How many addresses the prefix covers.
+    pub fn size(&self) -> u128 {
+        1u128.checked_shl(u32::from(max_prefix(&self.addr) - self.prefix)).unwrap_or(u128::MAX)
+    }
+
+    /// Every address the prefix covers, in order.
+    pub fn addresses(&self) -> impl Iterator<Item = net::IpAddr> {
+        let host_bits = u32::from(max_prefix(&self.addr) - self.prefix);
+        let first = bits(&self.addr) & !(1u128.checked_shl(host_bits).unwrap_or(0).wrapping_sub(1));
+        let v4 = self.addr.is_ipv4();
+        (0..self.size()).map(move |i| {
+            let addr = first + i;
+            if v4 { net::IpAddr::V4((addr as u32).into()) } else { net::IpAddr::V6(addr.into()) }
+        })
+    }
+
+    /// The address, if the prefix covers exactly one host.
+    pub fn host(&self) -> Option<net::IpAddr> {
+        if self.prefix == max_prefix(&self.addr) { Some(self.addr) } else { None }
+    }
+}
+
+impl str::FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Cidr, String> {
+        let invalid = || format!("invalid address {}: expected e.g. 192.0.2.1, 10.0.0.0/8 or 2001:db8::/32", s);
+        let (addr, prefix) = match s.find('/') {
+            Some(slash) => (&s[..slash], Some(&s[slash + 1..])),
+            None => (s, None),
+        };
+        let addr: net::IpAddr = addr.parse().map_err(|_| invalid())?;
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+            None => max_prefix(&addr),
+        };
+        if prefix > max_prefix(&addr) {
+            return Err(invalid());
+        }
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.host() {
+            Some(addr) => write!(f, "{}", addr),
+            None => write!(f, "{}/{}", self.addr, self.prefix),
+        }
+    }
+}
+
+/// Whether a kernel source filter lists the senders to accept or to drop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    Include,
+    Exclude,
+}
+
+/// Senders to accept: those matching an allowed prefix, if any are given,
+/// and no blocked prefix.
+#[derive(Debug, Clone, Default)]
+pub struct SourceFilter {
+    allow: Vec<Cidr>,
+    block: Vec<Cidr>,
+}
+
+impl SourceFilter {
+    pub fn new() -> SourceFilter {
+        SourceFilter::default()
+    }
+
+    pub fn allow(&mut self, cidr: Cidr) -> &mut SourceFilter {
+        self.allow.push(cidr);
+        self
+    }
+
+    pub fn block(&mut self, cidr: Cidr) -> &mut SourceFilter {
+        self.block.push(cidr);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.block.is_empty()
+    }
+
+    pub fn allows(&self, addr: &net::IpAddr) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(addr))) &&
+        !self.block.iter().any(|cidr| cidr.contains(addr))
+    }
+
+    /// The equivalent `MCAST_MSFILTER` mode and source list for groups of
+    /// `group`'s address family. The kernel only filters on single
+    /// addresses, so every prefix must be a host.
+    pub fn kernel_sources(&self, group: &net::IpAddr) -> io::Result<(FilterMode, Vec<net::IpAddr>)> {
+        let hosts = |cidrs: &[Cidr]| -> io::Result<Vec<net::IpAddr>> {
+            cidrs.iter()
+                .filter(|cidr| cidr.addr.is_ipv4() == group.is_ipv4())
+                .map(|cidr| {
+                    cidr.host().ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidInput,
+                                       format!("kernel source filters take single addresses, not {}", cidr))
+                    })
+                })
+                .collect()
+        };
+        let block = hosts(&self.block)?;
+        if self.allow.is_empty() {
+            return Ok((FilterMode::Exclude, block));
+        }
+        let allow = hosts(&self.allow)?.into_iter().filter(|addr| !block.contains(addr)).collect();
+        Ok((FilterMode::Include, allow))
+    }
+}
+
+/// What to look for in a payload: `hex:` followed by hex bytes, `re:`
+/// followed by a regular expression, or otherwise a literal substring.
+#[derive(Debug, Clone)]
+pub enum PayloadPattern {
+    Bytes(Vec<u8>),
+    Regex(Regex),
+}
+
+/// Parses pairs of hex digits, optionally separated by spaces or colons.
+pub fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s.bytes().filter(|&b| b != b' ' && b != b':').collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits.chunks(2)
+        .map(|pair| str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
+
+impl PayloadPattern {
+    pub fn matches(&self, data: &[u8]) -> bool {
+        match *self {
+            PayloadPattern::Bytes(ref needle) => data.windows(needle.len()).any(|window| window == &needle[..]),
+            PayloadPattern::Regex(ref regex) => regex.is_match(data),
+        }
+    }
+}
+
+impl str::FromStr for PayloadPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PayloadPattern, String> {
+        if let Some(hex) = s.strip_prefix("hex:") {
+            parse_hex(hex).map(PayloadPattern::Bytes)
+                .ok_or_else(|| format!("invalid hex pattern {}: expected pairs of hex digits", hex))
+        } else if let Some(regex) = s.strip_prefix("re:") {
+            Regex::new(regex).map(PayloadPattern::Regex)
+                .map_err(|err| format!("invalid regular expression {}: {}", regex, err))
+        } else if s.is_empty() {
+            Err("empty pattern".to_owned())
+        } else {
+            Ok(PayloadPattern::Bytes(s.as_bytes().to_vec()))
+        }
+    }
+}
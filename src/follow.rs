@@ -0,0 +1,74 @@
+use std::{fs, io, thread};
+use std::io::prelude::*;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often a file that has stopped growing is checked again.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Reads what is appended to a file, as `tail -F` does: it starts at the
+/// end, waits for more rather than ending, and reopens the file when it is
+/// rotated away or truncated.
+#[derive(Debug)]
+pub struct Follow {
+    path: PathBuf,
+    file: fs::File,
+    /// Device and inode of the open file.
+    id: (u64, u64),
+    pos: u64,
+}
+
+impl Follow {
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Follow> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = fs::File::open(&path)?;
+        let metadata = file.metadata()?;
+        let pos = file.seek(io::SeekFrom::End(0))?;
+        Ok(Follow {
+            path,
+            file,
+            id: (metadata.dev(), metadata.ino()),
+            pos,
+        })
+    }
+
+    /// Reopens the file if another has taken its name or it got shorter,
+    /// returning whether it did.
+    fn reopen_if_replaced(&mut self) -> io::Result<bool> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            // between being rotated away and recreated
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        let id = (metadata.dev(), metadata.ino());
+        if id == self.id && metadata.len() >= self.pos {
+            return Ok(false);
+        }
+        self.file = fs::File::open(&self.path)?;
+        let metadata = self.file.metadata()?;
+        self.id = (metadata.dev(), metadata.ino());
+        self.pos = 0;
+        Ok(true)
+    }
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let len = self.file.read(buf)?;
+            if len > 0 {
+                self.pos += len as u64;
+                return Ok(len);
+            }
+            // the rest of a rotated file is read before moving on
+            if !self.reopen_if_replaced()? {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::{io, mem, net};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+use listen::Datagram;
+
+/// Marks a fragment, followed by the message id as a big-endian u32 and
+/// the fragment's index and the message's fragment count as u16s.
+const MAGIC: &[u8] = b"MCF";
+pub const HEADER_LEN: usize = 11;
+
+/// Fragment size that stays clear of IP fragmentation on Ethernet.
+pub const DEFAULT_FRAGMENT_SIZE: usize = 1400;
+
+/// How long the rest of a message is waited for after its first fragment.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bytes held in incomplete messages, their slots for fragments included,
+/// before the oldest are given up on.
+const MAX_BUFFERED: usize = 64 << 20;
+
+/// Splits `message` into fragments of at most `size` bytes, header
+/// included. An empty message is still sent, as one empty fragment.
+pub fn split(id: u32, message: &[u8], size: usize) -> io::Result<Vec<Vec<u8>>> {
+    let chunk = size.saturating_sub(HEADER_LEN).max(1);
+    let count = message.len().div_ceil(chunk).max(1);
+    let count = u16::try_from(count).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput,
+                       format!("a message of {} bytes needs more than {} fragments of {} bytes",
+                               message.len(), u16::MAX, size))
+    })?;
+    let mut fragments = Vec::with_capacity(count as usize);
+    for index in 0..count {
+        let start = index as usize * chunk;
+        let end = (start + chunk).min(message.len());
+        let mut fragment = Vec::with_capacity(HEADER_LEN + end - start);
+        fragment.extend_from_slice(MAGIC);
+        fragment.extend_from_slice(&id.to_be_bytes());
+        fragment.extend_from_slice(&index.to_be_bytes());
+        fragment.extend_from_slice(&count.to_be_bytes());
+        fragment.extend_from_slice(&message[start..end]);
+        fragments.push(fragment);
+    }
+    Ok(fragments)
+}
+
+/// The fragments of a message received so far.
+#[derive(Debug)]
+struct Partial {
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    /// Bytes held, counting the slots for fragments yet to come.
+    size: usize,
+    started: Instant,
+}
+
+/// Puts messages split by `split` back together, per source and group.
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    partial: HashMap<(net::SocketAddr, net::SocketAddr, u32), Partial>,
+    buffered: usize,
+    incomplete: u64,
+}
+
+impl Reassembler {
+    pub fn new() -> Reassembler {
+        Reassembler::default()
+    }
+
+    /// How many messages were given up on with fragments still missing.
+    pub fn incomplete(&self) -> u64 {
+        self.incomplete
+    }
+
+    /// Adds a fragment, returning the message once it is complete. Datagrams
+    /// that aren't fragments are returned as they are.
+    pub fn add(&mut self, datagram: &Datagram) -> Result<Option<Vec<u8>>, String> {
+        let now = Instant::now();
+        self.expire(now);
+        let data = &datagram.data;
+        if data.len() < HEADER_LEN || !data.starts_with(MAGIC) {
+            return Ok(Some(data.clone()));
+        }
+        let id = u32::from_be_bytes([data[3], data[4], data[5], data[6]]);
+        let index = u16::from_be_bytes([data[7], data[8]]) as usize;
+        let count = u16::from_be_bytes([data[9], data[10]]) as usize;
+        if index >= count {
+            return Err(format!("fragment {} of a message in {}", index, count));
+        }
+        let payload = &data[HEADER_LEN..];
+        if count == 1 {
+            return Ok(Some(payload.to_vec()));
+        }
+        let key = (datagram.src, datagram.group, id);
+        let buffered = &mut self.buffered;
+        let partial = self.partial.entry(key).or_insert_with(|| {
+            // counted like payload, or first fragments of ever new messages
+            // could hold memory without limit
+            let slots = count * mem::size_of::<Option<Vec<u8>>>();
+            *buffered += slots;
+            Partial {
+                fragments: vec![None; count],
+                missing: count,
+                size: slots,
+                started: now,
+            }
+        });
+        if partial.fragments.len() != count {
+            let expected = partial.fragments.len();
+            self.drop_partial(&key);
+            return Err(format!("fragment of a message in {} where earlier ones said {}", count, expected));
+        }
+        if partial.fragments[index].is_some() {
+            // duplicated on the way
+            return Ok(None);
+        }
+        partial.fragments[index] = Some(payload.to_vec());
+        partial.missing -= 1;
+        partial.size += payload.len();
+        self.buffered += payload.len();
+        if partial.missing == 0 {
+            let partial = self.partial.remove(&key).expect("partial message");
+            self.buffered -= partial.size;
+            return Ok(Some(partial.fragments.into_iter().flatten().flatten().collect()));
+        }
+        while self.buffered > MAX_BUFFERED {
+            let oldest = *self.partial.iter().min_by_key(|&(_, partial)| partial.started).expect("partial message").0;
+            self.drop_partial(&oldest);
+            self.incomplete += 1;
+        }
+        Ok(None)
+    }
+
+    fn drop_partial(&mut self, key: &(net::SocketAddr, net::SocketAddr, u32)) {
+        if let Some(partial) = self.partial.remove(key) {
+            self.buffered -= partial.size;
+        }
+    }
+
+    /// Gives up on messages whose first fragment came too long ago.
+    fn expire(&mut self, now: Instant) {
+        let before = self.partial.len();
+        let mut freed = 0;
+        self.partial.retain(|_, partial| {
+            let keep = now.duration_since(partial.started) < REASSEMBLY_TIMEOUT;
+            if !keep {
+                freed += partial.size;
+            }
+            keep
+        });
+        self.buffered -= freed;
+        self.incomplete += (before - self.partial.len()) as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    fn datagram(data: Vec<u8>) -> Datagram {
+        Datagram {
+            size: data.len(),
+            data,
+            src: "192.0.2.1:4000".parse().unwrap(),
+            group: "239.1.2.3:5000".parse().unwrap(),
+            dst: None,
+            ifindex: None,
+            ttl: None,
+            tos: None,
+            drops: None,
+            received: SystemTime::now(),
+            tag: None,
+        }
+    }
+
+    fn fragment(id: u32, index: u16, count: u16, payload: &[u8]) -> Datagram {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&id.to_be_bytes());
+        data.extend_from_slice(&index.to_be_bytes());
+        data.extend_from_slice(&count.to_be_bytes());
+        data.extend_from_slice(payload);
+        datagram(data)
+    }
+
+    #[test]
+    fn round_trip() {
+        let message: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let fragments = split(7, &message, 1400).unwrap();
+        assert_eq!(fragments.len(), 4);
+        assert!(fragments.iter().all(|fragment| fragment.len() <= 1400));
+        let mut reassembler = Reassembler::new();
+        let (last, rest) = fragments.split_last().unwrap();
+        for fragment in rest {
+            assert_eq!(reassembler.add(&datagram(fragment.clone())), Ok(None));
+        }
+        assert_eq!(reassembler.add(&datagram(last.clone())), Ok(Some(message)));
+        assert_eq!(reassembler.buffered, 0);
+
+        let empty = split(8, b"", 1400).unwrap();
+        assert_eq!(empty.len(), 1);
+        assert_eq!(reassembler.add(&datagram(empty[0].clone())), Ok(Some(Vec::new())));
+        // not a fragment
+        assert_eq!(reassembler.add(&datagram(b"plain".to_vec())), Ok(Some(b"plain".to_vec())));
+    }
+
+    #[test]
+    fn out_of_order_and_duplicates() {
+        let mut reassembler = Reassembler::new();
+        assert_eq!(reassembler.add(&fragment(1, 2, 3, b"c")), Ok(None));
+        assert_eq!(reassembler.add(&fragment(1, 0, 3, b"a")), Ok(None));
+        assert_eq!(reassembler.add(&fragment(1, 2, 3, b"c")), Ok(None));
+        assert_eq!(reassembler.add(&fragment(1, 1, 3, b"b")), Ok(Some(b"abc".to_vec())));
+        // a late duplicate starts the message over rather than repeating it
+        assert_eq!(reassembler.add(&fragment(1, 0, 3, b"a")), Ok(None));
+        assert_eq!(reassembler.incomplete(), 0);
+    }
+
+    #[test]
+    fn mismatched_count() {
+        let mut reassembler = Reassembler::new();
+        assert_eq!(reassembler.add(&fragment(1, 0, 3, b"a")), Ok(None));
+        assert!(reassembler.add(&fragment(1, 1, 4, b"b")).is_err());
+        assert!(reassembler.partial.is_empty());
+        assert_eq!(reassembler.buffered, 0);
+        assert_eq!(reassembler.add(&fragment(1, 0, 2, b"a")), Ok(None));
+        assert_eq!(reassembler.add(&fragment(1, 1, 2, b"b")), Ok(Some(b"ab".to_vec())));
+    }
+
+    #[test]
+    fn index_past_count() {
+        let mut reassembler = Reassembler::new();
+        assert!(reassembler.add(&fragment(1, 3, 3, b"a")).is_err());
+        assert!(reassembler.add(&fragment(1, 0, 0, b"a")).is_err());
+        assert!(reassembler.partial.is_empty());
+    }
+
+    #[test]
+    fn expiry() {
+        let mut reassembler = Reassembler::new();
+        assert_eq!(reassembler.add(&fragment(1, 0, 2, b"a")), Ok(None));
+        reassembler.expire(Instant::now() + REASSEMBLY_TIMEOUT / 2);
+        assert_eq!(reassembler.partial.len(), 1);
+        reassembler.expire(Instant::now() + REASSEMBLY_TIMEOUT);
+        assert!(reassembler.partial.is_empty());
+        assert_eq!(reassembler.buffered, 0);
+        assert_eq!(reassembler.incomplete(), 1);
+    }
+
+    #[test]
+    fn slots_count_towards_the_cap() {
+        let mut reassembler = Reassembler::new();
+        let sent = 100;
+        for id in 0..sent {
+            assert_eq!(reassembler.add(&fragment(id, 0, u16::MAX, b"")), Ok(None));
+            assert!(reassembler.buffered <= MAX_BUFFERED);
+        }
+        let open = reassembler.partial.len() as u64;
+        assert!(open < sent as u64);
+        assert_eq!(reassembler.incomplete(), sent as u64 - open);
+        // the oldest were given up on
+        assert!(reassembler.partial.keys().all(|&(_, _, id)| id as u64 >= sent as u64 - open));
+    }
+}
@@ -0,0 +1,534 @@
+use std::{fmt, io, net};
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use auth::Key;
+use histogram::Histogram;
+use listen::Datagram;
+use loss::{LossCounts, LossEvent, LossTracker, SequenceExtractor};
+use rate::{Pacer, Rate};
+use socket::MulticastSocket;
+use sockopt;
+use tag;
+
+/// Marks the start of a generated datagram.
+pub const MAGIC: &[u8] = b"MCGN";
+
+/// Magic, 64-bit sequence number and 64-bit send time in nanoseconds since
+/// the Unix epoch, all big-endian.
+pub const HEADER_LEN: usize = 20;
+
+/// The sequence number and send time embedded in a generated datagram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+    pub seqnum: u64,
+    pub sent: SystemTime,
+}
+
+impl Stamp {
+    /// Writes the header into the start of `buf`, which must be at least
+    /// `HEADER_LEN` bytes long.
+    pub fn encode(&self, buf: &mut [u8]) {
+        let nanos = self.sent.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        buf[0..4].copy_from_slice(MAGIC);
+        buf[4..12].copy_from_slice(&self.seqnum.to_be_bytes());
+        buf[12..20].copy_from_slice(&nanos.to_be_bytes());
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Stamp> {
+        if data.len() < HEADER_LEN || !data.starts_with(MAGIC) {
+            return None;
+        }
+        let mut seqnum = [0u8; 8];
+        seqnum.copy_from_slice(&data[4..12]);
+        let mut nanos = [0u8; 8];
+        nanos.copy_from_slice(&data[12..20]);
+        Some(Stamp {
+            seqnum: u64::from_be_bytes(seqnum),
+            sent: UNIX_EPOCH + Duration::from_nanos(u64::from_be_bytes(nanos)),
+        })
+    }
+}
+
+/// Sequence numbers of generated datagrams.
+pub struct GeneratedSequence;
+
+impl SequenceExtractor for GeneratedSequence {
+    fn extract(&self, data: &[u8]) -> Option<u64> {
+        Stamp::decode(tag::payload(data)).map(|stamp| stamp.seqnum)
+    }
+}
+
+/// Iterator that sends stamped datagrams of a fixed size, yielding the
+/// number of bytes sent.
+pub struct Generator {
+    sock: net::UdpSocket,
+    /// Sockets on other interfaces that send every datagram as well.
+    also: Vec<net::UdpSocket>,
+    buf: Vec<u8>,
+    seqnum: u64,
+    pacer: Option<Pacer>,
+    key: Option<Key>,
+    /// Header naming this sender, in front of the stamp.
+    tag: Vec<u8>,
+    count: Option<u64>,
+    duration: Option<Duration>,
+    started: Option<Instant>,
+    /// Datagrams sent per system call, at most.
+    batch: usize,
+    bufs: Vec<Vec<u8>>,
+    /// Datagrams of the last batch not yet yielded.
+    pending: usize,
+}
+
+pub fn generate(config: &MulticastSocket, size: usize) -> io::Result<Generator> {
+    if size < HEADER_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("datagrams must be at least {} bytes", HEADER_LEN)));
+    }
+    let sock = config.sender()?;
+    sock.connect(config.target()?)?;
+    Ok(Generator {
+        sock,
+        also: Vec::new(),
+        buf: vec![0u8; size],
+        seqnum: 0,
+        pacer: None,
+        key: None,
+        tag: Vec::new(),
+        count: None,
+        duration: None,
+        started: None,
+        batch: 1,
+        bufs: Vec::new(),
+        pending: 0,
+    })
+}
+
+impl Generator {
+    /// Limits how fast datagrams are sent; unlimited by default.
+    pub fn rate(&mut self, rate: Rate) -> &mut Generator {
+        self.pacer = Some(Pacer::new(rate));
+        self
+    }
+
+    /// Ends each datagram with an HMAC tag in place of padding; the size
+    /// must leave room for it after the header.
+    pub fn key(&mut self, key: Key) -> &mut Generator {
+        self.key = Some(key);
+        self
+    }
+
+    /// Puts a header naming this sender in front of each datagram; the size
+    /// must leave room for it before the stamp.
+    pub fn tag(&mut self, tag: &str) -> &mut Generator {
+        self.tag = tag::header(tag);
+        self.buf[..self.tag.len()].copy_from_slice(&self.tag);
+        self
+    }
+
+    /// Stops after sending `count` datagrams.
+    pub fn packet_count(&mut self, count: u64) -> &mut Generator {
+        self.count = Some(count);
+        self
+    }
+
+    /// Stops once `duration` has passed since the first datagram.
+    pub fn duration(&mut self, duration: Duration) -> &mut Generator {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Sends up to `size` datagrams per system call with `sendmmsg`, where
+    /// available. Only datagrams already due under the rate join a batch.
+    pub fn batch(&mut self, size: usize) -> &mut Generator {
+        self.batch = size.max(1);
+        self
+    }
+
+    /// Number of datagrams sent so far.
+    pub fn sent(&self) -> u64 {
+        self.seqnum
+    }
+
+    /// Sends every datagram through a socket set up by `config` as well,
+    /// normally the same group on another interface, as over the redundant
+    /// paths of SMPTE 2022-7. Both copies carry the same sequence number.
+    pub fn also_via(&mut self, config: &MulticastSocket) -> io::Result<&mut Generator> {
+        let sock = config.sender()?;
+        sock.connect(config.target()?)?;
+        self.also.push(sock);
+        Ok(self)
+    }
+
+    pub fn socket(&self) -> &net::UdpSocket {
+        &self.sock
+    }
+
+    fn finished(&mut self) -> bool {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        self.count.is_some_and(|count| self.seqnum >= count) ||
+        self.duration.is_some_and(|duration| started.elapsed() >= duration)
+    }
+
+    fn send_batch(&mut self) -> io::Result<usize> {
+        let len = self.buf.len();
+        if self.bufs.len() != self.batch {
+            self.bufs = vec![vec![0u8; len]; self.batch];
+        }
+        let mut filled = 0;
+        while filled < self.batch && (filled == 0 || !self.finished()) {
+            if let Some(ref mut pacer) = self.pacer {
+                // the first datagram waits its turn; the rest must be due
+                if filled == 0 {
+                    pacer.wait(len);
+                } else if !pacer.try_wait(len) {
+                    break;
+                }
+            }
+            self.seqnum += 1;
+            let buf = &mut self.bufs[filled];
+            buf[..self.tag.len()].copy_from_slice(&self.tag);
+            Stamp { seqnum: self.seqnum, sent: SystemTime::now() }.encode(&mut buf[self.tag.len()..]);
+            if let Some(ref key) = self.key {
+                key.sign_in_place(&mut self.bufs[filled]);
+            }
+            filled += 1;
+        }
+        let bufs: Vec<&[u8]> = self.bufs[..filled].iter().map(|buf| &buf[..]).collect();
+        for sock in &self.also {
+            sockopt::send_mmsg(sock, &bufs)?;
+        }
+        sockopt::send_mmsg(&self.sock, &bufs)?;
+        self.pending = filled - 1;
+        Ok(len)
+    }
+}
+
+impl Iterator for Generator {
+    type Item = io::Result<usize>;
+
+    fn next(&mut self) -> Option<io::Result<usize>> {
+        if self.pending > 0 {
+            self.pending -= 1;
+            return Some(Ok(self.buf.len()));
+        }
+        if self.finished() {
+            return None;
+        }
+        if self.batch > 1 {
+            return Some(self.send_batch());
+        }
+        if let Some(ref mut pacer) = self.pacer {
+            pacer.wait(self.buf.len());
+        }
+        self.seqnum += 1;
+        Stamp { seqnum: self.seqnum, sent: SystemTime::now() }.encode(&mut self.buf[self.tag.len()..]);
+        if let Some(ref key) = self.key {
+            key.sign_in_place(&mut self.buf);
+        }
+        for sock in &self.also {
+            if let Err(err) = sock.send(&self.buf) {
+                return Some(Err(err));
+            }
+        }
+        Some(self.sock.send(&self.buf))
+    }
+}
+
+/// Throughput, loss and jitter of one generated stream over some period.
+#[derive(Debug, Clone)]
+pub struct StreamReport {
+    pub src: net::SocketAddr,
+    /// The sender's tag, if generate --tag put one on its datagrams.
+    pub tag: Option<String>,
+    pub group: net::SocketAddr,
+    pub period: Duration,
+    pub counts: LossCounts,
+    pub bytes: u64,
+    /// RFC 3550 interarrival jitter at the end of the period.
+    pub jitter: Duration,
+    pub delay: Option<DelayReport>,
+}
+
+impl StreamReport {
+    pub fn bits_per_second(&self) -> f64 {
+        let secs = self.period.as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 * 8.0 / secs } else { 0.0 }
+    }
+
+    pub fn loss_percent(&self) -> f64 {
+        let expected = self.counts.received - self.counts.duplicated + self.counts.missing;
+        if expected > 0 { self.counts.missing as f64 * 100.0 / expected as f64 } else { 0.0 }
+    }
+}
+
+impl fmt::Display for StreamReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.src)?;
+        if let Some(ref tag) = self.tag {
+            write!(f, " ({})", tag)?;
+        }
+        write!(f,
+               " to {}: {:.1} s, {} packets, {:.3} Mbit/s, lost {} ({:.2}%), jitter {:.3} ms, \
+                reordered {}{}, duplicated {}",
+               self.group,
+               self.period.as_secs_f64(),
+               self.counts.received,
+               self.bits_per_second() / 1e6,
+               self.counts.missing,
+               self.loss_percent(),
+               self.jitter.as_secs_f64() * 1e3,
+               self.counts.reordered,
+               self.counts.reordering(),
+               self.counts.duplicated)?;
+        if let Some(ref delay) = self.delay {
+            write!(f, "\n  {}", delay)?;
+        }
+        Ok(())
+    }
+}
+
+/// Percentiles of one-way delay, from the send times embedded by
+/// `generate`. In seconds, and negative if the clocks disagree.
+#[derive(Debug, Clone, Copy)]
+pub struct DelayReport {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub p999: f64,
+    pub max: f64,
+    /// How far the receiver's clock is ahead of the sender's, plus the
+    /// smallest delay, when the clocks are not assumed to be synced. The
+    /// percentiles are then delays beyond the smallest.
+    pub offset: Option<f64>,
+}
+
+impl fmt::Display for DelayReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = if self.offset.is_some() { "delay above the fastest" } else { "one-way delay" };
+        write!(f,
+               "{} p50/p90/p99/p99.9/max = {:.3}/{:.3}/{:.3}/{:.3}/{:.3} ms",
+               label,
+               self.p50 * 1e3,
+               self.p90 * 1e3,
+               self.p99 * 1e3,
+               self.p999 * 1e3,
+               self.max * 1e3)?;
+        if let Some(offset) = self.offset {
+            write!(f, ", clock offset plus base delay {:.3} ms", offset * 1e3)?;
+        }
+        Ok(())
+    }
+}
+
+fn delay_report(delays: &Histogram, shift: f64, base: Option<f64>) -> Option<DelayReport> {
+    if delays.count() == 0 {
+        return None;
+    }
+    Some(DelayReport {
+        p50: delays.percentile(50.0) + shift,
+        p90: delays.percentile(90.0) + shift,
+        p99: delays.percentile(99.0) + shift,
+        p999: delays.percentile(99.9) + shift,
+        max: delays.max() + shift,
+        offset: base,
+    })
+}
+
+fn counts_since(now: &LossCounts, then: &LossCounts) -> LossCounts {
+    LossCounts {
+        received: now.received.saturating_sub(then.received),
+        missing: now.missing.saturating_sub(then.missing),
+        duplicated: now.duplicated.saturating_sub(then.duplicated),
+        reordered: now.reordered.saturating_sub(then.reordered),
+        extent_total: now.extent_total.saturating_sub(then.extent_total),
+        // the caller knows the largest within the interval
+        extent_max: 0,
+    }
+}
+
+#[derive(Debug)]
+struct Stream {
+    tag: Option<String>,
+    first: Instant,
+    last: Instant,
+    bytes: u64,
+    transit: Option<f64>,
+    jitter: f64,
+    /// Receive minus send time of the first datagram, in seconds, which
+    /// the delay histograms count from.
+    reference: Option<f64>,
+    delays: Histogram,
+    // counters at the start of the current interval
+    interval_start: Instant,
+    interval_bytes: u64,
+    interval_counts: LossCounts,
+    interval_extent_max: u64,
+    interval_delays: Histogram,
+}
+
+/// Receiver side of `generate`: measures each generated stream arriving on
+/// the joined groups.
+pub struct Measurement {
+    loss: LossTracker,
+    streams: HashMap<(net::SocketAddr, net::SocketAddr), Stream>,
+    delays: bool,
+    synced_clocks: bool,
+}
+
+impl Default for Measurement {
+    fn default() -> Measurement {
+        Measurement::new()
+    }
+}
+
+impl Measurement {
+    pub fn new() -> Measurement {
+        Measurement {
+            loss: LossTracker::new(Box::new(GeneratedSequence)),
+            streams: HashMap::new(),
+            delays: false,
+            synced_clocks: false,
+        }
+    }
+
+    /// Reports percentiles of one-way delay, keeping the delay of every
+    /// datagram to do so. Unless `synced_clocks` is set, the clock offset
+    /// can't be told apart from the delay, so delays are reported beyond
+    /// the smallest seen.
+    pub fn one_way_delay(&mut self, synced_clocks: bool) -> &mut Measurement {
+        self.delays = true;
+        self.synced_clocks = synced_clocks;
+        self
+    }
+
+    /// Records a datagram, returning false if it was not generated by mccat.
+    pub fn record(&mut self, datagram: &Datagram) -> bool {
+        let (tag, data) = match tag::untag(&datagram.data) {
+            Some((tag, data)) => (Some(tag), data),
+            None => (None, &datagram.data[..]),
+        };
+        let stamp = match Stamp::decode(data) {
+            Some(stamp) => stamp,
+            None => return false,
+        };
+        let event = self.loss.track(datagram);
+        let now = Instant::now();
+        let stream = self.streams.entry((datagram.src, datagram.group)).or_insert_with(|| {
+            Stream {
+                tag: tag.map(str::to_owned),
+                first: now,
+                last: now,
+                bytes: 0,
+                transit: None,
+                jitter: 0.0,
+                reference: None,
+                delays: Histogram::new(),
+                interval_start: now,
+                interval_bytes: 0,
+                interval_counts: LossCounts::default(),
+                interval_extent_max: 0,
+                interval_delays: Histogram::new(),
+            }
+        });
+        if let Some(LossEvent::Reordered { extent, .. }) = event {
+            stream.interval_extent_max = stream.interval_extent_max.max(extent);
+        }
+        stream.last = now;
+        stream.bytes += datagram.size as u64;
+        // the clock offset between sender and receiver cancels out
+        let transit = match datagram.received.duration_since(stamp.sent) {
+            Ok(ahead) => ahead.as_secs_f64(),
+            Err(behind) => -behind.duration().as_secs_f64(),
+        };
+        if let Some(previous) = stream.transit {
+            stream.jitter += ((transit - previous).abs() - stream.jitter) / 16.0;
+        }
+        stream.transit = Some(transit);
+        if self.delays {
+            let nanos = ((transit - *stream.reference.get_or_insert(transit)) * 1e9) as i64;
+            stream.delays.record_nanos(nanos);
+            stream.interval_delays.record_nanos(nanos);
+        }
+        true
+    }
+
+    fn report(&self, key: &(net::SocketAddr, net::SocketAddr), stream: &Stream, since_start: bool) -> StreamReport {
+        let counts = self.loss.sources()
+            .into_iter()
+            .find(|&(src, group, _)| (src, group) == *key)
+            .map(|(_, _, counts)| counts)
+            .unwrap_or_default();
+        let (period, counts, bytes, delays) = if since_start {
+            (stream.last - stream.first, counts, stream.bytes, &stream.delays)
+        } else {
+            (stream.interval_start.elapsed(),
+             LossCounts { extent_max: stream.interval_extent_max, ..counts_since(&counts, &stream.interval_counts) },
+             stream.bytes - stream.interval_bytes,
+             &stream.interval_delays)
+        };
+        let (shift, base) = self.delay_shift(stream);
+        StreamReport {
+            src: key.0,
+            tag: stream.tag.clone(),
+            group: key.1,
+            period,
+            counts,
+            bytes,
+            jitter: Duration::from_secs_f64(stream.jitter),
+            delay: delay_report(delays, shift, base),
+        }
+    }
+
+    /// Reports each stream since the previous call and starts a new
+    /// interval.
+    pub fn interval(&mut self) -> Vec<StreamReport> {
+        let mut keys: Vec<_> = self.streams.keys().cloned().collect();
+        keys.sort();
+        let reports: Vec<_> = keys.iter().map(|key| self.report(key, &self.streams[key], false)).collect();
+        let now = Instant::now();
+        for (src, group, counts) in self.loss.sources() {
+            if let Some(stream) = self.streams.get_mut(&(src, group)) {
+                stream.interval_start = now;
+                stream.interval_bytes = stream.bytes;
+                stream.interval_counts = counts;
+                stream.interval_extent_max = 0;
+                stream.interval_delays = Histogram::new();
+            }
+        }
+        reports
+    }
+
+    /// What to add to a stream's histogram values to get delays, and the
+    /// clock offset plus base delay taken off them when clocks aren't
+    /// synced.
+    fn delay_shift(&self, stream: &Stream) -> (f64, Option<f64>) {
+        let reference = stream.reference.unwrap_or_default();
+        if self.synced_clocks {
+            (reference, None)
+        } else {
+            (-stream.delays.min(), Some(reference + stream.delays.min()))
+        }
+    }
+
+    /// A chart of each stream's one-way delays, with its source and group.
+    pub fn delay_charts(&self) -> Vec<(net::SocketAddr, net::SocketAddr, String)> {
+        let mut keys: Vec<_> = self.streams.keys().cloned().collect();
+        keys.sort();
+        keys.iter()
+            .filter(|key| self.streams[key].delays.count() > 0)
+            .map(|key| {
+                let stream = &self.streams[key];
+                (key.0, key.1, stream.delays.chart(-self.delay_shift(stream).0))
+            })
+            .collect()
+    }
+
+    /// Reports each stream from its first datagram to its last.
+    pub fn summary(&self) -> Vec<StreamReport> {
+        let mut keys: Vec<_> = self.streams.keys().cloned().collect();
+        keys.sort();
+        keys.iter().map(|key| self.report(key, &self.streams[key], true)).collect()
+    }
+}
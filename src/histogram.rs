@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+/// Values below this are counted exactly; above it, each power of two is
+/// split into this many buckets, keeping values within 1%.
+const SUB_BUCKETS: u64 = 128;
+/// Rows in a chart, plus one for the tail beyond p99.9.
+const CHART_ROWS: usize = 10;
+const CHART_WIDTH: usize = 40;
+
+fn index(magnitude: u64) -> usize {
+    if magnitude < SUB_BUCKETS * 2 {
+        return magnitude as usize;
+    }
+    let shift = u64::from(63 - magnitude.leading_zeros()) - 7;
+    (SUB_BUCKETS * shift + (magnitude >> shift)) as usize
+}
+
+/// The middle of the values counted in a bucket.
+fn magnitude(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS * 2 {
+        return index;
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let low = (index - SUB_BUCKETS * shift) << shift;
+    low + (1 << shift) / 2
+}
+
+/// Counts of latencies in nanoseconds in log-linear buckets, HDR
+/// histogram style, so percentiles of long runs take little memory.
+/// Negative values are kept too, for delays between unsynced clocks.
+#[derive(Debug, Default, Clone)]
+pub struct Histogram {
+    positive: Vec<u64>,
+    negative: Vec<u64>,
+    count: u64,
+    min: i64,
+    max: i64,
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram::default()
+    }
+
+    pub fn record(&mut self, value: Duration) {
+        self.record_nanos(value.as_nanos().min(i64::MAX as u128) as i64);
+    }
+
+    pub fn record_nanos(&mut self, nanos: i64) {
+        let buckets = if nanos < 0 { &mut self.negative } else { &mut self.positive };
+        let i = index(nanos.unsigned_abs());
+        if buckets.len() <= i {
+            buckets.resize(i + 1, 0);
+        }
+        buckets[i] += 1;
+        if self.count == 0 || nanos < self.min {
+            self.min = nanos;
+        }
+        if self.count == 0 || nanos > self.max {
+            self.max = nanos;
+        }
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Smallest value recorded, exactly, in seconds.
+    pub fn min(&self) -> f64 {
+        self.min as f64 / 1e9
+    }
+
+    /// Largest value recorded, exactly, in seconds.
+    pub fn max(&self) -> f64 {
+        self.max as f64 / 1e9
+    }
+
+    /// Bucket values in nanoseconds and their counts, smallest first.
+    fn buckets(&self) -> impl Iterator<Item = (i64, u64)> + '_ {
+        let negative = self.negative.iter().enumerate().rev().map(|(i, &count)| (-(magnitude(i) as i64), count));
+        let positive = self.positive.iter().enumerate().map(|(i, &count)| (magnitude(i) as i64, count));
+        negative.chain(positive).filter(|&(_, count)| count > 0)
+    }
+
+    /// The `p`th percentile in seconds, by nearest rank.
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = ((p / 100.0 * self.count as f64).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (value, count) in self.buckets() {
+            seen += count;
+            if seen >= rank {
+                // the bucket's middle can lie past what was recorded
+                return value.clamp(self.min, self.max) as f64 / 1e9;
+            }
+        }
+        self.max()
+    }
+
+    /// An ASCII bar chart in milliseconds, `offset` seconds subtracted
+    /// from every value: equal-width rows up to p99.9 and a row for the
+    /// rest.
+    pub fn chart(&self, offset: f64) -> String {
+        let mut chart = String::new();
+        if self.count == 0 {
+            return chart;
+        }
+        let low = self.min();
+        let high = self.percentile(99.9).max(low);
+        let width = (high - low) / CHART_ROWS as f64;
+        let mut rows = [0u64; CHART_ROWS + 1];
+        for (value, count) in self.buckets() {
+            let value = value.clamp(self.min, self.max) as f64 / 1e9;
+            let row = if value > high {
+                CHART_ROWS
+            } else if width > 0.0 {
+                (((value - low) / width) as usize).min(CHART_ROWS - 1)
+            } else {
+                0
+            };
+            rows[row] += count;
+        }
+        let most = rows.iter().cloned().max().unwrap_or(1).max(1);
+        let ms = |value: f64| (value - offset) * 1e3;
+        for (row, &count) in rows.iter().enumerate() {
+            let (start, end) = if row < CHART_ROWS {
+                (low + width * row as f64, low + width * (row + 1) as f64)
+            } else if count > 0 {
+                (high, self.max())
+            } else {
+                continue;
+            };
+            let bar = "#".repeat((count * CHART_WIDTH as u64).div_ceil(most) as usize);
+            chart.push_str(&format!("{:>10.3} - {:>10.3} ms |{:<w$}| {}\n",
+                                    ms(start),
+                                    ms(end),
+                                    bar,
+                                    count,
+                                    w = CHART_WIDTH));
+        }
+        chart
+    }
+}
@@ -0,0 +1,291 @@
+use std::{fmt, fs, io, net, ptr, str};
+use std::ffi::{CStr, CString};
+
+use libc;
+
+enum Spec<'a> {
+    Addr(net::IpAddr),
+    Index(u32),
+    Name(&'a str),
+}
+
+fn parse_spec<'a>(spec: &'a str) -> Spec<'a> {
+    if let Ok(addr) = spec.parse() {
+        Spec::Addr(addr)
+    } else if let Ok(index) = spec.parse() {
+        Spec::Index(index)
+    } else {
+        Spec::Name(spec)
+    }
+}
+
+fn not_found(spec: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no such interface: {}", spec))
+}
+
+/// Lists every (interface name, address) pair configured on the host.
+pub fn addresses() -> io::Result<Vec<(String, net::IpAddr)>> {
+    let mut ifap: *mut libc::ifaddrs = ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifap) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut result = Vec::new();
+    let mut cur = ifap;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) }.to_string_lossy().into_owned();
+        let addr = match i32::from(unsafe { (*ifa.ifa_addr).sa_family }) {
+            libc::AF_INET => {
+                let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+                net::IpAddr::V4(net::Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in6) };
+                net::IpAddr::V6(net::Ipv6Addr::from(sin6.sin6_addr.s6_addr))
+            }
+            _ => continue,
+        };
+        result.push((name, addr));
+    }
+    unsafe { libc::freeifaddrs(ifap) };
+    Ok(result)
+}
+
+pub fn name_to_index(name: &str) -> io::Result<u32> {
+    let cname = CString::new(name).map_err(|_| not_found(name))?;
+    match unsafe { libc::if_nametoindex(cname.as_ptr()) } {
+        0 => Err(not_found(name)),
+        index => Ok(index),
+    }
+}
+
+pub fn index_to_name(index: u32) -> io::Result<String> {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    let ret = unsafe { libc::if_indextoname(index, buf.as_mut_ptr()) };
+    if ret.is_null() {
+        return Err(not_found(&index.to_string()));
+    }
+    Ok(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned())
+}
+
+fn name_of_addr(addr: net::IpAddr) -> io::Result<String> {
+    addresses()?
+        .into_iter()
+        .find(|&(_, a)| a == addr)
+        .map(|(name, _)| name)
+        .ok_or_else(|| not_found(&addr.to_string()))
+}
+
+/// An address with an optional IPv6 zone naming the interface it is on,
+/// e.g. `ff02::1%eth0` or `ff02::1%2`. Link-local groups exist once per
+/// link, so on a host with several the zone says which one is meant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopedAddr {
+    pub addr: net::IpAddr,
+    /// An interface name or index.
+    pub zone: Option<String>,
+}
+
+impl ScopedAddr {
+    /// Parses `ADDRESS:PORT`, with an IPv6 address in brackets and
+    /// optionally zoned, e.g. `[ff02::fb%eth0]:5353`.
+    pub fn parse_with_port(s: &str) -> Result<(ScopedAddr, u16), String> {
+        let invalid = || format!("invalid address {}: expected ADDRESS:PORT, e.g. [ff02::1%eth0]:5000", s);
+        let colon = s.rfind(':').ok_or_else(invalid)?;
+        let port = s[colon + 1..].parse().map_err(|_| invalid())?;
+        let addr = &s[..colon];
+        let addr = match addr.strip_prefix('[').and_then(|addr| addr.strip_suffix(']')) {
+            Some(addr) => addr.parse::<ScopedAddr>().ok().filter(|addr| addr.addr.is_ipv6()),
+            None => addr.parse().ok().map(|addr: net::Ipv4Addr| ScopedAddr::from(net::IpAddr::V4(addr))),
+        };
+        addr.map(|addr| (addr, port)).ok_or_else(invalid)
+    }
+}
+
+impl From<net::IpAddr> for ScopedAddr {
+    fn from(addr: net::IpAddr) -> ScopedAddr {
+        ScopedAddr { addr, zone: None }
+    }
+}
+
+impl str::FromStr for ScopedAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ScopedAddr, String> {
+        let (addr, zone) = match s.find('%') {
+            Some(percent) => (&s[..percent], Some(&s[percent + 1..])),
+            None => (s, None),
+        };
+        let addr: net::IpAddr = addr.parse().map_err(|_| format!("invalid address {}", s))?;
+        match zone {
+            Some(_) if addr.is_ipv4() => Err(format!("invalid address {}: only IPv6 addresses take a zone", s)),
+            Some("") => Err(format!("invalid address {}: expected an interface after %", s)),
+            zone => {
+                Ok(ScopedAddr {
+                    addr,
+                    zone: zone.map(str::to_owned),
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for ScopedAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.zone {
+            Some(ref zone) => write!(f, "{}%{}", self.addr, zone),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
+
+/// Resolves an interface name, index or address to the local IPv4 address
+/// used for IPv4 joins and `IP_MULTICAST_IF`.
+pub fn resolve_v4(spec: &str) -> io::Result<net::Ipv4Addr> {
+    let name = match parse_spec(spec) {
+        Spec::Addr(net::IpAddr::V4(addr)) => return Ok(addr),
+        Spec::Addr(net::IpAddr::V6(_)) => {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("{} is not an IPv4 interface address", spec)))
+        }
+        Spec::Index(index) => index_to_name(index)?,
+        Spec::Name(name) => name.to_owned(),
+    };
+    addresses()?
+        .into_iter()
+        .filter_map(|(n, addr)| match addr {
+            net::IpAddr::V4(addr) if n == name => Some(addr),
+            _ => None,
+        })
+        .next()
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound,
+                           format!("interface {} has no IPv4 address", spec))
+        })
+}
+
+/// Resolves an interface name, index or address to the interface index
+/// used for IPv6 joins and `IPV6_MULTICAST_IF`.
+pub fn resolve_v6(spec: &str) -> io::Result<u32> {
+    match parse_spec(spec) {
+        Spec::Addr(addr) => name_to_index(&name_of_addr(addr)?),
+        Spec::Index(index) => index_to_name(index).map(|_| index),
+        Spec::Name(name) => name_to_index(name),
+    }
+}
+
+/// A network interface with its addresses and multicast state.
+#[derive(Debug, Clone)]
+pub struct Interface {
+    pub name: String,
+    pub index: u32,
+    pub up: bool,
+    pub loopback: bool,
+    pub multicast: bool,
+    pub addresses: Vec<net::IpAddr>,
+    /// Groups currently joined on the interface by any process.
+    pub groups: Vec<net::IpAddr>,
+}
+
+/// Lists the host's interfaces ordered by index.
+pub fn interfaces() -> io::Result<Vec<Interface>> {
+    let mut ifap: *mut libc::ifaddrs = ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifap) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut result: Vec<Interface> = Vec::new();
+    let mut cur = ifap;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) }.to_string_lossy().into_owned();
+        if !result.iter().any(|iface| iface.name == name) {
+            let flags = ifa.ifa_flags as libc::c_int;
+            result.push(Interface {
+                index: name_to_index(&name).unwrap_or(0),
+                name,
+                up: flags & libc::IFF_UP != 0,
+                loopback: flags & libc::IFF_LOOPBACK != 0,
+                multicast: flags & libc::IFF_MULTICAST != 0,
+                addresses: Vec::new(),
+                groups: Vec::new(),
+            });
+        }
+    }
+    unsafe { libc::freeifaddrs(ifap) };
+    for (name, addr) in addresses()? {
+        if let Some(iface) = result.iter_mut().find(|iface| iface.name == name) {
+            iface.addresses.push(addr);
+        }
+    }
+    for (name, group) in joined_groups()? {
+        if let Some(iface) = result.iter_mut().find(|iface| iface.name == name) {
+            iface.groups.push(group);
+        }
+    }
+    result.sort_by_key(|iface| iface.index);
+    Ok(result)
+}
+
+fn read_proc(path: &str) -> io::Result<String> {
+    match fs::read_to_string(path) {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        result => result,
+    }
+}
+
+/// Lists (interface name, group) memberships from /proc/net/igmp and
+/// /proc/net/igmp6. Empty where those files don't exist.
+pub fn joined_groups() -> io::Result<Vec<(String, net::IpAddr)>> {
+    let mut groups = Vec::new();
+    // "<idx>\t<name> : <count> <querier>" lines, each followed by indented
+    // "<group> <users> <timer> <reporter>" lines
+    let mut device: Option<String> = None;
+    for line in read_proc("/proc/net/igmp")?.lines().skip(1) {
+        let mut fields = line.split_whitespace();
+        if line.starts_with(char::is_whitespace) {
+            let group = fields.next().and_then(|hex| u32::from_str_radix(hex, 16).ok());
+            if let (Some(ref name), Some(group)) = (&device, group) {
+                // printed as the raw network order word
+                groups.push((name.clone(), net::IpAddr::V4(net::Ipv4Addr::from(group.to_ne_bytes()))));
+            }
+        } else {
+            device = fields.nth(1).map(|name| name.to_owned());
+        }
+    }
+    // "<idx> <name> <group hex> <users> <flags> <timer>"
+    for line in read_proc("/proc/net/igmp6")?.lines() {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        if fields.len() < 3 || fields[2].len() != 32 {
+            continue;
+        }
+        let group = u128::from_str_radix(fields[2], 16).map(net::Ipv6Addr::from);
+        if let Ok(group) = group {
+            groups.push((fields[1].to_owned(), net::IpAddr::V6(group)));
+        }
+    }
+    Ok(groups)
+}
+
+/// Names the system limit behind `err`, from joining a group, with its
+/// current setting where there is one: the memberships a socket may hold,
+/// the option memory they take for IPv6, or the open files a process may
+/// have.
+pub fn membership_limit(err: &io::Error, ipv6: bool) -> Option<String> {
+    let setting = match err.raw_os_error() {
+        Some(libc::ENOBUFS) | Some(libc::ENOMEM) if ipv6 => "net/core/optmem_max",
+        Some(libc::ENOBUFS) => "net/ipv4/igmp_max_memberships",
+        Some(libc::EMFILE) => return Some("the limit of open files per process (ulimit -n)".to_owned()),
+        _ => return None,
+    };
+    let value = read_proc(&format!("/proc/sys/{}", setting)).ok().filter(|value| !value.is_empty());
+    let name = setting.replace('/', ".");
+    Some(match value {
+        Some(value) => format!("{} = {}", name, value.trim()),
+        None => name,
+    })
+}
@@ -0,0 +1,801 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, io, net};
+
+use libc;
+
+use capture::{Capture, ETH_P_IP, ETH_P_IPV6};
+use pcap;
+use sockopt;
+
+const IGMP_QUERY: u8 = 0x11;
+const IGMP_V1_REPORT: u8 = 0x12;
+const IGMP_V2_REPORT: u8 = 0x16;
+const IGMP_LEAVE: u8 = 0x17;
+const IGMP_V3_REPORT: u8 = 0x22;
+const MLD_QUERY: u8 = 130;
+const MLD_V1_REPORT: u8 = 131;
+const MLD_DONE: u8 = 132;
+const MLD_V2_REPORT: u8 = 143;
+
+const IPPROTO_IGMP: u8 = 2;
+const IPPROTO_HOPOPTS: u8 = 0;
+const IPPROTO_ICMPV6: u8 = 58;
+
+/// How many times messages are sent to make up for loss, and what queries
+/// tell hosts to do.
+pub const ROBUSTNESS: u32 = 2;
+
+/// How long a querier goes unheard before others take over: the other
+/// querier present interval with the default robustness and intervals.
+pub const QUERIER_TIMEOUT: Duration = Duration::from_secs(255);
+
+/// The membership protocol and version a message belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Igmp(u8),
+    Mld(u8),
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Protocol::Igmp(version) => write!(f, "IGMPv{}", version),
+            Protocol::Mld(version) => write!(f, "MLDv{}", version),
+        }
+    }
+}
+
+/// The kind of an IGMPv3 or MLDv2 group record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    /// The current state, answering a query: members for the sources listed.
+    IsInclude,
+    /// The current state, answering a query: members for all but the sources listed.
+    IsExclude,
+    ToInclude,
+    ToExclude,
+    Allow,
+    Block,
+}
+
+/// A group record of an IGMPv3 or MLDv2 report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub kind: RecordKind,
+    pub group: net::IpAddr,
+    pub sources: Vec<net::IpAddr>,
+}
+
+fn list(sources: &[net::IpAddr]) -> String {
+    sources.iter().map(|source| source.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+impl fmt::Display for Record {
+    /// What the record means for the group: a join, a leave or the state
+    /// of a membership.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (group, sources) = (self.group, list(&self.sources));
+        match (self.kind, self.sources.is_empty()) {
+            (RecordKind::IsInclude, true) => write!(f, "not a member of {}", group),
+            (RecordKind::IsInclude, false) => write!(f, "member of {} from {}", group, sources),
+            (RecordKind::IsExclude, true) => write!(f, "member of {}", group),
+            (RecordKind::IsExclude, false) => write!(f, "member of {} except from {}", group, sources),
+            (RecordKind::ToInclude, true) => write!(f, "leave {}", group),
+            (RecordKind::ToInclude, false) => write!(f, "join {} from {} only", group, sources),
+            (RecordKind::ToExclude, true) => write!(f, "join {}", group),
+            (RecordKind::ToExclude, false) => write!(f, "join {} except from {}", group, sources),
+            (RecordKind::Allow, _) => write!(f, "join {} from {}", group, sources),
+            (RecordKind::Block, _) => write!(f, "leave {} from {}", group, sources),
+        }
+    }
+}
+
+/// An IGMP or MLD message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// A querier asking who is a member of any group or only of `group`,
+    /// and of that only for `sources` if any are given.
+    Query {
+        group: Option<net::IpAddr>,
+        sources: Vec<net::IpAddr>,
+        max_response: Duration,
+    },
+    /// An IGMPv1/v2 or MLDv1 membership report.
+    Report { group: net::IpAddr },
+    /// An IGMPv2 leave or MLDv1 done.
+    Leave { group: net::IpAddr },
+    /// An IGMPv3 or MLDv2 membership report.
+    Records(Vec<Record>),
+}
+
+/// An IGMP or MLD message and the addresses it was sent between.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub src: net::IpAddr,
+    pub dst: net::IpAddr,
+    pub protocol: Protocol,
+    pub message: Message,
+}
+
+impl Packet {
+    /// Parses an IPv4 packet carrying IGMP, `None` if it doesn't.
+    pub fn parse_v4(packet: &[u8]) -> Option<Packet> {
+        if packet.len() < 20 || packet[0] >> 4 != 4 || packet[9] != IPPROTO_IGMP {
+            return None;
+        }
+        let header_len = usize::from(packet[0] & 0x0f) * 4;
+        let total_len = usize::from(u16::from_be_bytes([packet[2], packet[3]])).min(packet.len());
+        let src = net::IpAddr::from(<[u8; 4]>::try_from(&packet[12..16]).ok()?);
+        let dst = net::IpAddr::from(<[u8; 4]>::try_from(&packet[16..20]).ok()?);
+        let igmp = packet.get(header_len..total_len)?;
+        if igmp.len() < 8 {
+            return None;
+        }
+        let group = net::IpAddr::from(<[u8; 4]>::try_from(&igmp[4..8]).ok()?);
+        let (protocol, message) = match igmp[0] {
+            IGMP_QUERY => {
+                let (version, max_response, sources) = if igmp.len() >= 12 {
+                    let count = usize::from(u16::from_be_bytes([igmp[10], igmp[11]]));
+                    let sources = addresses(&igmp[12..], count, 4)?;
+                    (3, Duration::from_millis(exponential(u32::from(igmp[1]), 7, 4) as u64 * 100), sources)
+                } else if igmp[1] == 0 {
+                    (1, Duration::from_secs(10), Vec::new())
+                } else {
+                    (2, Duration::from_millis(u64::from(igmp[1]) * 100), Vec::new())
+                };
+                let group = Some(group).filter(|group| !group.is_unspecified());
+                (Protocol::Igmp(version), Message::Query { group, sources, max_response })
+            }
+            IGMP_V1_REPORT => (Protocol::Igmp(1), Message::Report { group }),
+            IGMP_V2_REPORT => (Protocol::Igmp(2), Message::Report { group }),
+            IGMP_LEAVE => (Protocol::Igmp(2), Message::Leave { group }),
+            IGMP_V3_REPORT => {
+                let count = usize::from(u16::from_be_bytes([igmp[6], igmp[7]]));
+                (Protocol::Igmp(3), Message::Records(records(&igmp[8..], count, 4)?))
+            }
+            _ => return None,
+        };
+        Some(Packet { src, dst, protocol, message })
+    }
+
+    /// Parses an IPv6 packet carrying MLD, `None` if it doesn't.
+    pub fn parse_v6(packet: &[u8]) -> Option<Packet> {
+        if packet.len() < 40 || packet[0] >> 4 != 6 {
+            return None;
+        }
+        let src = net::IpAddr::from(<[u8; 16]>::try_from(&packet[8..24]).ok()?);
+        let dst = net::IpAddr::from(<[u8; 16]>::try_from(&packet[24..40]).ok()?);
+        let end = (40 + usize::from(u16::from_be_bytes([packet[4], packet[5]]))).min(packet.len());
+        let (mut next, mut offset) = (packet[6], 40);
+        // MLD comes after a hop-by-hop router alert option
+        while next == IPPROTO_HOPOPTS {
+            let header = packet.get(offset..offset + 2)?;
+            next = header[0];
+            offset += (usize::from(header[1]) + 1) * 8;
+        }
+        if next != IPPROTO_ICMPV6 {
+            return None;
+        }
+        let mld = packet.get(offset..end)?;
+        if mld.len() < 24 {
+            return None;
+        }
+        let group = net::IpAddr::from(<[u8; 16]>::try_from(&mld[8..24]).ok()?);
+        let (protocol, message) = match mld[0] {
+            MLD_QUERY => {
+                let code = u32::from(u16::from_be_bytes([mld[4], mld[5]]));
+                let (version, max_response, sources) = if mld.len() >= 28 {
+                    let count = usize::from(u16::from_be_bytes([mld[26], mld[27]]));
+                    (2, exponential(code, 15, 12), addresses(&mld[28..], count, 16)?)
+                } else {
+                    (1, code, Vec::new())
+                };
+                let group = Some(group).filter(|group| !group.is_unspecified());
+                let max_response = Duration::from_millis(u64::from(max_response));
+                (Protocol::Mld(version), Message::Query { group, sources, max_response })
+            }
+            MLD_V1_REPORT => (Protocol::Mld(1), Message::Report { group }),
+            MLD_DONE => (Protocol::Mld(1), Message::Leave { group }),
+            MLD_V2_REPORT => {
+                let count = usize::from(u16::from_be_bytes([mld[6], mld[7]]));
+                (Protocol::Mld(2), Message::Records(records(&mld[8..], count, 16)?))
+            }
+            _ => return None,
+        };
+        Some(Packet { src, dst, protocol, message })
+    }
+
+    /// A line for each thing the message says.
+    pub fn describe(&self) -> Vec<String> {
+        match self.message {
+            Message::Query { group: None, max_response, .. } => {
+                vec![format!("general query, answer within {:.1} s", max_response.as_secs_f64())]
+            }
+            Message::Query { group: Some(group), ref sources, max_response } if sources.is_empty() => {
+                vec![format!("query for {}, answer within {:.1} s", group, max_response.as_secs_f64())]
+            }
+            Message::Query { group: Some(group), ref sources, max_response } => {
+                vec![format!("query for {} from {}, answer within {:.1} s", group, list(sources),
+                             max_response.as_secs_f64())]
+            }
+            Message::Report { group } => vec![format!("join {}", group)],
+            Message::Leave { group } => vec![format!("leave {}", group)],
+            Message::Records(ref records) => records.iter().map(|record| record.to_string()).collect(),
+        }
+    }
+}
+
+/// Decodes an IGMPv3 or MLDv2 maximum response code, which `float_bit`
+/// marks as a 3-bit exponent over a mantissa of `mant_bits`.
+fn exponential(code: u32, float_bit: u32, mant_bits: u32) -> u32 {
+    if code < 1 << float_bit {
+        return code;
+    }
+    let mant = (code & ((1 << mant_bits) - 1)) | (1 << mant_bits);
+    let exp = (code >> mant_bits) & 0x7;
+    mant << (exp + 3)
+}
+
+/// Encodes `value` as a maximum response code or query interval code,
+/// the inverse of `exponential`, rounding down.
+fn to_exponential(value: u32, float_bit: u32, mant_bits: u32) -> u32 {
+    if value < 1 << float_bit {
+        return value;
+    }
+    let mask = (1 << mant_bits) - 1;
+    for exp in 0..8 {
+        let mant = value >> (exp + 3);
+        if mant <= mask << 1 | 1 {
+            return 1 << float_bit | exp << mant_bits | (mant & mask);
+        }
+    }
+    1 << float_bit | 7 << mant_bits | mask
+}
+
+/// An IGMPv3 query for every group or only `group`, to be answered within
+/// `max_response`, telling hosts that queries come every `interval`.
+pub fn query_v4(group: Option<net::Ipv4Addr>, max_response: Duration, interval: Duration) -> Vec<u8> {
+    let tenths = (max_response.as_millis() / 100).clamp(1, u32::MAX as u128) as u32;
+    let mut query = vec![IGMP_QUERY, to_exponential(tenths, 7, 4) as u8, 0, 0];
+    query.extend_from_slice(&group.unwrap_or(net::Ipv4Addr::UNSPECIFIED).octets());
+    query.push(ROBUSTNESS as u8);
+    query.push(to_exponential(interval.as_secs().min(u32::MAX as u64) as u32, 7, 4) as u8);
+    // no sources
+    query.extend_from_slice(&[0, 0]);
+    let checksum = pcap::checksum(&[&query]);
+    query[2..4].copy_from_slice(&checksum.to_be_bytes());
+    query
+}
+
+/// The MLDv2 counterpart of `query_v4`, its checksum left to the kernel.
+pub fn query_v6(group: Option<net::Ipv6Addr>, max_response: Duration, interval: Duration) -> Vec<u8> {
+    let millis = max_response.as_millis().clamp(1, u32::MAX as u128) as u32;
+    let mut query = vec![MLD_QUERY, 0, 0, 0];
+    query.extend_from_slice(&(to_exponential(millis, 15, 12) as u16).to_be_bytes());
+    query.extend_from_slice(&[0, 0]);
+    query.extend_from_slice(&group.unwrap_or(net::Ipv6Addr::UNSPECIFIED).octets());
+    query.push(ROBUSTNESS as u8);
+    query.push(to_exponential(interval.as_secs().min(u32::MAX as u64) as u32, 7, 4) as u8);
+    query.extend_from_slice(&[0, 0]);
+    query
+}
+
+/// `count` addresses of `len` bytes from the start of `data`.
+fn addresses(data: &[u8], count: usize, len: usize) -> Option<Vec<net::IpAddr>> {
+    let data = data.get(..count * len)?;
+    Some(data.chunks_exact(len).map(address).collect())
+}
+
+fn address(bytes: &[u8]) -> net::IpAddr {
+    match <[u8; 4]>::try_from(bytes) {
+        Ok(v4) => net::IpAddr::from(v4),
+        Err(_) => net::IpAddr::from(<[u8; 16]>::try_from(bytes).unwrap_or_default()),
+    }
+}
+
+/// `count` group records with addresses of `len` bytes.
+fn records(mut data: &[u8], count: usize, len: usize) -> Option<Vec<Record>> {
+    let mut records = Vec::with_capacity(count.min(64));
+    for _ in 0..count {
+        let header = data.get(..4 + len)?;
+        let kind = match header[0] {
+            1 => RecordKind::IsInclude,
+            2 => RecordKind::IsExclude,
+            3 => RecordKind::ToInclude,
+            4 => RecordKind::ToExclude,
+            5 => RecordKind::Allow,
+            6 => RecordKind::Block,
+            _ => return None,
+        };
+        let aux_len = usize::from(header[1]) * 4;
+        let sources = usize::from(u16::from_be_bytes([header[2], header[3]]));
+        records.push(Record {
+            kind,
+            group: address(&header[4..]),
+            sources: addresses(&data[4 + len..], sources, len)?,
+        });
+        data = data.get(4 + len + sources * len + aux_len..)?;
+    }
+    Some(records)
+}
+
+/// A change of querier on a link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Election {
+    /// The first querier heard, or one taking over from a querier gone quiet.
+    Elected(net::IpAddr),
+    /// A querier with a lower address, which wins the election.
+    Replaced { old: net::IpAddr, new: net::IpAddr },
+}
+
+/// Follows the querier elected on each link from the queries heard: the
+/// querier with the lowest address wins, and others take over when it has
+/// gone unheard for `QUERIER_TIMEOUT`.
+#[derive(Debug, Default)]
+pub struct Queriers {
+    /// The querier and when it last queried, by interface and protocol.
+    current: HashMap<(u32, bool), (net::IpAddr, Instant)>,
+}
+
+impl Queriers {
+    pub fn new() -> Queriers {
+        Queriers::default()
+    }
+
+    /// Notes a query from `src` heard on `interface`, returning the change
+    /// of querier it makes, if any.
+    pub fn query(&mut self, interface: u32, src: net::IpAddr, now: Instant) -> Option<Election> {
+        let key = (interface, src.is_ipv6());
+        let election = match self.current.get(&key) {
+            None => Some(Election::Elected(src)),
+            Some(&(querier, _)) if querier == src => None,
+            Some(&(querier, _)) if src < querier => Some(Election::Replaced { old: querier, new: src }),
+            Some(&(_, heard)) if now.duration_since(heard) >= QUERIER_TIMEOUT => Some(Election::Elected(src)),
+            // a querier that has lost the election, and should stop
+            Some(_) => return None,
+        };
+        self.current.insert(key, (src, now));
+        election
+    }
+
+    /// The querier on `interface` for IPv4 or IPv6, if one has been heard
+    /// within `QUERIER_TIMEOUT`.
+    pub fn querier(&self, interface: u32, ipv6: bool, now: Instant) -> Option<net::IpAddr> {
+        self.current
+            .get(&(interface, ipv6))
+            .filter(|&&(_, heard)| now.duration_since(heard) < QUERIER_TIMEOUT)
+            .map(|&(querier, _)| querier)
+    }
+}
+
+/// A change of a group's members.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Joined { group: net::IpAddr, member: net::IpAddr },
+    Left { group: net::IpAddr, member: net::IpAddr },
+    /// A member that hasn't reported for too long.
+    Expired { group: net::IpAddr, member: net::IpAddr },
+}
+
+/// The members of each group, from the reports heard. IGMPv3 and MLDv2
+/// hosts all report, but of IGMPv1/v2 and MLDv1 hosts only one per group
+/// answers each query.
+#[derive(Debug, Default)]
+pub struct Memberships {
+    /// When each member last reported, by group.
+    groups: BTreeMap<net::IpAddr, BTreeMap<net::IpAddr, Instant>>,
+}
+
+impl Memberships {
+    pub fn new() -> Memberships {
+        Memberships::default()
+    }
+
+    fn set(&mut self, group: net::IpAddr, member: net::IpAddr, joined: bool, now: Instant) -> Option<Change> {
+        if joined {
+            let members = self.groups.entry(group).or_default();
+            members.insert(member, now).is_none().then_some(Change::Joined { group, member })
+        } else {
+            let members = self.groups.get_mut(&group)?;
+            members.remove(&member)?;
+            if members.is_empty() {
+                self.groups.remove(&group);
+            }
+            Some(Change::Left { group, member })
+        }
+    }
+
+    /// Notes what `packet` says of its sender's memberships.
+    pub fn record(&mut self, packet: &Packet, now: Instant) -> Vec<Change> {
+        let member = packet.src;
+        match packet.message {
+            Message::Query { .. } => Vec::new(),
+            Message::Report { group } => self.set(group, member, true, now).into_iter().collect(),
+            Message::Leave { group } => self.set(group, member, false, now).into_iter().collect(),
+            Message::Records(ref records) => {
+                records.iter()
+                    .filter(|record| record.kind != RecordKind::Block)
+                    .filter_map(|record| {
+                        let include = record.kind == RecordKind::IsInclude || record.kind == RecordKind::ToInclude;
+                        self.set(record.group, member, !(include && record.sources.is_empty()), now)
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Forgets members that haven't reported within `timeout`.
+    pub fn expire(&mut self, timeout: Duration, now: Instant) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for (&group, members) in &mut self.groups {
+            members.retain(|&member, &mut reported| {
+                let fresh = now.duration_since(reported) < timeout;
+                if !fresh {
+                    changes.push(Change::Expired { group, member });
+                }
+                fresh
+            });
+        }
+        self.groups.retain(|_, members| !members.is_empty());
+        changes
+    }
+
+    /// The groups with members, each with its members, in order.
+    pub fn groups(&self) -> Vec<(net::IpAddr, Vec<net::IpAddr>)> {
+        self.groups.iter().map(|(&group, members)| (group, members.keys().cloned().collect())).collect()
+    }
+}
+
+/// Sends IGMPv3 and MLDv2 queries on an interface, standing in for a
+/// multicast router where there is none. Raw sockets take `CAP_NET_RAW`.
+#[derive(Debug)]
+pub struct Querier {
+    interface: u32,
+    interval: Duration,
+    v4: Option<OwnedFd>,
+    v6: Option<OwnedFd>,
+}
+
+impl Querier {
+    /// A querier on the interface with index `interface`, sending IGMP
+    /// from `ipv4` and MLD from the link-local `ipv6`, for those given,
+    /// with queries every `interval`.
+    pub fn new(interface: u32, ipv4: Option<net::Ipv4Addr>, ipv6: Option<net::Ipv6Addr>, interval: Duration)
+               -> io::Result<Querier> {
+        let v4 = match ipv4 {
+            Some(addr) => {
+                let sock = sockopt::raw_socket(libc::AF_INET, libc::IPPROTO_IGMP)?;
+                sockopt::set_multicast_if_v4(&sock, addr)?;
+                sockopt::set(&sock, libc::IPPROTO_IP, libc::IP_MULTICAST_TTL, &(1 as libc::c_int))?;
+                sockopt::set(&sock, libc::IPPROTO_IP, libc::IP_MULTICAST_LOOP, &(0 as libc::c_int))?;
+                // the router alert option, which queries carry
+                sockopt::set(&sock, libc::IPPROTO_IP, libc::IP_OPTIONS, &[0x94u8, 4, 0, 0])?;
+                Some(sock)
+            }
+            None => None,
+        };
+        let v6 = if let Some(addr) = ipv6 {
+            let sock = sockopt::raw_socket(libc::AF_INET6, libc::IPPROTO_ICMPV6)?;
+            // or queries to groups of wider scope would come from a global address
+            sockopt::bind(&sock, net::SocketAddrV6::new(addr, 0, 0, interface).into())?;
+            sockopt::set_multicast_if_v6(&sock, interface)?;
+            sockopt::set_multicast_hops_v6(&sock, 1)?;
+            sockopt::set(&sock, libc::IPPROTO_IPV6, libc::IPV6_MULTICAST_LOOP, &(0 as libc::c_int))?;
+            // a hop-by-hop header with a router alert for MLD, padded
+            sockopt::set(&sock, libc::IPPROTO_IPV6, libc::IPV6_HOPOPTS, &[0u8, 0, 5, 2, 0, 0, 1, 0])?;
+            Some(sock)
+        } else {
+            None
+        };
+        Ok(Querier { interface, interval, v4, v6 })
+    }
+
+    /// Asks every host on the link which groups it is a member of, with
+    /// IGMP or, if `ipv6`, MLD.
+    pub fn general_query(&self, ipv6: bool, max_response: Duration) -> io::Result<()> {
+        if ipv6 {
+            self.send_v6(None, max_response)
+        } else {
+            self.send_v4(None, max_response)
+        }
+    }
+
+    /// Asks whether any host is still a member of `group`, as after one has
+    /// left.
+    pub fn group_query(&self, group: net::IpAddr, max_response: Duration) -> io::Result<()> {
+        match group {
+            net::IpAddr::V4(group) => self.send_v4(Some(group), max_response),
+            net::IpAddr::V6(group) => self.send_v6(Some(group), max_response),
+        }
+    }
+
+    fn send_v4(&self, group: Option<net::Ipv4Addr>, max_response: Duration) -> io::Result<()> {
+        if let Some(ref sock) = self.v4 {
+            let dst = group.unwrap_or(net::Ipv4Addr::new(224, 0, 0, 1));
+            sockopt::send_to(sock, &query_v4(group, max_response, self.interval), (dst, 0).into())?;
+        }
+        Ok(())
+    }
+
+    fn send_v6(&self, group: Option<net::Ipv6Addr>, max_response: Duration) -> io::Result<()> {
+        if let Some(ref sock) = self.v6 {
+            let dst = group.unwrap_or(net::Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1));
+            let dst = net::SocketAddrV6::new(dst, 0, 0, self.interface);
+            sockopt::send_to(sock, &query_v6(group, max_response, self.interval), dst.into())?;
+        }
+        Ok(())
+    }
+}
+
+/// A message seen by an `Observer`.
+#[derive(Debug, Clone)]
+pub struct Observed {
+    pub packet: Packet,
+    pub interface: u32,
+    /// Whether this host sent it.
+    pub outgoing: bool,
+    pub received: SystemTime,
+}
+
+/// Captures the IGMP and MLD messages on one interface or all of them with
+/// a packet socket, which takes `CAP_NET_RAW`. Interfaces are put in
+/// all-multicast mode so that reports to groups this host hasn't joined get
+/// through, though switches that snoop IGMP send those only to routers.
+#[derive(Debug)]
+pub struct Observer {
+    capture: Capture,
+}
+
+impl AsRawFd for Observer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.capture.as_raw_fd()
+    }
+}
+
+/// Keeps IPv4 packets carrying IGMP and IPv6 packets carrying a hop-by-hop
+/// header or ICMPv6, as MLD messages do.
+fn filter() -> [libc::sock_filter; 10] {
+    let op = |code: u32, jt: u8, jf: u8, k: u32| libc::sock_filter { code: code as u16, jt, jf, k };
+    let (ld, ldb, jeq, ret) = (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS,
+                               libc::BPF_LD | libc::BPF_B | libc::BPF_ABS,
+                               libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                               libc::BPF_RET | libc::BPF_K);
+    [op(ld, 0, 0, (libc::SKF_AD_OFF + libc::SKF_AD_PROTOCOL) as u32),
+     op(jeq, 0, 2, u32::from(ETH_P_IP)),
+     op(ldb, 0, 0, 9),
+     op(jeq, 4, 5, u32::from(IPPROTO_IGMP)),
+     op(jeq, 0, 4, u32::from(ETH_P_IPV6)),
+     op(ldb, 0, 0, 6),
+     op(jeq, 1, 0, u32::from(IPPROTO_HOPOPTS)),
+     op(jeq, 0, 1, u32::from(IPPROTO_ICMPV6)),
+     op(ret, 0, 0, u32::from(u16::MAX)),
+     op(ret, 0, 0, 0)]
+}
+
+impl Observer {
+    /// Observes the interface with index `interface`, or all of them.
+    pub fn new(interface: Option<u32>) -> io::Result<Observer> {
+        Ok(Observer { capture: Capture::new(interface, &mut filter())? })
+    }
+
+    pub fn timeout(&mut self, timeout: Option<Duration>) -> io::Result<&mut Observer> {
+        self.capture.timeout(timeout)?;
+        Ok(self)
+    }
+
+    /// Waits for the next packet, `None` if it isn't a message that parses.
+    pub fn recv(&self) -> io::Result<Option<Observed>> {
+        let mut buf = [0u8; 65536];
+        let frame = self.capture.recv(&mut buf)?;
+        let received = SystemTime::now();
+        let packet = match frame.protocol {
+            ETH_P_IP => Packet::parse_v4(frame.data),
+            ETH_P_IPV6 => Packet::parse_v6(frame.data),
+            _ => None,
+        };
+        Ok(packet.map(|packet| {
+            Observed {
+                packet,
+                interface: frame.interface,
+                outgoing: frame.outgoing,
+                received,
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: [u8; 4] = [192, 0, 2, 1];
+    const HOST6: &str = "fe80::1";
+
+    fn ipv4(dst: [u8; 4], igmp: &[u8]) -> Vec<u8> {
+        // with the router alert option
+        let mut packet = vec![0x46, 0xc0, 0, 0, 0, 0, 0, 0, 1, IPPROTO_IGMP, 0, 0];
+        packet.extend_from_slice(&HOST);
+        packet.extend_from_slice(&dst);
+        packet.extend_from_slice(&[0x94, 0x04, 0, 0]);
+        packet.extend_from_slice(igmp);
+        let len = packet.len() as u16;
+        packet[2..4].copy_from_slice(&len.to_be_bytes());
+        packet
+    }
+
+    fn ipv6(dst: &str, mld: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend_from_slice(&(8 + mld.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[IPPROTO_HOPOPTS, 1]);
+        packet.extend_from_slice(&HOST6.parse::<net::Ipv6Addr>().unwrap().octets());
+        packet.extend_from_slice(&dst.parse::<net::Ipv6Addr>().unwrap().octets());
+        // router alert for MLD, then two bytes of padding
+        packet.extend_from_slice(&[IPPROTO_ICMPV6, 0, 5, 2, 0, 0, 1, 0]);
+        packet.extend_from_slice(mld);
+        packet
+    }
+
+    fn ip(s: &str) -> net::IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn v4(packet: &[u8]) -> (Protocol, Message) {
+        let packet = Packet::parse_v4(packet).unwrap();
+        assert_eq!(packet.src, net::IpAddr::from(HOST));
+        (packet.protocol, packet.message)
+    }
+
+    fn v6(packet: &[u8]) -> (Protocol, Message) {
+        let packet = Packet::parse_v6(packet).unwrap();
+        assert_eq!(packet.src, ip(HOST6));
+        (packet.protocol, packet.message)
+    }
+
+    #[test]
+    fn igmp_queries() {
+        let general = ipv4([224, 0, 0, 1], &[IGMP_QUERY, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(v4(&general),
+                   (Protocol::Igmp(1),
+                    Message::Query { group: None, sources: Vec::new(), max_response: Duration::from_secs(10) }));
+        let specific = ipv4([239, 1, 1, 1], &[IGMP_QUERY, 25, 0, 0, 239, 1, 1, 1]);
+        assert_eq!(v4(&specific),
+                   (Protocol::Igmp(2),
+                    Message::Query {
+                        group: Some(ip("239.1.1.1")),
+                        sources: Vec::new(),
+                        max_response: Duration::from_millis(2500),
+                    }));
+        // code 0x8f: mantissa 15 | 16, exponent 0, so 31 << 3 tenths
+        let sourced = ipv4([232, 1, 1, 1],
+                           &[IGMP_QUERY, 0x8f, 0, 0, 232, 1, 1, 1, 2, 125, 0, 2, 10, 0, 0, 1, 10, 0, 0, 2]);
+        assert_eq!(v4(&sourced),
+                   (Protocol::Igmp(3),
+                    Message::Query {
+                        group: Some(ip("232.1.1.1")),
+                        sources: vec![ip("10.0.0.1"), ip("10.0.0.2")],
+                        max_response: Duration::from_millis(248 * 100),
+                    }));
+        let sent = query_v4(None, Duration::from_secs(10), Duration::from_secs(125));
+        assert_eq!(v4(&ipv4([224, 0, 0, 1], &sent)),
+                   (Protocol::Igmp(3),
+                    Message::Query { group: None, sources: Vec::new(), max_response: Duration::from_secs(10) }));
+    }
+
+    #[test]
+    fn igmp_reports() {
+        let group = [239, 1, 1, 1];
+        let report = |kind| {
+            let mut igmp = vec![kind, 0, 0, 0];
+            igmp.extend_from_slice(&group);
+            v4(&ipv4(group, &igmp))
+        };
+        assert_eq!(report(IGMP_V1_REPORT), (Protocol::Igmp(1), Message::Report { group: ip("239.1.1.1") }));
+        assert_eq!(report(IGMP_V2_REPORT), (Protocol::Igmp(2), Message::Report { group: ip("239.1.1.1") }));
+        assert_eq!(report(IGMP_LEAVE), (Protocol::Igmp(2), Message::Leave { group: ip("239.1.1.1") }));
+        assert_eq!(Packet::parse_v4(&ipv4(group, &[0x42, 0, 0, 0, 239, 1, 1, 1])), None);
+
+        let records = [IGMP_V3_REPORT, 0, 0, 0, 0, 0, 0, 2,
+                       // to include 232.1.1.1 from 10.0.0.1, with a word of auxiliary data
+                       3, 1, 0, 1, 232, 1, 1, 1, 10, 0, 0, 1, 0xaa, 0xaa, 0xaa, 0xaa,
+                       // to exclude nothing: a plain join
+                       4, 0, 0, 0, 239, 1, 1, 2];
+        let (protocol, message) = v4(&ipv4([224, 0, 0, 22], &records));
+        assert_eq!(protocol, Protocol::Igmp(3));
+        let packet = Packet { src: ip("192.0.2.1"), dst: ip("224.0.0.22"), protocol, message };
+        assert_eq!(packet.describe(), vec!["join 232.1.1.1 from 10.0.0.1 only", "join 239.1.1.2"]);
+    }
+
+    #[test]
+    fn mld_queries() {
+        let mut query = vec![MLD_QUERY, 0, 0, 0, 0x03, 0xe8, 0, 0];
+        query.extend_from_slice(&[0; 16]);
+        assert_eq!(v6(&ipv6("ff02::1", &query)),
+                   (Protocol::Mld(1),
+                    Message::Query { group: None, sources: Vec::new(), max_response: Duration::from_secs(1) }));
+        let sent = query_v6(Some("ff3e::1".parse().unwrap()), Duration::from_secs(10), Duration::from_secs(125));
+        assert_eq!(v6(&ipv6("ff3e::1", &sent)),
+                   (Protocol::Mld(2),
+                    Message::Query {
+                        group: Some(ip("ff3e::1")),
+                        sources: Vec::new(),
+                        max_response: Duration::from_secs(10),
+                    }));
+        let mut sourced = sent.clone();
+        sourced[27] = 1;
+        sourced.extend_from_slice(&"2001:db8::5".parse::<net::Ipv6Addr>().unwrap().octets());
+        match v6(&ipv6("ff3e::1", &sourced)).1 {
+            Message::Query { sources, .. } => assert_eq!(sources, vec![ip("2001:db8::5")]),
+            message => panic!("{:?}", message),
+        }
+        // no hop-by-hop header to skip
+        let mut bare = ipv6("ff02::1", &query);
+        bare[6] = IPPROTO_ICMPV6;
+        bare.drain(40..48);
+        bare[4..6].copy_from_slice(&(query.len() as u16).to_be_bytes());
+        assert_eq!(v6(&bare).0, Protocol::Mld(1));
+    }
+
+    #[test]
+    fn mld_reports() {
+        let group = "ff3e::1234".parse::<net::Ipv6Addr>().unwrap().octets();
+        let mut report = vec![MLD_V1_REPORT, 0, 0, 0, 0, 0, 0, 0];
+        report.extend_from_slice(&group);
+        assert_eq!(v6(&ipv6("ff3e::1234", &report)), (Protocol::Mld(1), Message::Report { group: ip("ff3e::1234") }));
+        report[0] = MLD_DONE;
+        assert_eq!(v6(&ipv6("ff02::2", &report)), (Protocol::Mld(1), Message::Leave { group: ip("ff3e::1234") }));
+
+        let mut records = vec![MLD_V2_REPORT, 0, 0, 0, 0, 0, 0, 1, 6, 0, 0, 1];
+        records.extend_from_slice(&group);
+        records.extend_from_slice(&"2001:db8::5".parse::<net::Ipv6Addr>().unwrap().octets());
+        assert_eq!(v6(&ipv6("ff02::16", &records)),
+                   (Protocol::Mld(2),
+                    Message::Records(vec![Record {
+                                              kind: RecordKind::Block,
+                                              group: ip("ff3e::1234"),
+                                              sources: vec![ip("2001:db8::5")],
+                                          }])));
+    }
+
+    #[test]
+    fn counts_past_the_end() {
+        let records = [IGMP_V3_REPORT, 0, 0, 0, 0, 0, 0, 2, 4, 0, 0, 0, 239, 1, 1, 2];
+        assert_eq!(Packet::parse_v4(&ipv4([224, 0, 0, 22], &records)), None);
+        let sources = [IGMP_V3_REPORT, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 3, 232, 1, 1, 1, 10, 0, 0, 1];
+        assert_eq!(Packet::parse_v4(&ipv4([224, 0, 0, 22], &sources)), None);
+        let aux = [IGMP_V3_REPORT, 0, 0, 0, 0, 0, 0, 1, 1, 9, 0, 0, 232, 1, 1, 1];
+        assert_eq!(Packet::parse_v4(&ipv4([224, 0, 0, 22], &aux)), None);
+        let query = [IGMP_QUERY, 100, 0, 0, 232, 1, 1, 1, 2, 125, 0, 5, 10, 0, 0, 1];
+        assert_eq!(Packet::parse_v4(&ipv4([232, 1, 1, 1], &query)), None);
+        // an IP total length short of the message
+        let mut report = ipv4([239, 1, 1, 1], &[IGMP_V2_REPORT, 0, 0, 0, 239, 1, 1, 1]);
+        report[3] -= 1;
+        assert_eq!(Packet::parse_v4(&report), None);
+
+        let mut mld = vec![MLD_V2_REPORT, 0, 0, 0, 0, 0, 0, 2, 6, 0, 0, 0];
+        mld.extend_from_slice(&[0xff; 16]);
+        assert_eq!(Packet::parse_v6(&ipv6("ff02::16", &mld)), None);
+        // a hop-by-hop header longer than the packet
+        let mut long = ipv6("ff02::16", &mld);
+        long[41] = 200;
+        assert_eq!(Packet::parse_v6(&long), None);
+    }
+
+    #[test]
+    fn exponential_round_trip() {
+        for &(float_bit, mant_bits, max) in &[(7, 4, 0xff), (15, 12, 0xffff)] {
+            for code in 0..=max {
+                let value = exponential(code, float_bit, mant_bits);
+                assert_eq!(to_exponential(value, float_bit, mant_bits), code, "code {:#x}", code);
+                // values between codes round down
+                let below = to_exponential(value + 1, float_bit, mant_bits);
+                assert!(exponential(below, float_bit, mant_bits) <= value + 1);
+            }
+        }
+        assert_eq!(exponential(0x7f, 7, 4), 127);
+        assert_eq!(exponential(0x80, 7, 4), 128);
+        assert_eq!(to_exponential(u32::MAX, 7, 4), 0xff);
+    }
+}
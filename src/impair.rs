@@ -0,0 +1,224 @@
+use std::{cmp, net, process, str};
+use std::collections::BinaryHeap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Longest a reordered datagram waits for the one that is to overtake it,
+/// beyond when it would have been sent.
+const REORDER_HOLD: Duration = Duration::from_millis(100);
+
+/// A fixed delay plus or minus up to `jitter`, picked uniformly for each
+/// datagram.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Delay {
+    pub base: Duration,
+    pub jitter: Duration,
+}
+
+fn parse_duration(s: &str) -> Option<Duration> {
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+    let value: f64 = s[..split].parse().ok()?;
+    let secs = match &s[split..] {
+        "us" => value / 1e6,
+        "ms" => value / 1e3,
+        "s" => value,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+impl str::FromStr for Delay {
+    type Err = String;
+
+    /// Parses `20ms`, `20ms±5ms` or `20ms+-5ms`, in us, ms or s.
+    fn from_str(s: &str) -> Result<Delay, String> {
+        let invalid = || format!("invalid delay {}: expected e.g. 20ms or 20ms±5ms", s);
+        let separator = s.find('±').map(|at| (at, '±'.len_utf8())).or_else(|| s.find("+-").map(|at| (at, 2)));
+        let (base, jitter) = match separator {
+            Some((at, len)) => (&s[..at], Some(&s[at + len..])),
+            None => (s, None),
+        };
+        Ok(Delay {
+            base: parse_duration(base).ok_or_else(invalid)?,
+            jitter: match jitter {
+                Some(jitter) => parse_duration(jitter).ok_or_else(invalid)?,
+                None => Duration::ZERO,
+            },
+        })
+    }
+}
+
+/// xorshift64*, which is plenty for deciding the fate of datagrams.
+#[derive(Debug)]
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform in [0, 1).
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.unit() < probability
+    }
+}
+
+/// How many datagrams an `Impairment` has dropped, duplicated and
+/// reordered.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImpairCounts {
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+}
+
+/// A datagram waiting to be sent; ordered by when, then by when it was
+/// queued.
+type Queued = cmp::Reverse<(Instant, u64, Vec<u8>, net::SocketAddr)>;
+
+/// Drops, delays, duplicates and reorders datagrams on their way through,
+/// as netem does in the kernel, to see how receivers cope. Datagrams are
+/// handed to `submit` and taken back from `ready` when they are due; jitter
+/// larger than the gaps between datagrams reorders them too.
+#[derive(Debug)]
+pub struct Impairment {
+    drop: f64,
+    duplicate: f64,
+    reorder: f64,
+    delay: Delay,
+    random: Random,
+    queue: BinaryHeap<Queued>,
+    queued: u64,
+    /// A datagram picked to be overtaken, and the latest it may be sent.
+    held: Option<(Instant, Vec<u8>, net::SocketAddr)>,
+    counts: ImpairCounts,
+}
+
+impl Default for Impairment {
+    fn default() -> Impairment {
+        Impairment::new()
+    }
+}
+
+impl Impairment {
+    /// Passes everything straight through until told otherwise.
+    pub fn new() -> Impairment {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        let mut impairment = Impairment {
+            drop: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            delay: Delay::default(),
+            random: Random(1),
+            queue: BinaryHeap::new(),
+            queued: 0,
+            held: None,
+            counts: ImpairCounts::default(),
+        };
+        impairment.seed(nanos ^ (u64::from(process::id()) << 32));
+        impairment
+    }
+
+    /// Makes the same datagrams meet the same fate on every run.
+    pub fn seed(&mut self, seed: u64) -> &mut Impairment {
+        // xorshift never leaves zero
+        self.random = Random(seed.max(1));
+        self
+    }
+
+    /// Drops datagrams with this probability, from 0 to 1.
+    pub fn drop(&mut self, probability: f64) -> &mut Impairment {
+        self.drop = probability;
+        self
+    }
+
+    /// Sends datagrams twice with this probability.
+    pub fn duplicate(&mut self, probability: f64) -> &mut Impairment {
+        self.duplicate = probability;
+        self
+    }
+
+    /// Holds datagrams back with this probability until the next one has
+    /// been sent.
+    pub fn reorder(&mut self, probability: f64) -> &mut Impairment {
+        self.reorder = probability;
+        self
+    }
+
+    pub fn delay(&mut self, delay: Delay) -> &mut Impairment {
+        self.delay = delay;
+        self
+    }
+
+    pub fn counts(&self) -> ImpairCounts {
+        self.counts
+    }
+
+    fn push(&mut self, at: Instant, data: Vec<u8>, to: net::SocketAddr) {
+        self.queued += 1;
+        self.queue.push(cmp::Reverse((at, self.queued, data, to)));
+    }
+
+    fn sample_delay(&mut self) -> Duration {
+        let jitter = self.delay.jitter.as_secs_f64() * (self.random.unit() * 2.0 - 1.0);
+        Duration::from_secs_f64((self.delay.base.as_secs_f64() + jitter).max(0.0))
+    }
+
+    /// Decides what becomes of a datagram bound for `to`.
+    pub fn submit(&mut self, data: &[u8], to: net::SocketAddr) {
+        if self.random.chance(self.drop) {
+            self.counts.dropped += 1;
+            return;
+        }
+        let copies = if self.random.chance(self.duplicate) {
+            self.counts.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let at = Instant::now() + self.sample_delay();
+            if let Some((_, held, held_to)) = self.held.take() {
+                self.push(at, data.to_vec(), to);
+                self.push(at, held, held_to);
+            } else if self.random.chance(self.reorder) {
+                self.counts.reordered += 1;
+                self.held = Some((at + REORDER_HOLD, data.to_vec(), to));
+            } else {
+                self.push(at, data.to_vec(), to);
+            }
+        }
+    }
+
+    /// The datagrams due to be sent, in order, with where they go.
+    pub fn ready(&mut self) -> Vec<(Vec<u8>, net::SocketAddr)> {
+        let now = Instant::now();
+        if self.held.as_ref().is_some_and(|&(latest, _, _)| latest <= now) {
+            let (latest, data, to) = self.held.take().expect("a held datagram");
+            self.push(latest, data, to);
+        }
+        let mut ready = Vec::new();
+        while self.queue.peek().is_some_and(|queued| (queued.0).0 <= now) {
+            let cmp::Reverse((_, _, data, to)) = self.queue.pop().expect("a queued datagram");
+            ready.push((data, to));
+        }
+        ready
+    }
+
+    /// How long until a datagram is due, if any are waiting.
+    pub fn wait(&self) -> Option<Duration> {
+        let queued = self.queue.peek().map(|queued| (queued.0).0);
+        let held = self.held.as_ref().map(|&(latest, _, _)| latest);
+        let next = match (queued, held) {
+            (Some(queued), Some(held)) => queued.min(held),
+            (next, None) | (None, next) => next?,
+        };
+        Some(next.saturating_duration_since(Instant::now()))
+    }
+}
@@ -0,0 +1,69 @@
+extern crate libc;
+extern crate regex;
+
+pub mod alert;
+pub mod auth;
+mod capture;
+pub mod carousel;
+pub mod chat;
+pub mod cipher;
+pub mod compress;
+pub mod config;
+pub mod control;
+pub mod cycle;
+pub mod daemon;
+pub mod dedup;
+pub mod dns;
+pub mod dscp;
+pub mod event;
+pub mod exec;
+pub mod fec;
+pub mod filter;
+pub mod follow;
+pub mod fragment;
+pub mod generate;
+pub mod histogram;
+pub mod iface;
+pub mod igmp;
+pub mod impair;
+mod listen;
+pub mod log;
+pub mod loss;
+pub mod merge;
+pub mod metrics;
+pub mod mpegts;
+pub mod output;
+pub mod pcap;
+mod ping;
+pub mod profile;
+pub mod ptp;
+pub mod push;
+pub mod rate;
+pub mod relay;
+mod replay;
+pub mod resolve;
+pub mod rotate;
+pub mod routing;
+pub mod rtp;
+pub mod sap;
+pub mod script;
+pub mod sdp;
+mod send;
+pub mod signal;
+mod socket;
+mod sockopt;
+pub mod ssdp;
+pub mod stats;
+pub mod systemd;
+pub mod tag;
+pub mod timestamp;
+pub mod tui;
+pub mod wellknown;
+
+pub use generate::{generate, Generator};
+pub use listen::{listen, listen_all, Datagram, Listener, MultiListener, DEFAULT_BUFFER_SIZE};
+pub use ping::{is_probe, ping, Census, PingStats, Pinger, Pong, ReplyPath, RttStats, SizeSweepStats, SweepStats,
+                CENSUS_HEADER, MAX_PROBE_SIZE};
+pub use replay::{replay, Replayer};
+pub use send::{send, Framing, Sender};
+pub use socket::{kernel_drops, recv_buffer_size, send_buffer_size, MulticastSocket, DEFAULT_TTL};
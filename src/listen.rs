@@ -0,0 +1,355 @@
+use std::{io, net};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime};
+
+use event::{Interest, Poll, Token};
+use ping;
+use socket::MulticastSocket;
+use sockopt;
+
+pub const DEFAULT_BUFFER_SIZE: usize = 16384;
+
+/// A datagram received from the group.
+#[derive(Debug, Clone)]
+pub struct Datagram {
+    pub data: Vec<u8>,
+    /// Size of the datagram as sent; larger than `data.len()` if it did not
+    /// fit in the receive buffer.
+    pub size: usize,
+    pub src: net::SocketAddr,
+    /// The group and port the datagram was received on.
+    pub group: net::SocketAddr,
+    /// Destination address of the datagram, which can differ from `group`
+    /// when the socket also receives other groups joined on its port.
+    pub dst: Option<net::IpAddr>,
+    /// Index of the interface the datagram arrived on.
+    pub ifindex: Option<u32>,
+    /// Remaining TTL / hop limit on arrival.
+    pub ttl: Option<u8>,
+    /// TOS / traffic class byte, holding the DSCP and ECN bits.
+    pub tos: Option<u8>,
+    /// Datagrams the socket has dropped so far because its receive buffer
+    /// was full, once the kernel has dropped any.
+    pub drops: Option<u32>,
+    /// When the kernel received the datagram, or failing that when it was
+    /// read from the socket.
+    pub received: SystemTime,
+    /// The sender's tag, once listen --by-tag has taken it off `data`.
+    pub tag: Option<String>,
+}
+
+impl Datagram {
+    pub fn truncated(&self) -> bool {
+        self.size > self.data.len()
+    }
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "no datagram received")
+}
+
+/// Iterator over the datagrams arriving on a joined group.
+pub struct Listener {
+    sock: net::UdpSocket,
+    group: net::SocketAddr,
+    buf: Vec<u8>,
+    answer_pings: bool,
+    /// Socket and destination for PONGs sent to the group.
+    group_replies: Option<(net::UdpSocket, net::SocketAddr)>,
+    /// Datagrams received per system call.
+    batch: usize,
+    bufs: Vec<Vec<u8>>,
+    /// Datagrams from the last batch not yet returned.
+    queue: VecDeque<Datagram>,
+}
+
+pub fn listen(config: &MulticastSocket) -> io::Result<Listener> {
+    Ok(Listener {
+        sock: config.join()?,
+        group: config.addr(),
+        buf: vec![0u8; DEFAULT_BUFFER_SIZE],
+        answer_pings: true,
+        group_replies: None,
+        batch: 1,
+        bufs: Vec::new(),
+        queue: VecDeque::new(),
+    })
+}
+
+impl Listener {
+    /// Whether PING probes are answered with a unicast PONG as they arrive
+    /// (on by default). Turn this off to `answer` them only once they have
+    /// passed the caller's checks.
+    pub fn answer_pings(&mut self, answer: bool) -> &mut Listener {
+        self.answer_pings = answer;
+        self
+    }
+
+    /// Sends PONGs from `sock` to `target`, normally the group, instead of
+    /// back to the prober.
+    pub fn reply_to_group(&mut self, sock: net::UdpSocket, target: net::SocketAddr) -> &mut Listener {
+        self.group_replies = Some((sock, target));
+        self
+    }
+
+    /// Size of the receive buffer; longer datagrams are truncated.
+    pub fn buffer_size(&mut self, size: usize) -> &mut Listener {
+        self.buf.resize(size, 0);
+        self
+    }
+
+    /// Receives up to `size` datagrams per system call with `recvmmsg`,
+    /// where available, to keep up with high packet rates.
+    pub fn batch(&mut self, size: usize) -> &mut Listener {
+        self.batch = size.max(1);
+        self
+    }
+
+    /// Makes `recv` fail with `TimedOut` when no datagram arrives in time.
+    pub fn timeout(&mut self, timeout: Option<Duration>) -> io::Result<&mut Listener> {
+        self.sock.set_read_timeout(timeout)?;
+        Ok(self)
+    }
+
+    pub fn socket(&self) -> &net::UdpSocket {
+        &self.sock
+    }
+
+    pub fn group(&self) -> net::SocketAddr {
+        self.group
+    }
+
+    /// Receives the next datagram. Should a PING among them go unanswered
+    /// for an error, that is returned first and the datagrams follow.
+    pub fn recv(&mut self) -> io::Result<Datagram> {
+        if let Some(datagram) = self.queue.pop_front() {
+            return Ok(datagram);
+        }
+        if self.batch > 1 {
+            return self.recv_batch();
+        }
+        let (size, src, info) = match sockopt::recv_msg(&self.sock, &mut self.buf) {
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Err(timed_out()),
+            result => result?,
+        };
+        let data = self.buf[..size.min(self.buf.len())].to_vec();
+        self.queue.push_back(self.datagram(data, size, src, info));
+        self.answer_queued()?;
+        self.queue.pop_front().ok_or_else(timed_out)
+    }
+
+    fn recv_batch(&mut self) -> io::Result<Datagram> {
+        let len = self.buf.len();
+        if self.bufs.len() != self.batch || self.bufs[0].len() != len {
+            self.bufs = vec![vec![0u8; len]; self.batch];
+        }
+        let received = match sockopt::recv_mmsg(&self.sock, &mut self.bufs) {
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Err(timed_out()),
+            result => result?,
+        };
+        for (i, (size, src, info)) in received.into_iter().enumerate() {
+            let data = self.bufs[i][..size.min(len)].to_vec();
+            let datagram = self.datagram(data, size, src, info);
+            self.queue.push_back(datagram);
+        }
+        self.answer_queued()?;
+        self.queue.pop_front().ok_or_else(timed_out)
+    }
+
+    /// Answers the PINGs among the queued datagrams, if it is up to us,
+    /// failing with the first error once all have been tried.
+    fn answer_queued(&self) -> io::Result<()> {
+        if !self.answer_pings {
+            return Ok(());
+        }
+        let mut result = Ok(());
+        for datagram in &self.queue {
+            if let Err(err) = self.answer(datagram) {
+                result = result.and(Err(err));
+            }
+        }
+        result
+    }
+
+    /// Answers `datagram` with a PONG if it is a PING probe.
+    pub fn answer(&self, datagram: &Datagram) -> io::Result<()> {
+        if let Some(reply) = ping::reply(&datagram.data) {
+            match self.group_replies {
+                Some((ref sock, target)) => sock.send_to(&reply, target)?,
+                None => self.sock.send_to(&reply, datagram.src)?,
+            };
+        }
+        Ok(())
+    }
+
+    fn datagram(&self, data: Vec<u8>, size: usize, src: net::SocketAddr, info: sockopt::RecvInfo) -> Datagram {
+        Datagram {
+            data,
+            size,
+            src,
+            group: self.group,
+            dst: info.dst,
+            ifindex: info.ifindex,
+            ttl: info.ttl,
+            tos: info.tos,
+            drops: info.drops,
+            received: info.timestamp.unwrap_or_else(SystemTime::now),
+            tag: None,
+        }
+    }
+}
+
+impl Iterator for Listener {
+    type Item = io::Result<Datagram>;
+
+    fn next(&mut self) -> Option<io::Result<Datagram>> {
+        Some(self.recv())
+    }
+}
+
+/// Datagrams from several joined groups merged into one stream, all
+/// received on the calling thread.
+pub struct MultiListener {
+    listeners: Vec<Listener>,
+    poll: Poll,
+    /// Listeners that may have datagrams waiting, taken in turn.
+    ready: VecDeque<Token>,
+    timeout: Option<Duration>,
+}
+
+/// Joins every group in `configs`.
+pub fn listen_all(configs: &[MulticastSocket]) -> io::Result<MultiListener> {
+    let listeners = configs.iter().map(listen).collect::<io::Result<_>>()?;
+    MultiListener::new(listeners)
+}
+
+impl MultiListener {
+    pub fn new(listeners: Vec<Listener>) -> io::Result<MultiListener> {
+        let mut poll = Poll::new();
+        for (token, listener) in listeners.iter().enumerate() {
+            listener.sock.set_nonblocking(true)?;
+            poll.register(&listener.sock, token, Interest::Readable);
+        }
+        Ok(MultiListener {
+            listeners,
+            poll,
+            ready: VecDeque::new(),
+            timeout: None,
+        })
+    }
+
+    /// Adds a listener, e.g. for a group joined while running.
+    pub fn add(&mut self, listener: Listener) -> io::Result<()> {
+        listener.sock.set_nonblocking(true)?;
+        self.poll.register(&listener.sock, self.listeners.len(), Interest::Readable);
+        self.listeners.push(listener);
+        Ok(())
+    }
+
+    /// Takes out the listener for `group`, so the caller can leave it.
+    pub fn remove(&mut self, group: net::SocketAddr) -> Option<Listener> {
+        let i = self.listeners.iter().position(|listener| listener.group == group)?;
+        let listener = self.listeners.swap_remove(i);
+        // tokens are positions, and the last listener has moved
+        self.poll = Poll::new();
+        for (token, listener) in self.listeners.iter().enumerate() {
+            self.poll.register(&listener.sock, token, Interest::Readable);
+        }
+        self.ready.clear();
+        Some(listener)
+    }
+
+    /// Makes `recv` fail with `TimedOut` when no datagram arrives in time.
+    pub fn timeout(&mut self, timeout: Option<Duration>) -> &mut MultiListener {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn recv(&mut self) -> io::Result<Datagram> {
+        self.recv_indexed().map(|(_, datagram)| datagram)
+    }
+
+    /// Answers `datagram`, received from the listener at `index`, if it is
+    /// a PING probe.
+    pub fn answer(&self, index: usize, datagram: &Datagram) -> io::Result<()> {
+        self.listeners[index].answer(datagram)
+    }
+
+    /// Like `recv`, also saying which listener the datagram came from, by
+    /// the order they were added in; tells groups apart that share an
+    /// address and port on different interfaces.
+    pub fn recv_indexed(&mut self) -> io::Result<(usize, Datagram)> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            while let Some(token) = self.ready.pop_front() {
+                match self.listeners[token].recv() {
+                    Ok(datagram) => {
+                        // it may have more; let the others have a turn first
+                        self.ready.push_back(token);
+                        return Ok((token, datagram));
+                    }
+                    Err(ref err) if err.kind() == io::ErrorKind::TimedOut => continue,
+                    Err(err) => {
+                        // datagrams may be queued behind a reply that failed
+                        self.ready.push_back(token);
+                        return Err(err);
+                    }
+                }
+            }
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(timed_out());
+                    }
+                    Some(deadline - now)
+                }
+                None => None,
+            };
+            for event in self.poll.wait(timeout)? {
+                if !self.ready.contains(&event.token) {
+                    self.ready.push_back(event.token);
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for MultiListener {
+    type Item = io::Result<Datagram>;
+
+    fn next(&mut self) -> Option<io::Result<Datagram>> {
+        Some(self.recv())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net, thread};
+    use std::time::Duration;
+
+    use socket::MulticastSocket;
+    use super::*;
+
+    #[test]
+    fn failed_reply_keeps_the_batch() {
+        let group = net::Ipv4Addr::new(239, 255, 55, 55);
+        let sender = net::UdpSocket::bind((net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        sockopt::set_multicast_if_v4(&sender, net::Ipv4Addr::LOCALHOST).unwrap();
+        let mut config = MulticastSocket::new(group.into(), 0);
+        config.interface("127.0.0.1");
+        let mut listener = listen(&config).unwrap();
+        let port = listener.socket().local_addr().unwrap().port();
+        // replies can't go from an IPv6 socket to an IPv4 group
+        let unable = net::UdpSocket::bind((net::Ipv6Addr::LOCALHOST, 0)).unwrap();
+        listener.batch(8).reply_to_group(unable, (group, port).into()).timeout(Some(Duration::from_secs(5))).unwrap();
+        for payload in &["PING 1", "data", "PING 2"] {
+            sender.send_to(payload.as_bytes(), (group, port)).unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+        assert!(listener.recv().is_err());
+        for payload in &["PING 1", "data", "PING 2"] {
+            assert_eq!(listener.recv().unwrap().data, payload.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::{fmt, str};
+use std::ffi::CString;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::SystemTime;
+
+use libc;
+
+use output::json_string;
+use timestamp;
+
+/// How much a diagnostic matters, most important first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    /// Progress and reports the user asked for; shown by default.
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    const ALL: [Level; 5] = [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace];
+
+    fn syslog_priority(self) -> libc::c_int {
+        match self {
+            Level::Error => libc::LOG_ERR,
+            Level::Warn => libc::LOG_WARNING,
+            Level::Info => libc::LOG_INFO,
+            Level::Debug | Level::Trace => libc::LOG_DEBUG,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    /// The level `-v` and `-q` flags lead to, counting from `Info`.
+    pub fn from_verbosity(verbose: u8, quiet: u8) -> Level {
+        let index = (Level::Info as i32 + i32::from(verbose) - i32::from(quiet)).clamp(0, 4);
+        Level::ALL[index as usize]
+    }
+}
+
+/// How diagnostics are written to stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The message as is, warnings and errors prefixed with their level.
+    Text,
+    /// One JSON object per line with the time, level and message.
+    Json,
+}
+
+impl str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogFormat, String> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {}: expected text or json", s)),
+        }
+    }
+}
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static JSON: AtomicBool = AtomicBool::new(false);
+static SYSLOG: AtomicBool = AtomicBool::new(false);
+
+/// Hides diagnostics less important than `level`.
+pub fn set_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> Level {
+    Level::ALL[MAX_LEVEL.load(Ordering::Relaxed) as usize]
+}
+
+pub fn set_format(format: LogFormat) {
+    JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+/// Sends diagnostics to syslog, and so to journald where it runs, rather
+/// than stderr, for running as a daemon.
+pub fn to_syslog() {
+    // openlog keeps the pointer, so the ident must live forever
+    let ident = b"mccat\0".as_ptr() as *const libc::c_char;
+    unsafe { libc::openlog(ident, libc::LOG_PID, libc::LOG_DAEMON) };
+    SYSLOG.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Writes a diagnostic to stderr or syslog, never stdout, which is kept
+/// for payloads and results. Use the `error!`, `warn!`, `info!`,
+/// `debug!` and `trace!` macros rather than calling this directly.
+pub fn log(level: Level, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let message = args.to_string();
+    if SYSLOG.load(Ordering::Relaxed) {
+        // one entry per line, as syslog has no room for reports' layout
+        for line in message.lines().filter(|line| !line.trim().is_empty()) {
+            if let Ok(line) = CString::new(line) {
+                let format = b"%s\0".as_ptr() as *const libc::c_char;
+                unsafe { libc::syslog(level.syslog_priority(), format, line.as_ptr()) };
+            }
+        }
+        return;
+    }
+    let line = if JSON.load(Ordering::Relaxed) {
+        format!("{{\"time\":\"{}\",\"level\":\"{}\",\"message\":{}}}\n",
+                timestamp::iso8601(SystemTime::now()),
+                level.name(),
+                // reports open with a blank line to set them apart in text
+                json_string(message.trim_start_matches('\n')))
+    } else if level <= Level::Warn {
+        format!("{}: {}\n", level.name(), message)
+    } else {
+        format!("{}\n", message)
+    };
+    // a closed stderr has nowhere to report to
+    let _ = io::stderr().write_all(line.as_bytes());
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::Level::Error, format_args!($($arg)*)))
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::Level::Warn, format_args!($($arg)*)))
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::Level::Info, format_args!($($arg)*)))
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::Level::Debug, format_args!($($arg)*)))
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::Level::Trace, format_args!($($arg)*)))
+}
@@ -0,0 +1,267 @@
+use std::{fmt, net, str};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::collections::hash_map::Entry;
+
+use listen::Datagram;
+use ping;
+
+/// Sequence numbers further back than this are treated as a restarted
+/// sender rather than as duplicates or late arrivals.
+const WINDOW: u64 = 1024;
+
+/// Pulls a sequence number out of a datagram payload.
+pub trait SequenceExtractor {
+    fn extract(&self, data: &[u8]) -> Option<u64>;
+}
+
+/// Sequence numbers of mccat's own "PING <n>" probes.
+pub struct PingSequence;
+
+impl SequenceExtractor for PingSequence {
+    fn extract(&self, data: &[u8]) -> Option<u64> {
+        ping::parse_seqnum(data, ping::PING)
+    }
+}
+
+/// A big-endian unsigned integer of `width` bytes at byte `offset`.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldSequence {
+    pub offset: usize,
+    pub width: usize,
+}
+
+impl SequenceExtractor for FieldSequence {
+    fn extract(&self, data: &[u8]) -> Option<u64> {
+        data.get(self.offset..self.offset + self.width)
+            .map(|field| field.iter().fold(0, |acc, &b| acc << 8 | u64::from(b)))
+    }
+}
+
+impl str::FromStr for FieldSequence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FieldSequence, String> {
+        let invalid = || format!("invalid sequence field {}: expected OFFSET:WIDTH with WIDTH 1-8", s);
+        let mut parts = s.splitn(2, ':');
+        let offset = parts.next().and_then(|p| p.parse().ok()).ok_or_else(invalid)?;
+        let width = parts.next().and_then(|p| p.parse().ok()).ok_or_else(invalid)?;
+        if width == 0 || width > 8 {
+            return Err(invalid());
+        }
+        Ok(FieldSequence { offset, width })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LossEvent {
+    /// `count` packets starting at sequence number `first` never arrived.
+    Missing { first: u64, count: u64 },
+    /// A sequence number that had already been received.
+    Duplicate(u64),
+    /// A sequence number previously counted as missing arrived late,
+    /// `extent` packets after the first one that overtook it.
+    Reordered { seq: u64, extent: u64 },
+    /// The sender jumped backwards, most likely because it restarted.
+    Restart(u64),
+}
+
+impl fmt::Display for LossEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LossEvent::Missing { first, count: 1 } => write!(f, "missing seq {}", first),
+            LossEvent::Missing { first, count } => {
+                write!(f, "missing {} packets (seq {}-{})", count, first, first + count - 1)
+            }
+            LossEvent::Duplicate(seq) => write!(f, "duplicate seq {}", seq),
+            LossEvent::Reordered { seq, extent } => write!(f, "reordered seq {} (extent {})", seq, extent),
+            LossEvent::Restart(seq) => write!(f, "sequence restarted at {}", seq),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LossCounts {
+    pub received: u64,
+    pub missing: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    /// Sum of the reordering extents of the reordered packets: how many
+    /// packets arrived between the first that overtook each and it (RFC
+    /// 4737).
+    pub extent_total: u64,
+    pub extent_max: u64,
+}
+
+impl LossCounts {
+    fn add(&mut self, other: &LossCounts) {
+        self.received += other.received;
+        self.missing += other.missing;
+        self.duplicated += other.duplicated;
+        self.reordered += other.reordered;
+        self.extent_total += other.extent_total;
+        self.extent_max = self.extent_max.max(other.extent_max);
+    }
+
+    /// Percentage of the packets received, not counting duplicates, that
+    /// arrived after one with a higher sequence number.
+    pub fn reordered_percent(&self) -> f64 {
+        let unique = self.received - self.duplicated;
+        if unique > 0 { self.reordered as f64 * 100.0 / unique as f64 } else { 0.0 }
+    }
+
+    pub fn mean_extent(&self) -> f64 {
+        if self.reordered > 0 { self.extent_total as f64 / self.reordered as f64 } else { 0.0 }
+    }
+
+    /// The reordered ratio and extents, for appending to a count of
+    /// reordered packets; empty if none were.
+    pub fn reordering(&self) -> String {
+        if self.reordered == 0 {
+            return String::new();
+        }
+        format!(" ({:.2}%, extent mean {:.1}, max {})",
+                self.reordered_percent(),
+                self.mean_extent(),
+                self.extent_max)
+    }
+}
+
+impl fmt::Display for LossCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "received {}, missing {}, duplicated {}, reordered {}{}",
+               self.received,
+               self.missing,
+               self.duplicated,
+               self.reordered,
+               self.reordering())
+    }
+}
+
+struct Stream {
+    highest: u64,
+    missing: BTreeSet<u64>,
+    /// Sequence numbers of the last `WINDOW` packets, other than
+    /// duplicates, in arrival order.
+    arrivals: VecDeque<u64>,
+    counts: LossCounts,
+}
+
+impl Stream {
+    fn new(seq: u64) -> Stream {
+        Stream {
+            highest: seq,
+            missing: BTreeSet::new(),
+            arrivals: VecDeque::from(vec![seq]),
+            counts: LossCounts { received: 1, ..LossCounts::default() },
+        }
+    }
+
+    fn track(&mut self, seq: u64) -> Option<LossEvent> {
+        self.counts.received += 1;
+        let event = self.classify(seq);
+        match event {
+            Some(LossEvent::Duplicate(_)) => (),
+            Some(LossEvent::Restart(_)) => self.arrivals = VecDeque::from(vec![seq]),
+            _ => {
+                self.arrivals.push_back(seq);
+                if self.arrivals.len() as u64 > WINDOW {
+                    self.arrivals.pop_front();
+                }
+            }
+        }
+        event
+    }
+
+    /// How many packets have arrived since the first with a sequence number
+    /// above `seq`, or the whole window if that has been forgotten.
+    fn extent(&self, seq: u64) -> u64 {
+        let len = self.arrivals.len();
+        let first = self.arrivals.iter().position(|&arrived| arrived > seq).unwrap_or(0);
+        (len - first) as u64
+    }
+
+    fn classify(&mut self, seq: u64) -> Option<LossEvent> {
+        if seq > self.highest {
+            let first = self.highest + 1;
+            let count = seq - first;
+            self.highest = seq;
+            if count == 0 {
+                return None;
+            }
+            self.counts.missing += count;
+            self.missing.extend(first.max(seq.saturating_sub(WINDOW))..seq);
+            while self.missing.len() as u64 > WINDOW {
+                let oldest = *self.missing.iter().next().expect("non-empty");
+                self.missing.remove(&oldest);
+            }
+            Some(LossEvent::Missing { first, count })
+        } else if self.missing.remove(&seq) {
+            self.counts.missing -= 1;
+            let extent = self.extent(seq);
+            self.counts.reordered += 1;
+            self.counts.extent_total += extent;
+            self.counts.extent_max = self.counts.extent_max.max(extent);
+            Some(LossEvent::Reordered { seq, extent })
+        } else if self.highest - seq > WINDOW {
+            self.highest = seq;
+            self.missing.clear();
+            Some(LossEvent::Restart(seq))
+        } else {
+            self.counts.duplicated += 1;
+            Some(LossEvent::Duplicate(seq))
+        }
+    }
+}
+
+/// Tracks sequence numbers per source and group and detects missing,
+/// duplicated and reordered packets.
+pub struct LossTracker {
+    extractor: Box<dyn SequenceExtractor>,
+    streams: HashMap<(net::SocketAddr, net::SocketAddr), Stream>,
+}
+
+impl LossTracker {
+    pub fn new(extractor: Box<dyn SequenceExtractor>) -> LossTracker {
+        LossTracker {
+            extractor,
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Records a datagram, returning what (if anything) was unusual about it.
+    /// Datagrams without a sequence number are ignored.
+    pub fn track(&mut self, datagram: &Datagram) -> Option<LossEvent> {
+        let seq = self.extractor.extract(&datagram.data)?;
+        match self.streams.entry((datagram.src, datagram.group)) {
+            Entry::Occupied(mut entry) => entry.get_mut().track(seq),
+            Entry::Vacant(entry) => {
+                entry.insert(Stream::new(seq));
+                None
+            }
+        }
+    }
+
+    /// Counts for the stream `datagram` belongs to.
+    pub fn counts(&self, datagram: &Datagram) -> Option<LossCounts> {
+        self.streams.get(&(datagram.src, datagram.group)).map(|stream| stream.counts)
+    }
+
+    /// Counts per (source, group) stream.
+    pub fn sources(&self) -> Vec<(net::SocketAddr, net::SocketAddr, LossCounts)> {
+        let mut sources: Vec<_> = self.streams
+            .iter()
+            .map(|(&(src, group), stream)| (src, group, stream.counts))
+            .collect();
+        sources.sort_by_key(|&(src, group, _)| (src, group));
+        sources
+    }
+
+    pub fn total(&self) -> LossCounts {
+        let mut total = LossCounts::default();
+        for stream in self.streams.values() {
+            total.add(&stream.counts);
+        }
+        total
+    }
+}
@@ -1,136 +1,3609 @@
-use std::{env, io, net, process, thread};
+extern crate clap;
+#[macro_use]
+extern crate mccat;
+
+use std::{env, fs, io, iter, net, process, time};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::error::Error;
-use std::io::prelude::*;
-use std::time::Duration;
 
-enum Command {
-    Listen,
-    Send,
-    Ping,
+use clap::Parser;
+
+use mccat::{Census, Datagram, Framing, Listener, MultiListener, MulticastSocket, PingStats, ReplyPath, SizeSweepStats,
+            SweepStats};
+use mccat::{auth, compress, daemon, dns, dscp, iface, igmp, log, metrics, mpegts, profile, relay, routing, signal, ssdp,
+            systemd, tag, timestamp, wellknown};
+use mccat::alert::{AlertKind, Alerts};
+use mccat::carousel::{self, Carousel, Packet, Receiver};
+use mccat::chat::{self, Message};
+use mccat::config::{Config as ConfigFile, Group as ConfigGroup};
+use mccat::control::{self, Command as ControlCommand, Control};
+use mccat::cycle::{self, CycleTracker};
+use mccat::dedup::Dedup;
+use mccat::event::{Event, Interest, Poll, Token};
+use mccat::exec::{Exec, Trigger};
+use mccat::filter::SourceFilter;
+use mccat::follow::Follow;
+use mccat::fragment::Reassembler;
+use mccat::iface::ScopedAddr;
+use mccat::impair::Impairment;
+use mccat::resolve::{Family, Host};
+use mccat::generate::{self, Measurement};
+use mccat::log::Level;
+use mccat::loss::{LossTracker, PingSequence, SequenceExtractor};
+use mccat::merge::{Leg, Merged, Merger, Sequence};
+use mccat::metrics::Metrics;
+use mccat::ptp::{self, PtpMonitor};
+use mccat::push::{self, Pusher};
+use mccat::rate::{Pacer, Rate};
+use mccat::mpegts::{PidReport, TsMonitor};
+use mccat::output::{BinaryOutput, CsvOutput, Decode, GroupFiles, HexOutput, JsonOutput, Output, OutputFormat,
+                     RotatedOutput, TemplateOutput, TextOutput};
+use mccat::pcap::{PcapReader, PcapWriter};
+use mccat::rotate::{RotatingFile, Rotation, SharedFile};
+use mccat::rtp::{RtpHeader, RtpMonitor};
+use mccat::sap::{self, Announcer, SapPacket, SessionDirectory, SessionEvent};
+use mccat::script::{Script, Verdict};
+use mccat::sdp::Announcement;
+use mccat::stats::{ArrivalStats, RateMeter, SourceTable, TagTable};
+use mccat::systemd::Watchdog;
+use mccat::tui::{Dashboard, Terminal};
+
+mod cli;
+
+use cli::{BridgeArgs, BufferArgs, CensusArgs, ChatArgs, Cli, Command, CtlArgs, DaemonArgs, DiscoverCommand, FamilyArgs,
+          ForwardArgs, GenerateArgs, HttpRelayArgs, IgmpArgs, ImpairArgs, InterfaceArgs, JoinArgs, ListenArgs,
+          MdnsArgs, MergeArgs, ObserveCommand, PingArgs, PtpArgs, PullFileArgs, PushArgs, PushFileArgs, QuerierArgs,
+          RelayCommand, ReplayArgs, RespondArgs, RoutingArgs, SapArgs, ScanArgs, SendArgs, SenderArgs, SsdpArgs,
+          StressCommand, StressGroupsArgs, StressJoinsArgs, ZapArgs};
+
+const EXIT_OK: i32 = 0;
+const EXIT_ERROR: i32 = 1;
+// clap exits with 2 on usage errors
+/// ping received fewer replies than required.
+const EXIT_NO_REPLY: i32 = 3;
+/// listen --expect-traffic saw no traffic for the idle timeout, or zap or
+/// stress groups none on a group.
+const EXIT_IDLE: i32 = 4;
+/// listen --alert-exit saw a group's rate stay past an --alert-rate
+/// threshold.
+const EXIT_ALERT: i32 = 5;
+
+/// Longest wait between `ping --flood` probes.
+const FLOOD_INTERVAL: time::Duration = time::Duration::from_millis(10);
+/// Probes sent at each TTL or size by `ping --ttl-sweep` and `--sweep-size`.
+const SWEEP_PROBES: u64 = 3;
+/// How often `listen --measure` reports.
+const MEASURE_INTERVAL: time::Duration = time::Duration::from_secs(1);
+/// How long `listen` blocks before checking for signals and due reports.
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(250);
+/// How often `listen --tui` redraws and reads keys.
+const TUI_INTERVAL: time::Duration = time::Duration::from_millis(100);
+/// How often `listen --decode mpegts` prints its PID table by default.
+const TS_REPORT_INTERVAL: time::Duration = time::Duration::from_secs(5);
+/// How long `discover mdns` waits for answers unless told otherwise.
+const MDNS_DURATION: time::Duration = time::Duration::from_secs(3);
+
+type AppResult<T> = Result<T, Box<dyn Error>>;
+
+/// A group listen has joined, with a copy of the socket to leave it by.
+type Membership = (net::UdpSocket, MulticastSocket);
+
+fn main() {
+    match run() {
+        Ok(code) => process::exit(code),
+        Err(err) => {
+            error!("{}", err);
+            process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+fn run() -> AppResult<i32> {
+    let cli = Cli::parse();
+    log::set_level(Level::from_verbosity(cli.verbose, cli.quiet));
+    log::set_format(cli.log_format);
+    match cli.command {
+        Command::Listen(args) => listen(&args),
+        Command::Send(args) => send(&args),
+        Command::Ping(args) => ping(&args),
+        Command::Respond(args) => respond(&args),
+        Command::Census(args) => census(&args),
+        Command::Generate(args) => generate(&args),
+        Command::Interfaces => interfaces(),
+        Command::Profiles => profiles(),
+        Command::Replay(args) => replay(&args),
+        Command::Discover(DiscoverCommand::Sap(args)) => discover_sap(&args),
+        Command::Discover(DiscoverCommand::Mdns(args)) => discover_mdns(&args),
+        Command::Discover(DiscoverCommand::Ssdp(args)) => discover_ssdp(&args),
+        Command::Relay(RelayCommand::Http(args)) => relay_http(&args),
+        Command::Bridge(args) => bridge(&args),
+        Command::Forward(args) => forward(&args),
+        Command::Push(args) => push_file(&args),
+        Command::Pull(args) => pull_file(&args),
+        Command::Chat(args) => chat(&args),
+        Command::Scan(args) => scan(&args),
+        Command::Join(args) => join(&args),
+        Command::Zap(args) => zap(&args),
+        Command::Merge(args) => merge(&args),
+        Command::Observe(ObserveCommand::Igmp(args)) => observe_igmp(&args),
+        Command::Observe(ObserveCommand::Ptp(args)) => observe_ptp(&args),
+        Command::Observe(ObserveCommand::Routing(args)) => observe_routing(&args),
+        Command::Querier(args) => querier(&args),
+        Command::Stress(StressCommand::Joins(args)) => stress_joins(&args),
+        Command::Stress(StressCommand::Groups(args)) => stress_groups(&args),
+        Command::Ctl(args) => ctl(&args),
+    }
+}
+
+/// Ends startup: with --daemon, moves to the background and logs to syslog
+/// from then on, then tells systemd, when it runs us, that we're ready.
+/// Sockets are set up first so that mistakes are still reported on the
+/// terminal.
+fn finish_startup(args: &DaemonArgs) -> AppResult<Option<daemon::Pidfile>> {
+    let pidfile = if args.daemon {
+        log::to_syslog();
+        daemon::daemonize(args.pidfile.as_deref())?
+    } else {
+        None
+    };
+    notify_systemd("READY=1");
+    Ok(pidfile)
+}
+
+/// Passes a state change on to systemd, only warning if that fails, as
+/// the service works regardless.
+fn notify_systemd(state: &str) {
+    if let Err(err) = systemd::notify(state) {
+        warn!("can't notify systemd: {}", err);
+    }
+}
+
+fn invalid_input<S: Into<String>>(msg: S) -> Box<dyn Error> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidInput, msg.into()))
+}
+
+fn apply_interface(config: &mut MulticastSocket, args: &InterfaceArgs) {
+    if let Some(ref interface) = args.interface {
+        config.interface(interface);
+    }
+}
+
+/// Uses the interface a scoped address names, in place of -I.
+fn apply_zone(config: &mut MulticastSocket, address: &ScopedAddr) {
+    if let Some(ref zone) = address.zone {
+        config.interface(zone);
+    }
+}
+
+fn apply_buffers(config: &mut MulticastSocket, args: &BufferArgs) {
+    if let Some(size) = args.rcvbuf {
+        config.recv_buffer_size(size);
+    }
+    if let Some(size) = args.sndbuf {
+        config.send_buffer_size(size);
+    }
+}
+
+/// The impairments asked for, if any.
+fn impairment(args: &ImpairArgs) -> Option<Impairment> {
+    if args.drop.is_none() && args.delay.is_none() && args.duplicate.is_none() && args.reorder.is_none() {
+        return None;
+    }
+    let mut impairment = Impairment::new();
+    impairment.drop(args.drop.unwrap_or(0.0))
+              .duplicate(args.duplicate.unwrap_or(0.0))
+              .reorder(args.reorder.unwrap_or(0.0))
+              .delay(args.delay.unwrap_or_default());
+    if let Some(seed) = args.seed {
+        impairment.seed(seed);
+    }
+    Some(impairment)
+}
+
+/// Sends what `impairment` has due on `sock`, returning how long to wait
+/// for more, up to `POLL_INTERVAL`.
+fn send_impaired(impairment: &mut Impairment, sock: &net::UdpSocket) -> io::Result<time::Duration> {
+    for (data, to) in impairment.ready() {
+        sock.send_to(&data, to)?;
+    }
+    // a zero timeout would block forever
+    Ok(impairment.wait().map_or(POLL_INTERVAL, |wait| wait.clamp(time::Duration::from_millis(1), POLL_INTERVAL)))
+}
+
+fn report_impaired(impairment: &Impairment, direction: &str) {
+    let counts = impairment.counts();
+    info!("impaired {}: dropped {}, duplicated {}, reordered {}",
+          direction, counts.dropped, counts.duplicated, counts.reordered);
+}
+
+/// Tells the user what the kernel made of the requested buffer sizes.
+fn report_buffers(sock: &net::UdpSocket, name: &str, args: &BufferArgs) -> AppResult<()> {
+    if let Some(size) = args.rcvbuf {
+        info!("{}: receive buffer requested {} bytes, granted {} bytes",
+              name, size, mccat::recv_buffer_size(sock)?);
+    }
+    if let Some(size) = args.sndbuf {
+        info!("{}: send buffer requested {} bytes, granted {} bytes",
+              name, size, mccat::send_buffer_size(sock)?);
+    }
+    Ok(())
+}
+
+fn family(args: &FamilyArgs) -> Family {
+    match (args.ipv4, args.ipv6) {
+        (true, _) => Family::V4,
+        (_, true) => Family::V6,
+        _ => Family::Any,
+    }
+}
+
+fn sender_config(address: &Host, port: u16, args: &SenderArgs) -> AppResult<MulticastSocket> {
+    if args.interfaces.len() > 1 {
+        return Err(invalid_input("only send, generate and ping take more than one -I"));
+    }
+    Ok(sender_configs(address, port, args)?.remove(0))
+}
+
+/// A sender for each -I, or just the one without any.
+fn sender_configs(address: &Host, port: u16, args: &SenderArgs) -> AppResult<Vec<MulticastSocket>> {
+    let address = address.resolve(family(&args.family))?;
+    let mut config = MulticastSocket::new(address.addr, port);
+    config.ttl(args.ttl).loopback(args.loopback);
+    if let Some(tos) = args.tos.or(args.dscp.map(dscp::to_tos)) {
+        config.tos(tos);
+    }
+    apply_buffers(&mut config, &args.buffers);
+    if args.interfaces.len() > 1 && address.zone.is_some() {
+        return Err(invalid_input(format!("{} names its interface; leave out -I", address)));
+    }
+    let mut configs = match args.interfaces.len() {
+        0 => vec![config],
+        _ => {
+            args.interfaces
+                .iter()
+                .map(|interface| {
+                    let mut config = config.clone();
+                    config.interface(interface);
+                    config
+                })
+                .collect()
+        }
+    };
+    for config in &mut configs {
+        apply_zone(config, &address);
+    }
+    Ok(configs)
+}
+
+/// The groups given as ADDRESS PORT pairs, profile names and --group. A
+/// name that is not a profile is looked up in DNS.
+fn listen_groups(args: &ListenArgs) -> AppResult<Vec<ConfigGroup>> {
+    let mut groups: Vec<ConfigGroup> = Vec::new();
+    // read only when a name is given
+    let mut profiles = None;
+    let mut endpoints = args.endpoints.iter();
+    while let Some(endpoint) = endpoints.next() {
+        let host: Host = endpoint.parse().map_err(invalid_input)?;
+        if let Host::Name(ref name) = host {
+            if profiles.is_none() {
+                profiles = Some(profile::load().map_err(invalid_input)?);
+            }
+            if let Some(profile) = profiles.iter().flatten().find(|profile| profile.name == *name) {
+                groups.push(profile.group.clone());
+                continue;
+            }
+        }
+        let address = host.resolve(family(&args.family)).map_err(|err| {
+            invalid_input(format!("{} is neither a profile listed by mccat profiles nor a group name: {}", host, err))
+        })?;
+        let ports = match endpoints.next() {
+            Some(ports) => cli::parse_ports(ports).map_err(invalid_input)?,
+            None => return Err(invalid_input(format!("{} needs a port", host))),
+        };
+        for port in ports {
+            groups.push(ConfigGroup {
+                addr: (address.addr, port).into(),
+                interface: address.zone.clone(),
+                source: None,
+            });
+        }
+    }
+    groups.extend(args.groups.iter().cloned());
+    if args.dual_stack {
+        for group in groups.clone() {
+            let other = wellknown::counterpart(group.addr.ip()).ok_or_else(|| {
+                invalid_input(format!("{} has no well-known counterpart in the other address family",
+                                      group.addr.ip()))
+            })?;
+            let other = (other, group.addr.port()).into();
+            if !groups.iter().any(|group| group.addr == other) {
+                // a source is of the one family only
+                groups.push(ConfigGroup {
+                    addr: other,
+                    interface: group.interface,
+                    source: None,
+                });
+            }
+        }
+    }
+    Ok(groups)
+}
+
+fn source_filter(args: &ListenArgs) -> SourceFilter {
+    let mut filter = SourceFilter::new();
+    for &cidr in &args.allow_source {
+        filter.allow(cidr);
+    }
+    for &cidr in &args.block_source {
+        filter.block(cidr);
+    }
+    filter
+}
+
+/// Which datagrams listen passes on, per the command line and the config
+/// file, whose lists add to those given as options.
+fn selection(args: &ListenArgs, file: Option<&ConfigFile>) -> impl Fn(&Datagram) -> bool {
+    let mut filter = source_filter(args);
+    let mut patterns = args.patterns.clone();
+    let mut invert = args.invert_match;
+    if let Some(file) = file {
+        for &cidr in &file.allow_source {
+            filter.allow(cidr);
+        }
+        for &cidr in &file.block_source {
+            filter.block(cidr);
+        }
+        patterns.extend(file.patterns.iter().cloned());
+        invert |= file.invert_match;
+    }
+    move |datagram: &Datagram| {
+        filter.allows(&datagram.src.ip()) &&
+        (patterns.is_empty() || patterns.iter().any(|pattern| pattern.matches(&datagram.data)) != invert)
+    }
+}
+
+/// Appends to `path`, or writes to stdout if `None`.
+fn open_output(path: Option<&Path>) -> AppResult<Box<dyn Write>> {
+    match path {
+        Some(path) => {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|err| format!("can't open {}: {}", path.display(), err))?;
+            Ok(Box::new(io::BufWriter::new(file)))
+        }
+        None => Ok(Box::new(io::stdout().lock())),
+    }
+}
+
+/// Decompresses `datagram` if it was sent compressed. Returns false, having
+/// warned, if it doesn't decompress.
+fn restore(datagram: &mut Datagram) -> bool {
+    match compress::decompress(&datagram.data) {
+        Ok(Some(data)) => {
+            datagram.size = data.len();
+            datagram.data = data;
+            true
+        }
+        Ok(None) => true,
+        Err(err) => {
+            warn!("dropping a datagram from {} that doesn't decompress: {}", datagram.src, err);
+            false
+        }
+    }
+}
+
+/// Passes `datagram` through `script`, rewriting it if the script says so.
+/// Returns false if the script dropped it.
+fn judge(script: &mut Script, datagram: &mut Datagram) -> AppResult<bool> {
+    let verdict = script.judge(datagram).map_err(|err| format!("--script: {}", err))?;
+    match verdict {
+        Verdict::Pass => {}
+        Verdict::Drop => return Ok(false),
+        Verdict::Rewrite(payload) => {
+            datagram.size = payload.len();
+            datagram.data = payload;
+        }
+        Verdict::Annotate(note) => info!("{} {}", datagram.src, note),
+    }
+    Ok(true)
+}
+
+/// `listen_output` onto `path`, appended to and rotated per `rotation`,
+/// or onto stdout.
+fn file_output(args: &ListenArgs, format: OutputFormat, multiple: bool, path: Option<&Path>, rotation: Rotation)
+               -> AppResult<Box<dyn Output>> {
+    match path {
+        Some(path) if rotation.enabled() => {
+            let file = RotatingFile::append(path, rotation)
+                .map_err(|err| format!("can't open {}: {}", path.display(), err))?;
+            let file = SharedFile::new(file);
+            Ok(Box::new(RotatedOutput::new(file.clone(), listen_output(args, format, multiple, file))))
+        }
+        path => Ok(listen_output(args, format, multiple, open_output(path)?)),
+    }
+}
+
+/// Formats received datagrams as `args` ask, onto `out`.
+fn listen_output<W: Write + 'static>(args: &ListenArgs, format: OutputFormat, multiple: bool, out: W)
+                                     -> Box<dyn Output> {
+    match (args.format.clone(), format) {
+        (Some(template), _) => {
+            let output = TemplateOutput::new(out, template);
+            match args.timestamp {
+                Some(format) => Box::new(output.timestamps(format)),
+                None => Box::new(output),
+            }
+        }
+        (None, OutputFormat::Binary) => Box::new(BinaryOutput::new(out, args.length_prefix)),
+        (None, OutputFormat::Json) => Box::new(JsonOutput::new(out).payload_encoding(args.payload_encoding)),
+        (None, OutputFormat::Csv) => {
+            let output = CsvOutput::new(out, args.columns.clone()).payload_encoding(args.payload_encoding);
+            match args.timestamp {
+                Some(format) => Box::new(output.timestamps(format)),
+                None => Box::new(output),
+            }
+        }
+        (None, OutputFormat::Hex) => {
+            let output = HexOutput::new(out).show_group(multiple).verbose(args.verbose);
+            match args.timestamp {
+                Some(format) => Box::new(output.timestamps(format)),
+                None => Box::new(output),
+            }
+        }
+        (None, OutputFormat::Text) => {
+            let output = TextOutput::new(out).show_group(multiple).verbose(args.verbose);
+            match args.timestamp {
+                Some(format) => Box::new(output.timestamps(format)),
+                None => Box::new(output),
+            }
+        }
+    }
+}
+
+/// `shared` when there may be more than one group.
+fn listen_config(args: &ListenArgs, group: net::SocketAddr, shared: bool) -> MulticastSocket {
+    let mut config = MulticastSocket::new(group.ip(), group.port());
+    apply_interface(&mut config, &args.interface);
+    config.reuse(args.reuse).reuse_port(args.reuse_port).hardware_timestamps(args.hardware_timestamps);
+    apply_buffers(&mut config, &args.buffers);
+    if let Some(source) = args.source {
+        config.source(source);
+    }
+    if args.kernel_filter {
+        config.source_filter(source_filter(args));
+    }
+    if shared {
+        // one socket per group, possibly sharing a port
+        config.reuse(true).multicast_all(false);
+    }
+    config
+}
+
+/// A group from a profile or the config file, on the interface and from
+/// the sender it names, if any.
+fn group_config(args: &ListenArgs, group: &ConfigGroup, shared: bool) -> MulticastSocket {
+    let mut config = listen_config(args, group.addr, shared);
+    if let Some(ref interface) = group.interface {
+        config.interface(interface);
+    }
+    if let Some(source) = group.source {
+        config.source(source);
+    }
+    config
+}
+
+fn listen_configs(args: &ListenArgs, file: Option<&ConfigFile>) -> AppResult<Vec<MulticastSocket>> {
+    let groups = listen_groups(args)?;
+    // with --control or --config, more groups may be joined later
+    let shared = groups.len() > 1 || args.control.is_some() || file.is_some();
+    let mut configs: Vec<_> = groups.iter().map(|group| group_config(args, group, shared)).collect();
+    for group in file.map_or(&[][..], |file| &file.groups) {
+        if configs.iter().any(|config| config.addr() == group.addr) {
+            return Err(invalid_input(format!("{} is given both on the command line and in the config file",
+                                             group.addr)));
+        }
+        configs.push(group_config(args, group, true));
+    }
+    if configs.is_empty() {
+        return Err(invalid_input("no group given"));
+    }
+    Ok(configs)
+}
+
+/// Joins a group for listen, which answers probes itself once they have
+/// passed its filters.
+fn join_group(args: &ListenArgs, config: &MulticastSocket) -> AppResult<Listener> {
+    let mut listener = mccat::listen(config)?;
+    listener.buffer_size(args.buffer_size).batch(args.batch).answer_pings(false);
+    if args.reply == ReplyPath::Multicast {
+        listener.reply_to_group(config.sender()?, config.target()?);
+    }
+    report_buffers(listener.socket(), &config.addr().to_string(), &args.buffers)?;
+    debug!("joined {}", config.addr());
+    Ok(listener)
+}
+
+/// Answers `datagram`, received by the listener at `index`, if it is a
+/// probe, and with `key`, one with a good tag. Senders that listen filters
+/// out get no reply, so they can't use it to find or reflect off it.
+fn answer_probe(listener: &MultiListener, index: usize, datagram: &Datagram, key: Option<&auth::Key>) {
+    if !mccat::is_probe(&datagram.data) || key.is_some_and(|key| key.verify(&datagram.data).is_none()) {
+        return;
+    }
+    if let Err(err) = listener.answer(index, datagram) {
+        warn!("can't answer a probe from {}: {}", datagram.src, err);
+    }
+}
+
+/// What `listen` resolves from its arguments before it joins anything.
+struct ListenPaths {
+    config: Option<PathBuf>,
+    file: Option<ConfigFile>,
+    output_dir: Option<PathBuf>,
+    script: Option<PathBuf>,
+    decoder: Option<PathBuf>,
+    output_file: Option<PathBuf>,
+    rotation: Rotation,
+}
+
+/// Resolves the paths in `args`, loading the config file, and checks that
+/// the options given go together.
+fn listen_paths(args: &ListenArgs) -> AppResult<ListenPaths> {
+    // resolved before --daemon moves to /
+    let config_path = match args.config {
+        Some(ref path) => Some(env::current_dir()?.join(path)),
+        None => None,
+    };
+    let file = match config_path {
+        Some(ref path) => Some(ConfigFile::load(path).map_err(invalid_input)?),
+        None => None,
+    };
+    let output_dir = match args.output_dir {
+        Some(ref dir) => {
+            let dir = env::current_dir()?.join(dir);
+            fs::create_dir_all(&dir).map_err(|err| format!("can't create {}: {}", dir.display(), err))?;
+            Some(dir)
+        }
+        None => None,
+    };
+    let script_path = match args.script {
+        Some(ref path) => Some(env::current_dir()?.join(path)),
+        None => None,
+    };
+    let decoder_path = match args.decoder {
+        Some(ref path) => Some(env::current_dir()?.join(path)),
+        None => None,
+    };
+    let output_file = match args.output_file {
+        Some(ref path) => Some(env::current_dir()?.join(path)),
+        None => file.as_ref().and_then(|file| file.output.clone()),
+    };
+    let rotation = Rotation {
+        max_size: args.rotate_size.map(|size| size as u64),
+        interval: args.rotate_interval,
+        compress: args.compress,
+    };
+    if rotation.enabled() && output_dir.is_none() && output_file.is_none() {
+        return Err(invalid_input("--rotate-size and --rotate-interval need --output-file, --output-dir or an output \
+                                  in --config"));
+    }
+    if args.seq_field.is_some() && !args.detect_loss && !args.dedup {
+        return Err(invalid_input("--seq-field needs --detect-loss or --dedup"));
+    }
+    let on_idle = args.exec_on.iter().any(|&on| on == Trigger::Down || on == Trigger::Up);
+    if args.exec.is_some() && args.idle_timeout.is_none() && on_idle {
+        return Err(invalid_input("--exec-on down and up need --idle-timeout"));
+    }
+    if args.alert_rate.is_empty() && args.exec_on.iter().any(|&on| on == Trigger::Alert || on == Trigger::Clear) {
+        return Err(invalid_input("--exec-on alert and clear need --alert-rate"));
+    }
+    if let Some(compression) = args.compress {
+        if !rotation.enabled() {
+            return Err(invalid_input("--compress needs --rotate-size or --rotate-interval"));
+        }
+        compression.check()?;
+    }
+    Ok(ListenPaths {
+        config: config_path,
+        file,
+        output_dir,
+        script: script_path,
+        decoder: decoder_path,
+        output_file,
+        rotation,
+    })
+}
+
+/// Joins every group in `configs`, keeping a copy of each socket to leave
+/// the group by and read its drops from.
+fn join_groups(args: &ListenArgs, configs: Vec<MulticastSocket>) -> AppResult<(Vec<Listener>, Vec<Membership>)> {
+    let mut listeners = Vec::new();
+    let mut memberships = Vec::new();
+    for config in configs {
+        let listener = join_group(args, &config)?;
+        memberships.push((listener.socket().try_clone()?, config));
+        listeners.push(listener);
+    }
+    Ok((listeners, memberships))
+}
+
+fn listen(args: &ListenArgs) -> AppResult<i32> {
+    let paths = listen_paths(args)?;
+    let configs = listen_configs(args, paths.file.as_ref())?;
+    let multiple = configs.len() > 1 || args.control.is_some() || paths.file.is_some();
+    let (listeners, memberships) = join_groups(args, configs)?;
+    let control = match args.control {
+        Some(ref path) => Some(Control::bind(path)?),
+        None => None,
+    };
+    let _pidfile = finish_startup(&args.daemon)?;
+    let mut watchdog = Watchdog::from_env();
+    signal::install()?;
+    if paths.file.is_some() {
+        signal::install_hangup()?;
+    }
+    let mut listener = MultiListener::new(listeners)?;
+    listener.timeout(Some(POLL_INTERVAL));
+    if args.measure {
+        let groups: Vec<_> = memberships.iter().map(|(_, config)| config.addr().to_string()).collect();
+        info!("Measuring on {}", groups.join(", "));
+        let mut measurement = Measurement::new();
+        if args.one_way_delay {
+            measurement.one_way_delay(args.synced_clocks);
+        }
+        let wanted = selection(args, paths.file.as_ref());
+        let key = args.key.clone();
+        let datagrams = iter::from_fn(move || loop {
+            match listener.recv_indexed() {
+                Ok((index, datagram)) if wanted(&datagram) => {
+                    answer_probe(&listener, index, &datagram, key.as_ref());
+                    return Some(Ok(datagram));
+                }
+                Ok(_) => {}
+                Err(err) => return Some(Err(err)),
+            }
+        });
+        let exit = measure(Box::new(datagrams), measurement, args.duration, &mut watchdog)?;
+        leave_groups(&memberships)?;
+        return Ok(exit);
+    }
+    let format = match args.output {
+        Some(format) => format,
+        None if args.binary => OutputFormat::Binary,
+        None if args.hex => OutputFormat::Hex,
+        None => OutputFormat::Text,
+    };
+    if args.length_prefix && format != OutputFormat::Binary && paths.output_dir.is_none() {
+        return Err(invalid_input("--length-prefix requires binary output"));
+    }
+    let mut state = ListenState::new(args, paths, format, multiple, listener, memberships)?;
+    state.run(control.as_ref(), &mut watchdog)?;
+    state.finish()
+}
+
+/// Everything `listen` keeps between datagrams: the groups joined, where
+/// the output goes, and what each option tracks.
+struct ListenState<'a> {
+    args: &'a ListenArgs,
+    config_path: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    format: OutputFormat,
+    multiple: bool,
+    rotation: Rotation,
+    listener: MultiListener,
+    memberships: Vec<Membership>,
+    wanted: Box<dyn Fn(&Datagram) -> bool>,
+    /// The groups joined from the config file, to compare with it on SIGHUP.
+    file_groups: Vec<ConfigGroup>,
+    groups: Vec<String>,
+    output: Box<dyn Output>,
+    loss: Option<LossTracker>,
+    dedup: Option<Dedup>,
+    pcap: Option<PcapWriter>,
+    rtp: Option<RtpMonitor>,
+    ts: Option<(TsMonitor, time::Duration, time::Instant)>,
+    /// In the default text output, decoded RTP headers replace payloads,
+    /// and mpegts payloads are left out in favour of the per-PID tables.
+    decoded_text: bool,
+    stats: Option<ArrivalStats>,
+    sources: Option<SourceTable>,
+    tags: Option<TagTable>,
+    next_stats: Option<time::Instant>,
+    deadline: Option<time::Instant>,
+    received: u64,
+    session: RateMeter,
+    last_traffic: time::Instant,
+    idle_reported: bool,
+    exec: Option<Exec>,
+    alerts: Option<Alerts>,
+    unauthenticated: u64,
+    undecryptable: u64,
+    cycles: Option<CycleTracker>,
+    reassembler: Option<Reassembler>,
+    script: Option<Script>,
+    decoder: Option<Script>,
+    exit: i32,
+    shutdown: bool,
+    meter: Option<(RateMeter, time::Instant)>,
+    pushers: Vec<Pusher>,
+    next_push: time::Instant,
+    metrics: Option<Arc<Mutex<Metrics>>>,
+    next_drops_check: time::Instant,
+    log_level: Level,
+    dashboard: Option<(Terminal, Dashboard, time::Instant)>,
+}
+
+impl<'a> ListenState<'a> {
+    fn new(args: &'a ListenArgs,
+           paths: ListenPaths,
+           format: OutputFormat,
+           multiple: bool,
+           listener: MultiListener,
+           memberships: Vec<Membership>)
+           -> AppResult<ListenState<'a>> {
+        let wanted = Box::new(selection(args, paths.file.as_ref()));
+        let file_groups = paths.file.as_ref().map_or_else(Vec::new, |file| file.groups.clone());
+        let groups: Vec<_> = memberships.iter().map(|(_, config)| config.addr().to_string()).collect();
+        info!("Listening on {}", groups.join(", "));
+        let output: Box<dyn Output> = match paths.output_dir {
+            Some(ref dir) => {
+                Box::new(GroupFiles::new(dir, paths.rotation, args.length_prefix).max_tag_files(args.max_tag_files))
+            }
+            None => file_output(args, format, multiple, paths.output_file.as_deref(), paths.rotation)?,
+        };
+        let loss = if args.detect_loss {
+            let extractor: Box<dyn SequenceExtractor> = match args.seq_field {
+                Some(field) => Box::new(field),
+                None => Box::new(PingSequence),
+            };
+            Some(LossTracker::new(extractor))
+        } else {
+            None
+        };
+        let dedup = if args.dedup {
+            let mut dedup = Dedup::new(args.dedup_window as usize);
+            if let Some(field) = args.seq_field {
+                dedup.sequence(Box::new(field));
+            }
+            Some(dedup)
+        } else {
+            None
+        };
+        let pcap = match args.write_pcap {
+            Some(ref path) => {
+                let rotation = Rotation {
+                    max_size: args.pcap_rotate_size.map(|size| size as u64),
+                    interval: args.pcap_rotate_interval,
+                    compress: None,
+                };
+                Some(PcapWriter::create(path, rotation)?)
+            }
+            None => None,
+        };
+        let rtp = match args.decode {
+            Some(Decode::Rtp) => {
+                let mut monitor = RtpMonitor::new();
+                if let Some(rate) = args.clock_rate {
+                    monitor.clock_rate(rate);
+                }
+                Some(monitor)
+            }
+            _ => None,
+        };
+        let ts = match args.decode {
+            Some(Decode::Mpegts) => {
+                let interval = args.report_interval.unwrap_or(TS_REPORT_INTERVAL);
+                Some((TsMonitor::new(), interval, time::Instant::now() + interval))
+            }
+            _ => None,
+        };
+        let script = match paths.script {
+            Some(ref path) => Some(Script::spawn(path)?),
+            None => None,
+        };
+        let decoder = match paths.decoder {
+            Some(ref path) => Some(Script::spawn(path)?),
+            None => None,
+        };
+        let pushers = pushers(&args.push)?;
+        let metrics = if args.metrics_listen.is_some() || !pushers.is_empty() {
+            let addrs: Vec<_> = memberships.iter().map(|(_, config)| config.addr()).collect();
+            Some(Arc::new(Mutex::new(Metrics::new(&addrs))))
+        } else {
+            None
+        };
+        if let (Some(addr), Some(metrics)) = (args.metrics_listen, metrics.as_ref()) {
+            info!("serving metrics on http://{}/metrics", metrics::serve(addr, metrics.clone())?);
+        }
+        let decoded_text = args.decode.is_some() && format == OutputFormat::Text && args.format.is_none() &&
+                           paths.output_dir.is_none();
+        let log_level = log::level();
+        let dashboard = if args.tui {
+            let addrs: Vec<_> = memberships.iter().map(|(_, config)| config.addr()).collect();
+            let terminal = Terminal::enter()?;
+            // anything else written to the terminal would scribble over it
+            log::set_level(Level::Error);
+            Some((terminal, Dashboard::new(&addrs), time::Instant::now()))
+        } else {
+            None
+        };
+        Ok(ListenState {
+            args,
+            config_path: paths.config,
+            output_dir: paths.output_dir,
+            format,
+            multiple,
+            rotation: paths.rotation,
+            listener,
+            memberships,
+            wanted,
+            file_groups,
+            groups,
+            output,
+            loss,
+            dedup,
+            pcap,
+            rtp,
+            ts,
+            decoded_text,
+            stats: if args.stats { Some(ArrivalStats::new()) } else { None },
+            sources: if args.per_source { Some(SourceTable::new()) } else { None },
+            tags: if args.by_tag { Some(TagTable::new()) } else { None },
+            next_stats: args.stats_interval.map(|interval| time::Instant::now() + interval),
+            deadline: args.duration.map(|duration| time::Instant::now() + duration),
+            received: 0,
+            session: RateMeter::new(),
+            last_traffic: time::Instant::now(),
+            idle_reported: false,
+            exec: args.exec.as_ref().map(|command| Exec::new(command, &args.exec_on)),
+            alerts: if args.alert_rate.is_empty() {
+                None
+            } else {
+                Some(Alerts::new(&args.alert_rate, args.alert_after))
+            },
+            unauthenticated: 0,
+            undecryptable: 0,
+            cycles: if args.carousel { Some(CycleTracker::new()) } else { None },
+            reassembler: args.frame.as_ref().map(|_| Reassembler::new()),
+            script,
+            decoder,
+            exit: EXIT_OK,
+            shutdown: false,
+            meter: args.report_interval.map(|interval| (RateMeter::new(), time::Instant::now() + interval)),
+            pushers,
+            next_push: time::Instant::now() + args.push.push_interval,
+            metrics,
+            next_drops_check: time::Instant::now(),
+            log_level,
+            dashboard,
+        })
+    }
+
+    /// Receives until interrupted, shut down over `control`, or done as
+    /// --duration, --count, --expect-traffic or --alert-exit say.
+    fn run(&mut self, control: Option<&Control>, watchdog: &mut Watchdog) -> AppResult<()> {
+        while !self.shutdown {
+            if signal::interrupted() || self.deadline.is_some_and(|deadline| time::Instant::now() >= deadline) {
+                break;
+            }
+            if watchdog.due() {
+                notify_systemd("WATCHDOG=1");
+            }
+            if signal::hangup() {
+                self.hangup()?;
+            }
+            while let Some(request) = control.map_or(Ok(None), Control::accept)? {
+                let result = self.command(&request.command)?;
+                match result {
+                    Ok(_) => info!("control: {}", request.line()),
+                    Err(ref err) => warn!("control: {}: {}", request.line(), err),
+                }
+                self.refresh_groups();
+                if let Err(err) = request.reply(result) {
+                    warn!("control: can't reply: {}", err);
+                }
+            }
+            if !self.periodic()? {
+                break;
+            }
+            let (index, datagram) = match self.listener.recv_indexed() {
+                Ok(received) => received,
+                Err(ref err) if timed_out(err) => continue,
+                // checked for SIGINT, SIGTERM and SIGHUP at the top of the loop
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if !self.accept(index, &datagram) {
+                continue;
+            }
+            let mut datagram = match self.unwrap(datagram)? {
+                Some(datagram) => datagram,
+                None => continue,
+            };
+            self.record(&datagram)?;
+            self.decode(&mut datagram)?;
+            self.deliver(&datagram)?;
+            self.received += 1;
+            if self.args.count.is_some_and(|count| self.received >= count) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn refresh_groups(&mut self) {
+        self.groups = self.memberships.iter().map(|(_, config)| config.addr().to_string()).collect();
+    }
+
+    /// Reloads the --config file, if there is one, and reopens its output.
+    fn hangup(&mut self) -> AppResult<()> {
+        let path = match self.config_path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        match reload(self.args, path, &mut self.file_groups, &mut self.listener, &mut self.memberships) {
+            Ok(file) => {
+                self.wanted = Box::new(selection(self.args, Some(&file)));
+                // reopened even if unchanged, so it can be rotated
+                if self.output_dir.is_none() {
+                    self.output.flush()?;
+                    match file_output(self.args, self.format, self.multiple, file.output.as_deref(), self.rotation) {
+                        Ok(reopened) => self.output = reopened,
+                        Err(err) => warn!("{}", err),
+                    }
+                }
+                info!("reloaded {}", path.display());
+            }
+            Err(err) => warn!("keeping the previous configuration: {}", err),
+        }
+        self.refresh_groups();
+        Ok(())
+    }
+
+    /// Carries out a --control request, failing only if listen can't go on.
+    fn command(&mut self, command: &Result<ControlCommand, String>) -> AppResult<Result<String, String>> {
+        let args = self.args;
+        let result = match *command {
+            Ok(ControlCommand::Join(group)) => {
+                add_group(args, listen_config(args, group, true), &mut self.listener, &mut self.memberships)
+                    .map(|()| String::new())
+                    .map_err(|err| err.to_string())
+            }
+            Ok(ControlCommand::Leave(group)) if self.memberships.iter().all(|(_, config)| config.addr() == group) => {
+                Err("can't leave the last group; use shutdown".to_owned())
+            }
+            Ok(ControlCommand::Leave(group)) => {
+                remove_group(group, &mut self.listener, &mut self.memberships)
+                    .map(|()| String::new())
+                    .map_err(|err| err.to_string())
+            }
+            Ok(ControlCommand::Groups) => Ok(self.groups.join("\n")),
+            Ok(ControlCommand::Stats) => {
+                let mut body = format!("{}: {}", self.groups.join(", "), self.session.peek());
+                if let Some(ref mut stats) = self.stats {
+                    refresh_kernel_drops(stats, &self.memberships);
+                    body.push_str(&format!("\n{}", stats.summary()));
+                }
+                if let Some(ref sources) = self.sources {
+                    for source in sources.top_talkers() {
+                        body.push_str(&format!("\n  {}", source));
+                    }
+                }
+                if let Some(ref tags) = self.tags {
+                    for tag in tags.tags() {
+                        body.push_str(&format!("\n  {}", tag));
+                    }
+                }
+                Ok(body)
+            }
+            Ok(ControlCommand::Output(_)) if self.output_dir.is_some() => Err("output goes to --output-dir".to_owned()),
+            Ok(ControlCommand::Output(ref path)) => {
+                self.output.flush()?;
+                match file_output(args, self.format, self.multiple, path.as_deref(), self.rotation) {
+                    Ok(reopened) => {
+                        self.output = reopened;
+                        Ok(String::new())
+                    }
+                    Err(err) => Err(err.to_string()),
+                }
+            }
+            Ok(ControlCommand::Shutdown) => {
+                self.shutdown = true;
+                Ok(String::new())
+            }
+            Err(ref err) => Err(err.clone()),
+        };
+        Ok(result)
+    }
+
+    /// Reports, pushes and checks what is due between datagrams. Returns
+    /// false if listen is to stop at once.
+    fn periodic(&mut self) -> AppResult<bool> {
+        let args = self.args;
+        if let (Some(ref mut stats), Some(due)) = (self.stats.as_mut(), self.next_stats) {
+            let now = time::Instant::now();
+            if now >= due {
+                refresh_kernel_drops(stats, &self.memberships);
+                info!("{}", stats.interval());
+                self.next_stats = args.stats_interval.map(|interval| now + interval);
+            }
+        }
+        if let (Some((ref mut meter, ref mut due)), Some(interval)) = (self.meter.as_mut(), args.report_interval) {
+            let now = time::Instant::now();
+            if now >= *due {
+                info!("{} {}", timestamp::iso8601(time::SystemTime::now()), meter.report());
+                if let Some(ref sources) = self.sources {
+                    for source in sources.top_talkers() {
+                        info!("  {}", source);
+                    }
+                }
+                if let Some(ref tags) = self.tags {
+                    for tag in tags.tags() {
+                        info!("  {}", tag);
+                    }
+                }
+                *due = now + interval;
+            }
+        }
+        if let Some(ref metrics) = self.metrics {
+            let now = time::Instant::now();
+            if now >= self.next_drops_check {
+                refresh_metric_drops(&mut metrics.lock().expect("metrics lock"), &self.memberships);
+                self.next_drops_check = now + POLL_INTERVAL;
+            }
+            if !self.pushers.is_empty() && now >= self.next_push {
+                push(&mut self.pushers, &metrics.lock().expect("metrics lock").series());
+                self.next_push = now + args.push.push_interval;
+            }
+        }
+        if let Some(ref mut exec) = self.exec {
+            for status in exec.reap()? {
+                warn!("--exec command failed: {}", status);
+            }
+        }
+        if let Some(timeout) = args.idle_timeout {
+            if !self.idle_reported && self.last_traffic.elapsed() >= timeout {
+                let idle = self.last_traffic.elapsed();
+                warn!("no traffic on {} for {:.1} s", self.groups.join(", "), idle.as_secs_f64());
+                if let Some(ref mut exec) = self.exec {
+                    if let Err(err) = exec.event(Trigger::Down, &self.groups) {
+                        warn!("can't run --exec: {}", err);
+                    }
+                }
+                if args.expect_traffic {
+                    self.exit = EXIT_IDLE;
+                    return Ok(false);
+                }
+                self.idle_reported = true;
+            }
+        }
+        if let Some(alerts) = self.alerts.as_mut().filter(|alerts| alerts.due()) {
+            let addrs: Vec<_> = self.memberships.iter().map(|(_, config)| config.addr()).collect();
+            for alert in alerts.check(&addrs) {
+                match alert.kind {
+                    AlertKind::Raised => warn!("alert: {}", alert),
+                    AlertKind::Cleared => info!("cleared: {}", alert),
+                }
+                if let Some(ref mut exec) = self.exec {
+                    if let Err(err) = exec.alert(&alert) {
+                        warn!("can't run --exec: {}", err);
+                    }
+                }
+                if args.alert_exit && alert.kind == AlertKind::Raised {
+                    self.exit = EXIT_ALERT;
+                    self.shutdown = true;
+                }
+            }
+            if self.shutdown {
+                return Ok(false);
+            }
+        }
+        if let Some((ref mut terminal, ref mut dashboard, ref mut next_render)) = self.dashboard {
+            let now = time::Instant::now();
+            if now >= *next_render {
+                for key in terminal.keys()? {
+                    self.shutdown |= !dashboard.key(key);
+                }
+                let (width, height) = terminal.size();
+                terminal.draw(&dashboard.render(width, height))?;
+                *next_render = now + TUI_INTERVAL;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Whether `datagram`, received by the listener at `index`, passes the
+    /// source filters and patterns and isn't a duplicate to leave out.
+    /// Probes that pass are answered.
+    fn accept(&mut self, index: usize, datagram: &Datagram) -> bool {
+        if !(self.wanted)(datagram) {
+            return false;
+        }
+        answer_probe(&self.listener, index, datagram, self.args.key.as_ref());
+        if let Some(ref mut dedup) = self.dedup {
+            if let Some((src, received)) = dedup.check(datagram) {
+                let later = datagram.received.duration_since(received).unwrap_or_default();
+                info!("{} duplicate of a datagram from {} {:.3} ms earlier",
+                      datagram.src, src, later.as_secs_f64() * 1e3);
+                if self.args.drop_duplicates {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Verifies, untags, decrypts and decompresses `datagram`, strips its
+    /// carousel header, reassembles its message and runs it by --script.
+    /// Returns `None` for a datagram dropped on the way, or a fragment of a
+    /// message still incomplete.
+    fn unwrap(&mut self, mut datagram: Datagram) -> AppResult<Option<Datagram>> {
+        if let Some(ref key) = self.args.key {
+            match key.verify(&datagram.data).map(<[u8]>::len) {
+                Some(len) => {
+                    datagram.size -= datagram.data.len() - len;
+                    datagram.data.truncate(len);
+                }
+                None => {
+                    warn!("datagram from {} has a bad or missing tag", datagram.src);
+                    self.unauthenticated += 1;
+                }
+            }
+        }
+        if self.args.by_tag {
+            let untagged = tag::untag(&datagram.data).map(|(tag, payload)| (tag.to_owned(), payload.to_vec()));
+            if let Some((tag, payload)) = untagged {
+                datagram.size -= datagram.data.len() - payload.len();
+                datagram.data = payload;
+                datagram.tag = Some(tag);
+            }
+        }
+        if let Some(ref cipher) = self.args.encrypt {
+            match cipher.open(&datagram.data) {
+                Some(plaintext) => {
+                    datagram.size -= datagram.data.len() - plaintext.len();
+                    datagram.data = plaintext;
+                }
+                None => {
+                    warn!("dropping a datagram from {} that doesn't decrypt", datagram.src);
+                    self.undecryptable += 1;
+                    return Ok(None);
+                }
+            }
+        }
+        if self.args.decompress && !restore(&mut datagram) {
+            return Ok(None);
+        }
+        if let Some(ref mut cycles) = self.cycles {
+            let split = cycle::split(&datagram.data).map(|(position, payload)| (position, payload.to_vec()));
+            if let Some((position, payload)) = split {
+                for event in cycles.track(&datagram, position) {
+                    info!("{} {}", datagram.src, event);
+                }
+                datagram.size -= datagram.data.len() - payload.len();
+                datagram.data = payload;
+            }
+        }
+        if let Some(ref mut reassembler) = self.reassembler {
+            match reassembler.add(&datagram) {
+                Ok(Some(message)) => {
+                    datagram.size = message.len();
+                    datagram.data = message;
+                }
+                Ok(None) => return Ok(None),
+                Err(err) => {
+                    warn!("dropping a fragment from {}: {}", datagram.src, err);
+                    return Ok(None);
+                }
+            }
+        }
+        if let Some(ref mut script) = self.script {
+            if !judge(script, &mut datagram)? {
+                return Ok(None);
+            }
+        }
+        Ok(Some(datagram))
+    }
+
+    /// Counts `datagram` towards the statistics, loss and alerts, writes it
+    /// to --write-pcap and shows it on the dashboard.
+    fn record(&mut self, datagram: &Datagram) -> AppResult<()> {
+        if self.idle_reported {
+            info!("traffic on {} resumed", self.groups.join(", "));
+            self.idle_reported = false;
+            if let Some(ref mut exec) = self.exec {
+                if let Err(err) = exec.event(Trigger::Up, &self.groups) {
+                    warn!("can't run --exec: {}", err);
+                }
+            }
+        }
+        self.last_traffic = time::Instant::now();
+        trace!("{} bytes from {} to {}", datagram.size, datagram.src, datagram.group);
+        self.session.record(datagram);
+        if let Some(ref mut alerts) = self.alerts {
+            alerts.record(datagram);
+        }
+        if let Some(ref mut stats) = self.stats {
+            stats.record(datagram);
+        }
+        if let Some((ref mut meter, _)) = self.meter {
+            meter.record(datagram);
+        }
+        if let Some(ref mut tags) = self.tags {
+            tags.record(datagram);
+        }
+        if let Some(ref mut sources) = self.sources {
+            sources.record(datagram);
+        }
+        if let Some(ref metrics) = self.metrics {
+            metrics.lock().expect("metrics lock").record(datagram);
+        }
+        if let Some(ref mut pcap) = self.pcap {
+            pcap.write(datagram)?;
+        }
+        if datagram.truncated() {
+            warn!("datagram from {} truncated from {} to {} bytes, raise --buffer-size",
+                  datagram.src, datagram.size, datagram.data.len());
+        }
+        if let Some(ref mut loss) = self.loss {
+            if let Some(event) = loss.track(datagram) {
+                let counts = loss.counts(datagram).unwrap_or_default();
+                info!("{} {} [{}]", datagram.src, event, counts);
+            }
+            if let (Some(metrics), Some(counts)) = (self.metrics.as_ref(), loss.counts(datagram)) {
+                metrics.lock().expect("metrics lock").loss(datagram.group, datagram.src, counts);
+            }
+        }
+        if let Some((_, ref mut dashboard, _)) = self.dashboard {
+            dashboard.record(datagram, self.loss.as_ref().and_then(|loss| loss.counts(datagram)));
+        }
+        Ok(())
+    }
+
+    /// Replaces the payload of `datagram` with what --decode and --decoder
+    /// make of it.
+    fn decode(&mut self, datagram: &mut Datagram) -> AppResult<()> {
+        if let Some(ref mut rtp) = self.rtp {
+            match RtpHeader::parse(&datagram.data) {
+                Some(header) => {
+                    if let Some(event) = rtp.record(datagram, &header) {
+                        info!("{} {}", datagram.src, event);
+                    }
+                    let payload = datagram.data[header.payload_start..header.payload_end].to_vec();
+                    if self.decoded_text {
+                        // through the output, so it follows --output-file and the control socket
+                        let mut described = datagram.clone();
+                        described.data = format!("{}: {} bytes", header, payload.len()).into_bytes();
+                        self.output.write(&described)?;
+                    }
+                    datagram.size -= datagram.data.len() - payload.len();
+                    datagram.data = payload;
+                }
+                None => warn!("datagram from {} is not RTP", datagram.src),
+            }
+        }
+        if let Some((ref mut ts, interval, ref mut due)) = self.ts {
+            let payload = mpegts::payload(&datagram.data).to_vec();
+            for event in ts.record(&payload) {
+                info!("{} {}", datagram.src, event);
+            }
+            datagram.size -= datagram.data.len() - payload.len();
+            datagram.data = payload;
+            let now = time::Instant::now();
+            if now >= *due {
+                print_pid_table(&ts.interval());
+                *due = now + interval;
+            }
+        }
+        if let Some(ref mut decoder) = self.decoder {
+            let decoded = decoder.decode(datagram).map_err(|err| format!("--decoder: {}", err))?;
+            datagram.size = decoded.len();
+            datagram.data = decoded.into_bytes();
+        }
+        Ok(())
+    }
+
+    /// Writes `datagram` to the output and hands it to --exec.
+    fn deliver(&mut self, datagram: &Datagram) -> AppResult<()> {
+        if !self.decoded_text && self.dashboard.is_none() {
+            self.output.write(datagram)?;
+        }
+        if let Some(ref mut exec) = self.exec {
+            if let Err(err) = exec.datagram(datagram) {
+                warn!("can't run --exec: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Leaves the groups and prints the summaries, returning the exit code.
+    fn finish(mut self) -> AppResult<i32> {
+        let args = self.args;
+        if self.dashboard.take().is_some() {
+            log::set_level(self.log_level);
+        }
+        notify_systemd("STOPPING=1");
+        self.output.flush()?;
+        if let Some(ref mut exec) = self.exec {
+            for status in exec.wait()? {
+                warn!("--exec command failed: {}", status);
+            }
+            if exec.skipped() > 0 {
+                warn!("--exec skipped {} times while too many commands were running", exec.skipped());
+            }
+        }
+        if let (false, Some(metrics)) = (self.pushers.is_empty(), self.metrics.as_ref()) {
+            let mut metrics = metrics.lock().expect("metrics lock");
+            refresh_metric_drops(&mut metrics, &self.memberships);
+            push(&mut self.pushers, &metrics.series());
+        }
+        leave_groups(&self.memberships)?;
+        let session = self.session.report();
+        info!("\n--- {} ---\n{:.1} s: {}", self.groups.join(", "), session.period.as_secs_f64(), session);
+        if let Some(ref loss) = self.loss {
+            let sources = loss.sources();
+            if !sources.is_empty() {
+                info!("\n--- sequence numbers ---");
+                for (src, group, counts) in sources {
+                    info!("{} to {}: {}", src, group, counts);
+                }
+            }
+        }
+        if self.unauthenticated > 0 {
+            warn!("{} datagrams had a bad or missing tag", self.unauthenticated);
+        }
+        if self.undecryptable > 0 {
+            warn!("dropped {} datagrams that didn't decrypt", self.undecryptable);
+        }
+        if let Some(duplicates) = self.dedup.as_ref().map(Dedup::duplicates).filter(|&n| n > 0) {
+            warn!("{} duplicate datagrams{}", duplicates, if args.drop_duplicates { ", left out" } else { "" });
+        }
+        if let Some(ref cycles) = self.cycles {
+            let sources = cycles.sources();
+            if !sources.is_empty() {
+                info!("\n--- carousel ---");
+                for (src, group, counts) in sources {
+                    info!("{} to {}: {}", src, group, counts);
+                }
+            }
+        }
+        if let Some(incomplete) = self.reassembler.as_ref().map(Reassembler::incomplete).filter(|&n| n > 0) {
+            warn!("gave up on {} messages with fragments missing", incomplete);
+        }
+        if let Some(ref mut stats) = self.stats {
+            refresh_kernel_drops(stats, &self.memberships);
+            info!("\n--- statistics ---\n{}", stats.summary());
+        }
+        if let Some((ref ts, _, _)) = self.ts {
+            info!("\n--- mpegts ---");
+            print_pid_table(&ts.summary());
+            if ts.sync_losses() > 0 {
+                info!("{} packets without a sync byte", ts.sync_losses());
+            }
+        }
+        if let Some(ref rtp) = self.rtp {
+            info!("\n--- rtp ---");
+            for report in rtp.reports() {
+                info!("{}", report);
+            }
+        }
+        if let Some(ref sources) = self.sources {
+            info!("\n--- top talkers ---");
+            for (rank, source) in sources.top_talkers().iter().enumerate() {
+                info!("{:3}. {}", rank + 1, source);
+            }
+        }
+        if let Some(ref tags) = self.tags {
+            info!("\n--- by tag ---");
+            for tag in tags.tags() {
+                info!("{}", tag);
+            }
+        }
+        Ok(self.exit)
+    }
+}
+
+/// Logs a per-PID table.
+fn print_pid_table(reports: &[PidReport]) {
+    let mut lines = vec![mpegts::PID_HEADER.to_owned()];
+    lines.extend(reports.iter().map(PidReport::to_string));
+    let packets: u64 = reports.iter().map(|report| report.packets).sum();
+    let bits: f64 = reports.iter().map(|report| report.bits_per_second()).sum();
+    lines.push(format!("{:<6} {:>10} {:>10.3}", "total", packets, bits / 1e6));
+    for line in lines {
+        info!("{}", line);
+    }
+}
+
+/// Joins a group while running.
+fn add_group(args: &ListenArgs,
+             config: MulticastSocket,
+             listener: &mut MultiListener,
+             memberships: &mut Vec<Membership>)
+             -> AppResult<()> {
+    if memberships.iter().any(|(_, joined)| joined.addr() == config.addr()) {
+        return Err(invalid_input(format!("already joined {}", config.addr())));
+    }
+    let joined = join_group(args, &config)?;
+    memberships.push((joined.socket().try_clone()?, config));
+    listener.add(joined)?;
+    Ok(())
+}
+
+fn remove_group(group: net::SocketAddr,
+                listener: &mut MultiListener,
+                memberships: &mut Vec<Membership>)
+                -> AppResult<()> {
+    let i = match memberships.iter().position(|(_, config)| config.addr() == group) {
+        Some(i) => i,
+        None => return Err(invalid_input(format!("not joined to {}", group))),
+    };
+    let (sock, config) = memberships.remove(i);
+    listener.remove(group);
+    config.leave(&sock)?;
+    debug!("left {}", group);
+    Ok(())
+}
+
+/// Re-reads the --config file at `path`, leaving the groups in `joined`
+/// that it no longer lists and joining those it newly does. A group whose
+/// interface or source changed is left and joined again.
+fn reload(args: &ListenArgs,
+          path: &Path,
+          joined: &mut Vec<ConfigGroup>,
+          listener: &mut MultiListener,
+          memberships: &mut Vec<Membership>)
+          -> AppResult<ConfigFile> {
+    let file = ConfigFile::load(path).map_err(invalid_input)?;
+    let (kept, dropped): (Vec<_>, Vec<_>) = joined.drain(..).partition(|group| file.groups.contains(group));
+    *joined = kept;
+    for group in dropped {
+        match remove_group(group.addr, listener, memberships) {
+            Ok(()) => info!("left {}", group.addr),
+            Err(err) => warn!("can't leave {}: {}", group.addr, err),
+        }
+    }
+    for group in &file.groups {
+        if joined.contains(group) {
+            continue;
+        }
+        // not recorded as joined on failure, so the next reload tries again
+        match add_group(args, group_config(args, group, true), listener, memberships) {
+            Ok(()) => {
+                info!("joined {}", group.addr);
+                joined.push(group.clone());
+            }
+            Err(err) => warn!("can't join {}: {}", group.addr, err),
+        }
+    }
+    if memberships.is_empty() {
+        warn!("no groups joined");
+    }
+    Ok(file)
+}
+
+/// Picks up kernel drops that no datagram has reported yet, or at all on
+/// kernels without `SO_RXQ_OVFL`.
+fn refresh_metric_drops(metrics: &mut Metrics, memberships: &[Membership]) {
+    for (sock, config) in memberships {
+        if let Ok(Some(drops)) = mccat::kernel_drops(sock) {
+            metrics.kernel_drops(config.addr(), drops);
+        }
+    }
+}
+
+fn pushers(args: &PushArgs) -> io::Result<Vec<Pusher>> {
+    let mut pushers = Vec::new();
+    if let Some(target) = args.statsd {
+        pushers.push(Pusher::new(target, push::Format::Statsd)?);
+    }
+    if let Some(target) = args.influx {
+        pushers.push(Pusher::new(target, push::Format::Influx)?);
+    }
+    Ok(pushers)
+}
+
+/// Sends metrics to every --statsd and --influx target, only warning when
+/// one fails, as the collector may come and go.
+fn push(pushers: &mut [Pusher], series: &[metrics::Series]) {
+    for pusher in pushers {
+        if let Err(err) = pusher.push(series) {
+            warn!("pushing metrics to {}: {}", pusher.target(), err);
+        }
+    }
+}
+
+fn refresh_kernel_drops(stats: &mut ArrivalStats, memberships: &[Membership]) {
+    for (sock, config) in memberships {
+        if let Ok(Some(drops)) = mccat::kernel_drops(sock) {
+            stats.kernel_drops(config.addr(), drops);
+        }
+    }
+}
+
+fn leave_groups(memberships: &[Membership]) -> AppResult<()> {
+    for (sock, config) in memberships {
+        config.leave(sock)?;
+        debug!("left {}", config.addr());
+    }
+    Ok(())
+}
+
+/// Joins every group in `configs` for a command that only watches, which
+/// leaves probes unanswered so that it isn't counted as a member.
+fn listen_quietly(configs: &[MulticastSocket]) -> io::Result<MultiListener> {
+    let mut listeners = Vec::new();
+    for config in configs {
+        let mut listener = mccat::listen(config)?;
+        listener.answer_pings(false);
+        listeners.push(listener);
+    }
+    MultiListener::new(listeners)
+}
+
+fn timed_out(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::TimedOut
+}
+
+/// Reports on generated streams every `MEASURE_INTERVAL` until interrupted.
+fn measure(listener: Box<dyn Iterator<Item = io::Result<Datagram>>>,
+           mut measurement: Measurement,
+           duration: Option<time::Duration>,
+           watchdog: &mut Watchdog)
+           -> AppResult<i32> {
+    let mut next_report = time::Instant::now() + MEASURE_INTERVAL;
+    let deadline = duration.map(|duration| time::Instant::now() + duration);
+    for datagram in listener {
+        if watchdog.due() {
+            notify_systemd("WATCHDOG=1");
+        }
+        if deadline.is_some_and(|deadline| time::Instant::now() >= deadline) {
+            break;
+        }
+        match datagram {
+            Ok(datagram) => {
+                measurement.record(&datagram);
+            }
+            Err(ref err) if timed_out(err) => {}
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted && signal::interrupted() => break,
+            Err(err) => return Err(err.into()),
+        }
+        if signal::interrupted() {
+            break;
+        }
+        let now = time::Instant::now();
+        if now >= next_report {
+            for report in measurement.interval() {
+                println!("{}", report);
+            }
+            next_report = now + MEASURE_INTERVAL;
+        }
+    }
+    println!("\n--- summary ---");
+    for report in measurement.summary() {
+        println!("{}", report);
+    }
+    for (src, group, chart) in measurement.delay_charts() {
+        print!("\n--- one-way delay from {} to {} ---\n{}", src, group, chart);
+    }
+    Ok(EXIT_OK)
+}
+
+fn send(args: &SendArgs) -> AppResult<i32> {
+    let mut configs = sender_configs(&args.address, args.port, &args.sender)?;
+    let config = configs.remove(0);
+    let input: Box<dyn io::Read> = match (&args.file, &args.follow) {
+        (Some(path), _) => Box::new(fs::File::open(path)?),
+        (_, Some(path)) => Box::new(Follow::new(path)?),
+        _ => Box::new(io::stdin()),
+    };
+    let mut sender = mccat::send(&config, input)?;
+    for also in &configs {
+        sender.also_via(also)?;
+    }
+    sender.buffer_size(args.buffer_size);
+    match (args.frame, &args.follow) {
+        (Some(framing), _) => {
+            sender.framing(framing);
+        }
+        // whole lines, however the writer's appends are split
+        (None, Some(_)) => {
+            sender.framing(Framing::Line);
+        }
+        (None, None) => {}
+    }
+    if let Some(rate) = args.rate {
+        sender.rate(rate);
+    }
+    if let Some(ref key) = args.key {
+        sender.key(key.clone());
+    }
+    if let Some(ref cipher) = args.encrypt {
+        sender.encrypt(cipher.clone())?;
+    }
+    if let Some(codec) = args.compress {
+        sender.compress(codec);
+    }
+    if let Some(ref tag) = args.tag {
+        sender.tag(tag);
+    }
+    if args.carousel {
+        sender.carousel(args.interval);
+    }
+    report_buffers(sender.socket(), &config.addr().to_string(), &args.sender.buffers)?;
+    let _announcer = match args.announce_sap {
+        Some(ref name) => {
+            // stop reading on ^C so that the session's deletion is announced
+            signal::install()?;
+            Some(announce_sap(&config, args, name)?)
+        }
+        None => None,
+    };
+    for sent in sender {
+        match sent {
+            Ok(_) => {}
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted && signal::interrupted() => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(EXIT_OK)
+}
+
+fn announce_sap(config: &MulticastSocket, args: &SendArgs, name: &str) -> AppResult<Announcer> {
+    let group = wellknown::by_name("sap").expect("well-known SAP group");
+    let sap_group = if config.addr().is_ipv4() { net::IpAddr::V4(group.v4) } else { net::IpAddr::V6(group.v6) };
+    // announced from the first interface
+    let sap_config = sender_configs(&sap_group.into(), group.port, &args.sender)?.remove(0);
+    let session_id = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default().as_secs();
+    let announcer = Announcer::start(&sap_config, args.sap_interval, |origin| {
+        Announcement {
+                name: name.to_owned(),
+                origin,
+                session_id,
+                group: config.addr(),
+                ttl: args.sender.ttl,
+                payload_type: args.payload_type,
+            }
+            .to_string()
+    })?;
+    info!("Announcing {} on {}", name, sap_config.addr());
+    Ok(announcer)
+}
+
+fn ping(args: &PingArgs) -> AppResult<i32> {
+    let mut configs = sender_configs(&args.address, args.port, &args.sender)?;
+    let config = configs.remove(0);
+    signal::install()?;
+    let interval = if args.flood { FLOOD_INTERVAL } else { args.interval };
+    let mut pinger = mccat::ping(&config, interval)?;
+    for also in &configs {
+        pinger.also_via(also)?;
+    }
+    report_buffers(pinger.socket(), &config.addr().to_string(), &args.sender.buffers)?;
+    pinger.pace_by_replies(args.flood || args.adaptive);
+    if let Some(count) = args.count {
+        pinger.probe_count(count);
+    }
+    if let Some(deadline) = args.deadline {
+        pinger.deadline(deadline);
+    }
+    if args.reply == ReplyPath::Multicast {
+        pinger.group_replies(&config)?;
+    }
+    if let Some(ref key) = args.key {
+        pinger.key(key.clone());
+    }
+    let pattern = args.pattern.clone().unwrap_or_else(|| (0..=255).collect());
+    if let Some(size) = args.size {
+        pinger.payload(size, pattern.clone());
+    }
+    let mut size_sweep = args.sweep_size.map(|(first, last, step)| {
+        let mut sizes: Vec<_> = (first..=last).step_by(step).collect();
+        if sizes.last() != Some(&last) {
+            sizes.push(last);
+        }
+        pinger.size_sweep(sizes.clone(), SWEEP_PROBES, pattern);
+        SizeSweepStats::new(config.addr(), &sizes, SWEEP_PROBES)
+    });
+    let mut sweep = args.ttl_sweep.map(|(first, last)| {
+        pinger.ttl_sweep(first, last, SWEEP_PROBES);
+        SweepStats::new(config.addr(), first, last, SWEEP_PROBES)
+    });
+    let mut stats = PingStats::new(config.addr());
+    let mut pushers = pushers(&args.push)?;
+    let mut next_push = time::Instant::now() + args.push.push_interval;
+    if !pushers.is_empty() {
+        pinger.timeout(Some(POLL_INTERVAL));
+    }
+    let mut dots = 0;
+    loop {
+        if !pushers.is_empty() && time::Instant::now() >= next_push {
+            stats.set_transmitted(pinger.transmitted());
+            push(&mut pushers, &stats.series());
+            next_push = time::Instant::now() + args.push.push_interval;
+        }
+        let reply = match pinger.recv() {
+            Ok(Some(reply)) => reply,
+            Ok(None) => break,
+            Err(ref err) if timed_out(err) => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted && signal::interrupted() => break,
+            Err(err) => return Err(err.into()),
+        };
+        let answered = stats.answered();
+        stats.record(&reply);
+        if let Some(ref mut sweep) = sweep {
+            sweep.record(&reply);
+        }
+        if let Some(ref mut size_sweep) = size_sweep {
+            size_sweep.record(&reply);
+        }
+        if args.flood {
+            // a dot per probe, erased when the probe is answered
+            dots = flood_dots(dots, pinger.transmitted());
+            if stats.answered() > answered {
+                print!("\x08 \x08");
+            }
+            io::stdout().flush()?;
+            continue;
+        }
+        // padding would drown the line
+        let data = match reply.probe_size {
+            Some(_) => {
+                let seqnum = reply.seqnum.map_or_else(|| "?".to_owned(), |seqnum| seqnum.to_string());
+                format!("PONG {} ({} bytes)", seqnum, reply.datagram.size)
+            }
+            None => String::from_utf8_lossy(&reply.datagram.data).into_owned(),
+        };
+        let mut notes = reply.probe_ttl.map(|ttl| format!(" ttl={}", ttl)).unwrap_or_default();
+        if reply.multicast {
+            notes.push_str(" via group");
+        }
+        if let (false, Some(interface)) = (configs.is_empty(), args.sender.interfaces.get(reply.path)) {
+            notes.push_str(&format!(" on {}", interface));
+        }
+        if reply.corrupted {
+            notes.push_str(" (corrupted)");
+        }
+        match reply.rtt {
+            Some(rtt) => {
+                println!("{} from {}{} time={:.3} ms", data, reply.datagram.src, notes, rtt.as_secs_f64() * 1e3)
+            }
+            None => println!("{} from {}{}", data, reply.datagram.src, notes),
+        }
+    }
+    if args.flood {
+        flood_dots(dots, pinger.transmitted());
+        println!();
+    }
+    stats.set_transmitted(pinger.transmitted());
+    push(&mut pushers, &stats.series());
+    print!("\n{}", stats);
+    if stats.rtt().count() > 1 {
+        print!("\n--- rtt histogram ---\n{}", stats.rtt().histogram().chart(0.0));
+    }
+    if let Some(sweep) = sweep {
+        print!("\n{}", sweep);
+    }
+    if let Some(size_sweep) = size_sweep {
+        print!("\n{}", size_sweep);
+    }
+    let satisfied = if args.expect_all {
+        stats.transmitted() > 0 && stats.answered() == stats.transmitted()
+    } else {
+        stats.answered() > 0
+    };
+    Ok(if satisfied { EXIT_OK } else { EXIT_NO_REPLY })
+}
+
+fn respond(args: &RespondArgs) -> AppResult<i32> {
+    // TTL and marking only matter for multicast replies
+    let mut config = sender_config(&args.address, args.port, &args.sender)?;
+    config.reuse(true);
+    if let Some(source) = args.source {
+        config.source(source);
+    }
+    let mut listener = mccat::listen(&config)?;
+    listener.timeout(Some(POLL_INTERVAL))?;
+    if args.reply == ReplyPath::Multicast {
+        listener.reply_to_group(config.sender()?, config.target()?);
+    }
+    report_buffers(listener.socket(), &config.addr().to_string(), &args.sender.buffers)?;
+    info!("answering probes on {}", config.addr());
+    signal::install()?;
+    let mut answered = 0u64;
+    while !signal::interrupted() {
+        match listener.recv() {
+            Ok(datagram) => {
+                if mccat::is_probe(&datagram.data) {
+                    answered += 1;
+                }
+            }
+            Err(ref err) if timed_out(err) => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted && signal::interrupted() => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    info!("answered {} probes", answered);
+    Ok(EXIT_OK)
+}
+
+fn census(args: &CensusArgs) -> AppResult<i32> {
+    let config = sender_config(&args.address, args.port, &args.sender)?;
+    signal::install()?;
+    let mut pinger = mccat::ping(&config, args.window)?;
+    report_buffers(pinger.socket(), &config.addr().to_string(), &args.sender.buffers)?;
+    pinger.probe_count(1).deadline(args.window);
+    let mut census = Census::new();
+    loop {
+        match pinger.recv() {
+            Ok(Some(reply)) => census.record(&reply),
+            Ok(None) => break,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted && signal::interrupted() => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    println!("{}", mccat::CENSUS_HEADER);
+    print!("{}", census);
+    info!("{} hosts answered on {}", census.len(), config.addr());
+    Ok(if census.is_empty() { EXIT_NO_REPLY } else { EXIT_OK })
+}
+
+/// Prints a dot for each probe sent since `shown`, returning the new count.
+fn flood_dots(shown: u64, transmitted: u64) -> u64 {
+    print!("{}", ".".repeat(transmitted.saturating_sub(shown) as usize));
+    transmitted
+}
+
+fn generate(args: &GenerateArgs) -> AppResult<i32> {
+    let mut configs = sender_configs(&args.address, args.port, &args.sender)?;
+    let config = configs.remove(0);
+    signal::install()?;
+    let mut generator = mccat::generate(&config, args.size)?;
+    for also in &configs {
+        generator.also_via(also)?;
+    }
+    generator.rate(args.rate).duration(args.duration).batch(args.batch);
+    let header_len = generate::HEADER_LEN + args.tag.as_ref().map_or(0, |id| tag::header(id).len());
+    if let Some(ref id) = args.tag {
+        if args.size < header_len {
+            return Err(invalid_input(format!("datagrams must be at least {} bytes to carry the tag {}",
+                                             header_len, id)));
+        }
+        generator.tag(id);
+    }
+    if let Some(ref key) = args.key {
+        if args.size < header_len + auth::TAG_LEN {
+            return Err(invalid_input(format!("datagrams must be at least {} bytes to carry a tag",
+                                             header_len + auth::TAG_LEN)));
+        }
+        generator.key(key.clone());
+    }
+    if let Some(count) = args.count {
+        generator.packet_count(count);
+    }
+    report_buffers(generator.socket(), &config.addr().to_string(), &args.sender.buffers)?;
+    let started = time::Instant::now();
+    let mut bytes = 0u64;
+    for sent in generator.by_ref() {
+        match sent {
+            Ok(len) => bytes += len as u64,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted && signal::interrupted() => break,
+            Err(err) => return Err(err.into()),
+        }
+        if signal::interrupted() {
+            break;
+        }
+    }
+    let secs = started.elapsed().as_secs_f64();
+    info!("sent {} datagrams, {} bytes in {:.1} s ({:.3} Mbit/s) to {}",
+          generator.sent(), bytes, secs, bytes as f64 * 8.0 / secs.max(1e-9) / 1e6, config.addr());
+    Ok(EXIT_OK)
+}
+
+fn replay(args: &ReplayArgs) -> AppResult<i32> {
+    let config = sender_config(&args.address, args.port, &args.sender)?;
+    signal::install()?;
+    let mut replayer = mccat::replay(&config, PcapReader::open(&args.file)?)?;
+    replayer.speed(args.speed).batch(args.batch);
+    if let Some(rate) = args.rate {
+        replayer.rate(rate);
+    }
+    report_buffers(replayer.socket(), &config.addr().to_string(), &args.sender.buffers)?;
+    let mut sent = 0u64;
+    loop {
+        let before = sent;
+        for packet in replayer.by_ref() {
+            match packet {
+                Ok(_) => sent += 1,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted && signal::interrupted() => break,
+                Err(err) => return Err(err.into()),
+            }
+            if signal::interrupted() {
+                break;
+            }
+        }
+        if !args.repeat || signal::interrupted() {
+            break;
+        }
+        if sent == before {
+            return Err(invalid_input(format!("{} contains no multicast UDP datagrams",
+                                             args.file.display())));
+        }
+        replayer.rewind(PcapReader::open(&args.file)?);
+    }
+    info!("replayed {} datagrams to {}", sent, config.addr());
+    Ok(EXIT_OK)
 }
 
-const USAGE: &'static str = "Usage: mccat <listen | send | ping> address port";
+fn interfaces() -> AppResult<i32> {
+    for iface in iface::interfaces()? {
+        let mut flags = Vec::new();
+        if iface.up {
+            flags.push("UP");
+        }
+        if iface.loopback {
+            flags.push("LOOPBACK");
+        }
+        if iface.multicast {
+            flags.push("MULTICAST");
+        }
+        println!("{}: {} <{}>", iface.index, iface.name, flags.join(","));
+        for addr in &iface.addresses {
+            println!("    {} {}", if addr.is_ipv4() { "inet" } else { "inet6" }, addr);
+        }
+        for group in &iface.groups {
+            println!("    joined {}", group);
+        }
+    }
+    Ok(EXIT_OK)
+}
 
-type AppResult<T> = Result<T, Box<Error>>;
+fn profiles() -> AppResult<i32> {
+    for profile in profile::load().map_err(invalid_input)? {
+        let mut about = Vec::new();
+        about.extend(profile.description.map(str::to_owned));
+        about.extend(profile.group.interface.map(|interface| format!("on {}", interface)));
+        about.extend(profile.group.source.map(|source| format!("from {}", source)));
+        let line = format!("{:<14} {:<24} {}", profile.name, profile.group.addr, about.join(", "));
+        println!("{}", line.trim_end());
+    }
+    Ok(EXIT_OK)
+}
 
-fn main() {
-    if let Err(err) = run() {
-        eprintln!("{}", err);
-        process::exit(1);
+/// Joins the IPv4 and IPv6 groups of a well-known discovery protocol,
+/// settling for one family if the other can't be joined. Probes go
+/// unanswered, as discovery only listens.
+fn join_discovery(name: &str, args: &InterfaceArgs) -> AppResult<(Vec<Listener>, Vec<MulticastSocket>)> {
+    let group = wellknown::by_name(name).expect("well-known discovery group");
+    let mut listeners = Vec::new();
+    let mut configs = Vec::new();
+    for &addr in &[net::IpAddr::V4(group.v4), net::IpAddr::V6(group.v6)] {
+        let mut config = MulticastSocket::new(addr, group.port);
+        apply_interface(&mut config, args);
+        config.reuse(true).multicast_all(false);
+        match mccat::listen(&config) {
+            Ok(mut listener) => {
+                listener.answer_pings(false);
+                listeners.push(listener);
+                configs.push(config);
+            }
+            Err(err) => warn!("can't join {}: {}", config.addr(), err),
+        }
     }
+    if listeners.is_empty() {
+        return Err(invalid_input(format!("can't join the {} groups", name)));
+    }
+    Ok((listeners, configs))
 }
 
-fn run() -> AppResult<()> {
-    let (cmd, multiaddr, port) = parse_cmdline()?;
+/// Keeps `--sdp-dir` and `--m3u` in step with the session directory.
+fn save_sessions(args: &SapArgs, directory: &SessionDirectory, event: SessionEvent, session: &sap::Session)
+                 -> AppResult<()> {
+    if let Some(ref dir) = args.sdp_dir {
+        let name: String = session.sdp.name
+            .chars()
+            .map(|c| if c.is_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+            .collect();
+        let path = dir.join(format!("{}_{}.sdp", name, session.origin));
+        match event {
+            SessionEvent::Deleted => {
+                if let Err(err) = fs::remove_file(&path) {
+                    if err.kind() != io::ErrorKind::NotFound {
+                        return Err(err.into());
+                    }
+                }
+            }
+            _ => fs::write(&path, &session.text)?,
+        }
+    }
+    if let Some(ref path) = args.m3u {
+        fs::write(path, sap::playlist(&directory.sessions()))?;
+    }
+    Ok(())
+}
 
-    if !multiaddr.is_multicast() {
-        Err(io::Error::new(io::ErrorKind::InvalidInput,
-                           format!("{} is not a multicast address", multiaddr)))?
+fn discover_sap(args: &SapArgs) -> AppResult<i32> {
+    if let Some(ref dir) = args.sdp_dir {
+        fs::create_dir_all(dir)?;
+    }
+    let (listeners, configs) = join_discovery("sap", &args.interface)?;
+    let mut listener = MultiListener::new(listeners)?;
+    listener.timeout(Some(POLL_INTERVAL));
+    signal::install()?;
+    let groups: Vec<_> = configs.iter().map(|config| config.addr().to_string()).collect();
+    info!("Listening for SAP announcements on {}", groups.join(", "));
+    println!("{:<7} {}", "EVENT", sap::SESSION_HEADER);
+    let deadline = args.duration.map(|duration| time::Instant::now() + duration);
+    let mut directory = SessionDirectory::new();
+    for datagram in listener {
+        if signal::interrupted() || deadline.is_some_and(|deadline| time::Instant::now() >= deadline) {
+            break;
+        }
+        let datagram = match datagram {
+            Ok(datagram) => datagram,
+            Err(ref err) if timed_out(err) => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted && signal::interrupted() => break,
+            Err(err) => return Err(err.into()),
+        };
+        let update = SapPacket::parse(&datagram.data).and_then(|packet| directory.update(&packet));
+        match update {
+            Ok(Some((event, session))) => {
+                println!("{:<7} {}", event, session);
+                save_sessions(args, &directory, event, &session)?;
+            }
+            Ok(None) => {}
+            Err(err) => warn!("{}: {}", datagram.src, err),
+        }
+    }
+    let sessions = directory.sessions();
+    println!("\n--- {} {} ---", sessions.len(), if sessions.len() == 1 { "session" } else { "sessions" });
+    for session in sessions {
+        println!("{:<7} {}", "", session);
     }
+    Ok(EXIT_OK)
+}
 
-    match cmd {
-        Command::Listen => listen(multiaddr, port),
-        Command::Send => send(multiaddr, port),
-        Command::Ping => ping(multiaddr, port),
+/// Sends `types` queries for `name` on every joined family.
+fn send_mdns_query(senders: &[(net::UdpSocket, net::SocketAddr)], name: &str, types: &[u16]) {
+    let query = dns::query(0, name, types);
+    for &(ref sock, target) in senders {
+        if let Err(err) = sock.send_to(&query, target) {
+            warn!("can't query {}: {}", target, err);
+        }
     }
 }
 
-fn listen(multiaddr: net::IpAddr, port: u16) -> AppResult<()> {
-    let sock = match multiaddr {
-        net::IpAddr::V4(addr) => {
-            let sockaddr: net::SocketAddr = (net::Ipv4Addr::from(0), port).into();
-            let sock = net::UdpSocket::bind(sockaddr)?;
-            sock.join_multicast_v4(&addr, &0.into())?;
-            println!("Listening on {}", net::SocketAddr::from((addr, port)));
-            sock
+fn discover_mdns(args: &MdnsArgs) -> AppResult<i32> {
+    let (listeners, configs) = join_discovery("mdns", &args.interface)?;
+    // queries go out from the mDNS port so that answers are multicast back
+    let mut senders = Vec::new();
+    for (listener, config) in listeners.iter().zip(&configs) {
+        senders.push((listener.socket().try_clone()?, config.target()?));
+    }
+    let mut listener = MultiListener::new(listeners)?;
+    listener.timeout(Some(POLL_INTERVAL));
+    signal::install()?;
+    let browse = args.names.is_empty();
+    let names = if browse { vec![dns::SERVICES.to_owned()] } else { args.names.clone() };
+    if !args.passive {
+        for name in &names {
+            send_mdns_query(&senders, name, &args.types);
+        }
+    }
+    let duration = match args.duration {
+        Some(duration) => Some(duration),
+        None if args.passive => None,
+        None => Some(MDNS_DURATION),
+    };
+    let deadline = duration.map(|duration| time::Instant::now() + duration);
+    let mut seen = HashSet::new();
+    let mut followed = HashSet::new();
+    for datagram in listener {
+        if signal::interrupted() || deadline.is_some_and(|deadline| time::Instant::now() >= deadline) {
+            break;
+        }
+        let datagram = match datagram {
+            Ok(datagram) => datagram,
+            Err(ref err) if timed_out(err) => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted && signal::interrupted() => break,
+            Err(err) => return Err(err.into()),
+        };
+        let msg = match dns::Message::parse(&datagram.data) {
+            Ok(msg) => msg,
+            Err(err) => {
+                warn!("{}: {}", datagram.src, err);
+                continue;
+            }
+        };
+        if !msg.response {
+            if args.passive {
+                for &(ref name, qtype) in &msg.questions {
+                    println!("{} asked {} {}", datagram.src.ip(), name, dns::type_name(qtype));
+                }
+            }
+            continue;
+        }
+        for record in msg.records {
+            if browse && !args.passive && record.name == dns::SERVICES {
+                if let dns::RecordData::Ptr(ref service) = record.data {
+                    if followed.insert(service.clone()) {
+                        send_mdns_query(&senders, service, &args.types);
+                    }
+                }
+            }
+            let key = (record.name.clone(), record.rtype, record.data.clone());
+            if seen.insert(key) {
+                println!("{} {}", datagram.src.ip(), record);
+            }
         }
-        net::IpAddr::V6(addr) => {
-            let sockaddr: net::SocketAddr = (net::Ipv6Addr::from([0u8; 16]), port).into();
-            let sock = net::UdpSocket::bind(&sockaddr)?;
-            sock.join_multicast_v6(&addr, 0)?;
-            println!("Listening on {}", net::SocketAddr::from((addr, port)));
-            sock
+    }
+    Ok(EXIT_OK)
+}
+
+fn discover_ssdp(args: &SsdpArgs) -> AppResult<i32> {
+    let (listeners, configs) = join_discovery("ssdp", &args.interface)?;
+    // searches go out from the SSDP port, where the unicast answers arrive
+    let mut senders = Vec::new();
+    for (listener, config) in listeners.iter().zip(&configs) {
+        senders.push((listener.socket().try_clone()?, config.target()?));
+    }
+    let mut listener = MultiListener::new(listeners)?;
+    listener.timeout(Some(POLL_INTERVAL));
+    signal::install()?;
+    if !args.passive {
+        for &(ref sock, target) in &senders {
+            let host = if target.is_ipv4() { ssdp::HOST_V4 } else { ssdp::HOST_V6 };
+            if let Err(err) = sock.send_to(ssdp::search(host, &args.target, args.mx).as_bytes(), target) {
+                warn!("can't search on {}: {}", target, err);
+            }
         }
+    }
+    let duration = match args.duration {
+        Some(duration) => Some(duration),
+        None if args.passive => None,
+        None => Some(time::Duration::from_secs(u64::from(args.mx) + 1)),
     };
-    let mut buf = [0u8; 16384];
-    let mut reply = b"PONG".to_vec();
-    loop {
-        let (len, src) = sock.recv_from(&mut buf)?;
-        let data = &buf[..len];
-        if data.starts_with(b"PING") {
-            let seqnum = &data[4..];
-            reply.extend(seqnum);
-            sock.send_to(&reply, src)?;
-            reply.truncate(4);
+    let deadline = duration.map(|duration| time::Instant::now() + duration);
+    for datagram in listener {
+        if signal::interrupted() || deadline.is_some_and(|deadline| time::Instant::now() >= deadline) {
+            break;
+        }
+        let datagram = match datagram {
+            Ok(datagram) => datagram,
+            Err(ref err) if timed_out(err) => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted && signal::interrupted() => break,
+            Err(err) => return Err(err.into()),
+        };
+        let msg = match ssdp::Message::parse(&datagram.data) {
+            Ok(msg) => msg,
+            Err(err) => {
+                warn!("{}: {}", datagram.src, err);
+                continue;
+            }
+        };
+        // our own search comes back over loopback
+        if msg.kind == ssdp::Kind::Search && !args.passive {
+            continue;
+        }
+        if args.json {
+            println!("{}", msg.to_json(datagram.src));
+        } else {
+            println!("{} {}", datagram.src, msg.kind);
+            for (name, value) in &msg.headers {
+                println!("    {}: {}", name, value);
+            }
         }
-        println!("{} said: {}", src, String::from_utf8_lossy(data));
     }
+    Ok(EXIT_OK)
 }
 
-fn send(multiaddr: net::IpAddr, port: u16) -> AppResult<()> {
-    let sock = match multiaddr {
-        net::IpAddr::V4(_) => net::UdpSocket::bind((net::Ipv4Addr::from(0), 0))?,
-        net::IpAddr::V6(_) => net::UdpSocket::bind((net::Ipv6Addr::from([0u8; 16]), 0))?,
+fn relay_http(args: &HttpRelayArgs) -> AppResult<i32> {
+    // token 0 is the server; client n has 2n for its connection and 2n + 1
+    // for its group
+    const SERVER: Token = 0;
+    let server = match systemd::tcp_listener(&systemd::listen_fds())? {
+        Some(server) => server,
+        None => net::TcpListener::bind((args.bind, args.port))?,
     };
-    sock.connect((multiaddr, port))?;
-    let mut buf = [0u8; 16384];
-    let mut stdin = io::stdin();
-    loop {
-        let len = stdin.read(&mut buf)?;
-        if len == 0 {
-            return Ok(());
+    server.set_nonblocking(true)?;
+    info!("relaying on http://{}/", server.local_addr()?);
+    let _pidfile = finish_startup(&args.daemon)?;
+    let mut watchdog = Watchdog::from_env();
+    signal::install()?;
+    let mut poll = Poll::new();
+    poll.register(&server, SERVER, Interest::Readable);
+    let mut clients: HashMap<usize, relay::Client> = HashMap::new();
+    let mut next_id = 1;
+    while !signal::interrupted() {
+        if watchdog.due() {
+            notify_systemd("WATCHDOG=1");
+        }
+        let events = match poll.wait(Some(POLL_INTERVAL)) {
+            Ok(events) => events,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        for event in events {
+            if event.token == SERVER {
+                loop {
+                    let (stream, peer) = match server.accept() {
+                        Ok(accepted) => accepted,
+                        Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(err) => return Err(err.into()),
+                    };
+                    let client = relay::Client::new(stream, peer)?;
+                    debug!("{}: connected", peer);
+                    poll.register(client.stream(), next_id * 2, Interest::Readable);
+                    clients.insert(next_id, client);
+                    next_id += 1;
+                }
+                continue;
+            }
+            let id = event.token / 2;
+            let streaming = clients.values().filter(|client| client.listener().is_some()).count();
+            let done = match clients.get_mut(&id) {
+                Some(client) => {
+                    let done = match relay_event(&mut poll, id, client, event, args, streaming) {
+                        Ok(done) => done,
+                        Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof ||
+                                        err.kind() == io::ErrorKind::BrokenPipe ||
+                                        err.kind() == io::ErrorKind::ConnectionReset => true,
+                        Err(err) => {
+                            warn!("{}: {}", client.peer, err);
+                            true
+                        }
+                    };
+                    if done && client.listener().is_some() {
+                        info!("{}: done after {} bytes{}",
+                              client.peer,
+                              client.sent(),
+                              match client.dropped {
+                                  0 => String::new(),
+                                  dropped => format!(", {} datagrams dropped while it fell behind", dropped),
+                              });
+                    }
+                    done
+                }
+                None => false,
+            };
+            if done {
+                poll.deregister(id * 2);
+                poll.deregister(id * 2 + 1);
+                clients.remove(&id);
+            }
         }
-        let mut data = &buf[..len];
-        if let Some(&b'\n') = data.last() {
-            // chomp
-            data = &data[..len - 1];
+    }
+    notify_systemd("STOPPING=1");
+    Ok(EXIT_OK)
+}
+
+/// Handles a relay client's connection or group becoming ready,
+/// returning true once the client is done.
+fn relay_event(poll: &mut Poll,
+               id: usize,
+               client: &mut relay::Client,
+               event: Event,
+               args: &HttpRelayArgs,
+               streaming: usize)
+               -> io::Result<bool> {
+    if event.token % 2 == 1 {
+        client.relay()?;
+    } else if event.readable {
+        if let Some((method, path)) = client.read()? {
+            relay_request(client, &method, &path, args, streaming)?;
+            if let Some(listener) = client.listener() {
+                poll.register(listener.socket(), id * 2 + 1, Interest::Readable);
+            }
         }
-        sock.send(data)?;
     }
+    let done = client.flush()?;
+    poll.reregister(id * 2, if client.wants_write() { Interest::Both } else { Interest::Readable });
+    Ok(done)
 }
 
-fn ping(multiaddr: net::IpAddr, port: u16) -> AppResult<()> {
-    let sock = match multiaddr {
-        net::IpAddr::V4(_) => net::UdpSocket::bind((net::Ipv4Addr::from(0), 0))?,
-        net::IpAddr::V6(_) => net::UdpSocket::bind((net::Ipv6Addr::from([0u8; 16]), 0))?,
+/// Answers a request, starting to stream the group it names.
+fn relay_request(client: &mut relay::Client,
+                 method: &str,
+                 path: &str,
+                 args: &HttpRelayArgs,
+                 streaming: usize)
+                 -> io::Result<()> {
+    if method != "GET" {
+        return client.respond("405 Method Not Allowed", "only GET is supported");
+    }
+    let target: relay::Target = match path.parse() {
+        Ok(target) => target,
+        Err(err) => return client.respond("404 Not Found", &err),
     };
-    let sock2 = sock.try_clone()?;
-    thread::spawn(move || {
-        let mut buf = [0u8; 16384];
-        loop {
-            let (len, src) = sock2.recv_from(&mut buf).unwrap();
-            let data = &buf[..len];
-            println!("{} from {}", String::from_utf8_lossy(data), src);
+    if args.max_clients.is_some_and(|max| streaming >= max) {
+        return client.respond("503 Service Unavailable", "too many clients");
+    }
+    let mut config = MulticastSocket::new(target.group.ip(), target.group.port());
+    if let Some(ref interface) = args.interface.interface {
+        config.interface(interface);
+    }
+    config.reuse(true);
+    match mccat::listen(&config) {
+        Ok(mut listener) => {
+            // a failed reply would end the client's stream
+            listener.answer_pings(false);
+            info!("{}: streaming {}", client.peer, target.group);
+            client.start(listener, target.rtp)
+        }
+        Err(err) => {
+            warn!("{}: can't join {}: {}", client.peer, target.group, err);
+            client.respond("502 Bad Gateway", &format!("can't join {}: {}", target.group, err))
         }
+    }
+}
+
+fn bridge(args: &BridgeArgs) -> AppResult<i32> {
+    let activated = systemd::udp_socket(&systemd::listen_fds())?;
+    let bound = args.bind.is_some() || activated.is_some();
+    if args.direction.to_unicast() && args.peer.is_none() && !bound {
+        return Err(invalid_input("give --peer, or --bind to learn the peer from what it sends"));
+    }
+    if !args.direction.to_unicast() && !bound {
+        return Err(invalid_input("--bind is needed to receive from the unicast side"));
+    }
+    let mut config = sender_config(&args.address, args.port, &args.sender)?;
+    config.reuse(true);
+    let local = args.bind.unwrap_or_else(|| {
+        let any = match args.peer {
+            Some(net::SocketAddr::V6(_)) => net::Ipv6Addr::UNSPECIFIED.into(),
+            _ => net::Ipv4Addr::UNSPECIFIED.into(),
+        };
+        net::SocketAddr::new(any, 0)
     });
-    let mut seqnum = 0;
+    let unicast = match activated {
+        Some(sock) => sock,
+        None => net::UdpSocket::bind(local)?,
+    };
+    unicast.set_read_timeout(Some(POLL_INTERVAL))?;
+    report_buffers(&unicast, &unicast.local_addr()?.to_string(), &args.sender.buffers)?;
+    info!("bridging {} and {} via {}",
+          config.addr(),
+          args.peer.map_or_else(|| "the first unicast sender".to_owned(), |peer| peer.to_string()),
+          unicast.local_addr()?);
+    let _pidfile = finish_startup(&args.daemon)?;
+    let mut watchdog = Watchdog::from_env();
+    signal::install()?;
+    const GROUP: Token = 0;
+    const UNICAST: Token = 1;
+    let sender = config.sender()?;
+    let sender_port = sender.local_addr()?.port();
+    let target = config.target()?;
+    let mut poll = Poll::new();
+    let mut listener = if args.direction.to_unicast() {
+        let mut listener = mccat::listen(&config)?;
+        listener.timeout(Some(POLL_INTERVAL))?.answer_pings(false);
+        poll.register(listener.socket(), GROUP, Interest::Readable);
+        Some(listener)
+    } else {
+        None
+    };
+    if args.direction.to_multicast() {
+        poll.register(&unicast, UNICAST, Interest::Readable);
+    }
+    let local_addrs: HashSet<net::IpAddr> = iface::addresses()?.into_iter().map(|(_, addr)| addr).collect();
+    let mut peer = args.peer;
+    let mut buf = vec![0u8; mccat::DEFAULT_BUFFER_SIZE];
+    let (mut to_unicast, mut to_multicast) = (0, 0);
+    // each way is impaired on its own
+    let mut to_group = impairment(&args.impair);
+    let mut to_peer = impairment(&args.impair);
+    if let (Some(seed), Some(ref mut to_peer)) = (args.impair.seed, to_peer.as_mut()) {
+        to_peer.seed(seed.wrapping_add(1));
+    }
+    while !signal::interrupted() {
+        if watchdog.due() {
+            notify_systemd("WATCHDOG=1");
+        }
+        let mut wait = POLL_INTERVAL;
+        if let Some(ref mut to_group) = to_group {
+            wait = wait.min(send_impaired(to_group, &sender)?);
+        }
+        if let Some(ref mut to_peer) = to_peer {
+            wait = wait.min(send_impaired(to_peer, &unicast)?);
+        }
+        let events = match poll.wait(Some(wait)) {
+            Ok(events) => events,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        for event in events {
+            if event.token == UNICAST {
+                let (size, src) = match unicast.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock ||
+                                    err.kind() == io::ErrorKind::TimedOut ||
+                                    err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err.into()),
+                };
+                if args.peer.is_some_and(|fixed| fixed != src) {
+                    continue;
+                }
+                peer = Some(src);
+                match to_group {
+                    Some(ref mut to_group) => to_group.submit(&buf[..size], target),
+                    None => {
+                        sender.send_to(&buf[..size], target)?;
+                    }
+                }
+                to_multicast += 1;
+            } else if let Some(ref mut listener) = listener {
+                let datagram = match listener.recv() {
+                    Ok(datagram) => datagram,
+                    Err(ref err) if timed_out(err) || err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err.into()),
+                };
+                // don't send the peer's own datagrams back to it
+                if datagram.src.port() == sender_port && local_addrs.contains(&datagram.src.ip()) {
+                    continue;
+                }
+                if let Some(peer) = peer {
+                    match to_peer {
+                        Some(ref mut to_peer) => to_peer.submit(&datagram.data, peer),
+                        None => {
+                            unicast.send_to(&datagram.data, peer)?;
+                        }
+                    }
+                    to_unicast += 1;
+                }
+            }
+        }
+    }
+    notify_systemd("STOPPING=1");
+    info!("forwarded {} datagrams to the peer and {} to the group", to_unicast, to_multicast);
+    if let (Some(to_group), Some(to_peer)) = (to_group.as_ref(), to_peer.as_ref()) {
+        report_impaired(to_group, "to the group");
+        report_impaired(to_peer, "to the peer");
+    }
+    Ok(EXIT_OK)
+}
+
+fn forward(args: &ForwardArgs) -> AppResult<i32> {
+    let address = args.address.resolve(family(&args.sender.family))?;
+    let to = args.to.unwrap_or_else(|| net::SocketAddr::new(address.addr, args.port));
+    let mut from = MulticastSocket::new(address.addr, args.port);
+    // a zone on the group is where it is joined
+    let from_interface = address.zone.as_ref().or(args.from_interface.as_ref());
+    if let Some(interface) = from_interface {
+        from.interface(interface);
+    }
+    if let Some(source) = args.source {
+        from.source(source);
+    }
+    from.reuse(true);
+    apply_buffers(&mut from, &args.sender.buffers);
+    let config = sender_config(&to.ip().into(), to.port(), &args.sender)?;
+    if from.addr() == config.addr() && from_interface == args.sender.interfaces.first() {
+        return Err(invalid_input("forwarding a group to itself on the same interface would loop; give --to or -I"));
+    }
+    let mut listener = mccat::listen(&from)?;
+    listener.timeout(Some(POLL_INTERVAL))?.answer_pings(false);
+    let sender = config.sender()?;
+    let target = config.target()?;
+    report_buffers(listener.socket(), &from.addr().to_string(), &args.sender.buffers)?;
+    let sender_port = sender.local_addr()?.port();
+    let local_addrs: HashSet<net::IpAddr> = iface::addresses()?.into_iter().map(|(_, addr)| addr).collect();
+    let mut script = match args.script {
+        Some(ref path) => Some(Script::spawn(path)?),
+        None => None,
+    };
+    info!("forwarding {} to {}", from.addr(), config.addr());
+    signal::install()?;
+    let mut impairment = impairment(&args.impair);
+    let (mut forwarded, mut bytes) = (0u64, 0u64);
+    while !signal::interrupted() {
+        if let Some(ref mut impairment) = impairment {
+            listener.timeout(Some(send_impaired(impairment, &sender)?))?;
+        }
+        let datagram = match listener.recv() {
+            Ok(datagram) => datagram,
+            Err(ref err) if timed_out(err) => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted && signal::interrupted() => break,
+            Err(err) => return Err(err.into()),
+        };
+        // our own retransmissions when both sides share a group and port
+        if datagram.src.port() == sender_port && local_addrs.contains(&datagram.src.ip()) {
+            continue;
+        }
+        let mut datagram = datagram;
+        if let Some(ref mut script) = script {
+            if !judge(script, &mut datagram)? {
+                continue;
+            }
+        }
+        if args.decompress && !restore(&mut datagram) {
+            continue;
+        }
+        if let Some(codec) = args.compress {
+            match compress::compress(codec, &datagram.data) {
+                Ok(data) => datagram.data = data,
+                Err(err) => {
+                    warn!("dropping a datagram from {}: {}", datagram.src, err);
+                    continue;
+                }
+            }
+        }
+        match impairment {
+            Some(ref mut impairment) => impairment.submit(&datagram.data, target),
+            None => {
+                sender.send_to(&datagram.data, target)?;
+            }
+        }
+        forwarded += 1;
+        bytes += datagram.data.len() as u64;
+    }
+    info!("forwarded {} datagrams, {} bytes", forwarded, bytes);
+    if let Some(ref impairment) = impairment {
+        report_impaired(impairment, &config.addr().to_string());
+    }
+    Ok(EXIT_OK)
+}
+
+/// Blocks between manifests in a `push` carousel, so that receivers joining
+/// late soon learn what is being sent.
+const MANIFEST_INTERVAL: u32 = 8;
+/// How often `pull --nack` asks for what it is missing.
+const NACK_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+fn send_paced(sock: &net::UdpSocket, target: net::SocketAddr, pacer: &mut Pacer, packet: &[u8]) -> io::Result<()> {
+    pacer.wait(packet.len());
+    sock.send_to(packet, target).map(drop)
+}
+
+/// Resends what NACKs waiting on `sock` ask for, returning how many there
+/// were.
+fn answer_nacks(sock: &net::UdpSocket, target: net::SocketAddr, pacer: &mut Pacer, carousel: &Carousel)
+                -> io::Result<u64> {
+    let session = carousel.manifest().session;
+    let mut buf = [0u8; 2048];
+    let mut answered = 0;
     loop {
-        seqnum += 1;
-        sock.send_to(format!("PING {}", seqnum).as_bytes(), (multiaddr, port))?;
-        thread::sleep(Duration::from_millis(250));
+        let (len, src) = match sock.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock || timed_out(err) => return Ok(answered),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => return Ok(answered),
+            Err(err) => return Err(err),
+        };
+        let blocks = match Packet::parse(&buf[..len]) {
+            Some(Packet::Nack { session: nacked, blocks }) if nacked == session => blocks,
+            _ => continue,
+        };
+        debug!("{} asks for {} blocks", src, blocks.len());
+        answered += 1;
+        if blocks.is_empty() {
+            send_paced(sock, target, pacer, &carousel.manifest_packet())?;
+        }
+        for block in blocks.into_iter().filter(|&block| block < carousel.manifest().blocks()) {
+            for packet in carousel.block_packets(block) {
+                send_paced(sock, target, pacer, &packet)?;
+            }
+        }
+    }
+}
+
+fn push_file(args: &PushFileArgs) -> AppResult<i32> {
+    let config = sender_config(&args.address, args.port, &args.sender)?;
+    let name = match args.file.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return Err(invalid_input(format!("{} doesn't name a file", args.file.display()))),
+    };
+    let data = fs::read(&args.file)?;
+    let carousel = Carousel::new(&name, data, args.symbol_size, args.block, args.parity).map_err(invalid_input)?;
+    let manifest = carousel.manifest();
+    let sock = config.sender()?;
+    let target = config.target()?;
+    report_buffers(&sock, &config.addr().to_string(), &args.sender.buffers)?;
+    if args.nack {
+        sock.set_nonblocking(true)?;
+    }
+    let mut pacer = Pacer::new(args.rate);
+    info!("pushing {} ({} bytes in {} blocks) to {}", name, manifest.size, manifest.blocks(), config.addr());
+    signal::install()?;
+    let mut rounds = 0u64;
+    let mut nacks = 0u64;
+    'rounds: while args.rounds.is_none_or(|limit| rounds < limit) {
+        if manifest.blocks() == 0 {
+            send_paced(&sock, target, &mut pacer, &carousel.manifest_packet())?;
+        }
+        for block in 0..manifest.blocks() {
+            if signal::interrupted() {
+                break 'rounds;
+            }
+            if block % MANIFEST_INTERVAL == 0 {
+                send_paced(&sock, target, &mut pacer, &carousel.manifest_packet())?;
+            }
+            for packet in carousel.block_packets(block) {
+                send_paced(&sock, target, &mut pacer, &packet)?;
+            }
+            if args.nack {
+                nacks += answer_nacks(&sock, target, &mut pacer, &carousel)?;
+            }
+        }
+        rounds += 1;
+        debug!("round {} done", rounds);
+    }
+    if args.nack && !signal::interrupted() {
+        sock.set_nonblocking(false)?;
+        sock.set_read_timeout(Some(POLL_INTERVAL))?;
+        let mut quiet_since = time::Instant::now();
+        while !signal::interrupted() && quiet_since.elapsed() < args.linger {
+            let answered = answer_nacks(&sock, target, &mut pacer, &carousel)?;
+            if answered > 0 {
+                nacks += answered;
+                quiet_since = time::Instant::now();
+            }
+        }
+    }
+    info!("sent {} {} times, answered {} NACKs", name, rounds, nacks);
+    Ok(EXIT_OK)
+}
+
+fn pull_file(args: &PullFileArgs) -> AppResult<i32> {
+    let mut config = sender_config(&args.address, args.port, &args.sender)?;
+    config.reuse(true);
+    if let Some(source) = args.source {
+        config.source(source);
+    }
+    let mut listener = mccat::listen(&config)?;
+    listener.timeout(Some(POLL_INTERVAL))?.answer_pings(false);
+    report_buffers(listener.socket(), &config.addr().to_string(), &args.sender.buffers)?;
+    info!("waiting for a file on {}", config.addr());
+    signal::install()?;
+    let started = time::Instant::now();
+    let mut receiver: Option<Receiver> = None;
+    // the first session heard of, and who sends it
+    let mut session: Option<(u32, net::SocketAddr)> = None;
+    let mut last_symbol = started;
+    let mut next_nack = started + NACK_INTERVAL;
+    while !receiver.as_ref().is_some_and(Receiver::is_complete) {
+        if signal::interrupted() || args.deadline.is_some_and(|deadline| started.elapsed() >= deadline) {
+            let progress = match receiver {
+                Some(ref receiver) => format!("{} of {} blocks", receiver.done(), receiver.manifest().blocks()),
+                None => "nothing".to_owned(),
+            };
+            error!("gave up having received {}", progress);
+            return Ok(EXIT_ERROR);
+        }
+        let now = time::Instant::now();
+        if let (true, Some((id, sender))) = (args.nack && now >= next_nack, session) {
+            next_nack = now + NACK_INTERVAL;
+            // all that is missing once the sender has gone quiet
+            let blocks = receiver.as_ref().map(|receiver| receiver.missing(now - last_symbol >= NACK_INTERVAL));
+            if blocks.as_ref().is_none_or(|blocks| !blocks.is_empty()) {
+                listener.socket().send_to(&carousel::nack(id, &blocks.unwrap_or_default()), sender)?;
+            }
+        }
+        let datagram = match listener.recv() {
+            Ok(datagram) => datagram,
+            Err(ref err) if timed_out(err) => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        match Packet::parse(&datagram.data) {
+            Some(Packet::Manifest(manifest)) => {
+                if receiver.is_some() || session.is_some_and(|(id, _)| id != manifest.session) {
+                    continue;
+                }
+                info!("receiving {} ({} bytes in {} blocks) from {}",
+                      manifest.name, manifest.size, manifest.blocks(), datagram.src);
+                session = Some((manifest.session, datagram.src));
+                receiver = Some(Receiver::new(manifest));
+            }
+            Some(Packet::Symbol { session: id, block, index, symbol }) => {
+                match session {
+                    Some((expected, _)) if expected != id => continue,
+                    Some(_) => {}
+                    None => session = Some((id, datagram.src)),
+                }
+                last_symbol = time::Instant::now();
+                if let Some(ref mut receiver) = receiver {
+                    let done = receiver.done();
+                    match receiver.add(block, index, symbol) {
+                        Ok(()) if receiver.done() > done => {
+                            debug!("{} of {} blocks", receiver.done(), receiver.manifest().blocks())
+                        }
+                        Ok(()) => {}
+                        Err(err) => warn!("ignoring a datagram from {}: {}", datagram.src, err),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let receiver = receiver.expect("a complete file");
+    let name = receiver.manifest().name.clone();
+    let file = receiver.finish().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let path = match args.output {
+        Some(ref path) => path.clone(),
+        None => match Path::new(&name).file_name() {
+            Some(name) => Path::new(name).to_path_buf(),
+            None => return Err(invalid_input(format!("can't write a file named {:?}; give --output", name))),
+        },
+    };
+    if path == Path::new("-") {
+        io::stdout().write_all(&file)?;
+    } else {
+        fs::write(&path, &file)?;
+    }
+    info!("received {} ({} bytes) in {:.1} s", path.display(), file.len(), started.elapsed().as_secs_f64());
+    Ok(EXIT_OK)
+}
+
+fn chat(args: &ChatArgs) -> AppResult<i32> {
+    let mut config = sender_config(&args.address, args.port, &args.sender)?;
+    config.reuse(true);
+    let mut listener = mccat::listen(&config)?;
+    listener.timeout(Some(POLL_INTERVAL))?.answer_pings(false);
+    let sock = config.sender()?;
+    let target = config.target()?;
+    let nick = match args.nick {
+        Some(ref nick) => nick.clone(),
+        None => env::var("USER").ok().filter(|user| chat::valid_nick(user)).unwrap_or_else(|| "anonymous".to_owned()),
+    };
+    let now = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap_or_default();
+    let id = now.subsec_nanos().rotate_left(12) ^ process::id();
+    sock.send_to(Message::Join { id, nick: nick.clone() }.to_string().as_bytes(), target)?;
+    info!("chatting on {} as {}; ^D leaves", config.addr(), nick);
+    signal::install()?;
+    let input_done = Arc::new(AtomicBool::new(false));
+    {
+        let sock = sock.try_clone()?;
+        let nick = nick.clone();
+        let input_done = Arc::clone(&input_done);
+        thread::spawn(move || {
+            for line in io::stdin().lines() {
+                let text = match line {
+                    Ok(text) => text,
+                    Err(err) => {
+                        warn!("can't read stdin: {}", err);
+                        break;
+                    }
+                };
+                let message = Message::Say { id, nick: nick.clone(), text };
+                if let Err(err) = sock.send_to(message.to_string().as_bytes(), target) {
+                    warn!("can't send: {}", err);
+                }
+            }
+            input_done.store(true, Ordering::Relaxed);
+        });
+    }
+    while !signal::interrupted() && !input_done.load(Ordering::Relaxed) {
+        let datagram = match listener.recv() {
+            Ok(datagram) => datagram,
+            Err(ref err) if timed_out(err) => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let from = datagram.src.ip();
+        match Message::parse(&datagram.data) {
+            // our own, looped back
+            Some(ref message) if message.id() == id => {}
+            Some(Message::Join { nick, .. }) => println!("* {} ({}) joined", chat::printable(&nick), from),
+            Some(Message::Leave { nick, .. }) => println!("* {} ({}) left", chat::printable(&nick), from),
+            Some(Message::Say { nick, text, .. }) => {
+                println!("<{}@{}> {}", chat::printable(&nick), from, chat::printable(&text))
+            }
+            None => debug!("ignoring a datagram from {} that isn't chat", datagram.src),
+        }
+    }
+    sock.send_to(Message::Leave { id, nick }.to_string().as_bytes(), target)?;
+    Ok(EXIT_OK)
+}
+
+/// Most groups `scan` takes on in one go.
+const MAX_SCAN: u128 = 65536;
+
+fn scan(args: &ScanArgs) -> AppResult<i32> {
+    if !args.prefix.addr.is_multicast() {
+        return Err(invalid_input(format!("{} is not a multicast prefix", args.prefix)));
+    }
+    let ports = cli::parse_ports(&args.ports).map_err(invalid_input)?;
+    let count = args.prefix.size().saturating_mul(ports.len() as u128);
+    if count > MAX_SCAN {
+        return Err(invalid_input(format!("{} would be {} groups; scan at most {} at a time", args.prefix, count,
+                                         MAX_SCAN)));
+    }
+    let groups: Vec<net::SocketAddr> = args.prefix
+        .addresses()
+        .filter(net::IpAddr::is_multicast)
+        .flat_map(|addr| ports.iter().map(move |&port| net::SocketAddr::new(addr, port)))
+        .collect();
+    let batches = groups.len().div_ceil(args.parallel as usize);
+    info!("scanning {} groups, {} at a time for {:.1} s: about {:.0} s",
+          groups.len(), args.parallel, args.dwell.as_secs_f64(), batches as f64 * args.dwell.as_secs_f64());
+    signal::install()?;
+    let started = time::Instant::now();
+    let (mut scanned, mut active) = (0, 0);
+    for batch in groups.chunks(args.parallel as usize) {
+        if signal::interrupted() {
+            break;
+        }
+        let configs: Vec<MulticastSocket> = batch.iter().map(|group| {
+            let mut config = MulticastSocket::new(group.ip(), group.port());
+            apply_interface(&mut config, &args.interface);
+            config.reuse(true).multicast_all(false);
+            config
+        }).collect();
+        let mut listener = listen_quietly(&configs)?;
+        let mut traffic: HashMap<net::SocketAddr, (RateMeter, BTreeSet<net::IpAddr>)> = HashMap::new();
+        let deadline = time::Instant::now() + args.dwell;
+        while !signal::interrupted() {
+            let now = time::Instant::now();
+            if now >= deadline {
+                break;
+            }
+            listener.timeout(Some(deadline - now));
+            match listener.recv() {
+                Ok(datagram) => {
+                    let (meter, sources) = traffic.entry(datagram.group).or_default();
+                    meter.record(&datagram);
+                    sources.insert(datagram.src.ip());
+                }
+                Err(ref err) if timed_out(err) => break,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        scanned += batch.len();
+        for group in batch {
+            if let Some((meter, sources)) = traffic.get(group) {
+                let report = meter.peek();
+                let sources: Vec<String> = sources.iter().map(ToString::to_string).collect();
+                // as if joined for the whole dwell time, however soon the first datagram came
+                let period = args.dwell.as_secs_f64();
+                println!("{}\t{:.1} pps\t{:.3} Mbit/s\t{}",
+                         group, report.packets as f64 / period, report.bytes as f64 * 8.0 / period / 1e6,
+                         sources.join(","));
+                active += 1;
+            }
+        }
+    }
+    info!("{} of {} groups carry traffic, scanned in {:.1} s", active, scanned, started.elapsed().as_secs_f64());
+    Ok(EXIT_OK)
+}
+
+fn join(args: &JoinArgs) -> AppResult<i32> {
+    let mut configs = Vec::new();
+    for group in &args.groups {
+        // any port will do, as nothing is read
+        let mut config = MulticastSocket::new(group.addr, 0);
+        apply_interface(&mut config, &args.interface);
+        if let Some(ref zone) = group.zone {
+            config.interface(zone);
+        }
+        if let Some(source) = args.source {
+            config.source(source);
+        }
+        configs.push(config);
+    }
+    let mut sockets = configs.iter().map(MulticastSocket::join).collect::<io::Result<Vec<_>>>()?;
+    info!("holding {} memberships", sockets.len());
+    let _pidfile = finish_startup(&args.daemon)?;
+    let mut watchdog = Watchdog::from_env();
+    signal::install()?;
+    let mut rejoin = args.rejoin.map(|interval| time::Instant::now() + interval);
+    while !signal::interrupted() {
+        if watchdog.due() {
+            notify_systemd("WATCHDOG=1");
+        }
+        thread::sleep(POLL_INTERVAL);
+        if let (Some(at), Some(interval)) = (rejoin, args.rejoin) {
+            if time::Instant::now() >= at {
+                for (config, sock) in configs.iter().zip(&mut sockets) {
+                    config.leave(sock)?;
+                    *sock = config.join()?;
+                }
+                debug!("rejoined {} groups", sockets.len());
+                rejoin = Some(at + interval);
+            }
+        }
+    }
+    Ok(EXIT_OK)
+}
+
+/// How often `stress joins` reports its progress.
+const STRESS_REPORT_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+fn stress_joins(args: &StressJoinsArgs) -> AppResult<i32> {
+    signal::install()?;
+    let started = time::Instant::now();
+    let deadline = args.duration.map(|duration| started + duration);
+    let mut pacer = Pacer::new(Rate::Packets(args.rate));
+    let mut groups = args.groups.addresses();
+    // memberships in the order joined, each on a socket of its own
+    let mut held: VecDeque<(time::Instant, MulticastSocket, net::UdpSocket)> = VecDeque::new();
+    let mut holding: HashSet<net::IpAddr> = HashSet::new();
+    let (mut joins, mut leaves, mut most) = (0u64, 0u64, 0usize);
+    let mut errors: BTreeMap<String, u64> = BTreeMap::new();
+    let mut report = started + STRESS_REPORT_INTERVAL;
+    while !signal::interrupted() && deadline.is_none_or(|deadline| time::Instant::now() < deadline) {
+        let now = time::Instant::now();
+        while held.front().is_some_and(|&(joined, _, _)| now >= joined + args.hold) {
+            let (_, config, sock) = held.pop_front().expect("a membership");
+            holding.remove(&config.addr().ip());
+            match config.leave(&sock) {
+                Ok(()) => leaves += 1,
+                Err(err) => *errors.entry(format!("can't leave: {}", err)).or_insert(0) += 1,
+            }
+        }
+        if now >= report {
+            info!("{} joins, {} leaves, {} errors, holding {} groups (most {})",
+                  joins, leaves, errors.values().sum::<u64>(), held.len(), most);
+            report += STRESS_REPORT_INTERVAL;
+        }
+        if (holding.len() as u128) >= args.groups.size() {
+            thread::sleep(POLL_INTERVAL.min(args.hold));
+            continue;
+        }
+        pacer.wait(0);
+        let group = match groups.next() {
+            Some(group) => group,
+            None => {
+                groups = args.groups.addresses();
+                continue;
+            }
+        };
+        if holding.contains(&group) {
+            continue;
+        }
+        let mut config = MulticastSocket::new(group, 0);
+        apply_interface(&mut config, &args.interface);
+        match config.join() {
+            Ok(sock) => {
+                joins += 1;
+                holding.insert(group);
+                held.push_back((time::Instant::now(), config, sock));
+                most = most.max(held.len());
+            }
+            Err(err) => {
+                let count = errors.entry(format!("can't join: {}", err)).or_insert(0);
+                if *count == 0 {
+                    warn!("can't join {} while holding {} groups: {}", group, held.len(), err);
+                }
+                *count += 1;
+            }
+        }
+    }
+    let leaving = held.len();
+    drop(held);
+    println!("{} joins and {} leaves in {:.1} s, at most {} groups held at once",
+             joins, leaves + leaving as u64, started.elapsed().as_secs_f64(), most);
+    for (error, count) in &errors {
+        println!("{} x {}", count, error);
+    }
+    Ok(if errors.is_empty() { EXIT_OK } else { EXIT_ERROR })
+}
+
+/// Adds `group` to the memberships of `sock`, already joined to another.
+fn join_also(sock: &net::UdpSocket, group: net::IpAddr, interface: &InterfaceArgs) -> io::Result<()> {
+    match group {
+        net::IpAddr::V4(group) => {
+            let ifaddr = match interface.interface {
+                Some(ref spec) => iface::resolve_v4(spec)?,
+                None => net::Ipv4Addr::UNSPECIFIED,
+            };
+            sock.join_multicast_v4(&group, &ifaddr)
+        }
+        net::IpAddr::V6(group) => {
+            let ifindex = match interface.interface {
+                Some(ref spec) => iface::resolve_v6(spec)?,
+                None => 0,
+            };
+            sock.join_multicast_v6(&group, ifindex)
+        }
+    }
+}
+
+/// Most groups `stress groups` lists as silent.
+const MAX_SILENT_LISTED: usize = 20;
+
+fn stress_groups(args: &StressGroupsArgs) -> AppResult<i32> {
+    signal::install()?;
+    let ipv6 = args.groups.addresses().next().is_some_and(|group| group.is_ipv6());
+    let limit = |err: &io::Error| match iface::membership_limit(err, ipv6) {
+        Some(limit) => format!("{} ({})", err, limit),
+        None => err.to_string(),
+    };
+    let max = args.max.map_or(usize::MAX, |max| max as usize);
+    // each socket takes groups until the host refuses it more, then
+    // another takes over, until a fresh socket can't join either
+    let mut listeners: Vec<Listener> = Vec::new();
+    let mut joined: Vec<net::IpAddr> = Vec::new();
+    let (mut on_socket, mut most_on_socket) = (0usize, 0usize);
+    let mut socket_limit: Option<io::Error> = None;
+    let mut stopped: Option<io::Error> = None;
+    for group in args.groups.addresses().take(max) {
+        if signal::interrupted() {
+            break;
+        }
+        if let Some(listener) = listeners.last() {
+            match join_also(listener.socket(), group, &args.interface) {
+                Ok(()) => {
+                    on_socket += 1;
+                    joined.push(group);
+                    continue;
+                }
+                Err(err) => {
+                    if socket_limit.is_none() {
+                        debug!("a socket holds at most {} groups: {}", on_socket, limit(&err));
+                    }
+                    socket_limit.get_or_insert(err);
+                }
+            }
+        }
+        let mut config = MulticastSocket::new(group, args.port);
+        apply_interface(&mut config, &args.interface);
+        config.reuse(true);
+        match mccat::listen(&config) {
+            Ok(mut listener) => {
+                listener.answer_pings(false);
+                listeners.push(listener);
+                most_on_socket = most_on_socket.max(on_socket);
+                on_socket = 1;
+                joined.push(group);
+            }
+            Err(err) => {
+                stopped = Some(err);
+                break;
+            }
+        }
+    }
+    most_on_socket = most_on_socket.max(on_socket);
+    println!("joined {} groups on {} sockets", joined.len(), listeners.len());
+    if let Some(ref err) = socket_limit {
+        println!("each socket held at most {} groups: {}", most_on_socket, limit(err));
+    }
+    if let Some(ref err) = stopped {
+        println!("stopped at {} groups: {}", joined.len(), limit(err));
+    }
+    let hold = match args.hold {
+        Some(hold) if !joined.is_empty() && !signal::interrupted() => hold,
+        _ => return Ok(EXIT_OK),
+    };
+    info!("holding {} groups for {:?}", joined.len(), hold);
+    let mut listener = MultiListener::new(listeners)?;
+    listener.timeout(Some(POLL_INTERVAL));
+    let mut received: HashMap<net::IpAddr, u64> = HashMap::new();
+    let deadline = time::Instant::now() + hold;
+    for datagram in listener {
+        if signal::interrupted() || time::Instant::now() >= deadline {
+            break;
+        }
+        match datagram {
+            Ok(datagram) => *received.entry(datagram.dst.unwrap_or(datagram.group.ip())).or_insert(0) += 1,
+            Err(ref err) if timed_out(err) || err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        }
     }
+    let silent: Vec<net::IpAddr> = joined.iter().cloned().filter(|group| !received.contains_key(group)).collect();
+    println!("traffic on {} of {} groups, {} datagrams in all",
+             joined.len() - silent.len(), joined.len(), received.values().sum::<u64>());
+    if silent.is_empty() {
+        return Ok(EXIT_OK);
+    }
+    let listed: Vec<String> = silent.iter().take(MAX_SILENT_LISTED).map(|group| group.to_string()).collect();
+    let more = silent.len() - listed.len();
+    if more > 0 {
+        println!("no traffic on {} and {} more", listed.join(", "), more);
+    } else {
+        println!("no traffic on {}", listed.join(", "));
+    }
+    Ok(EXIT_IDLE)
 }
 
-fn parse_cmdline() -> AppResult<(Command, net::IpAddr, u16)> {
-    let mut args = env::args().skip(1);
+/// Time from joining to the first datagram and, with `zap --mpegts`, the
+/// first keyframe.
+#[derive(Debug, Default, Clone, Copy)]
+struct ZapTime {
+    datagram: Option<time::Duration>,
+    keyframe: Option<time::Duration>,
+}
 
-    if args.len() == 3 {
-        let cmd = args.next().expect("cmd arg");
-        let addr = args.next().expect("addr arg");
-        let port = args.next().expect("port arg");
+/// Joins `config` and times what it brings, leaving again when done.
+fn zap_once(config: &MulticastSocket, mpegts: bool, timeout: time::Duration) -> AppResult<ZapTime> {
+    let joined = time::SystemTime::now();
+    let started = time::Instant::now();
+    let mut listener = mccat::listen(config)?;
+    listener.answer_pings(false);
+    let mut zap = ZapTime::default();
+    while !signal::interrupted() {
+        let elapsed = started.elapsed();
+        if elapsed >= timeout {
+            break;
+        }
+        listener.timeout(Some(timeout - elapsed))?;
+        let datagram = match listener.recv() {
+            Ok(datagram) => datagram,
+            Err(ref err) if timed_out(err) => break,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        // the kernel's receive time, not when we got around to reading it
+        let since_join = datagram.received.duration_since(joined).unwrap_or_default();
+        zap.datagram.get_or_insert(since_join);
+        if !mpegts {
+            break;
+        }
+        if mpegts::random_access(mpegts::payload(&datagram.data)) {
+            zap.keyframe = Some(since_join);
+            break;
+        }
+    }
+    Ok(zap)
+}
 
-        let cmd = match &*cmd {
-            "listen" => Command::Listen,
-            "send" => Command::Send,
-            "ping" => Command::Ping,
-            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, USAGE))?
+fn zap(args: &ZapArgs) -> AppResult<i32> {
+    signal::install()?;
+    let mut times: Vec<Vec<ZapTime>> = vec![Vec::new(); args.groups.len()];
+    let ms = |duration: Option<time::Duration>| match duration {
+        Some(duration) => format!("{:.1} ms", duration.as_secs_f64() * 1e3),
+        None => "-".to_owned(),
+    };
+    'zapping: for _ in 0..args.count {
+        for (group, times) in args.groups.iter().zip(&mut times) {
+            if signal::interrupted() {
+                break 'zapping;
+            }
+            let mut config = MulticastSocket::new(group.addr.ip(), group.addr.port());
+            apply_interface(&mut config, &args.interface);
+            if let Some(ref interface) = group.interface {
+                config.interface(interface);
+            }
+            if let Some(source) = args.source.or(group.source) {
+                config.source(source);
+            }
+            config.reuse(true);
+            let zap = zap_once(&config, args.mpegts, args.timeout)?;
+            if args.mpegts {
+                println!("{}\tdatagram {}\tkeyframe {}", group.addr, ms(zap.datagram), ms(zap.keyframe));
+            } else {
+                println!("{}\tdatagram {}", group.addr, ms(zap.datagram));
+            }
+            times.push(zap);
+            thread::sleep(args.interval);
+        }
+    }
+    let mut exit = EXIT_OK;
+    info!("\n--- zap times, min/avg/max ---");
+    for (group, times) in args.groups.iter().zip(&times) {
+        let summary = |pick: fn(&ZapTime) -> Option<time::Duration>| {
+            let found: Vec<time::Duration> = times.iter().filter_map(pick).collect();
+            if found.is_empty() {
+                return "-".to_owned();
+            }
+            let min = found.iter().min().expect("a time").as_secs_f64() * 1e3;
+            let max = found.iter().max().expect("a time").as_secs_f64() * 1e3;
+            let avg = found.iter().sum::<time::Duration>().as_secs_f64() * 1e3 / found.len() as f64;
+            format!("{:.1}/{:.1}/{:.1} ms in {} of {}", min, avg, max, found.len(), times.len())
         };
+        if times.iter().any(|zap| zap.datagram.is_none()) {
+            exit = EXIT_IDLE;
+        }
+        if args.mpegts {
+            info!("{}: datagram {}, keyframe {}", group.addr, summary(|zap| zap.datagram),
+                  summary(|zap| zap.keyframe));
+        } else {
+            info!("{}: datagram {}", group.addr, summary(|zap| zap.datagram));
+        }
+    }
+    Ok(exit)
+}
 
-        let addr: net::IpAddr = addr.parse()?;
-        let port: u16 = port.parse()?;
+fn merge(args: &MergeArgs) -> AppResult<i32> {
+    if args.payload && !matches!(args.sequence, Sequence::Rtp) {
+        return Err(invalid_input("--payload takes RTP, the default --sequence"));
+    }
+    let leg = |group: &ConfigGroup, interface: &Option<String>, source: Option<net::IpAddr>| {
+        let mut config = MulticastSocket::new(group.addr.ip(), group.addr.port());
+        if let Some(interface) = group.interface.as_ref().or(interface.as_ref()) {
+            config.interface(interface);
+        }
+        if let Some(source) = source {
+            config.source(source);
+        }
+        // both legs are often the same group on two networks
+        config.reuse(true).multicast_all(false);
+        apply_buffers(&mut config, &args.buffers);
+        config
+    };
+    let main = leg(&args.main, &args.main_interface, args.main_source);
+    let backup = leg(&args.backup, &args.backup_interface, args.backup_source);
+    let interface = |group: &ConfigGroup, interface: &Option<String>| group.interface.clone().or(interface.clone());
+    if main.addr() == backup.addr() &&
+       interface(&args.main, &args.main_interface) == interface(&args.backup, &args.backup_interface) {
+        return Err(invalid_input("the main and backup legs are the same group on the same interface; give \
+                                  --main-interface and --backup-interface"));
+    }
+    let mut listeners = Vec::new();
+    for config in [&main, &backup] {
+        let mut listener = mccat::listen(config)?;
+        listener.answer_pings(false);
+        report_buffers(listener.socket(), &config.addr().to_string(), &args.buffers)?;
+        listeners.push(listener);
+    }
+    let mut listener = MultiListener::new(listeners)?;
+    let mut out: Box<dyn Write> = if args.output == Path::new("-") {
+        Box::new(io::stdout())
+    } else {
+        Box::new(fs::File::create(&args.output)?)
+    };
+    info!("merging {} (main) and {} (backup)", main.addr(), backup.addr());
+    signal::install()?;
+    let started = time::Instant::now();
+    let mut merger = Merger::new(args.sequence, args.max_skew);
+    let mut put_out = |merged: Vec<Merged>| -> AppResult<()> {
+        for merged in merged {
+            match merged {
+                Merged::Packet { seq, leg, data } => {
+                    trace!("seq {} from {}", seq, leg);
+                    let data = match RtpHeader::parse(&data) {
+                        Some(header) if args.payload => &data[header.payload_start..header.payload_end],
+                        _ => &data[..],
+                    };
+                    out.write_all(data)?;
+                }
+                Merged::Lost { first, count } => warn!("lost {} on both legs", seq_run(first, count)),
+                Merged::Recovered { first, count } => info!("recovered {} from backup", seq_run(first, count)),
+                Merged::Restart(seq) => info!("sequence numbers restarted at {}", seq),
+            }
+        }
+        Ok(())
+    };
+    while !signal::interrupted() && args.duration.is_none_or(|duration| started.elapsed() < duration) {
+        let now = time::Instant::now();
+        listener.timeout(Some(merger.wait(now).map_or(POLL_INTERVAL, |wait| wait.min(POLL_INTERVAL))));
+        match listener.recv_indexed() {
+            Ok((index, datagram)) => {
+                let leg = if index == 0 { Leg::Main } else { Leg::Backup };
+                merger.push(leg, &datagram.data, time::Instant::now());
+            }
+            Err(ref err) if timed_out(err) => {}
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted && signal::interrupted() => break,
+            Err(err) => return Err(err.into()),
+        }
+        put_out(merger.pop(time::Instant::now()))?;
+    }
+    put_out(merger.flush())?;
+    out.flush()?;
+    let counts = merger.counts();
+    info!("{}", counts);
+    if counts.duplicates + counts.late + counts.unsequenced > 0 {
+        info!("left out {} duplicates, {} late packets and {} datagrams without a sequence number",
+              counts.duplicates, counts.late, counts.unsequenced);
+    }
+    Ok(EXIT_OK)
+}
 
-        Ok((cmd, addr, port))
+/// `count` sequence numbers from `first`, for the log.
+fn seq_run(first: u64, count: u64) -> String {
+    if count == 1 {
+        format!("seq {}", first)
     } else {
-        Err(io::Error::new(io::ErrorKind::InvalidInput, USAGE).into())
+        format!("{} packets from seq {}", count, first)
+    }
+}
+
+fn observe_igmp(args: &IgmpArgs) -> AppResult<i32> {
+    let interface = match args.interface {
+        Some(ref spec) => Some(iface::resolve_v6(spec)?),
+        None => None,
+    };
+    let mut observer = match igmp::Observer::new(interface) {
+        Ok(observer) => observer,
+        Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            return Err(invalid_input("observing IGMP takes a raw socket: run as root or with CAP_NET_RAW"));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    observer.timeout(Some(POLL_INTERVAL))?;
+    signal::install()?;
+    let deadline = args.duration.map(|duration| time::Instant::now() + duration);
+    let mut queriers = igmp::Queriers::new();
+    while !signal::interrupted() && deadline.is_none_or(|deadline| time::Instant::now() < deadline) {
+        let observed = match observer.recv() {
+            Ok(Some(observed)) => observed,
+            Ok(None) => continue,
+            Err(ref err) if timed_out(err) || err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let packet = &observed.packet;
+        let name = iface::index_to_name(observed.interface).unwrap_or_else(|_| observed.interface.to_string());
+        let prefix = format!("{} {} {} {}{}",
+                             timestamp::iso8601(observed.received),
+                             name,
+                             packet.protocol,
+                             packet.src,
+                             if observed.outgoing { " (this host)" } else { "" });
+        for line in packet.describe() {
+            println!("{}: {}", prefix, line);
+        }
+        if let igmp::Message::Query { .. } = packet.message {
+            match queriers.query(observed.interface, packet.src, time::Instant::now()) {
+                Some(igmp::Election::Elected(querier)) => println!("{}: querier {} elected", prefix, querier),
+                Some(igmp::Election::Replaced { old, new }) => {
+                    println!("{}: querier {} replaces {}, having the lower address", prefix, new, old)
+                }
+                None => {}
+            }
+        }
+    }
+    Ok(EXIT_OK)
+}
+
+fn observe_routing(args: &RoutingArgs) -> AppResult<i32> {
+    let interface = match args.interface {
+        Some(ref spec) => Some(iface::resolve_v6(spec)?),
+        None => None,
+    };
+    let mut observer = match routing::Observer::new(interface) {
+        Ok(observer) => observer,
+        Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            return Err(invalid_input("observing routing protocols takes a raw socket: run as root or with \
+                                      CAP_NET_RAW"));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    observer.timeout(Some(POLL_INTERVAL))?;
+    signal::install()?;
+    let deadline = args.duration.map(|duration| time::Instant::now() + duration);
+    let mut speakers = routing::Speakers::new();
+    let name = |index: u32| iface::index_to_name(index).unwrap_or_else(|_| index.to_string());
+    while !signal::interrupted() && deadline.is_none_or(|deadline| time::Instant::now() < deadline) {
+        let observed = match observer.recv() {
+            Ok(Some(observed)) => observed,
+            Ok(None) => continue,
+            Err(ref err) if timed_out(err) || err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let packet = &observed.packet;
+        let prefix = format!("{} {} {} {}{}",
+                             timestamp::iso8601(observed.received),
+                             name(observed.interface),
+                             packet.protocol,
+                             packet.src,
+                             if observed.outgoing { " (this host)" } else { "" });
+        for line in packet.message.describe() {
+            println!("{}: {}", prefix, line);
+        }
+        speakers.record(&observed);
+    }
+    let speakers = speakers.speakers();
+    if speakers.is_empty() {
+        info!("no routing protocol messages seen");
+        return Ok(EXIT_IDLE);
+    }
+    info!("\n--- speakers ---");
+    for speaker in speakers {
+        info!("{} {} {}: {} messages since {}, last {}",
+              name(speaker.interface),
+              speaker.protocol,
+              speaker.src,
+              speaker.messages,
+              timestamp::iso8601(speaker.first_heard),
+              speaker.last);
+    }
+    Ok(EXIT_OK)
+}
+
+fn observe_ptp(args: &PtpArgs) -> AppResult<i32> {
+    let configs: Vec<MulticastSocket> = [ptp::EVENT_PORT, ptp::GENERAL_PORT]
+        .iter()
+        .map(|&port| {
+            let mut config = MulticastSocket::new(args.group, port);
+            apply_interface(&mut config, &args.interface);
+            config.reuse(true);
+            config
+        })
+        .collect();
+    let mut listener = match listen_quietly(&configs) {
+        Ok(listener) => listener,
+        Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            return Err(invalid_input("ports 319 and 320 are privileged: run as root or with CAP_NET_BIND_SERVICE"));
+        }
+        Err(ref err) if err.kind() == io::ErrorKind::AddrInUse => {
+            return Err(invalid_input("ports 319 and 320 are taken, most likely by a PTP daemon on this host"));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    listener.timeout(Some(POLL_INTERVAL));
+    info!("watching PTP on {} ports {} and {}", args.group, ptp::EVENT_PORT, ptp::GENERAL_PORT);
+    signal::install()?;
+    let deadline = args.duration.map(|duration| time::Instant::now() + duration);
+    let mut next_report = time::Instant::now() + args.report_interval;
+    let mut monitor = PtpMonitor::new();
+    while !signal::interrupted() && deadline.is_none_or(|deadline| time::Instant::now() < deadline) {
+        if time::Instant::now() >= next_report {
+            next_report += args.report_interval;
+            for report in monitor.interval_reports() {
+                println!("{} {}", timestamp::iso8601(time::SystemTime::now()), report);
+            }
+        }
+        let datagram = match listener.recv() {
+            Ok(datagram) => datagram,
+            Err(ref err) if timed_out(err) || err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let message = match ptp::Message::parse(&datagram.data) {
+            Some(message) => message,
+            None => {
+                debug!("{}: not a PTPv2 message", datagram.src);
+                continue;
+            }
+        };
+        if args.domain.is_some_and(|domain| domain != message.domain) {
+            continue;
+        }
+        let prefix = format!("{} {}", timestamp::iso8601(datagram.received), datagram.src.ip());
+        if args.messages {
+            println!("{}: {}", prefix, message);
+        }
+        for event in monitor.record(&message, datagram.src.ip()) {
+            println!("{}: {}", prefix, event);
+        }
+    }
+    let reports = monitor.reports();
+    if reports.is_empty() {
+        info!("no PTP messages seen");
+        return Ok(EXIT_IDLE);
+    }
+    info!("\n--- PTP ports ---");
+    for report in reports {
+        info!("{}", report);
+    }
+    Ok(EXIT_OK)
+}
+
+/// General queries sent at a quarter of the interval on startup, so that
+/// memberships are learned quickly.
+const STARTUP_QUERIES: u32 = igmp::ROBUSTNESS;
+/// How long hosts get to answer the query sent after one leaves a group.
+const LAST_MEMBER_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+fn print_memberships(interface: &str, memberships: &igmp::Memberships) {
+    let groups = memberships.groups();
+    println!("{} {} groups with members on {}", timestamp::iso8601(time::SystemTime::now()), groups.len(), interface);
+    for (group, members) in groups {
+        let members: Vec<String> = members.iter().map(|member| member.to_string()).collect();
+        println!("    {}\t{}", group, members.join(", "));
+    }
+}
+
+fn querier(args: &QuerierArgs) -> AppResult<i32> {
+    let index = iface::resolve_v6(&args.interface)?;
+    let name = iface::index_to_name(index)?;
+    let v4 = match iface::resolve_v4(&args.interface) {
+        _ if args.ipv6 => None,
+        Ok(addr) => Some(addr),
+        Err(err) if args.ipv4 => return Err(err.into()),
+        Err(err) => {
+            warn!("not querying with IGMP: {}", err);
+            None
+        }
+    };
+    // MLD queries come from the link-local address
+    let link_local = iface::interfaces()?
+        .into_iter()
+        .find(|interface| interface.index == index)
+        .and_then(|interface| {
+            interface.addresses.into_iter().find_map(|addr| match addr {
+                net::IpAddr::V6(addr) if addr.is_unicast_link_local() => Some(addr),
+                _ => None,
+            })
+        });
+    let v6 = match link_local {
+        _ if args.ipv4 => None,
+        Some(addr) => Some(addr),
+        None if args.ipv6 => return Err(invalid_input(format!("{} has no IPv6 link-local address", name))),
+        None => {
+            warn!("not querying with MLD: {} has no IPv6 link-local address", name);
+            None
+        }
+    };
+    let families: Vec<(bool, net::IpAddr)> = v4.map(|addr| (false, net::IpAddr::V4(addr)))
+        .into_iter()
+        .chain(v6.map(|addr| (true, net::IpAddr::V6(addr))))
+        .collect();
+    if families.is_empty() {
+        return Err(invalid_input(format!("{} has no address to query from", name)));
+    }
+    let opened = igmp::Querier::new(index, v4, v6, args.interval)
+        .and_then(|querier| Ok((querier, igmp::Observer::new(Some(index))?)));
+    let (querier, mut observer) = match opened {
+        Ok(opened) => opened,
+        Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            return Err(invalid_input("querying takes raw sockets: run as root or with CAP_NET_RAW"));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    observer.timeout(Some(POLL_INTERVAL))?;
+    signal::install()?;
+    info!("querying on {} every {:?}", name, args.interval);
+    let member_timeout = args.interval * igmp::ROBUSTNESS + args.max_response;
+    let mut queriers = igmp::Queriers::new();
+    let mut memberships = igmp::Memberships::new();
+    let mut startup = STARTUP_QUERIES;
+    let mut next_query = time::Instant::now();
+    let mut summary: Option<time::Instant> = None;
+    while !signal::interrupted() {
+        let now = time::Instant::now();
+        if now >= next_query {
+            for &(ipv6, addr) in &families {
+                // another querier with a lower address has been elected
+                if queriers.querier(index, ipv6, now).is_none_or(|querier| querier == addr) {
+                    querier.general_query(ipv6, args.max_response)?;
+                }
+            }
+            next_query = now + if startup > 1 { args.interval / 4 } else { args.interval };
+            startup = startup.saturating_sub(1);
+            summary = Some(now + args.max_response);
+        }
+        if summary.is_some_and(|at| now >= at) {
+            summary = None;
+            for change in memberships.expire(member_timeout, now) {
+                if let igmp::Change::Expired { group, member } = change {
+                    info!("{} stopped reporting for {}", member, group);
+                }
+            }
+            print_memberships(&name, &memberships);
+        }
+        let observed = match observer.recv() {
+            Ok(Some(observed)) => observed,
+            Ok(None) => continue,
+            Err(ref err) if timed_out(err) || err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let packet = &observed.packet;
+        if let igmp::Message::Query { .. } = packet.message {
+            match queriers.query(index, packet.src, time::Instant::now()) {
+                Some(igmp::Election::Elected(elected)) => info!("querier {} elected", elected),
+                Some(igmp::Election::Replaced { old, new }) => info!("querier {} replaces {}", new, old),
+                None => {}
+            }
+            continue;
+        }
+        for change in memberships.record(packet, time::Instant::now()) {
+            match change {
+                igmp::Change::Joined { group, member } => info!("{} joined {}", member, group),
+                igmp::Change::Left { group, member } => {
+                    info!("{} left {}", member, group);
+                    let ours = families.iter().any(|&(ipv6, addr)| {
+                        ipv6 == group.is_ipv6() && queriers.querier(index, ipv6, time::Instant::now()) == Some(addr)
+                    });
+                    if ours {
+                        querier.group_query(group, LAST_MEMBER_INTERVAL)?;
+                    }
+                }
+                igmp::Change::Expired { .. } => {}
+            }
+        }
+    }
+    print_memberships(&name, &memberships);
+    Ok(EXIT_OK)
+}
+
+fn ctl(args: &CtlArgs) -> AppResult<i32> {
+    let command = args.command.join(" ");
+    match control::send(&args.socket, &command)? {
+        Ok(body) => {
+            if !body.is_empty() {
+                println!("{}", body);
+            }
+            Ok(EXIT_OK)
+        }
+        Err(err) => {
+            error!("{}", err);
+            Ok(EXIT_ERROR)
+        }
     }
 }
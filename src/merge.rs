@@ -0,0 +1,419 @@
+use std::{fmt, str};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use generate::GeneratedSequence;
+use loss::{FieldSequence, PingSequence, SequenceExtractor};
+use rtp::RtpHeader;
+
+/// Sequence numbers further than this from the next one to put out are
+/// taken as the sender restarting, rather than as a late leg or as packets
+/// lost on both.
+const RESTART_GAP: u64 = 8192;
+
+/// One of the two paths carrying the same stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Leg {
+    Main,
+    Backup,
+}
+
+impl fmt::Display for Leg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Leg::Main => write!(f, "main"),
+            Leg::Backup => write!(f, "backup"),
+        }
+    }
+}
+
+/// Where the sequence number that lines the two legs up is read from.
+#[derive(Debug, Clone, Copy)]
+pub enum Sequence {
+    /// The RTP header's, which wraps at 16 bits.
+    Rtp,
+    /// The stamp of mccat generate.
+    Generated,
+    /// mccat's own "PING <n>" probes.
+    Ping,
+    /// A big-endian field, wrapping at its width.
+    Field(FieldSequence),
+}
+
+impl Sequence {
+    /// The sequence number in `data` and how many bits it has before it
+    /// wraps.
+    fn extract(&self, data: &[u8]) -> Option<(u64, u32)> {
+        match *self {
+            Sequence::Rtp => RtpHeader::parse(data).map(|header| (u64::from(header.seqnum), 16)),
+            Sequence::Generated => GeneratedSequence.extract(data).map(|seq| (seq, 64)),
+            Sequence::Ping => PingSequence.extract(data).map(|seq| (seq, 64)),
+            Sequence::Field(field) => field.extract(data).map(|seq| (seq, field.width as u32 * 8)),
+        }
+    }
+}
+
+impl str::FromStr for Sequence {
+    type Err = String;
+
+    /// Parses `rtp`, `generate`, `ping` or a field as `OFFSET:WIDTH`.
+    fn from_str(s: &str) -> Result<Sequence, String> {
+        match s {
+            "rtp" => Ok(Sequence::Rtp),
+            "generate" => Ok(Sequence::Generated),
+            "ping" => Ok(Sequence::Ping),
+            _ => s.parse().map(Sequence::Field).map_err(|_| {
+                format!("invalid sequence {}: expected rtp, generate, ping or OFFSET:WIDTH", s)
+            }),
+        }
+    }
+}
+
+/// What a `Merger` puts out, in sequence order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Merged {
+    /// The next packet of the merged stream, from the leg it came on first.
+    Packet { seq: u64, leg: Leg, data: Vec<u8> },
+    /// `count` packets from `first` on arrived on neither leg in time.
+    Lost { first: u64, count: u64 },
+    /// `count` packets from `first` on that only the backup leg delivered,
+    /// known once the main leg's copies can no longer be expected.
+    Recovered { first: u64, count: u64 },
+    /// The sender started over at this sequence number.
+    Restart(u64),
+}
+
+/// How the two legs made up the merged stream.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MergeCounts {
+    /// Packets put out.
+    pub packets: u64,
+    /// Packets that came on both legs.
+    pub both: u64,
+    /// Packets only the main leg delivered.
+    pub main_only: u64,
+    /// Packets only the backup leg delivered, which a single-leg receiver
+    /// would have lost.
+    pub recovered: u64,
+    /// Packets that came on neither leg in time.
+    pub lost: u64,
+    /// Copies that came on the same leg twice.
+    pub duplicates: u64,
+    /// Packets that came after the gap they were in had been given up on.
+    pub late: u64,
+    /// Datagrams without a sequence number, which are left out.
+    pub unsequenced: u64,
+    pub restarts: u64,
+    /// How much later the backup leg's copies came than the main leg's,
+    /// in seconds, negative when the backup leg was ahead.
+    pub skew_min: f64,
+    pub skew_max: f64,
+    skew_total: f64,
+}
+
+impl MergeCounts {
+    pub fn mean_skew(&self) -> f64 {
+        if self.both == 0 {
+            0.0
+        } else {
+            self.skew_total / self.both as f64
+        }
+    }
+
+    fn skew(&mut self, skew: f64) {
+        if self.both == 0 {
+            self.skew_min = skew;
+            self.skew_max = skew;
+        } else {
+            self.skew_min = self.skew_min.min(skew);
+            self.skew_max = self.skew_max.max(skew);
+        }
+        self.skew_total += skew;
+        self.both += 1;
+    }
+}
+
+impl fmt::Display for MergeCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{} packets: {} on both legs, {} main only, {} recovered from backup, {} lost on both",
+               self.packets,
+               self.both,
+               self.main_only,
+               self.recovered,
+               self.lost)?;
+        if self.both > 0 {
+            write!(f,
+                   "; backup behind main min/avg/max {:.3}/{:.3}/{:.3} ms",
+                   self.skew_min * 1e3,
+                   self.mean_skew() * 1e3,
+                   self.skew_max * 1e3)?;
+        }
+        Ok(())
+    }
+}
+
+/// The copies of one packet seen so far.
+#[derive(Debug)]
+struct Copies {
+    /// The packet, until it has been put out.
+    data: Option<Vec<u8>>,
+    main: Option<Instant>,
+    backup: Option<Instant>,
+}
+
+impl Copies {
+    fn first(&self) -> Instant {
+        match (self.main, self.backup) {
+            (Some(main), Some(backup)) => main.min(backup),
+            (Some(first), None) | (None, Some(first)) => first,
+            (None, None) => unreachable!("copies of a packet that never came"),
+        }
+    }
+
+    fn leg(&self) -> Leg {
+        match (self.main, self.backup) {
+            (Some(main), Some(backup)) if backup < main => Leg::Backup,
+            (Some(_), _) => Leg::Main,
+            (None, _) => Leg::Backup,
+        }
+    }
+}
+
+/// Merges two legs carrying the same stream into one, as SMPTE 2022-7
+/// receivers do: each packet is put out once, in sequence order, from
+/// whichever leg delivers it first. A gap waits up to the largest skew
+/// expected between the legs for the other leg to fill it before it is
+/// given up on.
+#[derive(Debug)]
+pub struct Merger {
+    sequence: Sequence,
+    max_skew: Duration,
+    /// Bits in the sequence number, once the first packet has come.
+    bits: u32,
+    /// The next sequence number to put out, extended past wrapping.
+    next: Option<u64>,
+    /// Packets waiting to be put out, and those put out whose copy on the
+    /// other leg may still come, by extended sequence number.
+    copies: BTreeMap<u64, Copies>,
+    /// Restarts and recovered packets seen while taking packets in.
+    ready: VecDeque<Merged>,
+    /// The run of packets recovered from the backup leg so far, as the
+    /// first and the number of them.
+    recovering: Option<(u64, u64)>,
+    counts: MergeCounts,
+}
+
+impl Merger {
+    pub fn new(sequence: Sequence, max_skew: Duration) -> Merger {
+        Merger {
+            sequence,
+            max_skew,
+            bits: 64,
+            next: None,
+            copies: BTreeMap::new(),
+            ready: VecDeque::new(),
+            recovering: None,
+            counts: MergeCounts::default(),
+        }
+    }
+
+    pub fn counts(&self) -> MergeCounts {
+        self.counts
+    }
+
+    /// The sequence number as sent.
+    fn wire(&self, seq: u64) -> u64 {
+        if self.bits >= 64 {
+            seq
+        } else {
+            seq & ((1 << self.bits) - 1)
+        }
+    }
+
+    /// Extends `seq` to the value nearest the next one to put out. The
+    /// first packet is put one wrap in, so that the ones just before it
+    /// extend to no less than zero.
+    fn extend(&self, seq: u64) -> u64 {
+        if self.bits >= 64 {
+            return seq;
+        }
+        let span = 1u64 << self.bits;
+        let next = match self.next {
+            Some(next) => next,
+            None => return span + seq,
+        };
+        let seq = (next & !(span - 1)) | seq;
+        if seq > next + span / 2 {
+            seq - span
+        } else if seq + span / 2 < next {
+            seq + span
+        } else {
+            seq
+        }
+    }
+
+    /// Takes in a datagram that arrived on `leg` at `now`.
+    pub fn push(&mut self, leg: Leg, data: &[u8], now: Instant) {
+        let (seq, bits) = match self.sequence.extract(data) {
+            Some(extracted) => extracted,
+            None => {
+                self.counts.unsequenced += 1;
+                return;
+            }
+        };
+        if self.next.is_none() {
+            self.bits = bits;
+        }
+        let seq = self.extend(seq);
+        let next = *self.next.get_or_insert(seq);
+        if seq > next + RESTART_GAP || seq + RESTART_GAP < next {
+            self.restart(seq);
+        } else if seq < next && !self.copies.contains_key(&seq) {
+            self.counts.late += 1;
+            return;
+        }
+        let copies = self.copies.entry(seq).or_insert(Copies { data: None, main: None, backup: None });
+        let arrived = match leg {
+            Leg::Main => &mut copies.main,
+            Leg::Backup => &mut copies.backup,
+        };
+        if arrived.is_some() {
+            self.counts.duplicates += 1;
+            return;
+        }
+        *arrived = Some(now);
+        if copies.data.is_none() && seq >= self.next.unwrap_or(seq) {
+            copies.data = Some(data.to_vec());
+        }
+    }
+
+    /// Puts out everything still waiting, and starts over at `seq`.
+    fn restart(&mut self, seq: u64) {
+        let pending: Vec<u64> = self.copies.keys().cloned().collect();
+        for seq in pending {
+            self.settle(seq);
+        }
+        self.next = Some(seq);
+        self.counts.restarts += 1;
+        let restart = Merged::Restart(self.wire(seq));
+        self.ready.push_back(restart);
+    }
+
+    /// Puts `seq` out if it hasn't been, and counts which legs had it.
+    fn settle(&mut self, seq: u64) {
+        let mut copies = match self.copies.remove(&seq) {
+            Some(copies) => copies,
+            None => return,
+        };
+        if let Some(packet) = self.packet(seq, &mut copies) {
+            self.ready.push_back(packet);
+        }
+        match (copies.main, copies.backup) {
+            (Some(main), Some(backup)) => {
+                let skew = if backup >= main {
+                    (backup - main).as_secs_f64()
+                } else {
+                    -(main - backup).as_secs_f64()
+                };
+                self.counts.skew(skew);
+                self.end_recovering();
+            }
+            (Some(_), None) => {
+                self.counts.main_only += 1;
+                self.end_recovering();
+            }
+            (None, _) => {
+                self.counts.recovered += 1;
+                match self.recovering {
+                    Some((first, count)) if first + count == seq => self.recovering = Some((first, count + 1)),
+                    _ => {
+                        self.end_recovering();
+                        self.recovering = Some((seq, 1));
+                    }
+                }
+            }
+        }
+    }
+
+    fn end_recovering(&mut self) {
+        if let Some((first, count)) = self.recovering.take() {
+            let recovered = Merged::Recovered { first: self.wire(first), count };
+            self.ready.push_back(recovered);
+        }
+    }
+
+    fn packet(&mut self, seq: u64, copies: &mut Copies) -> Option<Merged> {
+        let data = copies.data.take()?;
+        self.counts.packets += 1;
+        Some(Merged::Packet { seq: self.wire(seq), leg: copies.leg(), data })
+    }
+
+    /// What is ready to go out at `now`: the packets that are next in
+    /// sequence, gaps that have waited out the skew, and which legs
+    /// delivered the packets whose other copy can no longer come.
+    pub fn pop(&mut self, now: Instant) -> Vec<Merged> {
+        let mut merged: Vec<Merged> = self.ready.drain(..).collect();
+        let mut next = match self.next {
+            Some(next) => next,
+            None => return merged,
+        };
+        loop {
+            if let Some(mut copies) = self.copies.remove(&next) {
+                merged.extend(self.packet(next, &mut copies));
+                self.copies.insert(next, copies);
+                next += 1;
+                continue;
+            }
+            // the first packet past a gap has waited for the other leg long enough
+            let waited = self.copies.range(next..).next().map(|(&seq, copies)| (seq, copies.first()));
+            match waited {
+                Some((seq, first)) if now >= first + self.max_skew => {
+                    merged.push(Merged::Lost { first: self.wire(next), count: seq - next });
+                    self.counts.lost += seq - next;
+                    next = seq;
+                }
+                _ => break,
+            }
+        }
+        self.next = Some(next);
+        while let Some((&seq, copies)) = self.copies.iter().next() {
+            if seq >= next || now < copies.first() + self.max_skew {
+                break;
+            }
+            self.settle(seq);
+        }
+        merged.extend(self.ready.drain(..));
+        merged
+    }
+
+    /// Puts out everything still waiting, as when the stream ends.
+    pub fn flush(&mut self) -> Vec<Merged> {
+        let mut merged = self.ready.drain(..).collect::<Vec<_>>();
+        let mut next = match self.next {
+            Some(next) => next,
+            None => return merged,
+        };
+        let pending: Vec<u64> = self.copies.keys().cloned().collect();
+        for seq in pending {
+            if seq > next {
+                merged.push(Merged::Lost { first: self.wire(next), count: seq - next });
+                self.counts.lost += seq - next;
+            }
+            next = next.max(seq + 1);
+            self.settle(seq);
+            merged.extend(self.ready.drain(..));
+        }
+        self.end_recovering();
+        merged.extend(self.ready.drain(..));
+        self.next = Some(next);
+        merged
+    }
+
+    /// How long until a gap or a packet has waited out the skew, if any
+    /// are waiting.
+    pub fn wait(&self, now: Instant) -> Option<Duration> {
+        let earliest = self.copies.values().map(Copies::first).min()?;
+        Some((earliest + self.max_skew).saturating_duration_since(now))
+    }
+}
@@ -0,0 +1,254 @@
+use std::{io, net, thread};
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use listen::Datagram;
+use loss::LossCounts;
+use relay;
+
+/// Prometheus' text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counters {
+    packets: u64,
+    bytes: u64,
+    last: Option<SystemTime>,
+    last_gap: Option<Duration>,
+    /// RFC 3550-style jitter of the inter-arrival gaps, in seconds.
+    jitter: f64,
+}
+
+impl Counters {
+    fn record(&mut self, datagram: &Datagram) {
+        self.packets += 1;
+        self.bytes += datagram.size as u64;
+        let gap = self.last.map(|last| datagram.received.duration_since(last).unwrap_or_default());
+        if let (Some(gap), Some(last_gap)) = (gap, self.last_gap) {
+            let variation = (gap.as_secs_f64() - last_gap.as_secs_f64()).abs();
+            self.jitter += (variation - self.jitter) / 16.0;
+        }
+        self.last = Some(datagram.received);
+        self.last_gap = gap;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Group {
+    counters: Counters,
+    kernel_drops: u64,
+}
+
+#[derive(Debug, Default)]
+struct Source {
+    counters: Counters,
+    loss: Option<LossCounts>,
+}
+
+/// Counters and gauges of what `listen` receives, per group and per
+/// sender, rendered for Prometheus to scrape.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    groups: BTreeMap<net::SocketAddr, Group>,
+    sources: BTreeMap<(net::SocketAddr, net::SocketAddr), Source>,
+}
+
+impl Metrics {
+    /// Metrics for `groups`, which are listed even before any traffic.
+    pub fn new(groups: &[net::SocketAddr]) -> Metrics {
+        Metrics {
+            groups: groups.iter().map(|&group| (group, Group::default())).collect(),
+            sources: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, datagram: &Datagram) {
+        self.groups.entry(datagram.group).or_default().counters.record(datagram);
+        self.sources.entry((datagram.group, datagram.src)).or_default().counters.record(datagram);
+    }
+
+    /// Sets the loss counts of a sender's stream, as tracked by `--detect-loss`.
+    pub fn loss(&mut self, group: net::SocketAddr, src: net::SocketAddr, counts: LossCounts) {
+        self.sources.entry((group, src)).or_default().loss = Some(counts);
+    }
+
+    /// Sets the datagrams the kernel dropped on a group's socket.
+    pub fn kernel_drops(&mut self, group: net::SocketAddr, drops: u64) {
+        let group = self.groups.entry(group).or_default();
+        group.kernel_drops = group.kernel_drops.max(drops);
+    }
+
+    /// Everything measured so far, per group and per sender.
+    pub fn series(&self) -> Vec<Series> {
+        let mut series = Vec::new();
+        for (group, metrics) in &self.groups {
+            let mut fields = counter_fields(&metrics.counters, " per group");
+            fields.push(Field::counter("kernel_drops",
+                                       "Datagrams the kernel dropped per group because the receive buffer was full.",
+                                       metrics.kernel_drops));
+            series.push(Series::new("group", group_tags(group), fields));
+        }
+        for (&(group, src), metrics) in &self.sources {
+            let mut tags = group_tags(&group);
+            tags.push(("source", src.ip().to_string()));
+            tags.push(("source_port", src.port().to_string()));
+            let mut fields = counter_fields(&metrics.counters, " per sender");
+            if let Some(loss) = metrics.loss {
+                fields.push(Field::counter("missing", "Sequence numbers never received.", loss.missing));
+                fields.push(Field::counter("duplicated", "Datagrams received twice.", loss.duplicated));
+                fields.push(Field::counter("reordered", "Datagrams received out of order.", loss.reordered));
+            }
+            series.push(Series::new("source", tags, fields));
+        }
+        series
+    }
+
+    /// The metrics in Prometheus' text exposition format.
+    pub fn render(&self) -> String {
+        prometheus(&self.series())
+    }
+}
+
+fn counter_fields(counters: &Counters, per: &'static str) -> Vec<Field> {
+    let mut fields = vec![Field::counter("packets", "Datagrams received.", counters.packets),
+                          Field::counter("bytes", "Bytes received.", counters.bytes),
+                          Field::gauge("jitter_seconds",
+                                       "Smoothed variation of the inter-arrival gaps.",
+                                       counters.jitter)];
+    if let Some(last) = counters.last {
+        fields.push(Field::gauge("last_packet_timestamp_seconds", "When the last datagram arrived.", unix(last)));
+    }
+    for field in &mut fields {
+        field.per = per;
+    }
+    fields
+}
+
+/// The tags of a group, for metrics about it.
+pub fn group_tags(group: &net::SocketAddr) -> Vec<(&'static str, String)> {
+    vec![("group", group.ip().to_string()), ("port", group.port().to_string())]
+}
+
+fn unix(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Only ever goes up, until mccat restarts.
+    Counter,
+    Gauge,
+}
+
+/// One measured value.
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: &'static str,
+    pub help: &'static str,
+    /// Inserted before the end of `help`, e.g. " per sender".
+    per: &'static str,
+    pub kind: Kind,
+    pub value: f64,
+}
+
+impl Field {
+    pub fn counter(name: &'static str, help: &'static str, value: u64) -> Field {
+        Field { name, help, per: "", kind: Kind::Counter, value: value as f64 }
+    }
+
+    pub fn gauge(name: &'static str, help: &'static str, value: f64) -> Field {
+        Field { name, help, per: "", kind: Kind::Gauge, value }
+    }
+}
+
+/// The values measured for one thing, like a group or a sender: `name`
+/// says what kind of thing and `tags` which one.
+#[derive(Debug, Clone)]
+pub struct Series {
+    pub name: &'static str,
+    pub tags: Vec<(&'static str, String)>,
+    pub fields: Vec<Field>,
+}
+
+impl Series {
+    pub fn new(name: &'static str, tags: Vec<(&'static str, String)>, fields: Vec<Field>) -> Series {
+        Series { name, tags, fields }
+    }
+
+    /// The Prometheus name of a field; group fields go unqualified.
+    fn metric_name(&self, field: &Field) -> String {
+        let suffix = if field.kind == Kind::Counter { "_total" } else { "" };
+        match self.name {
+            "group" => format!("mccat_{}{}", field.name, suffix),
+            name => format!("mccat_{}_{}{}", name, field.name, suffix),
+        }
+    }
+}
+
+/// Renders `series` in Prometheus' text exposition format, each metric
+/// once with all its samples.
+pub fn prometheus(series: &[Series]) -> String {
+    let mut names: Vec<String> = Vec::new();
+    let mut families: BTreeMap<String, (&Field, Vec<String>)> = BTreeMap::new();
+    for series in series {
+        let labels: Vec<_> = series.tags.iter().map(|(tag, value)| format!("{}={:?}", tag, value)).collect();
+        for field in &series.fields {
+            let name = series.metric_name(field);
+            if !families.contains_key(&name) {
+                names.push(name.clone());
+            }
+            families.entry(name.clone())
+                .or_insert_with(|| (field, Vec::new()))
+                .1
+                .push(format!("{}{{{}}} {}", name, labels.join(","), field.value));
+        }
+    }
+    let mut out = String::new();
+    for name in names {
+        let (field, ref samples) = families[&name];
+        out.push_str(&format!("# HELP {} {}{}.\n# TYPE {} {}\n",
+                              name,
+                              field.help.trim_end_matches('.'),
+                              field.per,
+                              name,
+                              if field.kind == Kind::Counter { "counter" } else { "gauge" }));
+        for sample in samples {
+            out.push_str(sample);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Serves `/metrics` on `addr` from a background thread, one scrape at a
+/// time, returning the address bound.
+pub fn serve(addr: net::SocketAddr, metrics: Arc<Mutex<Metrics>>) -> io::Result<net::SocketAddr> {
+    let server = net::TcpListener::bind(addr)?;
+    let local = server.local_addr()?;
+    thread::spawn(move || {
+        for client in server.incoming().flatten() {
+            // a misbehaving scraper only loses its own request
+            let _ = scrape(client, &metrics);
+        }
+    });
+    Ok(local)
+}
+
+fn scrape(mut client: net::TcpStream, metrics: &Mutex<Metrics>) -> io::Result<()> {
+    client.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let (method, path) = relay::read_request(&mut io::BufReader::new(&client))?;
+    if method != "GET" {
+        return relay::respond(&mut client, "405 Method Not Allowed", "only GET is supported");
+    }
+    if path.split('?').next() != Some("/metrics") {
+        return relay::respond(&mut client, "404 Not Found", "metrics are at /metrics");
+    }
+    let body = metrics.lock().expect("metrics lock").render();
+    write!(client,
+           "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           CONTENT_TYPE,
+           body.len(),
+           body)
+}
@@ -0,0 +1,206 @@
+use std::fmt;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use rtp::RtpHeader;
+
+pub const PACKET_LEN: usize = 188;
+pub const SYNC_BYTE: u8 = 0x47;
+/// Stuffing packets, which carry no continuity counter.
+pub const NULL_PID: u16 = 0x1fff;
+
+/// The transport stream in a datagram, which is either plain TS or TS in
+/// RTP (RFC 2250).
+pub fn payload(data: &[u8]) -> &[u8] {
+    if data.first() == Some(&SYNC_BYTE) {
+        return data;
+    }
+    match RtpHeader::parse(data) {
+        Some(header) if data.get(header.payload_start) == Some(&SYNC_BYTE) => {
+            &data[header.payload_start..header.payload_end]
+        }
+        _ => data,
+    }
+}
+
+/// Whether any TS packet in `data`, as returned by `payload`, is flagged as
+/// a random access point, where a decoder can start: a keyframe.
+pub fn random_access(data: &[u8]) -> bool {
+    data.chunks(PACKET_LEN).any(|packet| {
+        let adaptation = packet.len() > 5 && packet[0] == SYNC_BYTE && packet[3] & 0x20 != 0 && packet[4] > 0;
+        adaptation && packet[5] & 0x40 != 0
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TsEvent {
+    /// No sync byte at this offset into the datagram.
+    SyncLoss { offset: usize },
+    /// The continuity counter skipped, meaning lost or corrupted packets.
+    Discontinuity { pid: u16, expected: u8, got: u8 },
+    /// The transport error indicator was set upstream.
+    TransportError { pid: u16 },
+}
+
+impl fmt::Display for TsEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TsEvent::SyncLoss { offset } => write!(f, "sync byte missing at offset {}", offset),
+            TsEvent::Discontinuity { pid, expected, got } => {
+                write!(f, "PID {:#06x}: continuity counter {} where {} was expected", pid, got, expected)
+            }
+            TsEvent::TransportError { pid } => write!(f, "PID {:#06x}: transport error indicator set", pid),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counters {
+    packets: u64,
+    cc_errors: u64,
+    transport_errors: u64,
+}
+
+#[derive(Debug, Default)]
+struct Pid {
+    cc: Option<u8>,
+    total: Counters,
+    interval: Counters,
+}
+
+/// Counts for one PID over some period.
+#[derive(Debug, Clone, Copy)]
+pub struct PidReport {
+    pub pid: u16,
+    pub packets: u64,
+    pub cc_errors: u64,
+    pub transport_errors: u64,
+    pub period: Duration,
+}
+
+impl PidReport {
+    pub fn bits_per_second(&self) -> f64 {
+        let secs = self.period.as_secs_f64();
+        if secs > 0.0 { (self.packets * PACKET_LEN as u64 * 8) as f64 / secs } else { 0.0 }
+    }
+}
+
+/// Column headings matching the `Display` of `PidReport`.
+pub const PID_HEADER: &str = "   PID    packets     Mbit/s  CC errors  TEI";
+
+impl fmt::Display for PidReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{:#06x} {:>10} {:>10.3} {:>10} {:>4}",
+               self.pid,
+               self.packets,
+               self.bits_per_second() / 1e6,
+               self.cc_errors,
+               self.transport_errors)
+    }
+}
+
+/// Checks sync bytes, continuity counters and transport errors of the
+/// transport streams in received datagrams, and counts packets per PID.
+#[derive(Debug)]
+pub struct TsMonitor {
+    pids: BTreeMap<u16, Pid>,
+    sync_losses: u64,
+    first: Option<Instant>,
+    interval_start: Instant,
+}
+
+impl Default for TsMonitor {
+    fn default() -> TsMonitor {
+        TsMonitor::new()
+    }
+}
+
+impl TsMonitor {
+    pub fn new() -> TsMonitor {
+        TsMonitor {
+            pids: BTreeMap::new(),
+            sync_losses: 0,
+            first: None,
+            interval_start: Instant::now(),
+        }
+    }
+
+    /// Records the TS packets in `data`, as returned by `payload`.
+    pub fn record(&mut self, data: &[u8]) -> Vec<TsEvent> {
+        self.first.get_or_insert_with(Instant::now);
+        let mut events = Vec::new();
+        for (i, packet) in data.chunks(PACKET_LEN).enumerate() {
+            if packet.len() < 4 || packet[0] != SYNC_BYTE {
+                self.sync_losses += 1;
+                events.push(TsEvent::SyncLoss { offset: i * PACKET_LEN });
+                continue;
+            }
+            let pid = u16::from(packet[1] & 0x1f) << 8 | u16::from(packet[2]);
+            let stats = self.pids.entry(pid).or_default();
+            stats.total.packets += 1;
+            stats.interval.packets += 1;
+            if packet[1] & 0x80 != 0 {
+                stats.total.transport_errors += 1;
+                stats.interval.transport_errors += 1;
+                events.push(TsEvent::TransportError { pid });
+                // the counter of a corrupt packet can't be trusted
+                stats.cc = None;
+                continue;
+            }
+            if pid == NULL_PID {
+                continue;
+            }
+            let cc = packet[3] & 0x0f;
+            let has_payload = packet[3] & 0x10 != 0;
+            let adaptation = packet[3] & 0x20 != 0;
+            let discontinuity = adaptation && packet.len() > 5 && packet[4] > 0 && packet[5] & 0x80 != 0;
+            if let (Some(last), false) = (stats.cc, discontinuity) {
+                let expected = if has_payload { (last + 1) & 0x0f } else { last };
+                // a single repeat of the last packet is allowed
+                if cc != expected && !(has_payload && cc == last) {
+                    stats.total.cc_errors += 1;
+                    stats.interval.cc_errors += 1;
+                    events.push(TsEvent::Discontinuity { pid, expected, got: cc });
+                }
+            }
+            stats.cc = Some(cc);
+        }
+        events
+    }
+
+    /// Packets that did not start with a sync byte.
+    pub fn sync_losses(&self) -> u64 {
+        self.sync_losses
+    }
+
+    /// Reports each PID since the previous call and starts a new interval.
+    pub fn interval(&mut self) -> Vec<PidReport> {
+        let period = self.interval_start.elapsed();
+        self.interval_start = Instant::now();
+        self.pids
+            .iter_mut()
+            .map(|(&pid, stats)| {
+                let counters = stats.interval;
+                stats.interval = Counters::default();
+                report(pid, counters, period)
+            })
+            .collect()
+    }
+
+    /// Reports each PID since the first datagram.
+    pub fn summary(&self) -> Vec<PidReport> {
+        let period = self.first.map(|first| first.elapsed()).unwrap_or_default();
+        self.pids.iter().map(|(&pid, stats)| report(pid, stats.total, period)).collect()
+    }
+}
+
+fn report(pid: u16, counters: Counters, period: Duration) -> PidReport {
+    PidReport {
+        pid,
+        packets: counters.packets,
+        cc_errors: counters.cc_errors,
+        transport_errors: counters.transport_errors,
+        period,
+    }
+}
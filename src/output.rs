@@ -0,0 +1,844 @@
+use std::{fmt, io, net, str};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use dscp::{self, Dscp};
+use iface;
+use listen::Datagram;
+use rotate::{RotatingFile, Rotation, SharedFile};
+use timestamp::{self, TimestampFormat, Timestamper};
+
+/// Destination for received datagrams.
+pub trait Output {
+    fn write(&mut self, datagram: &Datagram) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+/// Human readable "<src> said: <payload>" lines.
+pub struct TextOutput<W> {
+    out: W,
+    timestamps: Option<Timestamper>,
+    show_group: bool,
+    verbose: bool,
+}
+
+impl<W: Write> TextOutput<W> {
+    pub fn new(out: W) -> TextOutput<W> {
+        TextOutput {
+            out,
+            timestamps: None,
+            show_group: false,
+            verbose: false,
+        }
+    }
+
+    /// Tags each line with the group the datagram arrived on.
+    pub fn show_group(mut self, show: bool) -> TextOutput<W> {
+        self.show_group = show;
+        self
+    }
+
+    /// Prefixes each line with the receive time.
+    pub fn timestamps(mut self, format: TimestampFormat) -> TextOutput<W> {
+        self.timestamps = Some(Timestamper::new(format));
+        self
+    }
+
+    /// Adds the received TTL and DSCP to each line.
+    pub fn verbose(mut self, verbose: bool) -> TextOutput<W> {
+        self.verbose = verbose;
+        self
+    }
+}
+
+/// The address a datagram was sent to, falling back to the joined group,
+/// and the interface it arrived on.
+fn destination(datagram: &Datagram) -> net::SocketAddr {
+    (datagram.dst.unwrap_or_else(|| datagram.group.ip()), datagram.group.port()).into()
+}
+
+/// Whether the datagram was sent to a different group than the one joined,
+/// which happens when another socket joined it on the same port.
+fn stray(datagram: &Datagram) -> bool {
+    datagram.dst.is_some_and(|dst| dst != datagram.group.ip())
+}
+
+fn interface_name(datagram: &Datagram) -> Option<String> {
+    datagram.ifindex.and_then(|index| iface::index_to_name(index).ok())
+}
+
+/// Displays as "<dst>:<port> via <interface>".
+struct Destination<'a>(&'a Datagram);
+
+impl<'a> fmt::Display for Destination<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", destination(self.0))?;
+        match interface_name(self.0) {
+            Some(name) => write!(f, " via {}", name),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Displays as "ttl <n>, dscp <name>", leaving out what wasn't reported.
+struct IpHeader<'a>(&'a Datagram);
+
+impl<'a> fmt::Display for IpHeader<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut fields = Vec::new();
+        if let Some(ttl) = self.0.ttl {
+            fields.push(format!("ttl {}", ttl));
+        }
+        if let Some(tos) = self.0.tos {
+            fields.push(format!("dscp {}", Dscp(dscp::from_tos(tos))));
+        }
+        write!(f, "{}", fields.join(", "))
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn write_timestamp<W: Write>(out: &mut W,
+                             timestamps: &mut Option<Timestamper>,
+                             datagram: &Datagram)
+                             -> io::Result<()> {
+    match *timestamps {
+        Some(ref mut timestamps) => write!(out, "{} ", timestamps.stamp(datagram.received)),
+        None => Ok(()),
+    }
+}
+
+impl<W: Write> Output for TextOutput<W> {
+    fn write(&mut self, datagram: &Datagram) -> io::Result<()> {
+        write_timestamp(&mut self.out, &mut self.timestamps, datagram)?;
+        write!(self.out, "{}", datagram.src)?;
+        if let Some(ref tag) = datagram.tag {
+            write!(self.out, " ({})", tag)?;
+        }
+        write!(self.out, " said")?;
+        if self.show_group || stray(datagram) {
+            write!(self.out, " on {}", Destination(datagram))?;
+        }
+        if self.verbose {
+            write!(self.out, " [{}]", IpHeader(datagram))?;
+        }
+        writeln!(self.out, ": {}", String::from_utf8_lossy(&datagram.data))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Payloads written verbatim, optionally preceded by their length as a
+/// 32-bit big-endian integer so datagram boundaries survive.
+pub struct BinaryOutput<W> {
+    out: W,
+    length_prefix: bool,
+}
+
+impl<W: Write> BinaryOutput<W> {
+    pub fn new(out: W, length_prefix: bool) -> BinaryOutput<W> {
+        BinaryOutput { out, length_prefix }
+    }
+}
+
+impl<W: Write> Output for BinaryOutput<W> {
+    fn write(&mut self, datagram: &Datagram) -> io::Result<()> {
+        if self.length_prefix {
+            self.out.write_all(&(datagram.data.len() as u32).to_be_bytes())?;
+        }
+        self.out.write_all(&datagram.data)?;
+        self.out.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Another output onto a rotating file, which it rotates between
+/// datagrams so that no record is split across two files.
+pub struct RotatedOutput {
+    file: SharedFile,
+    output: Box<dyn Output>,
+}
+
+impl RotatedOutput {
+    /// `output` must write to `file`.
+    pub fn new(file: SharedFile, output: Box<dyn Output>) -> RotatedOutput {
+        RotatedOutput { file, output }
+    }
+}
+
+impl Output for RotatedOutput {
+    fn write(&mut self, datagram: &Datagram) -> io::Result<()> {
+        self.file.rotate_if_due()?;
+        self.output.write(datagram)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+pub const DEFAULT_MAX_TAG_FILES: usize = 64;
+
+/// Payloads appended to one file per group and port under a directory,
+/// e.g. `239.1.1.1_1234.bin`, as raw bytes like `BinaryOutput`. A file is
+/// opened when its group's first datagram arrives. Tagged datagrams go to
+/// a file per group and tag instead, e.g. `239.1.1.1_1234_camera1.bin`,
+/// up to a limit on tag files, past which new tags go to the group's file.
+pub struct GroupFiles {
+    dir: PathBuf,
+    rotation: Rotation,
+    length_prefix: bool,
+    files: HashMap<(net::SocketAddr, Option<String>), RotatingFile>,
+    tag_files: usize,
+    max_tag_files: usize,
+}
+
+impl GroupFiles {
+    pub fn new<P: AsRef<Path>>(dir: P, rotation: Rotation, length_prefix: bool) -> GroupFiles {
+        GroupFiles {
+            dir: dir.as_ref().to_path_buf(),
+            rotation,
+            length_prefix,
+            files: HashMap::new(),
+            tag_files: 0,
+            max_tag_files: DEFAULT_MAX_TAG_FILES,
+        }
+    }
+
+    /// How many files of their own tags get in all. Tags come from the
+    /// network, so each one can't be trusted with a file descriptor.
+    pub fn max_tag_files(mut self, max: usize) -> GroupFiles {
+        self.max_tag_files = max;
+        self
+    }
+}
+
+/// The file a group's payloads go to, before any rotation numbering.
+fn group_path(dir: &Path, group: net::SocketAddr, tag: Option<&str>) -> PathBuf {
+    match tag {
+        Some(tag) => dir.join(format!("{}_{}_{}.bin", group.ip(), group.port(), tag)),
+        None => dir.join(format!("{}_{}.bin", group.ip(), group.port())),
+    }
+}
+
+impl Output for GroupFiles {
+    fn write(&mut self, datagram: &Datagram) -> io::Result<()> {
+        let mut key = (datagram.group, datagram.tag.clone());
+        if key.1.is_some() && self.tag_files >= self.max_tag_files && !self.files.contains_key(&key) {
+            key.1 = None;
+        }
+        let file = match self.files.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let path = group_path(&self.dir, datagram.group, entry.key().1.as_deref());
+                let file = RotatingFile::append(path, self.rotation)?;
+                if entry.key().1.is_some() {
+                    self.tag_files += 1;
+                }
+                entry.insert(file)
+            }
+        };
+        file.rotate_if_due()?;
+        if self.length_prefix {
+            file.write_all(&(datagram.data.len() as u32).to_be_bytes())?;
+        }
+        file.write_all(&datagram.data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for file in self.files.values_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes `data` in the canonical `hexdump -C` layout.
+pub fn hexdump<W: Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+    for (line, chunk) in data.chunks(16).enumerate() {
+        write!(out, "{:08x}  ", line * 16)?;
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => write!(out, "{:02x} ", byte)?,
+                None => write!(out, "   ")?,
+            }
+            if i == 7 {
+                write!(out, " ")?;
+            }
+        }
+        let ascii: String = chunk.iter()
+            .map(|&b| if b == b' ' || b.is_ascii_graphic() { b as char } else { '.' })
+            .collect();
+        writeln!(out, " |{}|", ascii)?;
+    }
+    writeln!(out, "{:08x}", data.len())
+}
+
+/// A header line with source and length followed by a hexdump of the payload.
+pub struct HexOutput<W> {
+    out: W,
+    timestamps: Option<Timestamper>,
+    show_group: bool,
+    verbose: bool,
+}
+
+impl<W: Write> HexOutput<W> {
+    pub fn new(out: W) -> HexOutput<W> {
+        HexOutput {
+            out,
+            timestamps: None,
+            show_group: false,
+            verbose: false,
+        }
+    }
+
+    /// Tags each header line with the group the datagram arrived on.
+    pub fn show_group(mut self, show: bool) -> HexOutput<W> {
+        self.show_group = show;
+        self
+    }
+
+    /// Prefixes each header line with the receive time.
+    pub fn timestamps(mut self, format: TimestampFormat) -> HexOutput<W> {
+        self.timestamps = Some(Timestamper::new(format));
+        self
+    }
+
+    /// Adds the received TTL and DSCP to each header line.
+    pub fn verbose(mut self, verbose: bool) -> HexOutput<W> {
+        self.verbose = verbose;
+        self
+    }
+}
+
+impl<W: Write> Output for HexOutput<W> {
+    fn write(&mut self, datagram: &Datagram) -> io::Result<()> {
+        write_timestamp(&mut self.out, &mut self.timestamps, datagram)?;
+        write!(self.out, "{} sent {} bytes", datagram.src, datagram.data.len())?;
+        if self.show_group || stray(datagram) {
+            write!(self.out, " to {}", Destination(datagram))?;
+        }
+        if self.verbose {
+            write!(self.out, " [{}]", IpHeader(datagram))?;
+        }
+        writeln!(self.out, ":")?;
+        hexdump(&mut self.out, &datagram.data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// The output formats `listen` can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Binary,
+    Hex,
+    Json,
+    Csv,
+}
+
+impl str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "binary" => Ok(OutputFormat::Binary),
+            "hex" => Ok(OutputFormat::Hex),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("unknown output format {}: expected text, binary, hex, json or csv", s)),
+        }
+    }
+}
+
+/// Protocols `listen` can decode payloads as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decode {
+    Rtp,
+    Mpegts,
+}
+
+impl str::FromStr for Decode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Decode, String> {
+        match s {
+            "rtp" => Ok(Decode::Rtp),
+            "mpegts" => Ok(Decode::Mpegts),
+            _ => Err(format!("unknown protocol {}: expected rtp or mpegts", s)),
+        }
+    }
+}
+
+/// How payloads are represented in structured output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadEncoding {
+    /// UTF-8, with invalid sequences replaced.
+    Utf8,
+    Base64,
+}
+
+impl str::FromStr for PayloadEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PayloadEncoding, String> {
+        match s {
+            "utf8" => Ok(PayloadEncoding::Utf8),
+            "base64" => Ok(PayloadEncoding::Base64),
+            _ => Err(format!("unknown payload encoding {}: expected utf8 or base64", s)),
+        }
+    }
+}
+
+const BASE64_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 with padding.
+pub fn base64(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = u32::from(chunk[0]) << 16 |
+                   u32::from(*chunk.get(1).unwrap_or(&0)) << 8 |
+                   u32::from(*chunk.get(2).unwrap_or(&0));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Quotes `s` as a JSON string.
+pub fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// One JSON object per line (JSON Lines).
+pub struct JsonOutput<W> {
+    out: W,
+    encoding: PayloadEncoding,
+}
+
+impl<W: Write> JsonOutput<W> {
+    pub fn new(out: W) -> JsonOutput<W> {
+        JsonOutput {
+            out,
+            encoding: PayloadEncoding::Utf8,
+        }
+    }
+
+    pub fn payload_encoding(mut self, encoding: PayloadEncoding) -> JsonOutput<W> {
+        self.encoding = encoding;
+        self
+    }
+}
+
+impl<W: Write> Output for JsonOutput<W> {
+    fn write(&mut self, datagram: &Datagram) -> io::Result<()> {
+        let (key, payload) = match self.encoding {
+            PayloadEncoding::Utf8 => ("payload", json_string(&String::from_utf8_lossy(&datagram.data))),
+            PayloadEncoding::Base64 => ("payload_base64", json_string(&base64(&datagram.data))),
+        };
+        // only tagged datagrams have the field, leaving the rest as they were
+        let tag = datagram.tag.as_ref().map_or_else(String::new, |tag| format!(",\"tag\":{}", json_string(tag)));
+        writeln!(self.out,
+                 "{{\"time\":{},\"src\":{},\"src_port\":{},\"group\":{},\"port\":{},\"dst\":{},\
+                  \"interface\":{},\"ttl\":{},\"dscp\":{},\"length\":{},\"truncated\":{}{},\"{}\":{}}}",
+                 json_string(&timestamp::iso8601(datagram.received)),
+                 json_string(&datagram.src.ip().to_string()),
+                 datagram.src.port(),
+                 json_string(&datagram.group.ip().to_string()),
+                 datagram.group.port(),
+                 json_string(&destination(datagram).ip().to_string()),
+                 interface_name(datagram).map_or_else(|| "null".to_owned(), |name| json_string(&name)),
+                 datagram.ttl.map_or_else(|| "null".to_owned(), |ttl| ttl.to_string()),
+                 datagram.tos.map_or_else(|| "null".to_owned(), |tos| dscp::from_tos(tos).to_string()),
+                 datagram.size,
+                 datagram.truncated(),
+                 tag,
+                 key,
+                 payload)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// A column of CSV output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsvColumn {
+    Time,
+    Src,
+    SrcPort,
+    Group,
+    Port,
+    Dst,
+    Interface,
+    Ttl,
+    Dscp,
+    Len,
+    Payload,
+}
+
+pub const CSV_COLUMNS: &[CsvColumn] = &[
+    CsvColumn::Time,
+    CsvColumn::Src,
+    CsvColumn::SrcPort,
+    CsvColumn::Group,
+    CsvColumn::Port,
+    CsvColumn::Dst,
+    CsvColumn::Interface,
+    CsvColumn::Ttl,
+    CsvColumn::Dscp,
+    CsvColumn::Len,
+    CsvColumn::Payload,
+];
+
+impl CsvColumn {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CsvColumn::Time => "time",
+            CsvColumn::Src => "src",
+            CsvColumn::SrcPort => "src_port",
+            CsvColumn::Group => "group",
+            CsvColumn::Port => "port",
+            CsvColumn::Dst => "dst",
+            CsvColumn::Interface => "interface",
+            CsvColumn::Ttl => "ttl",
+            CsvColumn::Dscp => "dscp",
+            CsvColumn::Len => "len",
+            CsvColumn::Payload => "payload",
+        }
+    }
+}
+
+impl str::FromStr for CsvColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CsvColumn, String> {
+        CSV_COLUMNS.iter().cloned().find(|column| column.name() == s).ok_or_else(|| {
+            let names: Vec<_> = CSV_COLUMNS.iter().map(|column| column.name()).collect();
+            format!("unknown column {}: expected one of {}", s, names.join(", "))
+        })
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Comma separated values with a header row.
+pub struct CsvOutput<W> {
+    out: W,
+    columns: Vec<CsvColumn>,
+    timestamps: Option<Timestamper>,
+    encoding: PayloadEncoding,
+    header_written: bool,
+}
+
+impl<W: Write> CsvOutput<W> {
+    pub fn new(out: W, columns: Vec<CsvColumn>) -> CsvOutput<W> {
+        CsvOutput {
+            out,
+            columns,
+            timestamps: None,
+            encoding: PayloadEncoding::Utf8,
+            header_written: false,
+        }
+    }
+
+    /// Format of the time column; ISO 8601 by default.
+    pub fn timestamps(mut self, format: TimestampFormat) -> CsvOutput<W> {
+        self.timestamps = Some(Timestamper::new(format));
+        self
+    }
+
+    pub fn payload_encoding(mut self, encoding: PayloadEncoding) -> CsvOutput<W> {
+        self.encoding = encoding;
+        self
+    }
+
+    fn field(&mut self, column: CsvColumn, datagram: &Datagram) -> String {
+        match column {
+            CsvColumn::Time => match self.timestamps {
+                Some(ref mut timestamps) => timestamps.stamp(datagram.received),
+                None => timestamp::iso8601(datagram.received),
+            },
+            CsvColumn::Src => datagram.src.ip().to_string(),
+            CsvColumn::SrcPort => datagram.src.port().to_string(),
+            CsvColumn::Group => datagram.group.ip().to_string(),
+            CsvColumn::Port => datagram.group.port().to_string(),
+            CsvColumn::Dst => destination(datagram).ip().to_string(),
+            CsvColumn::Interface => interface_name(datagram).unwrap_or_default(),
+            CsvColumn::Ttl => optional(datagram.ttl),
+            CsvColumn::Dscp => optional(datagram.tos.map(dscp::from_tos)),
+            CsvColumn::Len => datagram.size.to_string(),
+            CsvColumn::Payload => match self.encoding {
+                PayloadEncoding::Utf8 => csv_field(&String::from_utf8_lossy(&datagram.data)),
+                PayloadEncoding::Base64 => base64(&datagram.data),
+            },
+        }
+    }
+}
+
+impl<W: Write> Output for CsvOutput<W> {
+    fn write(&mut self, datagram: &Datagram) -> io::Result<()> {
+        if !self.header_written {
+            let names: Vec<_> = self.columns.iter().map(|column| column.name()).collect();
+            writeln!(self.out, "{}", names.join(","))?;
+            self.header_written = true;
+        }
+        let columns = self.columns.clone();
+        let fields: Vec<_> = columns.into_iter().map(|column| self.field(column, datagram)).collect();
+        writeln!(self.out, "{}", fields.join(","))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// A value that can be substituted into an output template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placeholder {
+    Time,
+    Src,
+    SrcIp,
+    SrcPort,
+    Group,
+    GroupIp,
+    Port,
+    Dst,
+    Interface,
+    Ttl,
+    Dscp,
+    Len,
+    Payload,
+    PayloadHex,
+    PayloadBase64,
+}
+
+const PLACEHOLDERS: &[(&str, Placeholder)] = &[
+    ("time", Placeholder::Time),
+    ("src", Placeholder::Src),
+    ("src_ip", Placeholder::SrcIp),
+    ("src_port", Placeholder::SrcPort),
+    ("group", Placeholder::Group),
+    ("group_ip", Placeholder::GroupIp),
+    ("port", Placeholder::Port),
+    ("dst", Placeholder::Dst),
+    ("interface", Placeholder::Interface),
+    ("ttl", Placeholder::Ttl),
+    ("dscp", Placeholder::Dscp),
+    ("len", Placeholder::Len),
+    ("payload", Placeholder::Payload),
+    ("payload_hex", Placeholder::PayloadHex),
+    ("payload_base64", Placeholder::PayloadBase64),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Literal(String),
+    Value(Placeholder),
+}
+
+/// An output line template such as `{time} {src_ip} {len}`. `{{` and `}}`
+/// stand for literal braces, and `\n`, `\t` and `\\` are unescaped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+impl str::FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Template, String> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed {{ in template {}", s)),
+                        }
+                    }
+                    let placeholder = PLACEHOLDERS.iter()
+                        .find(|&&(known, _)| known == name)
+                        .map(|&(_, placeholder)| placeholder)
+                        .ok_or_else(|| {
+                            let names: Vec<_> = PLACEHOLDERS.iter().map(|&(name, _)| name).collect();
+                            format!("unknown placeholder {{{}}}: expected one of {}", name, names.join(", "))
+                        })?;
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(literal.split_off(0)));
+                    }
+                    pieces.push(Piece::Value(placeholder));
+                }
+                '}' => return Err(format!("unmatched }} in template {}", s)),
+                '\\' => match chars.next() {
+                    Some('n') => literal.push('\n'),
+                    Some('t') => literal.push('\t'),
+                    Some(other) => literal.push(other),
+                    None => literal.push('\\'),
+                },
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Ok(Template { pieces })
+    }
+}
+
+/// One line per datagram shaped by a `Template`.
+pub struct TemplateOutput<W> {
+    out: W,
+    template: Template,
+    timestamps: Option<Timestamper>,
+}
+
+impl<W: Write> TemplateOutput<W> {
+    pub fn new(out: W, template: Template) -> TemplateOutput<W> {
+        TemplateOutput {
+            out,
+            template,
+            timestamps: None,
+        }
+    }
+
+    /// Format of `{time}`; ISO 8601 by default.
+    pub fn timestamps(mut self, format: TimestampFormat) -> TemplateOutput<W> {
+        self.timestamps = Some(Timestamper::new(format));
+        self
+    }
+}
+
+impl<W: Write> Output for TemplateOutput<W> {
+    fn write(&mut self, datagram: &Datagram) -> io::Result<()> {
+        let mut line = String::new();
+        for piece in &self.template.pieces {
+            match *piece {
+                Piece::Literal(ref text) => line.push_str(text),
+                Piece::Value(placeholder) => line.push_str(&match placeholder {
+                    Placeholder::Time => match self.timestamps {
+                        Some(ref mut timestamps) => timestamps.stamp(datagram.received),
+                        None => timestamp::iso8601(datagram.received),
+                    },
+                    Placeholder::Src => datagram.src.to_string(),
+                    Placeholder::SrcIp => datagram.src.ip().to_string(),
+                    Placeholder::SrcPort => datagram.src.port().to_string(),
+                    Placeholder::Group => datagram.group.to_string(),
+                    Placeholder::GroupIp => datagram.group.ip().to_string(),
+                    Placeholder::Port => datagram.group.port().to_string(),
+                    Placeholder::Dst => destination(datagram).ip().to_string(),
+                    Placeholder::Interface => interface_name(datagram).unwrap_or_default(),
+                    Placeholder::Ttl => optional(datagram.ttl),
+                    Placeholder::Dscp => optional(datagram.tos.map(|tos| Dscp(dscp::from_tos(tos)))),
+                    Placeholder::Len => datagram.size.to_string(),
+                    Placeholder::Payload => String::from_utf8_lossy(&datagram.data).into_owned(),
+                    Placeholder::PayloadHex => datagram.data.iter().map(|b| format!("{:02x}", b)).collect(),
+                    Placeholder::PayloadBase64 => base64(&datagram.data),
+                }),
+            }
+        }
+        writeln!(self.out, "{}", line)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::time::SystemTime;
+
+    use super::*;
+
+    fn datagram(data: &[u8], tag: Option<&str>) -> Datagram {
+        Datagram {
+            data: data.to_vec(),
+            size: data.len(),
+            src: "192.0.2.1:4000".parse().unwrap(),
+            group: "239.1.2.3:5000".parse().unwrap(),
+            dst: None,
+            ifindex: None,
+            ttl: None,
+            tos: None,
+            drops: None,
+            received: SystemTime::now(),
+            tag: tag.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn tags_past_the_limit_go_to_the_group_file() {
+        let dir = env::temp_dir().join(format!("mccat-group-files-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut files = GroupFiles::new(&dir, Rotation::default(), false).max_tag_files(2);
+        for (data, tag) in &[("1", Some("a")), ("2", Some("b")), ("3", Some("c")), ("4", Some("a")), ("5", None)] {
+            files.write(&datagram(data.as_bytes(), *tag)).unwrap();
+        }
+        for tag in 0..100 {
+            files.write(&datagram(b".", Some(&format!("t{}", tag)))).unwrap();
+        }
+        files.flush().unwrap();
+        assert_eq!(files.files.len(), 3);
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("239.1.2.3_5000_a.bin"), "14");
+        assert_eq!(read("239.1.2.3_5000_b.bin"), "2");
+        assert_eq!(read("239.1.2.3_5000.bin"), format!("35{}", ".".repeat(100)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
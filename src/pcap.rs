@@ -0,0 +1,446 @@
+use std::{fs, io, net};
+use std::convert::TryInto;
+use std::io::prelude::*;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use listen::Datagram;
+use rotate::{Rotation, RotatingFile};
+
+/// Raw IPv4/IPv6 packets without a link layer header.
+const LINKTYPE_RAW: u16 = 101;
+
+const SHB_TYPE: u32 = 0x0A0D_0D0A;
+const IDB_TYPE: u32 = 1;
+const EPB_TYPE: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const IP_PROTO_UDP: u8 = 17;
+const SYNTHETIC_TTL: u8 = 64;
+
+/// The internet checksum over `chunks`, taken together.
+pub fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for chunk in chunks {
+        for pair in chunk.chunks(2) {
+            let word = u16::from(pair[0]) << 8 | u16::from(*pair.get(1).unwrap_or(&0));
+            sum += u32::from(word);
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn udp_header(src_port: u16, dst_port: u16, len: usize) -> [u8; 8] {
+    let mut udp = [0u8; 8];
+    udp[0..2].copy_from_slice(&src_port.to_be_bytes());
+    udp[2..4].copy_from_slice(&dst_port.to_be_bytes());
+    udp[4..6].copy_from_slice(&(len.min(0xffff) as u16).to_be_bytes());
+    udp
+}
+
+/// Wraps a received datagram in synthetic IP and UDP headers, as if it had
+/// been captured on the wire. Returns the packet and its original length.
+pub fn synthesize(datagram: &Datagram) -> (Vec<u8>, usize) {
+    let udp_len = 8 + datagram.size;
+    let mut udp = udp_header(datagram.src.port(), datagram.group.port(), udp_len);
+    match (datagram.src.ip(), datagram.group.ip()) {
+        (net::IpAddr::V4(src), net::IpAddr::V4(dst)) => {
+            let mut ip = [0u8; 20];
+            ip[0] = 0x45;
+            ip[2..4].copy_from_slice(&((20 + udp_len).min(0xffff) as u16).to_be_bytes());
+            ip[8] = SYNTHETIC_TTL;
+            ip[9] = IP_PROTO_UDP;
+            ip[12..16].copy_from_slice(&src.octets());
+            ip[16..20].copy_from_slice(&dst.octets());
+            let sum = checksum(&[&ip]);
+            ip[10..12].copy_from_slice(&sum.to_be_bytes());
+            // UDP checksums are optional over IPv4
+            let mut packet = Vec::with_capacity(28 + datagram.data.len());
+            packet.extend_from_slice(&ip);
+            packet.extend_from_slice(&udp);
+            packet.extend_from_slice(&datagram.data);
+            (packet, 20 + udp_len)
+        }
+        (src, dst) => {
+            let src = match src {
+                net::IpAddr::V4(src) => src.to_ipv6_mapped(),
+                net::IpAddr::V6(src) => src,
+            };
+            let dst = match dst {
+                net::IpAddr::V4(dst) => dst.to_ipv6_mapped(),
+                net::IpAddr::V6(dst) => dst,
+            };
+            let mut ip = [0u8; 40];
+            ip[0] = 0x60;
+            ip[4..6].copy_from_slice(&(udp_len.min(0xffff) as u16).to_be_bytes());
+            ip[6] = IP_PROTO_UDP;
+            ip[7] = SYNTHETIC_TTL;
+            ip[8..24].copy_from_slice(&src.octets());
+            ip[24..40].copy_from_slice(&dst.octets());
+            let mut pseudo = [0u8; 40];
+            pseudo[0..32].copy_from_slice(&ip[8..40]);
+            pseudo[32..36].copy_from_slice(&(udp_len as u32).to_be_bytes());
+            pseudo[39] = IP_PROTO_UDP;
+            let sum = match checksum(&[&pseudo, &udp, &datagram.data]) {
+                0 => 0xffff,
+                sum => sum,
+            };
+            udp[6..8].copy_from_slice(&sum.to_be_bytes());
+            let mut packet = Vec::with_capacity(48 + datagram.data.len());
+            packet.extend_from_slice(&ip);
+            packet.extend_from_slice(&udp);
+            packet.extend_from_slice(&datagram.data);
+            (packet, 40 + udp_len)
+        }
+    }
+}
+
+fn write_block<W: Write>(out: &mut W, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let padding = (4 - body.len() % 4) % 4;
+    let total_len = (12 + body.len() + padding) as u32;
+    out.write_all(&block_type.to_le_bytes())?;
+    out.write_all(&total_len.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&[0u8; 3][..padding])?;
+    out.write_all(&total_len.to_le_bytes())
+}
+
+/// Writes the section header and interface description that start a
+/// pcapng file.
+pub fn write_header<W: Write>(out: &mut W) -> io::Result<()> {
+    let mut shb = Vec::with_capacity(16);
+    shb.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+    shb.extend_from_slice(&1u16.to_le_bytes());
+    shb.extend_from_slice(&0u16.to_le_bytes());
+    shb.extend_from_slice(&(-1i64).to_le_bytes());
+    write_block(out, SHB_TYPE, &shb)?;
+
+    let mut idb = Vec::with_capacity(8);
+    idb.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    idb.extend_from_slice(&0u16.to_le_bytes());
+    idb.extend_from_slice(&0u32.to_le_bytes());
+    write_block(out, IDB_TYPE, &idb)
+}
+
+/// Writes one datagram as an enhanced packet block with a microsecond
+/// timestamp.
+pub fn write_packet<W: Write>(out: &mut W, datagram: &Datagram) -> io::Result<()> {
+    let (packet, original_len) = synthesize(datagram);
+    let since_epoch = datagram.received.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let micros = since_epoch.as_secs() * 1_000_000 + u64::from(since_epoch.subsec_micros());
+    let mut epb = Vec::with_capacity(20 + packet.len());
+    epb.extend_from_slice(&0u32.to_le_bytes());
+    epb.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+    epb.extend_from_slice(&(micros as u32).to_le_bytes());
+    epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    epb.extend_from_slice(&(original_len as u32).to_le_bytes());
+    epb.extend_from_slice(&packet);
+    write_block(out, EPB_TYPE, &epb)
+}
+
+/// A pcapng capture file of received datagrams, optionally rotated.
+pub struct PcapWriter {
+    file: RotatingFile,
+}
+
+impl PcapWriter {
+    pub fn create<P: AsRef<Path>>(path: P, rotation: Rotation) -> io::Result<PcapWriter> {
+        let mut file = RotatingFile::create(path, rotation)?;
+        write_header(&mut file)?;
+        Ok(PcapWriter { file })
+    }
+
+    pub fn write(&mut self, datagram: &Datagram) -> io::Result<()> {
+        if self.file.rotate_if_due()?.is_some() {
+            write_header(&mut self.file)?;
+        }
+        write_packet(&mut self.file, datagram)?;
+        self.file.flush()
+    }
+}
+
+/// A UDP datagram read back from a capture file.
+#[derive(Debug, Clone)]
+pub struct CapturedUdp {
+    /// Capture timestamp, relative to the Unix epoch.
+    pub time: Duration,
+    pub src: net::SocketAddr,
+    pub dst: net::SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+const LINKTYPE_NULL: u16 = 0;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW_OPENBSD: u16 = 12;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_IPV4: u16 = 228;
+const LINKTYPE_IPV6: u16 = 229;
+const LINKTYPE_LINUX_SLL2: u16 = 276;
+
+const SPB_TYPE: u32 = 3;
+const IF_TSRESOL: u16 = 9;
+
+fn invalid_data<S: Into<String>>(msg: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Finds the IP packet inside a link layer frame.
+fn ip_payload(linktype: u16, frame: &[u8]) -> Option<&[u8]> {
+    match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            loop {
+                let ethertype = u16::from_be_bytes([*frame.get(offset)?, *frame.get(offset + 1)?]);
+                match ethertype {
+                    // VLAN tags
+                    0x8100 | 0x88a8 | 0x9100 => offset += 4,
+                    0x0800 | 0x86dd => return frame.get(offset + 2..),
+                    _ => return None,
+                }
+            }
+        }
+        LINKTYPE_NULL => frame.get(4..),
+        LINKTYPE_RAW | LINKTYPE_RAW_OPENBSD | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(frame),
+        LINKTYPE_LINUX_SLL => frame.get(16..),
+        LINKTYPE_LINUX_SLL2 => frame.get(20..),
+        _ => None,
+    }
+}
+
+/// Extracts addresses and payload of an unfragmented UDP packet.
+fn parse_udp(packet: &[u8]) -> Option<(net::SocketAddr, net::SocketAddr, &[u8])> {
+    let (src, dst, udp) = match packet.first()? >> 4 {
+        4 => {
+            let ihl = usize::from(packet[0] & 0x0f) * 4;
+            let flags_offset = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]);
+            if *packet.get(9)? != IP_PROTO_UDP || flags_offset & 0x3fff != 0 {
+                return None;
+            }
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            (net::IpAddr::from(src), net::IpAddr::from(dst), packet.get(ihl..)?)
+        }
+        6 => {
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            let mut next = *packet.get(6)?;
+            let mut offset = 40;
+            // skip hop-by-hop, routing and destination option headers
+            while next == 0 || next == 43 || next == 60 {
+                next = *packet.get(offset)?;
+                offset += (usize::from(*packet.get(offset + 1)?) + 1) * 8;
+            }
+            if next != IP_PROTO_UDP {
+                return None;
+            }
+            (net::IpAddr::from(src), net::IpAddr::from(dst), packet.get(offset..)?)
+        }
+        _ => return None,
+    };
+    let src_port = u16::from_be_bytes([*udp.first()?, *udp.get(1)?]);
+    let dst_port = u16::from_be_bytes([*udp.get(2)?, *udp.get(3)?]);
+    let udp_len = usize::from(u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]));
+    let payload = udp.get(8..udp_len.max(8).min(udp.len()))?;
+    Some(((src, src_port).into(), (dst, dst_port).into(), payload))
+}
+
+enum Format {
+    Pcap { big_endian: bool, nanos: bool, linktype: u16 },
+    PcapNg { big_endian: bool, interfaces: Vec<(u16, u64)> },
+}
+
+/// Reads UDP datagrams from a pcap or pcapng capture, skipping everything
+/// else.
+pub struct PcapReader<R> {
+    input: R,
+    format: Format,
+    last_time: Duration,
+}
+
+fn read_u32(bytes: &[u8], big_endian: bool) -> u32 {
+    let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+}
+
+fn read_u16(bytes: &[u8], big_endian: bool) -> u16 {
+    let bytes = [bytes[0], bytes[1]];
+    if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+}
+
+/// Converts a timestamp in units of 1/`per_second` seconds.
+fn ticks_to_duration(ticks: u64, per_second: u64) -> Duration {
+    Duration::new(ticks / per_second, ((ticks % per_second) * 1_000_000_000 / per_second) as u32)
+}
+
+/// Reads `buf.len()` bytes, returning false on a clean end of file.
+fn read_exact_or_eof<R: Read>(input: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(invalid_data("truncated capture file")),
+            Ok(n) => filled += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(true)
+}
+
+impl PcapReader<io::BufReader<fs::File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<PcapReader<io::BufReader<fs::File>>> {
+        PcapReader::new(io::BufReader::new(fs::File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut input: R) -> io::Result<PcapReader<R>> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        let format = match u32::from_le_bytes(magic) {
+            SHB_TYPE => {
+                let mut rest = [0u8; 8];
+                input.read_exact(&mut rest)?;
+                let big_endian = match u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) {
+                    BYTE_ORDER_MAGIC => false,
+                    m if m.swap_bytes() == BYTE_ORDER_MAGIC => true,
+                    _ => return Err(invalid_data("bad pcapng byte order magic")),
+                };
+                let len = read_u32(&rest[0..4], big_endian) as usize;
+                if len < 16 {
+                    return Err(invalid_data("bad pcapng section header"));
+                }
+                let mut skip = vec![0u8; len - 12];
+                input.read_exact(&mut skip)?;
+                Format::PcapNg { big_endian, interfaces: Vec::new() }
+            }
+            _ => {
+                let (big_endian, nanos) = match magic {
+                    [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+                    [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+                    [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+                    [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+                    _ => return Err(invalid_data("not a pcap or pcapng file")),
+                };
+                let mut header = [0u8; 20];
+                input.read_exact(&mut header)?;
+                let linktype = read_u32(&header[16..20], big_endian) as u16;
+                Format::Pcap { big_endian, nanos, linktype }
+            }
+        };
+        Ok(PcapReader {
+            input,
+            format,
+            last_time: Duration::default(),
+        })
+    }
+
+    /// Reads the next frame as (link type, timestamp, frame bytes).
+    fn next_frame(&mut self) -> io::Result<Option<(u16, Duration, Vec<u8>)>> {
+        match self.format {
+            Format::Pcap { big_endian, nanos, linktype } => {
+                let mut header = [0u8; 16];
+                if !read_exact_or_eof(&mut self.input, &mut header)? {
+                    return Ok(None);
+                }
+                let secs = u64::from(read_u32(&header[0..4], big_endian));
+                let frac = u64::from(read_u32(&header[4..8], big_endian));
+                let caplen = read_u32(&header[8..12], big_endian) as usize;
+                let mut frame = vec![0u8; caplen];
+                self.input.read_exact(&mut frame)?;
+                let frac = if nanos { frac } else { frac * 1000 };
+                let time = Duration::new(secs, frac as u32);
+                Ok(Some((linktype, time, frame)))
+            }
+            Format::PcapNg { big_endian, ref mut interfaces } => {
+                loop {
+                    let mut header = [0u8; 8];
+                    if !read_exact_or_eof(&mut self.input, &mut header)? {
+                        return Ok(None);
+                    }
+                    let block_type = read_u32(&header[0..4], big_endian);
+                    let len = read_u32(&header[4..8], big_endian) as usize;
+                    if len < 12 {
+                        return Err(invalid_data("bad pcapng block length"));
+                    }
+                    let mut body = vec![0u8; len - 8];
+                    self.input.read_exact(&mut body)?;
+                    let body = &body[..len - 12];
+                    match block_type {
+                        SHB_TYPE => interfaces.clear(),
+                        IDB_TYPE if body.len() >= 8 => {
+                            let linktype = read_u16(&body[0..2], big_endian);
+                            let mut per_second = 1_000_000;
+                            let mut options = &body[8..];
+                            while options.len() >= 4 {
+                                let code = read_u16(&options[0..2], big_endian);
+                                let opt_len = usize::from(read_u16(&options[2..4], big_endian));
+                                if code == IF_TSRESOL && opt_len >= 1 && options.len() > 4 {
+                                    let resol = options[4];
+                                    per_second = if resol & 0x80 == 0 {
+                                        10u64.saturating_pow(u32::from(resol))
+                                    } else {
+                                        1u64 << (resol & 0x7f).min(63)
+                                    };
+                                }
+                                let padded = 4 + opt_len.div_ceil(4) * 4;
+                                options = options.get(padded..).unwrap_or(&[]);
+                            }
+                            interfaces.push((linktype, per_second));
+                        }
+                        EPB_TYPE if body.len() >= 20 => {
+                            let iface = read_u32(&body[0..4], big_endian) as usize;
+                            let ticks = u64::from(read_u32(&body[4..8], big_endian)) << 32 |
+                                        u64::from(read_u32(&body[8..12], big_endian));
+                            let caplen = read_u32(&body[12..16], big_endian) as usize;
+                            let &(linktype, per_second) = interfaces.get(iface)
+                                .ok_or_else(|| invalid_data("packet for unknown pcapng interface"))?;
+                            let frame = body.get(20..20 + caplen)
+                                .ok_or_else(|| invalid_data("bad pcapng packet length"))?;
+                            let time = ticks_to_duration(ticks, per_second.max(1));
+                            return Ok(Some((linktype, time, frame.to_vec())));
+                        }
+                        SPB_TYPE if body.len() >= 4 => {
+                            // simple packet blocks carry no timestamp
+                            let &(linktype, _) = interfaces.first()
+                                .ok_or_else(|| invalid_data("packet for unknown pcapng interface"))?;
+                            return Ok(Some((linktype, self.last_time, body[4..].to_vec())));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// The next UDP datagram in the capture, or `None` at the end.
+    pub fn next_udp(&mut self) -> io::Result<Option<CapturedUdp>> {
+        while let Some((linktype, time, frame)) = self.next_frame()? {
+            self.last_time = time;
+            let parsed = ip_payload(linktype, &frame).and_then(parse_udp);
+            if let Some((src, dst, payload)) = parsed {
+                return Ok(Some(CapturedUdp {
+                    time,
+                    src,
+                    dst,
+                    payload: payload.to_vec(),
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = io::Result<CapturedUdp>;
+
+    fn next(&mut self) -> Option<io::Result<CapturedUdp>> {
+        match self.next_udp() {
+            Ok(udp) => udp.map(Ok),
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
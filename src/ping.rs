@@ -0,0 +1,889 @@
+use std::{fmt, io, net, str};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::time::{Duration, Instant, SystemTime};
+
+use auth::{Key, TAG_LEN};
+use listen::Datagram;
+use socket::MulticastSocket;
+use event::{Interest, Poll};
+use histogram::Histogram;
+use metrics::{self, Field, Series};
+use sockopt;
+
+/// How many outstanding probes are remembered for matching replies.
+const MAX_OUTSTANDING: u64 = 65536;
+
+/// Largest UDP payload over IPv4.
+pub const MAX_PROBE_SIZE: usize = 65507;
+
+/// Minimum time to wait for replies after the last probe of a counted run.
+const LINGER: Duration = Duration::from_secs(1);
+
+pub const PING: &[u8] = b"PING";
+pub const PONG: &[u8] = b"PONG";
+
+/// Where listeners send their PONG replies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyPath {
+    /// Straight back to the prober.
+    Unicast,
+    /// To the group, exercising the multicast path in the other direction.
+    Multicast,
+}
+
+impl str::FromStr for ReplyPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ReplyPath, String> {
+        match s {
+            "unicast" => Ok(ReplyPath::Unicast),
+            "multicast" => Ok(ReplyPath::Multicast),
+            _ => Err(format!("unknown reply path {}: expected unicast or multicast", s)),
+        }
+    }
+}
+
+pub fn is_probe(data: &[u8]) -> bool {
+    data.starts_with(PING)
+}
+
+/// Builds the PONG reply for a PING probe, or `None` if `data` is not a probe.
+pub fn reply(data: &[u8]) -> Option<Vec<u8>> {
+    if data.starts_with(PING) {
+        let mut reply = PONG.to_vec();
+        reply.extend(&data[PING.len()..]);
+        Some(reply)
+    } else {
+        None
+    }
+}
+
+/// Parses the sequence number following `prefix` in a "PING <n>" or
+/// "PONG <n>" message.
+pub fn parse_seqnum(data: &[u8], prefix: &[u8]) -> Option<u64> {
+    if !data.starts_with(prefix) {
+        return None;
+    }
+    let rest = &data[prefix.len()..];
+    let start = rest.iter().position(|&b| b != b' ')?;
+    let digits = rest[start..].iter().take_while(|b| b.is_ascii_digit()).count();
+    str::from_utf8(&rest[start..start + digits]).ok()?.parse().ok()
+}
+
+/// A reply to one of our probes.
+#[derive(Debug, Clone)]
+pub struct Pong {
+    pub datagram: Datagram,
+    pub seqnum: Option<u64>,
+    /// Round trip time, if the reply matched a probe we sent.
+    pub rtt: Option<Duration>,
+    /// TTL of the matching probe during a TTL sweep.
+    pub probe_ttl: Option<u8>,
+    /// Size the matching probe was padded to.
+    pub probe_size: Option<usize>,
+    /// Whether the reply was sent to the group rather than straight to us.
+    pub multicast: bool,
+    /// Which socket the reply came back to: 0 for the first, and 1 and on
+    /// for those added with `also_via`, in order.
+    pub path: usize,
+    /// Whether the reply came back shorter or with a different padding
+    /// than its probe was sent with, or with a tag that doesn't match.
+    pub corrupted: bool,
+}
+
+/// The padding pattern repeated over `len` bytes.
+fn fill(pattern: &[u8], len: usize) -> impl Iterator<Item = u8> + '_ {
+    pattern.iter().cloned().cycle().take(len)
+}
+
+/// What we remember of a probe for matching its replies.
+#[derive(Debug, Clone, Copy)]
+struct Probe {
+    sent: Instant,
+    ttl: Option<u8>,
+    size: Option<usize>,
+}
+
+/// Iterator that probes the group at a fixed interval and yields the replies.
+pub struct Pinger {
+    sock: net::UdpSocket,
+    /// Sockets on other interfaces that send every probe as well.
+    also: Vec<net::UdpSocket>,
+    /// Joined to the group to hear replies sent there.
+    group_sock: Option<net::UdpSocket>,
+    dest: net::SocketAddr,
+    interval: Duration,
+    /// Whether a reply to the latest probe brings the next one forward.
+    paced: bool,
+    next_send: Instant,
+    seqnum: u64,
+    sent: HashMap<u64, Probe>,
+    count: Option<u64>,
+    /// First and last TTL, and probes sent at each.
+    ttl_sweep: Option<(u8, u8, u64)>,
+    /// Size to pad probes to, and the pattern padding them.
+    size: Option<usize>,
+    pattern: Vec<u8>,
+    /// Sizes to pad probes to in turn, and probes sent at each.
+    size_sweep: Option<(Vec<usize>, u64)>,
+    key: Option<Key>,
+    deadline: Option<Instant>,
+    /// How long `recv` waits for a reply before failing with `TimedOut`.
+    timeout: Option<Duration>,
+    buf: Vec<u8>,
+}
+
+pub fn ping(config: &MulticastSocket, interval: Duration) -> io::Result<Pinger> {
+    Ok(Pinger {
+        sock: config.sender()?,
+        also: Vec::new(),
+        group_sock: None,
+        dest: config.target()?,
+        interval,
+        paced: false,
+        next_send: Instant::now(),
+        seqnum: 0,
+        sent: HashMap::new(),
+        count: None,
+        ttl_sweep: None,
+        size: None,
+        pattern: Vec::new(),
+        size_sweep: None,
+        key: None,
+        deadline: None,
+        timeout: None,
+        buf: vec![0u8; MAX_PROBE_SIZE],
+    })
+}
+
+impl Pinger {
+    pub fn socket(&self) -> &net::UdpSocket {
+        &self.sock
+    }
+
+    /// Stops probing after `count` probes, then waits a little for the
+    /// last replies before finishing.
+    pub fn probe_count(&mut self, count: u64) -> &mut Pinger {
+        self.count = Some(count);
+        self
+    }
+
+    /// Pads probes to `size` bytes with `pattern` repeated, and checks that
+    /// replies bring the padding back intact.
+    pub fn payload(&mut self, size: usize, pattern: Vec<u8>) -> &mut Pinger {
+        self.size = Some(size);
+        self.pattern = pattern;
+        self
+    }
+
+    /// Sends `per_size` probes padded to each of `sizes` in turn, then
+    /// finishes like a counted run. Replies carry the size of their probe.
+    pub fn size_sweep(&mut self, sizes: Vec<usize>, per_size: u64, pattern: Vec<u8>) -> &mut Pinger {
+        self.count = Some(sizes.len() as u64 * per_size);
+        self.size_sweep = Some((sizes, per_size));
+        self.pattern = pattern;
+        self
+    }
+
+    /// Ends probes with an HMAC tag, which replies echo back. Padded probes
+    /// keep their size, with the tag in place of the last of the padding.
+    pub fn key(&mut self, key: Key) -> &mut Pinger {
+        self.key = Some(key);
+        self
+    }
+
+    fn tag_len(&self) -> usize {
+        if self.key.is_some() { TAG_LEN } else { 0 }
+    }
+
+    fn probe(&self, seqnum: u64, size: Option<usize>) -> Vec<u8> {
+        let mut probe = format!("PING {}", seqnum).into_bytes();
+        if let Some(size) = size {
+            probe.push(b' ');
+            let len = size.saturating_sub(probe.len() + self.tag_len());
+            probe.extend(fill(&self.pattern, len));
+        }
+        if let Some(ref key) = self.key {
+            key.sign(&mut probe);
+        }
+        probe
+    }
+
+    /// Whether a reply lacks the padding of its probe.
+    fn corrupted(&self, data: &[u8], size: Option<usize>) -> bool {
+        let size = match size {
+            Some(size) => size.saturating_sub(self.tag_len()),
+            None => return false,
+        };
+        let pattern = &self.pattern;
+        let header = data.iter().skip(PONG.len() + 1).position(|&b| b == b' ').map(|end| PONG.len() + end + 2);
+        match header {
+            Some(start) => {
+                let expected = size.saturating_sub(start);
+                data.len() - start != expected || !fill(pattern, expected).eq(data[start..].iter().cloned())
+            }
+            None => true,
+        }
+    }
+
+    /// Also joins the group to collect replies that responders send there
+    /// rather than back to us.
+    pub fn group_replies(&mut self, config: &MulticastSocket) -> io::Result<&mut Pinger> {
+        let mut config = config.clone();
+        config.reuse(true);
+        self.group_sock = Some(config.join()?);
+        Ok(self)
+    }
+
+    /// Sends every probe through a socket set up by `config` as well,
+    /// normally the same group on another interface, to probe redundant
+    /// paths. Each path's replies come back to its own socket.
+    pub fn also_via(&mut self, config: &MulticastSocket) -> io::Result<&mut Pinger> {
+        self.also.push(config.sender()?);
+        Ok(self)
+    }
+
+    /// Sends `per_ttl` probes at each TTL from `first` to `last`, then
+    /// finishes like a counted run. Replies carry the TTL of their probe.
+    pub fn ttl_sweep(&mut self, first: u8, last: u8, per_ttl: u64) -> &mut Pinger {
+        self.ttl_sweep = Some((first, last, per_ttl));
+        self.count = Some(u64::from(last.saturating_sub(first)) * per_ttl + per_ttl);
+        self
+    }
+
+    /// Sends the next probe as soon as the latest one is answered, waiting
+    /// at most the interval.
+    pub fn pace_by_replies(&mut self, paced: bool) -> &mut Pinger {
+        self.paced = paced;
+        self
+    }
+
+    /// Finishes after `deadline` regardless of how many probes were sent.
+    pub fn deadline(&mut self, deadline: Duration) -> &mut Pinger {
+        self.deadline = Some(Instant::now() + deadline);
+        self
+    }
+
+    /// Makes `recv` fail with `TimedOut` when no reply arrives within
+    /// `timeout`, still sending probes meanwhile, so the caller can do
+    /// periodic work.
+    pub fn timeout(&mut self, timeout: Option<Duration>) -> &mut Pinger {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Number of probes sent so far.
+    pub fn transmitted(&self) -> u64 {
+        self.seqnum
+    }
+
+    fn sending(&self) -> bool {
+        self.count.is_none_or(|count| self.seqnum < count)
+    }
+
+    fn finish_time(&self) -> Option<Instant> {
+        let linger_end = if self.sending() {
+            None
+        } else {
+            let last_sent = self.sent.get(&self.seqnum).map_or_else(Instant::now, |probe| probe.sent);
+            Some(last_sent + self.interval.max(LINGER))
+        };
+        match (linger_end, self.deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn send_probe(&mut self) -> io::Result<()> {
+        let ttl = match self.ttl_sweep {
+            Some((first, _, per_ttl)) => {
+                let ttl = first.saturating_add((self.seqnum / per_ttl).min(255) as u8);
+                if self.seqnum.is_multiple_of(per_ttl) {
+                    for sock in Some(&self.sock).into_iter().chain(&self.also) {
+                        match self.dest {
+                            net::SocketAddr::V4(_) => sock.set_multicast_ttl_v4(ttl.into())?,
+                            net::SocketAddr::V6(_) => sockopt::set_multicast_hops_v6(sock, ttl)?,
+                        }
+                    }
+                }
+                Some(ttl)
+            }
+            None => None,
+        };
+        let size = match self.size_sweep {
+            Some((ref sizes, per_size)) => sizes.get((self.seqnum / per_size) as usize).cloned(),
+            None => self.size,
+        };
+        self.seqnum += 1;
+        let probe = self.probe(self.seqnum, size);
+        for sock in &self.also {
+            sock.send_to(&probe, self.dest)?;
+        }
+        self.sock.send_to(&probe, self.dest)?;
+        self.sent.insert(self.seqnum, Probe { sent: Instant::now(), ttl, size });
+        if self.seqnum > MAX_OUTSTANDING {
+            self.sent.remove(&(self.seqnum - MAX_OUTSTANDING));
+        }
+        self.next_send = if self.paced { Instant::now() } else { self.next_send } + self.interval;
+        Ok(())
+    }
+
+    /// Waits for the next reply, sending probes as they fall due. Returns
+    /// `None` once the count or deadline has been reached.
+    pub fn recv(&mut self) -> io::Result<Option<Pong>> {
+        let give_up = self.timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let now = Instant::now();
+            let finish = self.finish_time();
+            if finish.is_some_and(|finish| now >= finish) {
+                return Ok(None);
+            }
+            if give_up.is_some_and(|give_up| now >= give_up) {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "no reply"));
+            }
+            if self.sending() && now >= self.next_send {
+                self.send_probe()?;
+                continue;
+            }
+            let wake = match (self.sending(), finish) {
+                (true, Some(finish)) => self.next_send.min(finish),
+                (true, None) => self.next_send,
+                (false, Some(finish)) => finish,
+                (false, None) => unreachable!("counted runs always finish"),
+            };
+            let wake = give_up.map_or(wake, |give_up| wake.min(give_up));
+            let ready = {
+                let mut poll = Poll::new();
+                poll.register(&self.sock, 0, Interest::Readable);
+                if let Some(ref group_sock) = self.group_sock {
+                    poll.register(group_sock, 1, Interest::Readable);
+                }
+                for (path, sock) in self.also.iter().enumerate() {
+                    poll.register(sock, path + 2, Interest::Readable);
+                }
+                match poll.wait(Some(wake - now))?.first() {
+                    Some(event) => event.token,
+                    None => continue,
+                }
+            };
+            let multicast = ready == 1;
+            let (sock, path) = match ready {
+                0 => (&self.sock, 0),
+                1 => (self.group_sock.as_ref().expect("a group socket"), 0),
+                _ => (&self.also[ready - 2], ready - 1),
+            };
+            match sockopt::recv_msg(sock, &mut self.buf) {
+                // the group also carries our own and others' probes
+                Ok((len, _, _)) if multicast && !self.buf[..len.min(self.buf.len())].starts_with(PONG) => continue,
+                Ok((len, src, _)) => {
+                    let arrived = Instant::now();
+                    // a reply too big for the buffer comes back cut short
+                    let truncated = len > self.buf.len();
+                    let mut data = self.buf[..len.min(self.buf.len())].to_vec();
+                    // the echoed tag must still match the probe it came with
+                    let mut forged = false;
+                    if let Some(ref key) = self.key {
+                        let mut probe = PING.to_vec();
+                        probe.extend(data.get(PONG.len()..).unwrap_or_default());
+                        match key.verify(&probe) {
+                            Some(payload) => data.truncate(payload.len()),
+                            None => forged = true,
+                        }
+                    }
+                    let seqnum = parse_seqnum(&data, PONG);
+                    let probe = seqnum.and_then(|seqnum| self.sent.get(&seqnum));
+                    let rtt = probe.map(|probe| arrived - probe.sent);
+                    let probe_ttl = probe.and_then(|probe| probe.ttl);
+                    let probe_size = probe.map_or(self.size, |probe| probe.size);
+                    if self.paced && seqnum == Some(self.seqnum) {
+                        self.next_send = arrived;
+                    }
+                    let corrupted = forged || truncated || self.corrupted(&data, probe_size);
+                    let datagram = Datagram {
+                        data,
+                        size: len,
+                        src,
+                        group: self.dest,
+                        dst: None,
+                        ifindex: None,
+                        ttl: None,
+                        tos: None,
+                        drops: None,
+                        received: SystemTime::now(),
+                        tag: None,
+                    };
+                    return Ok(Some(Pong {
+                        datagram,
+                        seqnum,
+                        rtt,
+                        probe_ttl,
+                        probe_size,
+                        multicast,
+                        path,
+                        corrupted,
+                    }));
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock ||
+                                err.kind() == io::ErrorKind::TimedOut => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Iterator for Pinger {
+    type Item = io::Result<Pong>;
+
+    fn next(&mut self) -> Option<io::Result<Pong>> {
+        match self.recv() {
+            Ok(pong) => pong.map(Ok),
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+/// Running min/avg/max/stddev and a histogram of round trip times.
+#[derive(Debug, Default, Clone)]
+pub struct RttStats {
+    count: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
+    sum_sq: f64,
+    histogram: Histogram,
+}
+
+impl RttStats {
+    pub fn record(&mut self, rtt: Duration) {
+        let ms = rtt.as_secs_f64() * 1e3;
+        if self.count == 0 || ms < self.min {
+            self.min = ms;
+        }
+        if ms > self.max {
+            self.max = ms;
+        }
+        self.count += 1;
+        self.sum += ms;
+        self.sum_sq += ms * ms;
+        self.histogram.record(rtt);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn avg(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.sum / self.count as f64 }
+    }
+
+    pub fn stddev(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let avg = self.avg();
+        (self.sum_sq / self.count as f64 - avg * avg).max(0.0).sqrt()
+    }
+
+    pub fn histogram(&self) -> &Histogram {
+        &self.histogram
+    }
+
+    /// Gauges of the round trip times in seconds, for pushing as metrics.
+    fn fields(&self) -> Vec<Field> {
+        if self.count == 0 {
+            return Vec::new();
+        }
+        vec![Field::gauge("rtt_min_seconds", "Shortest round trip time.", self.min / 1e3),
+             Field::gauge("rtt_avg_seconds", "Average round trip time.", self.avg() / 1e3),
+             Field::gauge("rtt_max_seconds", "Longest round trip time.", self.max / 1e3),
+             Field::gauge("rtt_p50_seconds", "Median round trip time.", self.histogram.percentile(50.0)),
+             Field::gauge("rtt_p99_seconds", "99th percentile round trip time.", self.histogram.percentile(99.0))]
+    }
+
+    /// The tail of the distribution, which min/avg/max hide.
+    pub fn percentiles(&self) -> String {
+        format!("rtt p50/p90/p99/p99.9 = {:.3}/{:.3}/{:.3}/{:.3} ms",
+                self.histogram.percentile(50.0) * 1e3,
+                self.histogram.percentile(90.0) * 1e3,
+                self.histogram.percentile(99.0) * 1e3,
+                self.histogram.percentile(99.9) * 1e3)
+    }
+}
+
+impl fmt::Display for RttStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "rtt min/avg/max/stddev = {:.3}/{:.3}/{:.3}/{:.3} ms",
+               self.min,
+               self.avg(),
+               self.max,
+               self.stddev())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Responder {
+    answered: HashSet<u64>,
+    replies: u64,
+    rtt: RttStats,
+}
+
+fn loss_percent(transmitted: u64, answered: usize) -> f64 {
+    if transmitted == 0 {
+        0.0
+    } else {
+        100.0 * transmitted.saturating_sub(answered as u64) as f64 / transmitted as f64
+    }
+}
+
+/// Ping(8) style accounting of replies, overall and per responder.
+#[derive(Debug)]
+pub struct PingStats {
+    dest: net::SocketAddr,
+    transmitted: u64,
+    answered: HashSet<u64>,
+    replies: u64,
+    corrupted: u64,
+    rtt: RttStats,
+    responders: BTreeMap<net::SocketAddr, Responder>,
+}
+
+impl PingStats {
+    pub fn new(dest: net::SocketAddr) -> PingStats {
+        PingStats {
+            dest,
+            transmitted: 0,
+            answered: HashSet::new(),
+            replies: 0,
+            corrupted: 0,
+            rtt: RttStats::default(),
+            responders: BTreeMap::new(),
+        }
+    }
+
+    pub fn set_transmitted(&mut self, transmitted: u64) {
+        self.transmitted = transmitted;
+    }
+
+    pub fn record(&mut self, pong: &Pong) {
+        let responder = self.responders.entry(pong.datagram.src).or_default();
+        self.replies += 1;
+        responder.replies += 1;
+        if pong.corrupted {
+            self.corrupted += 1;
+        }
+        if let Some(seqnum) = pong.seqnum {
+            self.answered.insert(seqnum);
+            responder.answered.insert(seqnum);
+        }
+        if let Some(rtt) = pong.rtt {
+            self.rtt.record(rtt);
+            responder.rtt.record(rtt);
+        }
+    }
+
+    pub fn transmitted(&self) -> u64 {
+        self.transmitted
+    }
+
+    /// Number of probes that got at least one reply.
+    pub fn answered(&self) -> u64 {
+        self.answered.len() as u64
+    }
+
+    /// Number of replies whose padding didn't match the probe's.
+    pub fn corrupted(&self) -> u64 {
+        self.corrupted
+    }
+
+    pub fn rtt(&self) -> &RttStats {
+        &self.rtt
+    }
+
+    /// The statistics as metrics, overall and per responder.
+    pub fn series(&self) -> Vec<Series> {
+        let mut fields = vec![Field::counter("transmitted", "Probes sent.", self.transmitted),
+                              Field::counter("answered", "Probes that got at least one reply.", self.answered()),
+                              Field::counter("replies", "Replies received.", self.replies),
+                              Field::counter("corrupted", "Replies with corrupted padding.", self.corrupted),
+                              Field::gauge("loss_ratio",
+                                           "Share of probes not answered.",
+                                           loss_percent(self.transmitted, self.answered.len()) / 100.0)];
+        fields.extend(self.rtt.fields());
+        let mut series = vec![Series::new("ping", metrics::group_tags(&self.dest), fields)];
+        for (src, responder) in &self.responders {
+            let mut tags = metrics::group_tags(&self.dest);
+            tags.push(("responder", src.ip().to_string()));
+            tags.push(("responder_port", src.port().to_string()));
+            let mut fields = vec![Field::counter("replies", "Replies received.", responder.replies),
+                                  Field::gauge("loss_ratio",
+                                               "Share of probes not answered.",
+                                               loss_percent(self.transmitted, responder.answered.len()) / 100.0)];
+            fields.extend(responder.rtt.fields());
+            series.push(Series::new("responder", tags, fields));
+        }
+        series
+    }
+}
+
+impl fmt::Display for PingStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "--- {} ping statistics ---", self.dest)?;
+        writeln!(f,
+                 "{} probes transmitted, {} answered, {:.1}% loss, {} replies from {} responders",
+                 self.transmitted,
+                 self.answered.len(),
+                 loss_percent(self.transmitted, self.answered.len()),
+                 self.replies,
+                 self.responders.len())?;
+        if self.corrupted > 0 {
+            writeln!(f, "{} replies with corrupted padding", self.corrupted)?;
+        }
+        if self.rtt.count() > 0 {
+            writeln!(f, "{}", self.rtt)?;
+            writeln!(f, "{}", self.rtt.percentiles())?;
+        }
+        for (src, responder) in &self.responders {
+            write!(f,
+                   "{}: {} replies, {:.1}% loss",
+                   src,
+                   responder.replies,
+                   loss_percent(self.transmitted, responder.answered.len()))?;
+            if responder.rtt.count() > 0 {
+                write!(f, ", {}", responder.rtt)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Hop {
+    answered: HashSet<u64>,
+    responders: HashSet<net::SocketAddr>,
+}
+
+/// How far probes had to travel before each responder answered.
+#[derive(Debug)]
+pub struct SweepStats {
+    dest: net::SocketAddr,
+    per_ttl: u64,
+    hops: BTreeMap<u8, Hop>,
+    /// The lowest probe TTL each responder answered.
+    reach: BTreeMap<net::SocketAddr, u8>,
+}
+
+impl SweepStats {
+    pub fn new(dest: net::SocketAddr, first: u8, last: u8, per_ttl: u64) -> SweepStats {
+        SweepStats {
+            dest,
+            per_ttl,
+            hops: (first..=last).map(|ttl| (ttl, Hop::default())).collect(),
+            reach: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, pong: &Pong) {
+        let ttl = match pong.probe_ttl {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        let hop = self.hops.entry(ttl).or_default();
+        hop.responders.insert(pong.datagram.src);
+        if let Some(seqnum) = pong.seqnum {
+            hop.answered.insert(seqnum);
+        }
+        let reach = self.reach.entry(pong.datagram.src).or_insert(ttl);
+        *reach = (*reach).min(ttl);
+    }
+}
+
+impl fmt::Display for SweepStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "--- {} TTL sweep ---", self.dest)?;
+        for (&ttl, hop) in &self.hops {
+            write!(f,
+                   "ttl {:>3}: {}/{} probes answered, {} responders",
+                   ttl,
+                   hop.answered.len(),
+                   self.per_ttl,
+                   hop.responders.len())?;
+            let new: Vec<_> = self.reach
+                .iter()
+                .filter(|&(_, &reach)| reach == ttl)
+                .map(|(src, _)| src.to_string())
+                .collect();
+            if !new.is_empty() {
+                write!(f, ", first reached: {}", new.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Member {
+    ports: BTreeSet<u16>,
+    replies: u64,
+    rtt: Option<Duration>,
+}
+
+/// Column headings matching the rows of `Census`.
+pub const CENSUS_HEADER: &str = "HOST                                        RTT ms  REPLIES  PORTS";
+
+/// The hosts that answered a single solicitation, one row each.
+#[derive(Debug)]
+pub struct Census {
+    members: BTreeMap<net::IpAddr, Member>,
+}
+
+impl Default for Census {
+    fn default() -> Census {
+        Census::new()
+    }
+}
+
+impl Census {
+    pub fn new() -> Census {
+        Census { members: BTreeMap::new() }
+    }
+
+    pub fn record(&mut self, pong: &Pong) {
+        let member = self.members.entry(pong.datagram.src.ip()).or_default();
+        member.ports.insert(pong.datagram.src.port());
+        member.replies += 1;
+        if let Some(rtt) = pong.rtt {
+            member.rtt = Some(member.rtt.map_or(rtt, |first| first.min(rtt)));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+impl fmt::Display for Census {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (host, member) in &self.members {
+            let rtt = member.rtt.map_or_else(|| "-".to_owned(), |rtt| format!("{:.3}", rtt.as_secs_f64() * 1e3));
+            let ports: Vec<_> = member.ports.iter().map(|port| port.to_string()).collect();
+            writeln!(f, "{:<39} {:>10} {:>8}  {}", host, rtt, member.replies, ports.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// The largest probes each responder answered.
+#[derive(Debug)]
+pub struct SizeSweepStats {
+    dest: net::SocketAddr,
+    per_size: u64,
+    sizes: BTreeMap<usize, Hop>,
+    largest: BTreeMap<net::SocketAddr, usize>,
+}
+
+impl SizeSweepStats {
+    pub fn new(dest: net::SocketAddr, sizes: &[usize], per_size: u64) -> SizeSweepStats {
+        SizeSweepStats {
+            dest,
+            per_size,
+            sizes: sizes.iter().map(|&size| (size, Hop::default())).collect(),
+            largest: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, pong: &Pong) {
+        let size = match pong.probe_size {
+            Some(size) if !pong.corrupted => size,
+            _ => return,
+        };
+        let stats = self.sizes.entry(size).or_default();
+        stats.responders.insert(pong.datagram.src);
+        if let Some(seqnum) = pong.seqnum {
+            stats.answered.insert(seqnum);
+        }
+        let largest = self.largest.entry(pong.datagram.src).or_insert(size);
+        *largest = (*largest).max(size);
+    }
+}
+
+impl fmt::Display for SizeSweepStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "--- {} size sweep ---", self.dest)?;
+        for (&size, stats) in &self.sizes {
+            writeln!(f,
+                     "size {:>5}: {}/{} probes answered, {} responders",
+                     size,
+                     stats.answered.len(),
+                     self.per_size,
+                     stats.responders.len())?;
+        }
+        for (src, &largest) in &self.largest {
+            write!(f, "{}: largest answered {} bytes", src, largest)?;
+            if let Some((&next, _)) = self.sizes.range(largest + 1..).next() {
+                write!(f, ", nothing intact from {} bytes up", next)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net, thread};
+    use std::time::Duration;
+
+    use socket::MulticastSocket;
+    use super::*;
+
+    /// Answers probes to `group` on loopback until `count` have come in.
+    fn responder(group: net::Ipv4Addr, count: usize) -> (u16, thread::JoinHandle<()>) {
+        let sock = net::UdpSocket::bind((net::Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        sock.join_multicast_v4(&group, &net::Ipv4Addr::LOCALHOST).unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let port = sock.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let mut buf = vec![0u8; MAX_PROBE_SIZE];
+            for _ in 0..count {
+                let (len, src) = match sock.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(_) => return,
+                };
+                if let Some(pong) = reply(&buf[..len]) {
+                    sock.send_to(&pong, src).unwrap();
+                }
+            }
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn size_sweep_above_16k() {
+        let group = net::Ipv4Addr::new(239, 255, 51, 52);
+        let sizes = vec![1000, 16384, 20000, 40000, MAX_PROBE_SIZE];
+        let (port, handle) = responder(group, sizes.len() * 2);
+        let mut config = MulticastSocket::new(group.into(), port);
+        config.interface("127.0.0.1");
+        let mut pinger = ping(&config, Duration::from_millis(10)).unwrap();
+        pinger.size_sweep(sizes.clone(), 2, (0..=255).collect()).timeout(Some(Duration::from_secs(5)));
+        let mut stats = SizeSweepStats::new(config.addr(), &sizes, 2);
+        for pong in &mut pinger {
+            let pong = pong.unwrap();
+            assert!(!pong.corrupted, "{} byte reply corrupted", pong.datagram.size);
+            assert_eq!(pong.datagram.size, pong.probe_size.unwrap());
+            stats.record(&pong);
+        }
+        handle.join().unwrap();
+        for (&size, hop) in &stats.sizes {
+            assert_eq!(hop.answered.len(), 2, "size {}", size);
+        }
+        assert_eq!(stats.largest.values().cloned().collect::<Vec<_>>(), vec![MAX_PROBE_SIZE]);
+    }
+}
@@ -0,0 +1,67 @@
+use std::{env, fs, io, net};
+use std::path::PathBuf;
+
+use config::{self, Group};
+use wellknown;
+
+/// A name to use in place of a group address and port, e.g. `mdns`.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub group: Group,
+    /// What the group is for, for the built-in profiles.
+    pub description: Option<&'static str>,
+}
+
+/// The user's profiles file: `mccat/profiles.toml` under
+/// `$XDG_CONFIG_HOME`, or else under `~/.config`.
+pub fn path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("mccat").join("profiles.toml"))
+}
+
+/// The well-known groups that have a UDP port, at their IPv4 addresses;
+/// `--dual-stack` adds the IPv6 ones.
+pub fn builtin() -> Vec<Profile> {
+    wellknown::GROUPS.iter()
+        .filter(|group| group.port != 0)
+        .map(|group| {
+            Profile {
+                name: group.name.to_owned(),
+                group: Group {
+                    addr: (net::IpAddr::V4(group.v4), group.port).into(),
+                    interface: None,
+                    source: None,
+                },
+                description: Some(group.description),
+            }
+        })
+        .collect()
+}
+
+/// The built-in profiles and those in the profiles file, if there is one.
+/// A profile in the file replaces a built-in one of the same name.
+pub fn load() -> Result<Vec<Profile>, String> {
+    let mut profiles = builtin();
+    let path = match path() {
+        Some(path) => path,
+        None => return Ok(profiles),
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(profiles),
+        Err(err) => return Err(format!("can't read {}: {}", path.display(), err)),
+    };
+    for (name, group) in config::profiles(&text).map_err(|err| format!("{}: {}", path.display(), err))? {
+        profiles.retain(|profile| profile.name != name);
+        profiles.push(Profile {
+            name,
+            group,
+            description: None,
+        });
+    }
+    Ok(profiles)
+}
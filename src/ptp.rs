@@ -0,0 +1,576 @@
+use std::{fmt, net};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// The group PTP over UDP/IPv4 sends everything but peer delay messages
+/// to (IEEE 1588 annex D).
+pub const PTP_GROUP: net::Ipv4Addr = net::Ipv4Addr::new(224, 0, 1, 129);
+/// Port of Sync, Delay_Req and the other timestamped messages.
+pub const EVENT_PORT: u16 = 319;
+/// Port of Follow_Up, Announce and the other general messages.
+pub const GENERAL_PORT: u16 = 320;
+
+const HEADER_LEN: usize = 34;
+
+/// An EUI-64 naming a clock, shown as ptp4l does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ClockIdentity(pub [u8; 8]);
+
+impl fmt::Display for ClockIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(f,
+               "{:02x}{:02x}{:02x}.{:02x}{:02x}.{:02x}{:02x}{:02x}",
+               b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7])
+    }
+}
+
+/// A clock and one of its ports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PortIdentity {
+    pub clock: ClockIdentity,
+    pub port: u16,
+}
+
+impl fmt::Display for PortIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.clock, self.port)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MessageType {
+    Sync,
+    DelayReq,
+    PdelayReq,
+    PdelayResp,
+    FollowUp,
+    DelayResp,
+    PdelayRespFollowUp,
+    Announce,
+    Signaling,
+    Management,
+    Other(u8),
+}
+
+impl MessageType {
+    fn from_nibble(nibble: u8) -> MessageType {
+        match nibble {
+            0x0 => MessageType::Sync,
+            0x1 => MessageType::DelayReq,
+            0x2 => MessageType::PdelayReq,
+            0x3 => MessageType::PdelayResp,
+            0x8 => MessageType::FollowUp,
+            0x9 => MessageType::DelayResp,
+            0xa => MessageType::PdelayRespFollowUp,
+            0xb => MessageType::Announce,
+            0xc => MessageType::Signaling,
+            0xd => MessageType::Management,
+            other => MessageType::Other(other),
+        }
+    }
+}
+
+impl fmt::Display for MessageType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MessageType::Sync => write!(f, "sync"),
+            MessageType::DelayReq => write!(f, "delay_req"),
+            MessageType::PdelayReq => write!(f, "pdelay_req"),
+            MessageType::PdelayResp => write!(f, "pdelay_resp"),
+            MessageType::FollowUp => write!(f, "follow_up"),
+            MessageType::DelayResp => write!(f, "delay_resp"),
+            MessageType::PdelayRespFollowUp => write!(f, "pdelay_resp_follow_up"),
+            MessageType::Announce => write!(f, "announce"),
+            MessageType::Signaling => write!(f, "signaling"),
+            MessageType::Management => write!(f, "management"),
+            MessageType::Other(nibble) => write!(f, "type {:#x}", nibble),
+        }
+    }
+}
+
+/// A PTP timestamp: seconds and nanoseconds, in the grandmaster's
+/// timescale, usually TAI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    pub secs: u64,
+    pub nanos: u32,
+}
+
+impl Timestamp {
+    fn parse(data: &[u8]) -> Option<Timestamp> {
+        let data = data.get(..10)?;
+        Some(Timestamp {
+            secs: data[..6].iter().fold(0, |acc, &b| acc << 8 | u64::from(b)),
+            nanos: u32::from_be_bytes([data[6], data[7], data[8], data[9]]),
+        })
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:09}", self.secs, self.nanos)
+    }
+}
+
+/// What an Announce says about the grandmaster, which the best master
+/// clock algorithm picks between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Announce {
+    /// TAI minus UTC, in seconds.
+    pub utc_offset: i16,
+    pub priority1: u8,
+    pub clock_class: u8,
+    pub clock_accuracy: u8,
+    pub variance: u16,
+    pub priority2: u8,
+    pub grandmaster: ClockIdentity,
+    /// Boundary clocks between the grandmaster and the sender.
+    pub steps_removed: u16,
+    pub time_source: u8,
+}
+
+impl Announce {
+    fn parse(body: &[u8]) -> Option<Announce> {
+        // past the origin timestamp
+        let body = body.get(10..30)?;
+        let mut grandmaster = [0; 8];
+        grandmaster.copy_from_slice(&body[9..17]);
+        Some(Announce {
+            utc_offset: i16::from_be_bytes([body[0], body[1]]),
+            priority1: body[3],
+            clock_class: body[4],
+            clock_accuracy: body[5],
+            variance: u16::from_be_bytes([body[6], body[7]]),
+            priority2: body[8],
+            grandmaster: ClockIdentity(grandmaster),
+            steps_removed: u16::from_be_bytes([body[17], body[18]]),
+            time_source: body[19],
+        })
+    }
+
+    /// The grandmaster's time source by name.
+    pub fn time_source_name(&self) -> String {
+        match self.time_source {
+            0x10 => "atomic clock".to_owned(),
+            0x20 => "GNSS".to_owned(),
+            0x30 => "terrestrial radio".to_owned(),
+            0x39 => "serial time code".to_owned(),
+            0x40 => "PTP".to_owned(),
+            0x50 => "NTP".to_owned(),
+            0x60 => "hand set".to_owned(),
+            0x90 => "other".to_owned(),
+            0xa0 => "internal oscillator".to_owned(),
+            other => format!("{:#04x}", other),
+        }
+    }
+}
+
+impl fmt::Display for Announce {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "grandmaster {} priority1 {} class {} accuracy {:#04x} variance {:#06x} priority2 {} steps {} \
+                source {} utc-offset {}",
+               self.grandmaster,
+               self.priority1,
+               self.clock_class,
+               self.clock_accuracy,
+               self.variance,
+               self.priority2,
+               self.steps_removed,
+               self.time_source_name(),
+               self.utc_offset)
+    }
+}
+
+/// A PTPv2 message, with what mccat makes of its body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Message {
+    pub kind: MessageType,
+    /// The majorSdoId, formerly transportSpecific: 1 for 802.1AS.
+    pub sdo: u8,
+    pub domain: u8,
+    /// A Sync whose precise time follows in a Follow_Up.
+    pub two_step: bool,
+    pub source: PortIdentity,
+    pub sequence: u16,
+    /// The interval between messages of this kind the sender means to
+    /// keep, as a power of two in seconds.
+    pub log_interval: i8,
+    /// The origin timestamp of Sync, Delay_Req and Announce, or the
+    /// precise one of Follow_Up.
+    pub timestamp: Option<Timestamp>,
+    pub announce: Option<Announce>,
+}
+
+impl Message {
+    /// Parses a PTP version 2 message as carried over UDP.
+    pub fn parse(data: &[u8]) -> Option<Message> {
+        if data.len() < HEADER_LEN || data[1] & 0x0f != 2 {
+            return None;
+        }
+        let kind = MessageType::from_nibble(data[0] & 0x0f);
+        let mut clock = [0; 8];
+        clock.copy_from_slice(&data[20..28]);
+        let body = &data[HEADER_LEN..];
+        let timestamp = match kind {
+            MessageType::Sync | MessageType::DelayReq | MessageType::FollowUp | MessageType::Announce => {
+                Timestamp::parse(body)
+            }
+            _ => None,
+        };
+        Some(Message {
+            kind,
+            sdo: data[0] >> 4,
+            domain: data[4],
+            two_step: data[6] & 0x02 != 0,
+            source: PortIdentity {
+                clock: ClockIdentity(clock),
+                port: u16::from_be_bytes([data[28], data[29]]),
+            },
+            sequence: u16::from_be_bytes([data[30], data[31]]),
+            log_interval: data[33] as i8,
+            timestamp,
+            announce: if kind == MessageType::Announce { Announce::parse(body) } else { None },
+        })
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "domain {} {} seq {} from {}", self.domain, self.kind, self.sequence, self.source)?;
+        if self.kind == MessageType::Sync && self.two_step {
+            write!(f, " two-step")?;
+        }
+        if let Some(timestamp) = self.timestamp {
+            write!(f, " origin {}", timestamp)?;
+        }
+        if let Some(ref announce) = self.announce {
+            write!(f, " {}", announce)?;
+        }
+        Ok(())
+    }
+}
+
+/// Something worth telling as it happens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PtpEvent {
+    /// A port sent its first message.
+    NewPort { domain: u8, source: PortIdentity, addr: net::IpAddr },
+    /// A port announced a grandmaster for the first time, or a different
+    /// one, or the same one with a different quality.
+    Announced { domain: u8, source: PortIdentity, announce: Announce, previous: Option<Announce> },
+    /// A two-step Sync had no Follow_Up before the next Sync.
+    MissingFollowUp { domain: u8, source: PortIdentity, sequence: u16 },
+}
+
+impl fmt::Display for PtpEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PtpEvent::NewPort { domain, source, addr } => {
+                write!(f, "domain {} new port {} at {}", domain, source, addr)
+            }
+            PtpEvent::Announced { domain, source, announce, previous: None } => {
+                write!(f, "domain {} {} announces {}", domain, source, announce)
+            }
+            PtpEvent::Announced { domain, source, announce, previous: Some(previous) } => {
+                if announce.grandmaster == previous.grandmaster {
+                    write!(f, "domain {} {} now announces {}", domain, source, announce)
+                } else {
+                    write!(f,
+                           "domain {} {} changes grandmaster from {} to {}",
+                           domain, source, previous.grandmaster, announce)
+                }
+            }
+            PtpEvent::MissingFollowUp { domain, source, sequence } => {
+                write!(f, "domain {} {} sent no follow_up for sync seq {}", domain, source, sequence)
+            }
+        }
+    }
+}
+
+/// A port as seen over a period: what it sent how often, and the
+/// grandmaster it last announced.
+#[derive(Debug, Clone)]
+pub struct PortReport {
+    pub domain: u8,
+    pub source: PortIdentity,
+    pub addr: net::IpAddr,
+    /// Messages per second by kind.
+    pub rates: Vec<(MessageType, f64)>,
+    pub announce: Option<Announce>,
+    pub two_step: bool,
+    pub missing_follow_ups: u64,
+}
+
+impl PortReport {
+    /// Whether the port is a master, sending Sync or Announce.
+    pub fn master(&self) -> bool {
+        self.rates.iter().any(|&(kind, _)| kind == MessageType::Sync || kind == MessageType::Announce)
+    }
+}
+
+impl fmt::Display for PortReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "domain {} {} {} at {}",
+               self.domain,
+               if self.master() { "master" } else { "port" },
+               self.source,
+               self.addr)?;
+        let rates: Vec<String> = self.rates.iter().map(|&(kind, rate)| format!("{} {:.1}/s", kind, rate)).collect();
+        if !rates.is_empty() {
+            write!(f, ": {}", rates.join(", "))?;
+        }
+        if self.two_step {
+            write!(f, ", two-step")?;
+        }
+        if self.missing_follow_ups > 0 {
+            write!(f, ", {} syncs without follow_up", self.missing_follow_ups)?;
+        }
+        if let Some(ref announce) = self.announce {
+            write!(f, "; {}", announce)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Port {
+    addr: net::IpAddr,
+    counts: BTreeMap<MessageType, u64>,
+    interval_counts: BTreeMap<MessageType, u64>,
+    first_seen: Instant,
+    announce: Option<Announce>,
+    two_step: bool,
+    /// The two-step Sync still waiting for its Follow_Up.
+    pending_sync: Option<u16>,
+    /// The last Follow_Up, which can overtake its Sync between the ports.
+    last_follow_up: Option<u16>,
+    missing_follow_ups: u64,
+}
+
+/// Keeps track of the PTP ports on a network from the messages they send:
+/// how often they send each kind, which grandmaster they announce, and
+/// Follow_Ups that go missing.
+#[derive(Debug)]
+pub struct PtpMonitor {
+    ports: HashMap<(u8, PortIdentity), Port>,
+    last_report: Instant,
+}
+
+impl Default for PtpMonitor {
+    fn default() -> PtpMonitor {
+        PtpMonitor::new()
+    }
+}
+
+impl PtpMonitor {
+    pub fn new() -> PtpMonitor {
+        PtpMonitor { ports: HashMap::new(), last_report: Instant::now() }
+    }
+
+    /// Takes in a message from `addr`, returning what's new about its port.
+    pub fn record(&mut self, message: &Message, addr: net::IpAddr) -> Vec<PtpEvent> {
+        let (domain, source) = (message.domain, message.source);
+        let mut events = Vec::new();
+        let port = self.ports.entry((domain, source)).or_insert_with(|| {
+            events.push(PtpEvent::NewPort { domain, source, addr });
+            Port {
+                addr,
+                counts: BTreeMap::new(),
+                interval_counts: BTreeMap::new(),
+                first_seen: Instant::now(),
+                announce: None,
+                two_step: false,
+                pending_sync: None,
+                last_follow_up: None,
+                missing_follow_ups: 0,
+            }
+        });
+        port.addr = addr;
+        *port.counts.entry(message.kind).or_default() += 1;
+        *port.interval_counts.entry(message.kind).or_default() += 1;
+        match message.kind {
+            MessageType::Sync => {
+                if let Some(sequence) = port.pending_sync.take() {
+                    port.missing_follow_ups += 1;
+                    events.push(PtpEvent::MissingFollowUp { domain, source, sequence });
+                }
+                port.two_step = message.two_step;
+                if message.two_step && port.last_follow_up != Some(message.sequence) {
+                    port.pending_sync = Some(message.sequence);
+                }
+            }
+            MessageType::FollowUp => {
+                port.last_follow_up = Some(message.sequence);
+                if port.pending_sync == Some(message.sequence) {
+                    port.pending_sync = None;
+                }
+            }
+            MessageType::Announce => {
+                if let Some(announce) = message.announce {
+                    if port.announce != Some(announce) {
+                        events.push(PtpEvent::Announced { domain, source, announce, previous: port.announce });
+                        port.announce = Some(announce);
+                    }
+                }
+            }
+            _ => {}
+        }
+        events
+    }
+
+    fn report(key: &(u8, PortIdentity), port: &Port, counts: &BTreeMap<MessageType, u64>, period: Duration)
+              -> PortReport {
+        let secs = period.as_secs_f64().max(1e-9);
+        PortReport {
+            domain: key.0,
+            source: key.1,
+            addr: port.addr,
+            rates: counts.iter().map(|(&kind, &count)| (kind, count as f64 / secs)).collect(),
+            announce: port.announce,
+            two_step: port.two_step,
+            missing_follow_ups: port.missing_follow_ups,
+        }
+    }
+
+    fn sorted(mut reports: Vec<PortReport>) -> Vec<PortReport> {
+        reports.sort_by_key(|report| (report.domain, !report.master(), report.source));
+        reports
+    }
+
+    /// Each port's rates since the last interval report, starting a new
+    /// interval. Ports silent all interval are left out.
+    pub fn interval_reports(&mut self) -> Vec<PortReport> {
+        let now = Instant::now();
+        let period = now - self.last_report;
+        self.last_report = now;
+        let mut reports = Vec::new();
+        for (key, port) in &mut self.ports {
+            if !port.interval_counts.is_empty() {
+                reports.push(PtpMonitor::report(key, port, &port.interval_counts, period));
+                port.interval_counts.clear();
+            }
+        }
+        PtpMonitor::sorted(reports)
+    }
+
+    /// Each port's rates since it was first seen.
+    pub fn reports(&self) -> Vec<PortReport> {
+        let reports = self.ports
+            .iter()
+            .map(|(key, port)| PtpMonitor::report(key, port, &port.counts, port.first_seen.elapsed()))
+            .collect();
+        PtpMonitor::sorted(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOCK: [u8; 8] = [0x00, 0x1b, 0x21, 0xff, 0xfe, 0x0a, 0x0b, 0x0c];
+
+    fn header(kind: u8, flags: u8, sequence: u16, log_interval: u8) -> Vec<u8> {
+        let mut data = vec![kind, 2, 0, 0, 24, 0, flags, 0];
+        // correction and reserved
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&CLOCK);
+        data.extend_from_slice(&[0, 1]);
+        data.extend_from_slice(&sequence.to_be_bytes());
+        data.extend_from_slice(&[0, log_interval]);
+        assert_eq!(data.len(), HEADER_LEN);
+        data
+    }
+
+    /// 1700000000.5 s
+    const TIMESTAMP: [u8; 10] = [0x00, 0x00, 0x65, 0x53, 0xf1, 0x00, 0x1d, 0xcd, 0x65, 0x00];
+
+    fn source() -> PortIdentity {
+        PortIdentity { clock: ClockIdentity(CLOCK), port: 1 }
+    }
+
+    #[test]
+    fn sync_and_follow_up() {
+        let mut sync = header(0x0, 0x02, 7, 0xfd);
+        sync.extend_from_slice(&[0; 10]);
+        let message = Message::parse(&sync).unwrap();
+        assert_eq!(message,
+                   Message {
+                       kind: MessageType::Sync,
+                       sdo: 0,
+                       domain: 24,
+                       two_step: true,
+                       source: source(),
+                       sequence: 7,
+                       log_interval: -3,
+                       timestamp: Some(Timestamp { secs: 0, nanos: 0 }),
+                       announce: None,
+                   });
+        assert_eq!(message.to_string(), "domain 24 sync seq 7 from 001b21.fffe.0a0b0c-1 two-step origin 0.000000000");
+
+        // 802.1AS
+        let mut follow_up = header(0x18, 0, 7, 0xfd);
+        follow_up.extend_from_slice(&TIMESTAMP);
+        let message = Message::parse(&follow_up).unwrap();
+        assert_eq!(message.kind, MessageType::FollowUp);
+        assert_eq!(message.sdo, 1);
+        assert_eq!(message.timestamp, Some(Timestamp { secs: 1_700_000_000, nanos: 500_000_000 }));
+        assert_eq!(message.timestamp.unwrap().to_string(), "1700000000.500000000");
+    }
+
+    #[test]
+    fn announce() {
+        let mut data = header(0xb, 0, 300, 1);
+        data.extend_from_slice(&TIMESTAMP);
+        data.extend_from_slice(&[0, 37, 0, 128, 6, 0x21, 0x4e, 0x5d, 128]);
+        data.extend_from_slice(&[0xec, 0x46, 0x70, 0xff, 0xfe, 0x00, 0x00, 0x01]);
+        data.extend_from_slice(&[0, 1, 0x20]);
+        let message = Message::parse(&data).unwrap();
+        assert_eq!(message.kind, MessageType::Announce);
+        assert_eq!(message.timestamp, Some(Timestamp { secs: 1_700_000_000, nanos: 500_000_000 }));
+        let announce = message.announce.unwrap();
+        assert_eq!(announce,
+                   Announce {
+                       utc_offset: 37,
+                       priority1: 128,
+                       clock_class: 6,
+                       clock_accuracy: 0x21,
+                       variance: 0x4e5d,
+                       priority2: 128,
+                       grandmaster: ClockIdentity([0xec, 0x46, 0x70, 0xff, 0xfe, 0x00, 0x00, 0x01]),
+                       steps_removed: 1,
+                       time_source: 0x20,
+                   });
+        assert_eq!(announce.to_string(),
+                   "grandmaster ec4670.fffe.000001 priority1 128 class 6 accuracy 0x21 variance 0x4e5d priority2 128 \
+                    steps 1 source GNSS utc-offset 37");
+    }
+
+    #[test]
+    fn short_bodies() {
+        // a Sync without its timestamp
+        let mut sync = header(0x0, 0, 1, 0);
+        sync.extend_from_slice(&TIMESTAMP[..9]);
+        let message = Message::parse(&sync).unwrap();
+        assert_eq!(message.timestamp, None);
+
+        // an Announce cut short of the time source
+        let mut data = header(0xb, 0, 1, 1);
+        data.extend_from_slice(&TIMESTAMP);
+        data.extend_from_slice(&[0; 19]);
+        let message = Message::parse(&data).unwrap();
+        assert!(message.timestamp.is_some());
+        assert_eq!(message.announce, None);
+
+        // no body for messages without a timestamp
+        let message = Message::parse(&header(0xd, 0, 1, 0x7f)).unwrap();
+        assert_eq!((message.kind, message.timestamp), (MessageType::Management, None));
+
+        let mut v1 = header(0x0, 0, 1, 0);
+        v1[1] = 1;
+        assert_eq!(Message::parse(&v1), None);
+        assert_eq!(Message::parse(&header(0x0, 0, 1, 0)[..HEADER_LEN - 1]), None);
+    }
+}
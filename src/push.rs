@@ -0,0 +1,133 @@
+use std::{io, net};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use metrics::{Kind, Series};
+
+/// Keeps each datagram within a typical MTU, so nothing is fragmented.
+const MAX_PAYLOAD: usize = 1400;
+
+/// The line protocol metrics are pushed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `mccat.group.packets:12|c|#group:239.1.1.1,port:5000`, with
+    /// DogStatsD-style tags; counters are sent as increments.
+    Statsd,
+    /// `mccat_group,group=239.1.1.1,port=5000 packets=12i,... <ns>`.
+    Influx,
+}
+
+/// Pushes metrics over UDP to a StatsD agent or an InfluxDB UDP listener,
+/// for setups without Prometheus.
+#[derive(Debug)]
+pub struct Pusher {
+    sock: net::UdpSocket,
+    target: net::SocketAddr,
+    format: Format,
+    /// Counter values last pushed, as statsd wants increments.
+    pushed: HashMap<String, f64>,
+}
+
+impl Pusher {
+    pub fn new(target: net::SocketAddr, format: Format) -> io::Result<Pusher> {
+        let any: net::SocketAddr = if target.is_ipv4() {
+            (net::Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (net::Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        Ok(Pusher {
+            sock: net::UdpSocket::bind(any)?,
+            target,
+            format,
+            pushed: HashMap::new(),
+        })
+    }
+
+    pub fn target(&self) -> net::SocketAddr {
+        self.target
+    }
+
+    /// Sends `series` as lines, as many to a datagram as fit.
+    pub fn push(&mut self, series: &[Series]) -> io::Result<()> {
+        let lines = match self.format {
+            Format::Statsd => self.statsd(series),
+            Format::Influx => influx(series, SystemTime::now()),
+        };
+        let mut payload = String::new();
+        for line in lines {
+            if !payload.is_empty() && payload.len() + 1 + line.len() > MAX_PAYLOAD {
+                self.sock.send_to(payload.as_bytes(), self.target)?;
+                payload.clear();
+            }
+            if !payload.is_empty() {
+                payload.push('\n');
+            }
+            payload.push_str(&line);
+        }
+        if !payload.is_empty() {
+            self.sock.send_to(payload.as_bytes(), self.target)?;
+        }
+        Ok(())
+    }
+
+    fn statsd(&mut self, series: &[Series]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for series in series {
+            let tags: Vec<_> = series.tags.iter().map(|(tag, value)| format!("{}:{}", tag, value)).collect();
+            let tags = tags.join(",");
+            for field in &series.fields {
+                let name = format!("mccat.{}.{}", series.name, field.name);
+                let (value, kind) = match field.kind {
+                    Kind::Counter => {
+                        let last = self.pushed.insert(format!("{}|{}", name, tags), field.value).unwrap_or(0.0);
+                        if field.value == last {
+                            continue;
+                        }
+                        // a counter that went down was reset, so count from zero
+                        let delta = if field.value < last { field.value } else { field.value - last };
+                        (delta, "c")
+                    }
+                    // a leading sign would make statsd add to the gauge
+                    Kind::Gauge if field.value < 0.0 => continue,
+                    Kind::Gauge => (field.value, "g"),
+                };
+                lines.push(format!("{}:{}|{}|#{}", name, value, kind, tags));
+            }
+        }
+        lines
+    }
+}
+
+fn influx(series: &[Series], now: SystemTime) -> Vec<String> {
+    let nanos = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    series.iter()
+        .filter(|series| !series.fields.is_empty())
+        .map(|series| {
+            let mut line = format!("mccat_{}", escape(series.name));
+            for (tag, value) in &series.tags {
+                line.push_str(&format!(",{}={}", escape(tag), escape(value)));
+            }
+            let fields: Vec<_> = series.fields
+                .iter()
+                .map(|field| match field.kind {
+                    Kind::Counter => format!("{}={}i", escape(field.name), field.value as u64),
+                    Kind::Gauge => format!("{}={}", escape(field.name), field.value),
+                })
+                .collect();
+            line.push_str(&format!(" {} {}", fields.join(","), nanos));
+            line
+        })
+        .collect()
+}
+
+/// Escapes a measurement, tag or field name or a tag value.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == ',' || c == '=' || c == ' ' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
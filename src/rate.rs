@@ -0,0 +1,112 @@
+use std::{fmt, str, thread};
+use std::time::{Duration, Instant};
+
+/// How much bursting a pacer tolerates after being idle.
+const BURST: Duration = Duration::from_millis(10);
+
+/// A transmit rate limit, in packets or payload bits per second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rate {
+    Packets(f64),
+    Bits(f64),
+}
+
+impl Rate {
+    fn per_second(&self) -> f64 {
+        match *self {
+            Rate::Packets(pps) => pps,
+            Rate::Bits(bps) => bps,
+        }
+    }
+
+    fn cost(&self, len: usize) -> f64 {
+        match *self {
+            Rate::Packets(_) => 1.0,
+            Rate::Bits(_) => len as f64 * 8.0,
+        }
+    }
+}
+
+impl str::FromStr for Rate {
+    type Err = String;
+
+    /// Parses `500pps`, `64kbit`, `10mbit`, `1gbps` and the like. Bandwidth
+    /// units are decimal.
+    fn from_str(s: &str) -> Result<Rate, String> {
+        let invalid = || format!("invalid rate {}: expected e.g. 500pps, 64kbit or 10mbit", s);
+        let lower = s.to_ascii_lowercase();
+        let split = lower.find(|c: char| !(c.is_ascii_digit() || c == '.')).ok_or_else(invalid)?;
+        let value: f64 = lower[..split].parse().map_err(|_| invalid())?;
+        if value <= 0.0 || !value.is_finite() {
+            return Err(invalid());
+        }
+        match &lower[split..] {
+            "pps" => Ok(Rate::Packets(value)),
+            "bit" | "bps" => Ok(Rate::Bits(value)),
+            "kbit" | "kbps" => Ok(Rate::Bits(value * 1e3)),
+            "mbit" | "mbps" => Ok(Rate::Bits(value * 1e6)),
+            "gbit" | "gbps" => Ok(Rate::Bits(value * 1e9)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Rate::Packets(pps) => write!(f, "{}pps", pps),
+            Rate::Bits(bps) => write!(f, "{}bit", bps),
+        }
+    }
+}
+
+/// Token bucket that delays transmissions to stay within a `Rate`.
+#[derive(Debug)]
+pub struct Pacer {
+    rate: Rate,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Pacer {
+    pub fn new(rate: Rate) -> Pacer {
+        Pacer {
+            rate,
+            tokens: 0.0,
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, capacity: f64) {
+        let now = Instant::now();
+        let earned = now.duration_since(self.last).as_secs_f64() * self.rate.per_second();
+        self.tokens = (self.tokens + earned).min(capacity);
+        self.last = now;
+    }
+
+    /// Blocks until a packet of `len` payload bytes may be sent.
+    pub fn wait(&mut self, len: usize) {
+        let cost = self.rate.cost(len);
+        // always room for at least one packet, however large
+        let capacity = (self.rate.per_second() * BURST.as_secs_f64()).max(cost);
+        self.refill(capacity);
+        if self.tokens < cost {
+            thread::sleep(Duration::from_secs_f64((cost - self.tokens) / self.rate.per_second()));
+            self.refill(capacity);
+        }
+        self.tokens -= cost;
+    }
+
+    /// Takes the tokens for a packet of `len` bytes if they are available
+    /// now, without blocking.
+    pub fn try_wait(&mut self, len: usize) -> bool {
+        let cost = self.rate.cost(len);
+        let capacity = (self.rate.per_second() * BURST.as_secs_f64()).max(cost);
+        self.refill(capacity);
+        if self.tokens < cost {
+            return false;
+        }
+        self.tokens -= cost;
+        true
+    }
+}
@@ -0,0 +1,268 @@
+use std::{io, net, str};
+use std::io::prelude::*;
+
+use listen::Listener;
+use rtp::RtpHeader;
+
+/// udpxy's default port.
+pub const DEFAULT_HTTP_PORT: u16 = 4022;
+
+/// Most payload bytes held for a client that falls behind; datagrams
+/// beyond it are dropped rather than stalling the other clients.
+pub const MAX_BACKLOG: usize = 1 << 20;
+
+/// Longest request head accepted.
+const MAX_REQUEST: usize = 8192;
+
+/// Which way `bridge` forwards datagrams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Both,
+    /// From the group to the unicast peer.
+    ToUnicast,
+    /// From the unicast peer to the group.
+    ToMulticast,
+}
+
+impl Direction {
+    pub fn to_unicast(self) -> bool {
+        self != Direction::ToMulticast
+    }
+
+    pub fn to_multicast(self) -> bool {
+        self != Direction::ToUnicast
+    }
+}
+
+impl str::FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Direction, String> {
+        match s {
+            "both" => Ok(Direction::Both),
+            "to-unicast" => Ok(Direction::ToUnicast),
+            "to-multicast" => Ok(Direction::ToMulticast),
+            _ => Err(format!("unknown direction {}: expected both, to-unicast or to-multicast", s)),
+        }
+    }
+}
+
+/// A group requested over HTTP, as `/udp/239.1.1.1:1234` or
+/// `/rtp/[ff15::1]:1234`. The `rtp` form strips RTP headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+    pub group: net::SocketAddr,
+    pub rtp: bool,
+}
+
+impl str::FromStr for Target {
+    type Err = String;
+
+    fn from_str(path: &str) -> Result<Target, String> {
+        let path = path.split('?').next().unwrap_or_default();
+        let (rtp, addr) = if let Some(addr) = path.strip_prefix("/udp/") {
+            (false, addr)
+        } else if let Some(addr) = path.strip_prefix("/rtp/") {
+            (true, addr)
+        } else {
+            return Err(format!("unknown path {}: expected /udp/GROUP:PORT or /rtp/GROUP:PORT", path));
+        };
+        // udpxy also accepts 239.1.1.1%3A1234 and 239.1.1.1/1234
+        let addr = addr.trim_end_matches('/').replace("%3A", ":").replace("%3a", ":");
+        let group: net::SocketAddr = match addr.rfind('/') {
+            Some(slash) => format!("{}:{}", &addr[..slash], &addr[slash + 1..]).parse(),
+            None => addr.parse(),
+        }
+        .map_err(|_| format!("invalid group {}: expected e.g. 239.1.1.1:1234", addr))?;
+        if !group.ip().is_multicast() {
+            return Err(format!("{} is not a multicast address", group.ip()));
+        }
+        Ok(Target { group, rtp })
+    }
+}
+
+/// Reads a request head, returning the method and path.
+pub fn read_request<R: BufRead>(client: &mut R) -> io::Result<(String, String)> {
+    let mut line = String::new();
+    client.read_line(&mut line)?;
+    let mut fields = line.split_whitespace();
+    let (method, path) = match (fields.next(), fields.next()) {
+        (Some(method), Some(path)) => (method.to_owned(), path.to_owned()),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP request")),
+    };
+    // skip the headers
+    loop {
+        line.clear();
+        if client.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+    Ok((method, path))
+}
+
+/// Writes a complete response with a plain text body.
+pub fn respond<W: Write>(client: &mut W, status: &str, body: &str) -> io::Result<()> {
+    write!(client,
+           "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           status,
+           body.len() + 1,
+           body)?;
+    writeln!(client)
+}
+
+/// One HTTP client of the relay, driven by a `Poll` event loop rather
+/// than a thread of its own. Its socket is nonblocking; payloads wait in a
+/// backlog until the client can take them.
+pub struct Client {
+    stream: net::TcpStream,
+    pub peer: net::SocketAddr,
+    request: Vec<u8>,
+    backlog: Vec<u8>,
+    /// How much of `backlog` has been written.
+    written: usize,
+    /// Hang up once the backlog is written.
+    closing: bool,
+    listener: Option<Listener>,
+    rtp: bool,
+    /// Bytes written to the client, response head included.
+    total: u64,
+    head_len: u64,
+    /// Datagrams dropped because the client fell behind.
+    pub dropped: u64,
+}
+
+impl Client {
+    pub fn new(stream: net::TcpStream, peer: net::SocketAddr) -> io::Result<Client> {
+        stream.set_nonblocking(true)?;
+        Ok(Client {
+            stream,
+            peer,
+            request: Vec::new(),
+            backlog: Vec::new(),
+            written: 0,
+            closing: false,
+            listener: None,
+            rtp: false,
+            total: 0,
+            head_len: 0,
+            dropped: 0,
+        })
+    }
+
+    pub fn stream(&self) -> &net::TcpStream {
+        &self.stream
+    }
+
+    /// The group being streamed, once the request was accepted.
+    pub fn listener(&self) -> Option<&Listener> {
+        self.listener.as_ref()
+    }
+
+    /// Reads what the client sent, returning the method and path once the
+    /// request head is complete. Anything sent while streaming is ignored;
+    /// fails with `UnexpectedEof` when the client hangs up.
+    pub fn read(&mut self) -> io::Result<Option<(String, String)>> {
+        let mut buf = [0u8; 1024];
+        loop {
+            let len = match self.stream.read(&mut buf) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "client hung up")),
+                Ok(len) => len,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            if self.listener.is_some() || self.closing {
+                continue;
+            }
+            self.request.extend_from_slice(&buf[..len]);
+            if let Some(end) = self.request.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head = self.request[..end + 4].to_vec();
+                self.request.clear();
+                return read_request(&mut &head[..]).map(Some);
+            }
+            if self.request.len() > MAX_REQUEST {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "HTTP request head too long"));
+            }
+        }
+    }
+
+    /// Queues a complete response, hanging up once it is written.
+    pub fn respond(&mut self, status: &str, body: &str) -> io::Result<()> {
+        respond(&mut self.backlog, status, body)?;
+        self.closing = true;
+        Ok(())
+    }
+
+    /// Starts streaming the group's payloads as a progressive HTTP body,
+    /// stripping RTP headers if `rtp` is set.
+    pub fn start(&mut self, listener: Listener, rtp: bool) -> io::Result<()> {
+        const HEAD: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nConnection: close\r\n\r\n";
+        listener.socket().set_nonblocking(true)?;
+        self.backlog.extend_from_slice(HEAD);
+        self.head_len = HEAD.len() as u64;
+        self.listener = Some(listener);
+        self.rtp = rtp;
+        Ok(())
+    }
+
+    /// Moves the datagrams waiting on the group into the backlog, dropping
+    /// those that would grow it past `MAX_BACKLOG`.
+    pub fn relay(&mut self) -> io::Result<()> {
+        let listener = match self.listener {
+            Some(ref mut listener) => listener,
+            None => return Ok(()),
+        };
+        loop {
+            let datagram = match listener.recv() {
+                Ok(datagram) => datagram,
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            let payload = match RtpHeader::parse(&datagram.data) {
+                Some(header) if self.rtp => &datagram.data[header.payload_start..header.payload_end],
+                _ => &datagram.data[..],
+            };
+            if self.backlog.len() - self.written + payload.len() > MAX_BACKLOG {
+                self.dropped += 1;
+                continue;
+            }
+            self.backlog.extend_from_slice(payload);
+        }
+    }
+
+    /// Payload bytes written to the client.
+    pub fn sent(&self) -> u64 {
+        self.total.saturating_sub(self.head_len)
+    }
+
+    /// Whether there is a backlog to wait for the client to take.
+    pub fn wants_write(&self) -> bool {
+        self.written < self.backlog.len()
+    }
+
+    /// Writes as much of the backlog as the client takes, returning true
+    /// once a queued response has been written and the client is done.
+    pub fn flush(&mut self) -> io::Result<bool> {
+        while self.wants_write() {
+            match self.stream.write(&self.backlog[self.written..]) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "client stopped reading")),
+                Ok(len) => {
+                    self.written += len;
+                    self.total += len as u64;
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        if !self.wants_write() {
+            self.backlog.clear();
+            self.written = 0;
+        } else if self.written > MAX_BACKLOG {
+            // reclaim what was written rather than grow without bound
+            self.backlog.drain(..self.written);
+            self.written = 0;
+        }
+        Ok(self.closing && !self.wants_write())
+    }
+}
@@ -0,0 +1,150 @@
+use std::{io, net, thread};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use pcap::CapturedUdp;
+use rate::{Pacer, Rate};
+use socket::MulticastSocket;
+use sockopt;
+
+/// Iterator that resends captured multicast datagrams to a group, spaced
+/// like the original capture. Yields each datagram once it has been sent.
+pub struct Replayer<I> {
+    sock: net::UdpSocket,
+    packets: I,
+    speed: f64,
+    pacer: Option<Pacer>,
+    start: Option<(Instant, Duration)>,
+    /// Datagrams sent per system call, at most.
+    batch: usize,
+    /// A packet read ahead that was not yet due for the last batch.
+    held: Option<io::Result<CapturedUdp>>,
+    /// Packets of the last batch not yet yielded.
+    sent: VecDeque<CapturedUdp>,
+}
+
+pub fn replay<I>(config: &MulticastSocket, packets: I) -> io::Result<Replayer<I>>
+    where I: Iterator<Item = io::Result<CapturedUdp>>
+{
+    let sock = config.sender()?;
+    sock.connect(config.target()?)?;
+    Ok(Replayer {
+        sock,
+        packets,
+        speed: 1.0,
+        pacer: None,
+        start: None,
+        batch: 1,
+        held: None,
+        sent: VecDeque::new(),
+    })
+}
+
+impl<I> Replayer<I> {
+    /// Scales the original timing: 2.0 replays twice as fast.
+    pub fn speed(&mut self, speed: f64) -> &mut Replayer<I> {
+        self.speed = speed;
+        self
+    }
+
+    /// Caps the send rate, delaying packets beyond their captured timing
+    /// if necessary.
+    pub fn rate(&mut self, rate: Rate) -> &mut Replayer<I> {
+        self.pacer = Some(Pacer::new(rate));
+        self
+    }
+
+    /// Sends up to `size` datagrams per system call with `sendmmsg`, where
+    /// available. Only packets already due join a batch.
+    pub fn batch(&mut self, size: usize) -> &mut Replayer<I> {
+        self.batch = size.max(1);
+        self
+    }
+
+    /// Starts over with a fresh set of packets, restarting the clock.
+    pub fn rewind(&mut self, packets: I) {
+        self.packets = packets;
+        self.start = None;
+        self.held = None;
+    }
+
+    pub fn socket(&self) -> &net::UdpSocket {
+        &self.sock
+    }
+
+    fn send_time(&mut self, captured: Duration) -> Instant {
+        let (started, first) = *self.start.get_or_insert_with(|| (Instant::now(), captured));
+        // packets stamped before the first one go out immediately
+        started + captured.checked_sub(first).unwrap_or_default().div_f64(self.speed)
+    }
+
+    fn wait_for(&mut self, captured: Duration) {
+        if let Some(delay) = self.send_time(captured).checked_duration_since(Instant::now()) {
+            thread::sleep(delay);
+        }
+    }
+}
+
+impl<I: Iterator<Item = io::Result<CapturedUdp>>> Replayer<I> {
+    /// The next packet addressed to a multicast group.
+    fn next_packet(&mut self) -> Option<io::Result<CapturedUdp>> {
+        if let Some(held) = self.held.take() {
+            return Some(held);
+        }
+        loop {
+            match self.packets.next()? {
+                Ok(ref packet) if !packet.dst.ip().is_multicast() => continue,
+                packet => return Some(packet),
+            }
+        }
+    }
+
+    fn send_batch(&mut self, first: CapturedUdp) -> io::Result<CapturedUdp> {
+        let mut batch = vec![first];
+        while batch.len() < self.batch {
+            let packet = match self.next_packet() {
+                Some(Ok(packet)) => packet,
+                Some(Err(err)) => {
+                    self.held = Some(Err(err));
+                    break;
+                }
+                None => break,
+            };
+            let due = self.send_time(packet.time) <= Instant::now() &&
+                      self.pacer.as_mut().is_none_or(|pacer| pacer.try_wait(packet.payload.len()));
+            if !due {
+                self.held = Some(Ok(packet));
+                break;
+            }
+            batch.push(packet);
+        }
+        let payloads: Vec<&[u8]> = batch.iter().map(|packet| &packet.payload[..]).collect();
+        sockopt::send_mmsg(&self.sock, &payloads)?;
+        let mut batch = batch.into_iter();
+        let first = batch.next().expect("batch starts with a packet");
+        self.sent.extend(batch);
+        Ok(first)
+    }
+}
+
+impl<I: Iterator<Item = io::Result<CapturedUdp>>> Iterator for Replayer<I> {
+    type Item = io::Result<CapturedUdp>;
+
+    fn next(&mut self) -> Option<io::Result<CapturedUdp>> {
+        if let Some(packet) = self.sent.pop_front() {
+            return Some(Ok(packet));
+        }
+        let packet = match self.next_packet()? {
+            Ok(packet) => packet,
+            Err(err) => return Some(Err(err)),
+        };
+        self.wait_for(packet.time);
+        if let Some(ref mut pacer) = self.pacer {
+            pacer.wait(packet.payload.len());
+        }
+        if self.batch > 1 {
+            return Some(self.send_batch(packet));
+        }
+        Some(self.sock.send(&packet.payload).map(|_| packet))
+    }
+}
@@ -0,0 +1,116 @@
+use std::{fmt, io, net, str};
+use std::ffi::CString;
+use std::net::ToSocketAddrs;
+
+use libc;
+
+use iface::ScopedAddr;
+
+/// Which address family a name may resolve to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    Any,
+    V4,
+    V6,
+}
+
+impl Family {
+    fn allows(self, addr: &net::IpAddr) -> bool {
+        match self {
+            Family::Any => true,
+            Family::V4 => addr.is_ipv4(),
+            Family::V6 => addr.is_ipv6(),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Family::Any => "IP",
+            Family::V4 => "IPv4",
+            Family::V6 => "IPv6",
+        }
+    }
+}
+
+/// A group given as an address, optionally zoned, or as a DNS name, as
+/// some IPTV middleware publishes channels as A or AAAA records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Host {
+    Addr(ScopedAddr),
+    Name(String),
+}
+
+impl Host {
+    /// The group address, looking up a name with the system resolver for
+    /// an address of `family`.
+    pub fn resolve(&self, family: Family) -> io::Result<ScopedAddr> {
+        match *self {
+            Host::Addr(ref addr) => Ok(addr.clone()),
+            Host::Name(ref name) => group(name, family).map(ScopedAddr::from),
+        }
+    }
+}
+
+impl From<net::IpAddr> for Host {
+    fn from(addr: net::IpAddr) -> Host {
+        Host::Addr(addr.into())
+    }
+}
+
+impl str::FromStr for Host {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Host, String> {
+        if s.contains('%') || s.parse::<net::IpAddr>().is_ok() {
+            return s.parse().map(Host::Addr);
+        }
+        let valid = |label: &str| {
+            !label.is_empty() && !label.starts_with('-') &&
+            label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        };
+        if s.trim_end_matches('.').split('.').all(valid) {
+            Ok(Host::Name(s.to_owned()))
+        } else {
+            Err(format!("invalid address {}: expected an IP address or a host name", s))
+        }
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Host::Addr(ref addr) => fmt::Display::fmt(addr, f),
+            Host::Name(ref name) => f.write_str(name),
+        }
+    }
+}
+
+/// Resolves `name` to a multicast address of `family`, skipping any
+/// unicast addresses it also has.
+pub fn group(name: &str, family: Family) -> io::Result<net::IpAddr> {
+    let addrs: Vec<net::IpAddr> = (name, 0).to_socket_addrs()?
+        .map(|addr| addr.ip())
+        .filter(|addr| family.allows(addr))
+        .collect();
+    if let Some(&addr) = addrs.iter().find(|addr| addr.is_multicast()) {
+        return Ok(addr);
+    }
+    let msg = match addrs.first() {
+        Some(addr) => format!("{} resolves to {}, which is not a multicast group", name, addr),
+        None => format!("{} has no {} address", name, family.name()),
+    };
+    Err(io::Error::new(io::ErrorKind::NotFound, msg))
+}
+
+/// The UDP port of a service such as `ntp`, from /etc/services or
+/// wherever the system's name service keeps them.
+pub fn service_port(name: &str) -> Option<u16> {
+    let name = CString::new(name).ok()?;
+    let proto = b"udp\0".as_ptr() as *const libc::c_char;
+    let entry = unsafe { libc::getservbyname(name.as_ptr(), proto) };
+    if entry.is_null() {
+        return None;
+    }
+    // s_port is in network byte order
+    Some(u16::from_be(unsafe { (*entry).s_port } as u16))
+}
@@ -0,0 +1,227 @@
+use std::{fs, io, str, thread};
+use std::cell::RefCell;
+use std::ffi::OsString;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// How to compress each finished file of a rotated series.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    const ALL: [Compression; 2] = [Compression::Gzip, Compression::Zstd];
+
+    fn program(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// What the compressor appends to the file name.
+    fn suffix(self) -> &'static str {
+        match self {
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// Fails unless the compressor can be run, so that a missing one is
+    /// found at startup rather than at the first rotation.
+    pub fn check(self) -> io::Result<()> {
+        let status = Command::new(self.program())
+            .arg("--version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|err| io::Error::new(err.kind(), format!("can't run {}: {}", self.program(), err)))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("{} --version failed: {}", self.program(), status)))
+        }
+    }
+
+    /// Replaces `path` by its compressed form in the background, so the
+    /// next file isn't held up.
+    fn spawn(self, path: &Path) -> io::Result<()> {
+        let mut command = Command::new(self.program());
+        match self {
+            Compression::Gzip => command.arg("-f"),
+            Compression::Zstd => command.args(["-q", "-f", "--rm"]),
+        };
+        let mut child = command.arg(path).stdin(Stdio::null()).spawn()?;
+        // only to reap it; a failed compressor leaves the file as it was
+        thread::spawn(move || child.wait());
+        Ok(())
+    }
+}
+
+impl str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Compression, String> {
+        match s {
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!("unknown compression {}: expected gzip or zstd", s)),
+        }
+    }
+}
+
+/// When to start a new output file.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Rotation {
+    pub max_size: Option<u64>,
+    pub interval: Option<Duration>,
+    /// Compress each file once the next one is started.
+    pub compress: Option<Compression>,
+}
+
+impl Rotation {
+    pub fn enabled(&self) -> bool {
+        self.max_size.is_some() || self.interval.is_some()
+    }
+}
+
+/// The path of the `index`th file in a rotated series: `capture.pcapng`
+/// becomes `capture_00001.pcapng`.
+pub fn numbered_path(path: &Path, index: u32) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{:05}.{}", stem, index, ext.to_string_lossy()),
+        None => format!("{}_{:05}", stem, index),
+    };
+    path.with_file_name(name)
+}
+
+/// Whether `path` exists, or did before it was compressed.
+fn exists(path: &Path) -> bool {
+    path.exists() ||
+    Compression::ALL.iter().any(|compression| {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(compression.suffix());
+        Path::new(&name).exists()
+    })
+}
+
+/// A file that is replaced by a fresh, numbered file once it grows too
+/// large or too old.
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    index: u32,
+    file: io::BufWriter<fs::File>,
+    written: u64,
+    opened: Instant,
+}
+
+impl RotatingFile {
+    pub fn create<P: AsRef<Path>>(path: P, rotation: Rotation) -> io::Result<RotatingFile> {
+        let path = path.as_ref().to_path_buf();
+        let current = if rotation.enabled() { numbered_path(&path, 1) } else { path.clone() };
+        Ok(RotatingFile {
+            file: io::BufWriter::new(fs::File::create(&current)?),
+            path,
+            rotation,
+            index: 1,
+            written: 0,
+            opened: Instant::now(),
+        })
+    }
+
+    /// Like `create`, but adds to the file left by an earlier run, or to the
+    /// last of its numbered files when rotating, rather than replacing it.
+    pub fn append<P: AsRef<Path>>(path: P, rotation: Rotation) -> io::Result<RotatingFile> {
+        let path = path.as_ref().to_path_buf();
+        let mut index = 1;
+        while rotation.enabled() && exists(&numbered_path(&path, index + 1)) {
+            index += 1;
+        }
+        let current = if rotation.enabled() { numbered_path(&path, index) } else { path.clone() };
+        let file = fs::OpenOptions::new().create(true).append(true).open(&current)?;
+        Ok(RotatingFile {
+            written: file.metadata()?.len(),
+            file: io::BufWriter::new(file),
+            path,
+            rotation,
+            index,
+            // its age is unknown, so it gets a full interval
+            opened: Instant::now(),
+        })
+    }
+
+    /// The file currently being written.
+    pub fn current_path(&self) -> PathBuf {
+        if self.rotation.enabled() { numbered_path(&self.path, self.index) } else { self.path.clone() }
+    }
+
+    fn due(&self) -> bool {
+        self.written > 0 &&
+        (self.rotation.max_size.is_some_and(|max| self.written >= max) ||
+         self.rotation.interval.is_some_and(|interval| self.opened.elapsed() >= interval))
+    }
+
+    /// Starts the next file if the current one is due for rotation. Returns
+    /// the path of the finished file so callers can write headers to the
+    /// new one or post-process the old one.
+    pub fn rotate_if_due(&mut self) -> io::Result<Option<PathBuf>> {
+        if !self.due() {
+            return Ok(None);
+        }
+        self.file.flush()?;
+        let finished = self.current_path();
+        self.index += 1;
+        self.file = io::BufWriter::new(fs::File::create(self.current_path())?);
+        self.written = 0;
+        self.opened = Instant::now();
+        if let Some(compression) = self.rotation.compress {
+            compression.spawn(&finished)?;
+        }
+        Ok(Some(finished))
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.file.write(buf)?;
+        self.written += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// A `RotatingFile` that an output writes through while its owner decides
+/// when to rotate, so that a rotation never splits a record.
+#[derive(Clone)]
+pub struct SharedFile(Rc<RefCell<RotatingFile>>);
+
+impl SharedFile {
+    pub fn new(file: RotatingFile) -> SharedFile {
+        SharedFile(Rc::new(RefCell::new(file)))
+    }
+
+    pub fn rotate_if_due(&self) -> io::Result<Option<PathBuf>> {
+        self.0.borrow_mut().rotate_if_due()
+    }
+}
+
+impl Write for SharedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
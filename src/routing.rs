@@ -0,0 +1,568 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, SystemTime};
+use std::{fmt, io, net};
+
+use libc;
+
+use capture::{Capture, ETH_P_IP, ETH_P_IPV6};
+
+const IPPROTO_HOPOPTS: u8 = 0;
+const IPPROTO_UDP: u8 = 17;
+const IPPROTO_OSPF: u8 = 89;
+const IPPROTO_PIM: u8 = 103;
+const IPPROTO_VRRP: u8 = 112;
+
+const RIP_PORT: u16 = 520;
+const RIPNG_PORT: u16 = 521;
+const HSRP_PORT: u16 = 1985;
+const HSRP_V6_PORT: u16 = 2029;
+
+/// The routing or redundancy protocol and version a message belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Protocol {
+    Ospf(u8),
+    Rip(u8),
+    RipNg,
+    Pim,
+    Vrrp(u8),
+    Hsrp(u8),
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Protocol::Ospf(version) => write!(f, "OSPFv{}", version),
+            Protocol::Rip(version) => write!(f, "RIPv{}", version),
+            Protocol::RipNg => write!(f, "RIPng"),
+            Protocol::Pim => write!(f, "PIMv2"),
+            Protocol::Vrrp(version) => write!(f, "VRRPv{}", version),
+            Protocol::Hsrp(version) => write!(f, "HSRPv{}", version),
+        }
+    }
+}
+
+/// A route in a RIP or RIPng message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    pub prefix: net::IpAddr,
+    pub len: u8,
+    pub metric: u32,
+}
+
+/// What a control-plane message says, as far as who is talking goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// An OSPF hello, naming the router and the neighbours it hears.
+    OspfHello {
+        router_id: net::Ipv4Addr,
+        area: net::Ipv4Addr,
+        priority: u8,
+        hello: Duration,
+        dead: Duration,
+        dr: net::Ipv4Addr,
+        bdr: net::Ipv4Addr,
+        neighbors: Vec<net::Ipv4Addr>,
+    },
+    /// Any other OSPF packet, exchanged once routers are adjacent.
+    Ospf { router_id: net::Ipv4Addr, area: net::Ipv4Addr, kind: u8 },
+    Rip { response: bool, routes: Vec<Route> },
+    PimHello { holdtime: Duration, dr_priority: Option<u32>, generation_id: Option<u32> },
+    /// Any other PIM message, such as a join/prune or an assert.
+    Pim { kind: u8 },
+    /// A VRRP master advertising that it holds the virtual addresses.
+    Vrrp { vrid: u8, priority: u8, interval: Duration, addresses: Vec<net::IpAddr> },
+    Hsrp { opcode: u8, group: u16, state: u8, priority: u32, hello: Duration, hold: Duration, virtual_ip: net::IpAddr },
+}
+
+fn list<T: fmt::Display>(items: &[T]) -> String {
+    items.iter().map(|item| item.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn secs(duration: Duration) -> String {
+    format!("{} s", duration.as_secs_f64())
+}
+
+fn ospf_kind(kind: u8) -> String {
+    match kind {
+        2 => "database description".to_owned(),
+        3 => "link state request".to_owned(),
+        4 => "link state update".to_owned(),
+        5 => "link state ack".to_owned(),
+        other => format!("type {}", other),
+    }
+}
+
+fn pim_kind(kind: u8) -> String {
+    match kind {
+        1 => "register".to_owned(),
+        2 => "register-stop".to_owned(),
+        3 => "join/prune".to_owned(),
+        4 => "bootstrap".to_owned(),
+        5 => "assert".to_owned(),
+        8 => "candidate RP advertisement".to_owned(),
+        other => format!("type {}", other),
+    }
+}
+
+fn hsrp_state(state: u8) -> String {
+    match state {
+        0 => "initial".to_owned(),
+        1 => "learn".to_owned(),
+        2 => "listen".to_owned(),
+        4 => "speak".to_owned(),
+        8 => "standby".to_owned(),
+        16 => "active".to_owned(),
+        other => format!("state {}", other),
+    }
+}
+
+impl Message {
+    /// A line for the message, then one for each route a RIP message
+    /// carries.
+    pub fn describe(&self) -> Vec<String> {
+        match *self {
+            Message::OspfHello { router_id, area, priority, hello, dead, dr, bdr, ref neighbors } => {
+                let mut line = format!("hello router-id {} area {} priority {} hello {} dead {} DR {} BDR {}",
+                                       router_id, area, priority, secs(hello), secs(dead), dr, bdr);
+                if !neighbors.is_empty() {
+                    line.push_str(&format!(" neighbors {}", list(neighbors)));
+                }
+                vec![line]
+            }
+            Message::Ospf { router_id, area, kind } => {
+                vec![format!("{} router-id {} area {}", ospf_kind(kind), router_id, area)]
+            }
+            Message::Rip { response: false, ref routes } if routes.is_empty() => vec!["request".to_owned()],
+            Message::Rip { response, ref routes } => {
+                let mut lines = vec![format!("{} with {} routes", if response { "response" } else { "request" },
+                                             routes.len())];
+                lines.extend(routes.iter().map(|route| {
+                    format!("route {}/{} metric {}", route.prefix, route.len, route.metric)
+                }));
+                lines
+            }
+            Message::PimHello { holdtime, dr_priority, generation_id } => {
+                let mut line = format!("hello holdtime {}", secs(holdtime));
+                if let Some(priority) = dr_priority {
+                    line.push_str(&format!(" DR priority {}", priority));
+                }
+                if let Some(id) = generation_id {
+                    line.push_str(&format!(" generation {:#010x}", id));
+                }
+                vec![line]
+            }
+            Message::Pim { kind } => vec![pim_kind(kind)],
+            Message::Vrrp { vrid, priority, interval, ref addresses } => {
+                let role = match priority {
+                    0 => " (stepping down)",
+                    255 => " (address owner)",
+                    _ => "",
+                };
+                vec![format!("advertisement vrid {} priority {}{} every {} for {}",
+                             vrid, priority, role, secs(interval), list(addresses))]
+            }
+            Message::Hsrp { opcode, group, state, priority, hello, hold, virtual_ip } => {
+                let opcode = match opcode {
+                    0 => "hello".to_owned(),
+                    1 => "coup".to_owned(),
+                    2 => "resign".to_owned(),
+                    other => format!("opcode {}", other),
+                };
+                vec![format!("{} group {} {} priority {} virtual {} hello {} hold {}",
+                             opcode, group, hsrp_state(state), priority, virtual_ip, secs(hello), secs(hold))]
+            }
+        }
+    }
+}
+
+/// A control-plane message and the addresses it was sent between.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub src: net::IpAddr,
+    pub dst: net::IpAddr,
+    pub protocol: Protocol,
+    pub message: Message,
+}
+
+fn v4(bytes: &[u8]) -> net::Ipv4Addr {
+    net::Ipv4Addr::from(<[u8; 4]>::try_from(bytes).unwrap_or_default())
+}
+
+fn address(bytes: &[u8]) -> net::IpAddr {
+    match <[u8; 4]>::try_from(bytes) {
+        Ok(v4) => net::IpAddr::from(v4),
+        Err(_) => net::IpAddr::from(<[u8; 16]>::try_from(bytes).unwrap_or_default()),
+    }
+}
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    data.get(at..at + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    data.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+impl Packet {
+    /// Parses an IPv4 packet carrying a routing protocol, `None` if it
+    /// doesn't.
+    pub fn parse_v4(packet: &[u8]) -> Option<Packet> {
+        if packet.len() < 20 || packet[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = usize::from(packet[0] & 0x0f) * 4;
+        let total_len = usize::from(u16::from_be_bytes([packet[2], packet[3]])).min(packet.len());
+        let src = address(&packet[12..16]);
+        let dst = address(&packet[16..20]);
+        let (protocol, message) = parse(packet[9], packet.get(header_len..total_len)?, false)?;
+        Some(Packet { src, dst, protocol, message })
+    }
+
+    /// Parses an IPv6 packet carrying a routing protocol, `None` if it
+    /// doesn't.
+    pub fn parse_v6(packet: &[u8]) -> Option<Packet> {
+        if packet.len() < 40 || packet[0] >> 4 != 6 {
+            return None;
+        }
+        let src = address(&packet[8..24]);
+        let dst = address(&packet[24..40]);
+        let end = (40 + usize::from(u16::from_be_bytes([packet[4], packet[5]]))).min(packet.len());
+        let (mut next, mut offset) = (packet[6], 40);
+        while next == IPPROTO_HOPOPTS {
+            let header = packet.get(offset..offset + 2)?;
+            next = header[0];
+            offset += (usize::from(header[1]) + 1) * 8;
+        }
+        let (protocol, message) = parse(next, packet.get(offset..end)?, true)?;
+        Some(Packet { src, dst, protocol, message })
+    }
+}
+
+fn parse(protocol: u8, data: &[u8], ipv6: bool) -> Option<(Protocol, Message)> {
+    match protocol {
+        IPPROTO_OSPF => ospf(data),
+        IPPROTO_PIM => pim(data),
+        IPPROTO_VRRP => vrrp(data, ipv6),
+        IPPROTO_UDP => {
+            let port = u16_at(data, 2)?;
+            let data = data.get(8..)?;
+            match port {
+                RIP_PORT if !ipv6 => rip(data),
+                RIPNG_PORT if ipv6 => ripng(data),
+                HSRP_PORT if !ipv6 => hsrp(data),
+                HSRP_V6_PORT if ipv6 => hsrp(data),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn ospf(data: &[u8]) -> Option<(Protocol, Message)> {
+    let version = *data.first()?;
+    let kind = *data.get(1)?;
+    let (router_id, area) = (v4(data.get(4..8)?), v4(data.get(8..12)?));
+    let message = match (version, kind) {
+        (2, 1) => {
+            let hello = data.get(24..44)?;
+            Message::OspfHello {
+                router_id,
+                area,
+                priority: hello[7],
+                hello: Duration::from_secs(u64::from(u16_at(hello, 4)?)),
+                dead: Duration::from_secs(u64::from(u32_at(hello, 8)?)),
+                dr: v4(&hello[12..16]),
+                bdr: v4(&hello[16..20]),
+                neighbors: data[44..].chunks_exact(4).map(v4).collect(),
+            }
+        }
+        (3, 1) => {
+            let hello = data.get(16..36)?;
+            Message::OspfHello {
+                router_id,
+                area,
+                priority: hello[4],
+                hello: Duration::from_secs(u64::from(u16_at(hello, 8)?)),
+                dead: Duration::from_secs(u64::from(u16_at(hello, 10)?)),
+                dr: v4(&hello[12..16]),
+                bdr: v4(&hello[16..20]),
+                neighbors: data[36..].chunks_exact(4).map(v4).collect(),
+            }
+        }
+        (2, _) | (3, _) => Message::Ospf { router_id, area, kind },
+        _ => return None,
+    };
+    Some((Protocol::Ospf(version), message))
+}
+
+fn pim(data: &[u8]) -> Option<(Protocol, Message)> {
+    let first = *data.first()?;
+    if first >> 4 != 2 {
+        return None;
+    }
+    let kind = first & 0x0f;
+    if kind != 0 {
+        return Some((Protocol::Pim, Message::Pim { kind }));
+    }
+    let (mut holdtime, mut dr_priority, mut generation_id) = (None, None, None);
+    let mut options = data.get(4..)?;
+    while options.len() >= 4 {
+        let (option, len) = (u16_at(options, 0)?, usize::from(u16_at(options, 2)?));
+        let value = options.get(4..4 + len)?;
+        match option {
+            1 => holdtime = u16_at(value, 0),
+            19 => dr_priority = u32_at(value, 0),
+            20 => generation_id = u32_at(value, 0),
+            _ => {}
+        }
+        options = &options[4 + len..];
+    }
+    let holdtime = Duration::from_secs(u64::from(holdtime?));
+    Some((Protocol::Pim, Message::PimHello { holdtime, dr_priority, generation_id }))
+}
+
+fn vrrp(data: &[u8], ipv6: bool) -> Option<(Protocol, Message)> {
+    let header = data.get(..8)?;
+    let version = header[0] >> 4;
+    // advertisements are the only type there is
+    if header[0] & 0x0f != 1 {
+        return None;
+    }
+    let (vrid, priority, count) = (header[1], header[2], usize::from(header[3]));
+    let (interval, len) = match version {
+        2 if !ipv6 => (Duration::from_secs(u64::from(header[5])), 4),
+        3 => (Duration::from_millis(u64::from(u16::from_be_bytes([header[4], header[5]]) & 0x0fff) * 10),
+              if ipv6 { 16 } else { 4 }),
+        _ => return None,
+    };
+    let addresses = data.get(8..8 + count * len)?.chunks_exact(len).map(address).collect();
+    Some((Protocol::Vrrp(version), Message::Vrrp { vrid, priority, interval, addresses }))
+}
+
+fn rip(data: &[u8]) -> Option<(Protocol, Message)> {
+    let header = data.get(..4)?;
+    if header[1] != 2 {
+        return None;
+    }
+    let routes = data[4..]
+        .chunks_exact(20)
+        // authentication entries and the whole-table request aren't routes
+        .filter(|entry| u16_at(entry, 0) == Some(libc::AF_INET as u16) && !(header[0] == 1 && entry[4..8] == [0; 4]))
+        .map(|entry| {
+            Route {
+                prefix: address(&entry[4..8]),
+                len: u32_at(entry, 8).unwrap_or_default().count_ones() as u8,
+                metric: u32_at(entry, 16).unwrap_or_default(),
+            }
+        })
+        .collect();
+    Some((Protocol::Rip(2), Message::Rip { response: header[0] == 2, routes }))
+}
+
+fn ripng(data: &[u8]) -> Option<(Protocol, Message)> {
+    let header = data.get(..4)?;
+    if header[1] != 1 {
+        return None;
+    }
+    let routes = data[4..]
+        .chunks_exact(20)
+        // next hop entries, and the whole-table request, with metric 16
+        .filter(|entry| entry[19] != 0xff && !(header[0] == 1 && entry[..16] == [0; 16]))
+        .map(|entry| {
+            Route {
+                prefix: address(&entry[..16]),
+                len: entry[18],
+                metric: u32::from(entry[19]),
+            }
+        })
+        .collect();
+    Some((Protocol::RipNg, Message::Rip { response: header[0] == 2, routes }))
+}
+
+fn hsrp(data: &[u8]) -> Option<(Protocol, Message)> {
+    match *data.first()? {
+        0 => {
+            let data = data.get(..20)?;
+            Some((Protocol::Hsrp(1),
+                  Message::Hsrp {
+                      opcode: data[1],
+                      state: data[2],
+                      hello: Duration::from_secs(u64::from(data[3])),
+                      hold: Duration::from_secs(u64::from(data[4])),
+                      priority: u32::from(data[5]),
+                      group: u16::from(data[6]),
+                      virtual_ip: address(&data[16..20]),
+                  }))
+        }
+        // the group state TLV that leads every HSRPv2 packet
+        1 => {
+            let tlv = data.get(2..42)?;
+            if tlv[0] != 2 {
+                return None;
+            }
+            let virtual_ip = match tlv[3] {
+                6 => address(&tlv[24..40]),
+                _ => address(&tlv[24..28]),
+            };
+            Some((Protocol::Hsrp(2),
+                  Message::Hsrp {
+                      opcode: tlv[1],
+                      state: tlv[2],
+                      group: u16_at(tlv, 4)?,
+                      priority: u32_at(tlv, 12)?,
+                      hello: Duration::from_millis(u64::from(u32_at(tlv, 16)?)),
+                      hold: Duration::from_millis(u64::from(u32_at(tlv, 20)?)),
+                      virtual_ip,
+                  }))
+        }
+        _ => None,
+    }
+}
+
+/// A message seen by an `Observer`.
+#[derive(Debug, Clone)]
+pub struct Observed {
+    pub packet: Packet,
+    pub interface: u32,
+    /// Whether this host sent it.
+    pub outgoing: bool,
+    pub received: SystemTime,
+}
+
+/// Captures OSPF, RIP, PIM, VRRP and HSRP messages to link-local groups on
+/// one interface or all of them with a packet socket, which takes
+/// `CAP_NET_RAW`. This sees the protocols that aren't carried over UDP,
+/// and those that are without taking their ports from a routing daemon.
+#[derive(Debug)]
+pub struct Observer {
+    capture: Capture,
+}
+
+impl AsRawFd for Observer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.capture.as_raw_fd()
+    }
+}
+
+/// Keeps IPv4 packets to 224.0.0.0/24 and IPv6 packets to ff02::/16 that
+/// carry OSPF, PIM, VRRP or UDP; the UDP ports are left to `parse`.
+fn filter() -> [libc::sock_filter; 21] {
+    let op = |code: u32, jt: u8, jf: u8, k: u32| libc::sock_filter { code: code as u16, jt, jf, k };
+    let (ld, ldh, ldb, jeq, ret) = (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS,
+                                    libc::BPF_LD | libc::BPF_H | libc::BPF_ABS,
+                                    libc::BPF_LD | libc::BPF_B | libc::BPF_ABS,
+                                    libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                                    libc::BPF_RET | libc::BPF_K);
+    let (ospf, pim, vrrp, udp) = (u32::from(IPPROTO_OSPF), u32::from(IPPROTO_PIM), u32::from(IPPROTO_VRRP),
+                                  u32::from(IPPROTO_UDP));
+    [op(ld, 0, 0, (libc::SKF_AD_OFF + libc::SKF_AD_PROTOCOL) as u32),
+     op(jeq, 0, 9, u32::from(ETH_P_IP)),
+     op(ldh, 0, 0, 16),
+     op(jeq, 0, 16, 0xe000),
+     op(ldb, 0, 0, 18),
+     op(jeq, 0, 14, 0),
+     op(ldb, 0, 0, 9),
+     op(jeq, 11, 0, ospf),
+     op(jeq, 10, 0, pim),
+     op(jeq, 9, 0, vrrp),
+     op(jeq, 8, 9, udp),
+     op(jeq, 0, 8, u32::from(ETH_P_IPV6)),
+     op(ldh, 0, 0, 24),
+     op(jeq, 0, 6, 0xff02),
+     op(ldb, 0, 0, 6),
+     op(jeq, 3, 0, ospf),
+     op(jeq, 2, 0, pim),
+     op(jeq, 1, 0, vrrp),
+     op(jeq, 0, 1, udp),
+     op(ret, 0, 0, u32::from(u16::MAX)),
+     op(ret, 0, 0, 0)]
+}
+
+impl Observer {
+    /// Observes the interface with index `interface`, or all of them.
+    pub fn new(interface: Option<u32>) -> io::Result<Observer> {
+        Ok(Observer { capture: Capture::new(interface, &mut filter())? })
+    }
+
+    pub fn timeout(&mut self, timeout: Option<Duration>) -> io::Result<&mut Observer> {
+        self.capture.timeout(timeout)?;
+        Ok(self)
+    }
+
+    /// Waits for the next packet, `None` if it isn't a message that parses.
+    pub fn recv(&self) -> io::Result<Option<Observed>> {
+        let mut buf = [0u8; 65536];
+        let frame = self.capture.recv(&mut buf)?;
+        let received = SystemTime::now();
+        let packet = match frame.protocol {
+            ETH_P_IP => Packet::parse_v4(frame.data),
+            ETH_P_IPV6 => Packet::parse_v6(frame.data),
+            _ => None,
+        };
+        Ok(packet.map(|packet| {
+            Observed {
+                packet,
+                interface: frame.interface,
+                outgoing: frame.outgoing,
+                received,
+            }
+        }))
+    }
+}
+
+/// A router heard on a link, by the protocol it spoke.
+#[derive(Debug, Clone)]
+pub struct Speaker {
+    pub interface: u32,
+    pub protocol: Protocol,
+    pub src: net::IpAddr,
+    pub messages: u64,
+    pub first_heard: SystemTime,
+    /// The first line of the last message.
+    pub last: String,
+}
+
+/// Who has been talking on each link, and in what protocol.
+#[derive(Debug, Default)]
+pub struct Speakers {
+    speakers: BTreeMap<(u32, Protocol, net::IpAddr), Speaker>,
+}
+
+impl Speakers {
+    pub fn new() -> Speakers {
+        Speakers::default()
+    }
+
+    /// Counts a message, returning whether its sender is new.
+    pub fn record(&mut self, observed: &Observed) -> bool {
+        let packet = &observed.packet;
+        let key = (observed.interface, packet.protocol, packet.src);
+        let last = packet.message.describe().swap_remove(0);
+        match self.speakers.get_mut(&key) {
+            Some(speaker) => {
+                speaker.messages += 1;
+                speaker.last = last;
+                false
+            }
+            None => {
+                let speaker = Speaker {
+                    interface: observed.interface,
+                    protocol: packet.protocol,
+                    src: packet.src,
+                    messages: 1,
+                    first_heard: observed.received,
+                    last,
+                };
+                self.speakers.insert(key, speaker);
+                true
+            }
+        }
+    }
+
+    /// Every speaker, by interface, protocol and address.
+    pub fn speakers(&self) -> Vec<&Speaker> {
+        self.speakers.values().collect()
+    }
+}
@@ -0,0 +1,326 @@
+use std::{fmt, net};
+use std::collections::HashMap;
+use std::time::{Duration, UNIX_EPOCH};
+
+use listen::Datagram;
+
+/// Clock rate assumed for dynamic payload types.
+pub const DEFAULT_CLOCK_RATE: u32 = 90000;
+
+/// The fixed RTP header (RFC 3550) and where the payload lies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtpHeader {
+    pub marker: bool,
+    pub payload_type: u8,
+    pub seqnum: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    /// Offset of the payload, past CSRCs and any header extension.
+    pub payload_start: usize,
+    /// End of the payload, before any padding.
+    pub payload_end: usize,
+}
+
+impl RtpHeader {
+    pub fn parse(data: &[u8]) -> Option<RtpHeader> {
+        if data.len() < 12 || data[0] >> 6 != 2 {
+            return None;
+        }
+        let csrcs = usize::from(data[0] & 0x0f);
+        let mut start = 12 + csrcs * 4;
+        if data[0] & 0x10 != 0 {
+            let words = data.get(start + 2..start + 4)?;
+            start += 4 + usize::from(u16::from_be_bytes([words[0], words[1]])) * 4;
+        }
+        let mut end = data.len();
+        if data[0] & 0x20 != 0 {
+            end = end.checked_sub(usize::from(*data.last()?))?;
+        }
+        if start > end {
+            return None;
+        }
+        Some(RtpHeader {
+            marker: data[1] & 0x80 != 0,
+            payload_type: data[1] & 0x7f,
+            seqnum: u16::from_be_bytes([data[2], data[3]]),
+            timestamp: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            ssrc: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+            payload_start: start,
+            payload_end: end,
+        })
+    }
+}
+
+impl fmt::Display for RtpHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "ssrc {:08x} pt {} seq {} ts {}{}",
+               self.ssrc,
+               self.payload_type,
+               self.seqnum,
+               self.timestamp,
+               if self.marker { " marker" } else { "" })
+    }
+}
+
+/// The clock rate of a static payload type (RFC 3551).
+pub fn clock_rate(payload_type: u8) -> Option<u32> {
+    match payload_type {
+        0..=5 | 7..=9 | 12 | 13 | 15 | 18 => Some(8000),
+        6 => Some(16000),
+        10 | 11 => Some(44100),
+        16 => Some(11025),
+        17 => Some(22050),
+        14 | 25 | 26 | 28 | 31..=34 => Some(90000),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtpEvent {
+    /// `count` packets starting at sequence number `first` were skipped.
+    Gap { ssrc: u32, first: u16, count: u16 },
+    /// A packet arrived with a sequence number already passed.
+    Late { ssrc: u32, seqnum: u16 },
+}
+
+impl fmt::Display for RtpEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RtpEvent::Gap { ssrc, first, count: 1 } => write!(f, "ssrc {:08x}: missing seq {}", ssrc, first),
+            RtpEvent::Gap { ssrc, first, count } => {
+                write!(f,
+                       "ssrc {:08x}: missing {} packets (seq {}-{})",
+                       ssrc,
+                       count,
+                       first,
+                       first.wrapping_add(count - 1))
+            }
+            RtpEvent::Late { ssrc, seqnum } => write!(f, "ssrc {:08x}: late or duplicate seq {}", ssrc, seqnum),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Stream {
+    src: net::SocketAddr,
+    payload_type: u8,
+    clock_rate: f64,
+    packets: u64,
+    bytes: u64,
+    lost: u64,
+    late: u64,
+    last_seqnum: u16,
+    transit: Option<f64>,
+    jitter: f64,
+}
+
+/// Per-stream sequence gaps and interarrival jitter.
+#[derive(Debug, Clone, Copy)]
+pub struct RtpReport {
+    pub ssrc: u32,
+    pub src: net::SocketAddr,
+    pub payload_type: u8,
+    pub packets: u64,
+    pub bytes: u64,
+    pub lost: u64,
+    pub late: u64,
+    /// RFC 3550 interarrival jitter.
+    pub jitter: Duration,
+}
+
+impl fmt::Display for RtpReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "ssrc {:08x} from {}: pt {}, {} packets, {} payload bytes, lost {}, late {}, jitter {:.3} ms",
+               self.ssrc,
+               self.src,
+               self.payload_type,
+               self.packets,
+               self.bytes,
+               self.lost,
+               self.late,
+               self.jitter.as_secs_f64() * 1e3)
+    }
+}
+
+/// Follows the RTP streams in received datagrams, keyed by SSRC.
+#[derive(Debug)]
+pub struct RtpMonitor {
+    clock_rate: Option<u32>,
+    streams: HashMap<u32, Stream>,
+}
+
+impl Default for RtpMonitor {
+    fn default() -> RtpMonitor {
+        RtpMonitor::new()
+    }
+}
+
+impl RtpMonitor {
+    pub fn new() -> RtpMonitor {
+        RtpMonitor {
+            clock_rate: None,
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Timestamp clock rate for jitter, overriding the payload type's.
+    pub fn clock_rate(&mut self, rate: u32) -> &mut RtpMonitor {
+        self.clock_rate = Some(rate);
+        self
+    }
+
+    /// Records a datagram with an already parsed header.
+    pub fn record(&mut self, datagram: &Datagram, header: &RtpHeader) -> Option<RtpEvent> {
+        let rate = self.clock_rate.or_else(|| clock_rate(header.payload_type)).unwrap_or(DEFAULT_CLOCK_RATE);
+        let stream = self.streams.entry(header.ssrc).or_insert_with(|| {
+            Stream {
+                src: datagram.src,
+                payload_type: header.payload_type,
+                clock_rate: f64::from(rate),
+                packets: 0,
+                bytes: 0,
+                lost: 0,
+                late: 0,
+                last_seqnum: header.seqnum.wrapping_sub(1),
+                transit: None,
+                jitter: 0.0,
+            }
+        });
+        stream.packets += 1;
+        stream.bytes += (header.payload_end - header.payload_start) as u64;
+        // in timestamp units; the clock offset between sender and receiver
+        // cancels out
+        let arrival = datagram.received.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64() *
+                      stream.clock_rate;
+        let transit = arrival - f64::from(header.timestamp);
+        if let Some(previous) = stream.transit {
+            let mut d = (transit - previous).abs();
+            // timestamps wrap at 2^32
+            d = d.min((d - 4294967296.0).abs());
+            stream.jitter += (d - stream.jitter) / 16.0;
+        }
+        stream.transit = Some(transit);
+        let delta = header.seqnum.wrapping_sub(stream.last_seqnum);
+        if delta == 0 || delta >= 0x8000 {
+            stream.late += 1;
+            return Some(RtpEvent::Late { ssrc: header.ssrc, seqnum: header.seqnum });
+        }
+        stream.last_seqnum = header.seqnum;
+        if delta > 1 {
+            stream.lost += u64::from(delta - 1);
+            return Some(RtpEvent::Gap {
+                ssrc: header.ssrc,
+                first: header.seqnum.wrapping_sub(delta - 1),
+                count: delta - 1,
+            });
+        }
+        None
+    }
+
+    /// Every stream seen so far, ordered by SSRC.
+    pub fn reports(&self) -> Vec<RtpReport> {
+        let mut reports: Vec<_> = self.streams
+            .iter()
+            .map(|(&ssrc, stream)| {
+                RtpReport {
+                    ssrc,
+                    src: stream.src,
+                    payload_type: stream.payload_type,
+                    packets: stream.packets,
+                    bytes: stream.bytes,
+                    lost: stream.lost,
+                    late: stream.late,
+                    jitter: Duration::from_secs_f64(stream.jitter / stream.clock_rate),
+                }
+            })
+            .collect();
+        reports.sort_by_key(|report| report.ssrc);
+        reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A header with `first` as its first byte, then marker, payload type
+    /// 96, sequence number 513, timestamp 90000 and SSRC 0x12345678.
+    fn packet(first: u8) -> Vec<u8> {
+        vec![first, 0x80 | 96, 0x02, 0x01, 0x00, 0x01, 0x5f, 0x90, 0x12, 0x34, 0x56, 0x78]
+    }
+
+    #[test]
+    fn plain() {
+        let mut data = packet(0x80);
+        data.extend_from_slice(b"payload");
+        let header = RtpHeader::parse(&data).unwrap();
+        assert_eq!(header,
+                   RtpHeader {
+                       marker: true,
+                       payload_type: 96,
+                       seqnum: 513,
+                       timestamp: 90000,
+                       ssrc: 0x12345678,
+                       payload_start: 12,
+                       payload_end: 19,
+                   });
+        assert_eq!(header.to_string(), "ssrc 12345678 pt 96 seq 513 ts 90000 marker");
+        assert_eq!(RtpHeader::parse(&data[..11]), None);
+        // version 1
+        data[0] = 0x40;
+        assert_eq!(RtpHeader::parse(&data), None);
+    }
+
+    #[test]
+    fn csrcs() {
+        let mut data = packet(0x82);
+        data.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2]);
+        data.extend_from_slice(b"payload");
+        let header = RtpHeader::parse(&data).unwrap();
+        assert_eq!(&data[header.payload_start..header.payload_end], b"payload");
+        // 15 CSRCs, which aren't there
+        let mut data = packet(0x8f);
+        data.extend_from_slice(&[0; 12]);
+        assert_eq!(RtpHeader::parse(&data), None);
+    }
+
+    #[test]
+    fn extension() {
+        // one CSRC and an extension of two words
+        let mut data = packet(0x91);
+        data.extend_from_slice(&[0, 0, 0, 1]);
+        data.extend_from_slice(&[0xbe, 0xde, 0, 2, 1, 2, 3, 4, 5, 6, 7, 8]);
+        data.extend_from_slice(b"payload");
+        let header = RtpHeader::parse(&data).unwrap();
+        assert_eq!(header.payload_start, 28);
+        assert_eq!(&data[header.payload_start..header.payload_end], b"payload");
+        // an empty payload
+        assert_eq!(RtpHeader::parse(&data[..28]).map(|header| header.payload_end), Some(28));
+        // the extension runs past the end
+        assert_eq!(RtpHeader::parse(&data[..27]), None);
+        let mut data = packet(0x90);
+        data.extend_from_slice(&[0xbe, 0xde, 0xff, 0xff]);
+        assert_eq!(RtpHeader::parse(&data), None);
+        // no room for the extension's length
+        let mut data = packet(0x90);
+        data.extend_from_slice(&[0xbe, 0xde, 0]);
+        assert_eq!(RtpHeader::parse(&data), None);
+    }
+
+    #[test]
+    fn padding() {
+        let mut data = packet(0xa0);
+        data.extend_from_slice(b"payload");
+        data.extend_from_slice(&[0, 0, 3]);
+        let header = RtpHeader::parse(&data).unwrap();
+        assert_eq!(&data[header.payload_start..header.payload_end], b"payload");
+        // more padding than packet
+        *data.last_mut().unwrap() = 255;
+        assert_eq!(RtpHeader::parse(&data), None);
+        // padding reaching into the header
+        *data.last_mut().unwrap() = 11;
+        assert_eq!(RtpHeader::parse(&data), None);
+    }
+}
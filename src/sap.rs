@@ -0,0 +1,360 @@
+use std::{fmt, io, net, process, str, thread};
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
+
+use sdp::SessionDescription;
+use socket::MulticastSocket;
+
+/// MIME type of the only payload SAP is commonly used for.
+pub const SDP_MIME_TYPE: &str = "application/sdp";
+
+/// A session announcement protocol packet (RFC 2974).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SapPacket {
+    /// Deletes the session rather than announcing it.
+    pub deletion: bool,
+    pub encrypted: bool,
+    pub compressed: bool,
+    pub msg_id_hash: u16,
+    /// The announcing host.
+    pub origin: net::IpAddr,
+    pub payload_type: String,
+    pub payload: Vec<u8>,
+}
+
+impl SapPacket {
+    pub fn parse(data: &[u8]) -> Result<SapPacket, String> {
+        if data.len() < 4 {
+            return Err("SAP packet too short".to_owned());
+        }
+        let flags = data[0];
+        if flags >> 5 != 1 {
+            return Err(format!("unsupported SAP version {}", flags >> 5));
+        }
+        let ipv6 = flags & 0x10 != 0;
+        let auth_len = usize::from(data[1]) * 4;
+        let msg_id_hash = u16::from_be_bytes([data[2], data[3]]);
+        let origin_len = if ipv6 { 16 } else { 4 };
+        let header_len = 4 + origin_len + auth_len;
+        if data.len() < header_len {
+            return Err("SAP packet too short".to_owned());
+        }
+        let origin = if ipv6 {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&data[4..20]);
+            net::IpAddr::from(octets)
+        } else {
+            net::IpAddr::from([data[4], data[5], data[6], data[7]])
+        };
+        let mut payload = &data[header_len..];
+        // the payload type is optional; SDP without one starts with "v=0"
+        let mut payload_type = SDP_MIME_TYPE.to_owned();
+        if !payload.starts_with(b"v=0") {
+            if let Some(end) = payload.iter().position(|&b| b == 0) {
+                payload_type = String::from_utf8_lossy(&payload[..end]).into_owned();
+                payload = &payload[end + 1..];
+            }
+        }
+        Ok(SapPacket {
+            deletion: flags & 0x04 != 0,
+            encrypted: flags & 0x02 != 0,
+            compressed: flags & 0x01 != 0,
+            msg_id_hash,
+            origin,
+            payload_type,
+            payload: payload.to_vec(),
+        })
+    }
+
+    /// An announcement of `sdp`, or its deletion.
+    pub fn announce(origin: net::IpAddr, msg_id_hash: u16, sdp: &str, deletion: bool) -> SapPacket {
+        SapPacket {
+            deletion,
+            encrypted: false,
+            compressed: false,
+            msg_id_hash,
+            origin,
+            payload_type: SDP_MIME_TYPE.to_owned(),
+            payload: sdp.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut flags = 0x20;
+        if self.origin.is_ipv6() {
+            flags |= 0x10;
+        }
+        if self.deletion {
+            flags |= 0x04;
+        }
+        if self.encrypted {
+            flags |= 0x02;
+        }
+        if self.compressed {
+            flags |= 0x01;
+        }
+        let mut data = vec![flags, 0];
+        data.extend_from_slice(&self.msg_id_hash.to_be_bytes());
+        match self.origin {
+            net::IpAddr::V4(addr) => data.extend_from_slice(&addr.octets()),
+            net::IpAddr::V6(addr) => data.extend_from_slice(&addr.octets()),
+        }
+        data.extend_from_slice(self.payload_type.as_bytes());
+        data.push(0);
+        data.extend_from_slice(&self.payload);
+        data
+    }
+
+    /// The announced session description, if the payload is plain SDP.
+    pub fn sdp(&self) -> Result<SessionDescription, String> {
+        if self.encrypted || self.compressed {
+            return Err("encrypted or compressed SAP payloads are not supported".to_owned());
+        }
+        if self.payload_type != SDP_MIME_TYPE {
+            return Err(format!("unsupported SAP payload type {}", self.payload_type));
+        }
+        let text = str::from_utf8(&self.payload).map_err(|_| "SDP is not valid UTF-8".to_owned())?;
+        SessionDescription::parse(text)
+    }
+}
+
+/// An announced session as last seen.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub sdp: SessionDescription,
+    /// The raw description, for saving as an .sdp file.
+    pub text: String,
+    pub origin: net::IpAddr,
+    pub last_seen: SystemTime,
+}
+
+/// Column headings matching the `Display` of `Session`.
+pub const SESSION_HEADER: &str = "NAME                             GROUP                     PORT CODEC            SOURCE";
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let destination = self.sdp.destination();
+        let codec = self.sdp.media.first().and_then(|media| media.codec());
+        write!(f,
+               "{:<32} {:<24} {:>5} {:<16} {}",
+               self.sdp.name,
+               destination.map_or_else(|| "-".to_owned(), |addr| addr.ip().to_string()),
+               destination.map_or_else(|| "-".to_owned(), |addr| addr.port().to_string()),
+               codec.as_ref().map_or("-", String::as_str),
+               self.origin)
+    }
+}
+
+/// An M3U playlist of the sessions' streams, as understood by VLC.
+pub fn playlist(sessions: &[&Session]) -> String {
+    let mut m3u = String::from("#EXTM3U\n");
+    for session in sessions {
+        let destination = match session.sdp.destination() {
+            Some(destination) => destination,
+            None => continue,
+        };
+        let scheme = if session.sdp.media.first().is_some_and(|media| media.is_rtp()) { "rtp" } else { "udp" };
+        let host = match destination.ip() {
+            net::IpAddr::V4(addr) => addr.to_string(),
+            net::IpAddr::V6(addr) => format!("[{}]", addr),
+        };
+        m3u.push_str(&format!("#EXTINF:-1,{}\n{}://@{}:{}\n", session.sdp.name, scheme, host, destination.port()));
+    }
+    m3u
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    New,
+    Changed,
+    Deleted,
+}
+
+impl fmt::Display for SessionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match *self {
+            SessionEvent::New => "new",
+            SessionEvent::Changed => "changed",
+            SessionEvent::Deleted => "deleted",
+        })
+    }
+}
+
+/// Sessions currently announced, keyed by origin and message id hash.
+#[derive(Debug, Default)]
+pub struct SessionDirectory {
+    sessions: HashMap<(net::IpAddr, u16), Session>,
+}
+
+impl SessionDirectory {
+    pub fn new() -> SessionDirectory {
+        SessionDirectory::default()
+    }
+
+    /// Applies an announcement or deletion, returning what changed, if
+    /// anything, along with the session concerned.
+    pub fn update(&mut self, packet: &SapPacket) -> Result<Option<(SessionEvent, Session)>, String> {
+        let key = (packet.origin, packet.msg_id_hash);
+        if packet.deletion {
+            return Ok(self.sessions.remove(&key).map(|session| (SessionEvent::Deleted, session)));
+        }
+        let sdp = packet.sdp()?;
+        let session = Session {
+            text: String::from_utf8_lossy(&packet.payload).into_owned(),
+            sdp,
+            origin: packet.origin,
+            last_seen: SystemTime::now(),
+        };
+        let event = match self.sessions.insert(key, session.clone()) {
+            None => Some(SessionEvent::New),
+            Some(ref previous) if previous.sdp != session.sdp => Some(SessionEvent::Changed),
+            Some(_) => None,
+        };
+        Ok(event.map(|event| (event, session)))
+    }
+
+    /// Current sessions ordered by name.
+    pub fn sessions(&self) -> Vec<&Session> {
+        let mut sessions: Vec<_> = self.sessions.values().collect();
+        sessions.sort_by(|a, b| a.sdp.name.cmp(&b.sdp.name).then(a.origin.cmp(&b.origin)));
+        sessions
+    }
+}
+
+/// Repeats a SAP announcement in the background until dropped, then
+/// announces its deletion.
+pub struct Announcer {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Announcer {
+    /// Starts announcing on the SAP group `config` describes. `sdp` is
+    /// called with the address announcements are sent from, which becomes
+    /// the SAP origin.
+    pub fn start<F>(config: &MulticastSocket, interval: Duration, sdp: F) -> io::Result<Announcer>
+        where F: FnOnce(net::IpAddr) -> String
+    {
+        let sock = config.sender()?;
+        sock.connect(config.target()?)?;
+        let origin = sock.local_addr()?.ip();
+        let text = sdp(origin);
+        let msg_id_hash = (SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos() as u16) ^ (process::id() as u16);
+        let announcement = SapPacket::announce(origin, msg_id_hash, &text, false).encode();
+        let deletion = SapPacket::announce(origin, msg_id_hash, &text, true).encode();
+        sock.send(&announcement)?;
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                // a lost announcement is repeated at the next interval
+                let _ = sock.send(&announcement);
+            }
+            let _ = sock.send(&deletion);
+        });
+        Ok(Announcer {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Announcer {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdp::Announcement;
+
+    use super::*;
+
+    fn announcement(origin: net::IpAddr, group: net::SocketAddr) -> String {
+        Announcement {
+            name: "camera 1".to_owned(),
+            origin,
+            session_id: 42,
+            group,
+            ttl: 16,
+            payload_type: Some(33),
+        }.to_string()
+    }
+
+    #[test]
+    fn announce_round_trip() {
+        let origin: net::IpAddr = "192.0.2.1".parse().unwrap();
+        let sdp = announcement(origin, "239.1.2.3:5004".parse().unwrap());
+        let packet = SapPacket::announce(origin, 0x1234, &sdp, false);
+        let data = packet.encode();
+        assert_eq!(data[0], 0x20);
+        assert_eq!(SapPacket::parse(&data), Ok(packet.clone()));
+        let description = packet.sdp().unwrap();
+        assert_eq!(description.name, "camera 1");
+        assert_eq!(description.destination(), Some("239.1.2.3:5004".parse().unwrap()));
+        assert_eq!(description.media[0].codec(), Some("MP2T/90000".to_owned()));
+
+        let deletion = SapPacket::announce(origin, 0x1234, &sdp, true);
+        assert!(SapPacket::parse(&deletion.encode()).unwrap().deletion);
+    }
+
+    #[test]
+    fn ipv6_origin() {
+        let origin: net::IpAddr = "2001:db8::1".parse().unwrap();
+        let sdp = announcement(origin, "[ff0e::1:2]:5004".parse().unwrap());
+        let packet = SapPacket::announce(origin, 7, &sdp, false);
+        let data = packet.encode();
+        assert_eq!(data[0], 0x30);
+        let parsed = SapPacket::parse(&data).unwrap();
+        assert_eq!(parsed.origin, origin);
+        assert_eq!(parsed.sdp().unwrap().destination(), Some("[ff0e::1:2]:5004".parse().unwrap()));
+        // the flag says the origin is 16 bytes, so 4 don't do
+        let mut short = vec![0x30, 0, 0, 7, 192, 0, 2, 1];
+        short.extend_from_slice(b"v=0\r\n");
+        assert!(SapPacket::parse(&short).is_err());
+    }
+
+    #[test]
+    fn auth_past_the_end() {
+        let mut data = SapPacket::announce("192.0.2.1".parse().unwrap(), 1, "v=0\r\n", false).encode();
+        // 4 * 255 bytes of authentication data
+        data[1] = 255;
+        assert_eq!(SapPacket::parse(&data), Err("SAP packet too short".to_owned()));
+        // skipped over when it fits
+        data[1] = 1;
+        let mut data = data[..8].to_vec();
+        data.extend_from_slice(&[1, 2, 3, 4]);
+        data.extend_from_slice(b"v=0\r\n");
+        assert_eq!(SapPacket::parse(&data).unwrap().payload, b"v=0\r\n");
+        assert!(SapPacket::parse(&[0x20, 0, 0]).is_err());
+        assert!(SapPacket::parse(&[0x40, 0, 0, 0, 192, 0, 2, 1]).is_err());
+    }
+
+    #[test]
+    fn payload_type_optional() {
+        let header = [0x20, 0, 0, 1, 192, 0, 2, 1];
+        let mut data = header.to_vec();
+        data.extend_from_slice(b"v=0\r\ns=bare\r\n");
+        let packet = SapPacket::parse(&data).unwrap();
+        assert_eq!(packet.payload_type, SDP_MIME_TYPE);
+        assert_eq!(packet.sdp().unwrap().name, "bare");
+
+        let mut data = header.to_vec();
+        data.extend_from_slice(b"application/sdp\0v=0\r\ns=typed\r\n");
+        assert_eq!(SapPacket::parse(&data).unwrap().sdp().unwrap().name, "typed");
+
+        let mut data = header.to_vec();
+        data.extend_from_slice(b"text/plain\0hello");
+        let packet = SapPacket::parse(&data).unwrap();
+        assert_eq!(packet.payload_type, "text/plain");
+        assert_eq!(packet.payload, b"hello");
+        assert_eq!(packet.sdp(), Err("unsupported SAP payload type text/plain".to_owned()));
+    }
+}
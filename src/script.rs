@@ -0,0 +1,121 @@
+use std::{fmt, io, str};
+use std::io::prelude::*;
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use filter;
+use listen::Datagram;
+
+/// Runs `.wasm` programs, as `wasmtime run MODULE`.
+const WASM_RUNTIME: &str = "wasmtime";
+
+/// What a script decided about a datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    Drop,
+    /// Pass the datagram on with this payload instead.
+    Rewrite(Vec<u8>),
+    /// Pass the datagram on, logging this note about it.
+    Annotate(String),
+}
+
+impl str::FromStr for Verdict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Verdict, String> {
+        let (word, arg) = match s.split_once(' ') {
+            Some((word, arg)) => (word, Some(arg)),
+            None => (s, None),
+        };
+        match (word, arg) {
+            ("pass", None) => Ok(Verdict::Pass),
+            ("drop", None) => Ok(Verdict::Drop),
+            ("rewrite", None) => Ok(Verdict::Rewrite(Vec::new())),
+            ("rewrite", Some(hex)) => {
+                filter::parse_hex(hex)
+                    .map(Verdict::Rewrite)
+                    .ok_or_else(|| format!("invalid rewrite {}: expected hex bytes", hex))
+            }
+            ("note", Some(text)) => Ok(Verdict::Annotate(text.to_owned())),
+            _ => Err(format!("invalid answer from the script {:?}: expected pass, drop, rewrite HEX or note TEXT", s)),
+        }
+    }
+}
+
+/// A program that judges datagrams, run for as long as mccat runs. It gets
+/// one line per datagram on stdin, `SRC GROUP PAYLOAD` with the payload in
+/// hex, e.g. `192.0.2.1:40000 239.1.1.1:5000 68690a`, and must answer each
+/// with a line of its own: `pass`, `drop`, `rewrite HEX` or `note TEXT`.
+/// Being any executable, it can be written in whatever language suits; a
+/// WebAssembly module (`.wasm`) is run under the `wasmtime` WASI runtime.
+///
+/// A decoder gets the same lines and answers each with the decoded
+/// payload, e.g. a JSON object, as one line of text.
+pub struct Script {
+    child: Child,
+    stdin: io::BufWriter<ChildStdin>,
+    stdout: io::BufReader<ChildStdout>,
+    line: String,
+}
+
+impl Script {
+    pub fn spawn<P: AsRef<Path>>(path: P) -> io::Result<Script> {
+        let path = path.as_ref();
+        let (mut command, program) = if path.extension().is_some_and(|ext| ext == "wasm") {
+            let mut command = Command::new(WASM_RUNTIME);
+            command.arg("run").arg(path);
+            (command, format!("{} run {}", WASM_RUNTIME, path.display()))
+        } else {
+            (Command::new(path), path.display().to_string())
+        };
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(), format!("can't run {}: {}", program, err)))?;
+        let stdin = child.stdin.take().expect("piped stdin");
+        let stdout = child.stdout.take().expect("piped stdout");
+        Ok(Script {
+            child,
+            stdin: io::BufWriter::new(stdin),
+            stdout: io::BufReader::new(stdout),
+            line: String::new(),
+        })
+    }
+
+    /// Sends `datagram` to the program and waits for its answer.
+    fn ask(&mut self, datagram: &Datagram) -> io::Result<&str> {
+        let payload: String = datagram.data.iter().map(|b| format!("{:02x}", b)).collect();
+        writeln!(self.stdin, "{} {} {}", datagram.src, datagram.group, payload)?;
+        self.stdin.flush()?;
+        self.line.clear();
+        if self.stdout.read_line(&mut self.line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the program exited"));
+        }
+        Ok(self.line.trim_end_matches(['\r', '\n']))
+    }
+
+    /// Asks the script what to do with `datagram`.
+    pub fn judge(&mut self, datagram: &Datagram) -> io::Result<Verdict> {
+        self.ask(datagram)?.parse().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Asks the decoder for `datagram`'s payload, decoded.
+    pub fn decode(&mut self, datagram: &Datagram) -> io::Result<String> {
+        self.ask(datagram).map(str::to_owned)
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Script").field("pid", &self.child.id()).finish()
+    }
+}
+
+impl Drop for Script {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
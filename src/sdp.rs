@@ -0,0 +1,203 @@
+use std::{fmt, net};
+
+/// A media description (`m=` line) with the attributes that follow it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Media {
+    pub kind: String,
+    pub port: u16,
+    pub proto: String,
+    pub formats: Vec<String>,
+    /// `a=rtpmap:` encodings by payload type, e.g. ("96", "H264/90000").
+    pub rtpmap: Vec<(String, String)>,
+    /// A media-level `c=` address, overriding the session's.
+    pub connection: Option<net::IpAddr>,
+}
+
+impl Media {
+    /// The encoding of the first format, from `a=rtpmap` or the static RTP
+    /// payload types.
+    pub fn codec(&self) -> Option<String> {
+        let format = self.formats.first()?;
+        if let Some((_, encoding)) = self.rtpmap.iter().find(|&(pt, _)| pt == format) {
+            return Some(encoding.clone());
+        }
+        let name = match format.as_str() {
+            "0" => "PCMU/8000",
+            "3" => "GSM/8000",
+            "8" => "PCMA/8000",
+            "9" => "G722/8000",
+            "10" | "11" => "L16/44100",
+            "14" => "MPA/90000",
+            "26" => "JPEG/90000",
+            "31" => "H261/90000",
+            "32" => "MPV/90000",
+            "33" => "MP2T/90000",
+            "34" => "H263/90000",
+            _ => return Some(format.clone()),
+        };
+        Some(name.to_owned())
+    }
+
+    /// Whether the media is carried in RTP rather than raw UDP.
+    pub fn is_rtp(&self) -> bool {
+        self.proto.starts_with("RTP/")
+    }
+}
+
+/// The parts of a session description (RFC 4566) needed to find and label
+/// a stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionDescription {
+    /// The `o=` line, which identifies the session and its version.
+    pub origin: String,
+    /// The `s=` session name.
+    pub name: String,
+    /// The session-level `c=` address, without TTL or count.
+    pub connection: Option<net::IpAddr>,
+    pub media: Vec<Media>,
+}
+
+/// Parses the address out of `IN IP4 239.1.2.3/32`.
+fn parse_connection(value: &str) -> Option<net::IpAddr> {
+    let addr = value.split_whitespace().nth(2)?;
+    addr.split('/').next()?.parse().ok()
+}
+
+impl SessionDescription {
+    pub fn parse(text: &str) -> Result<SessionDescription, String> {
+        let mut sdp = SessionDescription::default();
+        let mut version = false;
+        for line in text.lines() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+            let bytes = line.as_bytes();
+            if bytes.len() < 2 || bytes[1] != b'=' {
+                return Err(format!("invalid SDP line {:?}", line));
+            }
+            let value = &line[2..];
+            match bytes[0] {
+                b'v' => version = true,
+                b'o' => sdp.origin = value.to_owned(),
+                b's' => sdp.name = value.to_owned(),
+                b'c' => {
+                    let addr = parse_connection(value);
+                    match sdp.media.last_mut() {
+                        Some(media) => media.connection = addr,
+                        None => sdp.connection = addr,
+                    }
+                }
+                b'm' => {
+                    let mut fields = value.split_whitespace();
+                    let kind = fields.next().unwrap_or_default().to_owned();
+                    // "port/count"
+                    let port = fields.next()
+                        .and_then(|port| port.split('/').next())
+                        .and_then(|port| port.parse().ok())
+                        .ok_or_else(|| format!("invalid SDP media line {:?}", line))?;
+                    let proto = fields.next().unwrap_or_default().to_owned();
+                    sdp.media.push(Media {
+                        kind,
+                        port,
+                        proto,
+                        formats: fields.map(str::to_owned).collect(),
+                        rtpmap: Vec::new(),
+                        connection: None,
+                    });
+                }
+                b'a' => {
+                    if let (Some(rtpmap), Some(media)) = (value.strip_prefix("rtpmap:"), sdp.media.last_mut()) {
+                        let mut fields = rtpmap.splitn(2, ' ');
+                        if let (Some(pt), Some(encoding)) = (fields.next(), fields.next()) {
+                            media.rtpmap.push((pt.to_owned(), encoding.trim().to_owned()));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !version {
+            return Err("SDP without a v= line".to_owned());
+        }
+        Ok(sdp)
+    }
+
+    /// Where the first media stream is sent.
+    pub fn destination(&self) -> Option<net::SocketAddr> {
+        let media = self.media.first()?;
+        let addr = media.connection.or(self.connection)?;
+        Some((addr, media.port).into())
+    }
+}
+
+/// A minimal description of one multicast stream, for announcing it.
+#[derive(Debug, Clone)]
+pub struct Announcement {
+    pub name: String,
+    /// The sender's address.
+    pub origin: net::IpAddr,
+    pub session_id: u64,
+    pub group: net::SocketAddr,
+    pub ttl: u8,
+    /// The RTP payload type, if the stream is RTP.
+    pub payload_type: Option<u8>,
+}
+
+impl fmt::Display for Announcement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let family = if self.origin.is_ipv4() { "IP4" } else { "IP6" };
+        write!(f, "v=0\r\n")?;
+        write!(f, "o=- {} 1 IN {} {}\r\n", self.session_id, family, self.origin)?;
+        write!(f, "s={}\r\n", self.name)?;
+        match self.group.ip() {
+            net::IpAddr::V4(addr) => write!(f, "c=IN IP4 {}/{}\r\n", addr, self.ttl)?,
+            net::IpAddr::V6(addr) => write!(f, "c=IN IP6 {}\r\n", addr)?,
+        }
+        write!(f, "t=0 0\r\n")?;
+        match self.payload_type {
+            Some(pt) => write!(f, "m=video {} RTP/AVP {}\r\n", self.group.port(), pt),
+            None => write!(f, "m=video {} udp mpeg\r\n", self.group.port()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn media_connection_overrides() {
+        let sdp = SessionDescription::parse("v=0\r\no=- 1 1 IN IP4 192.0.2.1\r\ns=two\r\nc=IN IP4 239.1.1.1/32\r\n\
+                                             m=audio 5004/2 RTP/AVP 96\r\na=rtpmap:96 opus/48000/2\r\n\
+                                             c=IN IP4 239.1.1.2/32\r\nm=video 5006 RTP/AVP 33\r\n")
+            .unwrap();
+        assert_eq!(sdp.name, "two");
+        assert_eq!(sdp.origin, "- 1 1 IN IP4 192.0.2.1");
+        assert_eq!(sdp.connection, Some("239.1.1.1".parse().unwrap()));
+        assert_eq!(sdp.destination(), Some("239.1.1.2:5004".parse().unwrap()));
+        assert_eq!(sdp.media[0].codec(), Some("opus/48000/2".to_owned()));
+        assert_eq!(sdp.media[1].codec(), Some("MP2T/90000".to_owned()));
+        assert!(sdp.media[1].is_rtp());
+    }
+
+    #[test]
+    fn missing_lines() {
+        // no c= line, so nowhere to listen
+        let sdp = SessionDescription::parse("v=0\ns=no address\nm=video 5004 udp mpeg\n").unwrap();
+        assert_eq!(sdp.connection, None);
+        assert_eq!(sdp.destination(), None);
+        assert!(!sdp.media[0].is_rtp());
+        // no m= line, so no port
+        let sdp = SessionDescription::parse("v=0\ns=no media\nc=IN IP4 239.1.1.1/32\n").unwrap();
+        assert!(sdp.media.is_empty());
+        assert_eq!(sdp.destination(), None);
+        // an address that doesn't parse
+        let sdp = SessionDescription::parse("v=0\nc=IN IP4 somewhere\nm=video 5004 udp mpeg\n").unwrap();
+        assert_eq!(sdp.destination(), None);
+
+        assert!(SessionDescription::parse("s=no version\n").is_err());
+        assert!(SessionDescription::parse("v=0\nm=video port udp\n").is_err());
+        assert!(SessionDescription::parse("v=0\nnot sdp\n").is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use std::{io, net, str, thread};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use std::io::prelude::*;
+
+use auth::Key;
+use cipher::{Cipher, Nonces};
+use compress::{self, Codec};
+use cycle::{self, Position};
+use fragment;
+use listen::DEFAULT_BUFFER_SIZE;
+use rate::{Pacer, Rate};
+use socket::MulticastSocket;
+use tag;
+
+/// How input is cut into datagrams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// One datagram per read, minus a trailing newline.
+    Read,
+    /// One datagram per line, without the newline.
+    Line,
+    /// Fixed-size chunks; the last one may be shorter.
+    Size(usize),
+    /// The whole input as a single datagram.
+    Whole,
+    /// One message per line, split into numbered fragments of at most this
+    /// many bytes for listen --frame mccat to put back together.
+    Mccat(usize),
+}
+
+impl str::FromStr for Framing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Framing, String> {
+        let invalid = || format!("unknown framing {}: expected line, size:N, whole or mccat[:N]", s);
+        match s {
+            "line" => Ok(Framing::Line),
+            "whole" => Ok(Framing::Whole),
+            "mccat" => Ok(Framing::Mccat(fragment::DEFAULT_FRAGMENT_SIZE)),
+            _ if s.starts_with("size:") => match s["size:".len()..].parse() {
+                Ok(size) if size > 0 => Ok(Framing::Size(size)),
+                _ => Err(invalid()),
+            },
+            _ if s.starts_with("mccat:") => match s["mccat:".len()..].parse() {
+                Ok(size) if size > fragment::HEADER_LEN => Ok(Framing::Mccat(size)),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The input's frames, sent over and over.
+#[derive(Debug)]
+struct Cycle {
+    interval: Duration,
+    /// Read in full before the first cycle.
+    frames: Option<Vec<Vec<u8>>>,
+    number: u32,
+    index: usize,
+    started: Instant,
+}
+
+/// Iterator that sends each frame read from `input` as one datagram,
+/// yielding the number of bytes sent.
+pub struct Sender<R> {
+    sock: net::UdpSocket,
+    /// Sockets on other interfaces that send every datagram as well.
+    also: Vec<net::UdpSocket>,
+    input: io::BufReader<R>,
+    framing: Framing,
+    pacer: Option<Pacer>,
+    codec: Option<Codec>,
+    key: Option<Key>,
+    cipher: Option<(Cipher, Nonces)>,
+    /// Header naming this sender, put in front of each datagram.
+    tag: Option<Vec<u8>>,
+    buf: Vec<u8>,
+    done: bool,
+    /// Fragments of the current message not yet sent, and the id of the
+    /// next message.
+    fragments: VecDeque<Vec<u8>>,
+    message_id: u32,
+    cycle: Option<Cycle>,
+}
+
+pub fn send<R: Read>(config: &MulticastSocket, input: R) -> io::Result<Sender<R>> {
+    let sock = config.sender()?;
+    sock.connect(config.target()?)?;
+    Ok(Sender {
+        sock,
+        also: Vec::new(),
+        input: io::BufReader::new(input),
+        framing: Framing::Read,
+        pacer: None,
+        codec: None,
+        key: None,
+        cipher: None,
+        tag: None,
+        buf: vec![0u8; DEFAULT_BUFFER_SIZE],
+        done: false,
+        fragments: VecDeque::new(),
+        message_id: 0,
+        cycle: None,
+    })
+}
+
+impl<R> Sender<R> {
+    /// Largest chunk read from the input, and thus the largest datagram sent
+    /// with the default framing.
+    pub fn buffer_size(&mut self, size: usize) -> &mut Sender<R> {
+        self.buf.resize(size, 0);
+        self
+    }
+
+    pub fn framing(&mut self, framing: Framing) -> &mut Sender<R> {
+        self.framing = framing;
+        self
+    }
+
+    /// Limits how fast datagrams are sent.
+    pub fn rate(&mut self, rate: Rate) -> &mut Sender<R> {
+        self.pacer = Some(Pacer::new(rate));
+        self
+    }
+
+    /// Compresses datagrams that get smaller for it, behind a header that
+    /// listen --decompress recognizes.
+    pub fn compress(&mut self, codec: Codec) -> &mut Sender<R> {
+        self.codec = Some(codec);
+        self
+    }
+
+    /// Appends an HMAC tag to each datagram, making it that much longer.
+    pub fn key(&mut self, key: Key) -> &mut Sender<R> {
+        self.key = Some(key);
+        self
+    }
+
+    /// Encrypts each datagram with `cipher`, making it `cipher::OVERHEAD`
+    /// bytes longer.
+    pub fn encrypt(&mut self, cipher: Cipher) -> io::Result<&mut Sender<R>> {
+        self.cipher = Some((cipher, Nonces::new()?));
+        Ok(self)
+    }
+
+    /// Puts a header naming this sender in front of each datagram, which
+    /// listen --by-tag counts and files datagrams by. It goes outside any
+    /// compression and encryption, so receivers can tell senders apart
+    /// without the secret, and is covered by the HMAC.
+    pub fn tag(&mut self, tag: &str) -> &mut Sender<R> {
+        self.tag = Some(tag::header(tag));
+        self
+    }
+
+    /// Reads the whole input, then sends it again and again, a cycle
+    /// starting every `interval` or as soon as the last one is done. Each
+    /// datagram starts with a `cycle::header` saying where in which cycle
+    /// it belongs, so receivers can tell where cycles begin.
+    pub fn carousel(&mut self, interval: Duration) -> &mut Sender<R> {
+        self.cycle = Some(Cycle {
+            interval,
+            frames: None,
+            number: 0,
+            index: 0,
+            started: Instant::now(),
+        });
+        self
+    }
+
+    /// Sends every datagram through a socket set up by `config` as well,
+    /// normally the same group on another interface, as over the redundant
+    /// paths of SMPTE 2022-7.
+    pub fn also_via(&mut self, config: &MulticastSocket) -> io::Result<&mut Sender<R>> {
+        let sock = config.sender()?;
+        sock.connect(config.target()?)?;
+        self.also.push(sock);
+        Ok(self)
+    }
+
+    pub fn socket(&self) -> &net::UdpSocket {
+        &self.sock
+    }
+
+    fn send(&self, datagram: &[u8]) -> io::Result<usize> {
+        for sock in &self.also {
+            sock.send(datagram)?;
+        }
+        self.sock.send(datagram)
+    }
+}
+
+impl<R: Read> Sender<R> {
+    /// Reads the next frame into `buf`, returning its length or `None` at
+    /// the end of input.
+    fn read_frame(&mut self) -> io::Result<Option<usize>> {
+        match self.framing {
+            Framing::Read => {
+                let len = self.input.read(&mut self.buf)?;
+                if len == 0 {
+                    return Ok(None);
+                }
+                if self.buf[len - 1] == b'\n' {
+                    // chomp
+                    return Ok(Some(len - 1));
+                }
+                Ok(Some(len))
+            }
+            Framing::Line => {
+                self.buf.clear();
+                if self.input.read_until(b'\n', &mut self.buf)? == 0 {
+                    return Ok(None);
+                }
+                if let Some(&b'\n') = self.buf.last() {
+                    self.buf.pop();
+                }
+                Ok(Some(self.buf.len()))
+            }
+            Framing::Size(size) => {
+                self.buf.clear();
+                (&mut self.input).take(size as u64).read_to_end(&mut self.buf)?;
+                Ok(if self.buf.is_empty() { None } else { Some(self.buf.len()) })
+            }
+            Framing::Whole => {
+                if self.done {
+                    return Ok(None);
+                }
+                self.done = true;
+                self.buf.clear();
+                self.input.read_to_end(&mut self.buf)?;
+                Ok(Some(self.buf.len()))
+            }
+            Framing::Mccat(size) => {
+                if self.fragments.is_empty() {
+                    let mut message = Vec::new();
+                    if self.input.read_until(b'\n', &mut message)? == 0 {
+                        return Ok(None);
+                    }
+                    if let Some(&b'\n') = message.last() {
+                        message.pop();
+                    }
+                    self.fragments = fragment::split(self.message_id, &message, size)?.into();
+                    self.message_id = self.message_id.wrapping_add(1);
+                }
+                self.buf = self.fragments.pop_front().expect("a fragment");
+                Ok(Some(self.buf.len()))
+            }
+        }
+    }
+
+    /// The next datagram's payload, put in `buf`, from the input or the
+    /// carousel.
+    fn next_frame(&mut self) -> io::Result<Option<usize>> {
+        if self.cycle.as_ref().is_some_and(|cycle| cycle.frames.is_none()) {
+            let mut frames = Vec::new();
+            while let Some(len) = self.read_frame()? {
+                frames.push(self.buf[..len].to_vec());
+            }
+            let cycle = self.cycle.as_mut().expect("carousel");
+            cycle.frames = Some(frames);
+            cycle.started = Instant::now();
+        }
+        let cycle = match self.cycle {
+            Some(ref mut cycle) => cycle,
+            None => return self.read_frame(),
+        };
+        let frames = cycle.frames.as_ref().expect("frames read");
+        if frames.is_empty() {
+            return Ok(None);
+        }
+        if cycle.index == frames.len() {
+            let due = cycle.started + cycle.interval;
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+            cycle.started = due.max(now);
+            cycle.number = cycle.number.wrapping_add(1);
+            cycle.index = 0;
+        }
+        let frame = &frames[cycle.index];
+        self.buf.clear();
+        self.buf.extend(cycle::header(Position {
+            cycle: cycle.number,
+            index: cycle.index as u32,
+            count: frames.len() as u32,
+        }));
+        self.buf.extend_from_slice(frame);
+        cycle.index += 1;
+        Ok(Some(self.buf.len()))
+    }
+}
+
+impl<R: Read> Iterator for Sender<R> {
+    type Item = io::Result<usize>;
+
+    fn next(&mut self) -> Option<io::Result<usize>> {
+        match self.next_frame() {
+            Ok(Some(len)) => {
+                if let Some(ref mut pacer) = self.pacer {
+                    pacer.wait(len);
+                }
+                if self.codec.is_none() && self.key.is_none() && self.cipher.is_none() && self.tag.is_none() {
+                    return Some(self.send(&self.buf[..len]));
+                }
+                let mut datagram = match self.codec {
+                    // before encrypting, after which nothing compresses
+                    Some(codec) => match compress::compress(codec, &self.buf[..len]) {
+                        Ok(datagram) => datagram,
+                        Err(err) => return Some(Err(io::Error::new(io::ErrorKind::InvalidInput, err))),
+                    },
+                    None => self.buf[..len].to_vec(),
+                };
+                if let Some((ref cipher, ref mut nonces)) = self.cipher {
+                    match nonces.generate() {
+                        Ok(nonce) => datagram = cipher.seal(&nonce, &datagram),
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+                if let Some(ref header) = self.tag {
+                    datagram.splice(..0, header.iter().cloned());
+                }
+                // the tag covers what is sent, so it can be checked before decrypting
+                if let Some(ref key) = self.key {
+                    key.sign(&mut datagram);
+                }
+                Some(self.send(&datagram))
+            }
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::{io, mem, ptr};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use libc;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handler(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn hangup_handler(_: libc::c_int) {
+    HANGUP.store(true, Ordering::SeqCst);
+}
+
+fn catch(signum: libc::c_int, handler: extern "C" fn(libc::c_int)) -> io::Result<()> {
+    let mut action: libc::sigaction = unsafe { mem::zeroed() };
+    action.sa_sigaction = handler as libc::sighandler_t;
+    unsafe { libc::sigemptyset(&mut action.sa_mask) };
+    if unsafe { libc::sigaction(signum, &action, ptr::null_mut()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Catches SIGINT and SIGTERM so the caller can shut down cleanly.
+///
+/// `SA_RESTART` is deliberately not set: blocking socket calls fail with
+/// `ErrorKind::Interrupted`, after which `interrupted()` tells the caller to
+/// stop.
+pub fn install() -> io::Result<()> {
+    for &signum in &[libc::SIGINT, libc::SIGTERM] {
+        catch(signum, handler)?;
+    }
+    Ok(())
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Catches SIGHUP, the conventional request to re-read configuration,
+/// rather than letting it end the process. Blocking calls fail with
+/// `ErrorKind::Interrupted` when it arrives, as with `install()`.
+pub fn install_hangup() -> io::Result<()> {
+    catch(libc::SIGHUP, hangup_handler)
+}
+
+/// Whether SIGHUP arrived since the last call.
+pub fn hangup() -> bool {
+    HANGUP.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,314 @@
+use std::{io, net};
+
+use filter::{FilterMode, SourceFilter};
+use iface;
+use sockopt;
+
+pub const DEFAULT_TTL: u8 = 1;
+
+/// Builder describing a multicast group endpoint and the socket options used
+/// to join it or send to it.
+#[derive(Debug, Clone)]
+pub struct MulticastSocket {
+    group: net::IpAddr,
+    port: u16,
+    interface: Option<String>,
+    source: Option<net::IpAddr>,
+    source_filter: Option<SourceFilter>,
+    ttl: u8,
+    tos: Option<u8>,
+    loopback: bool,
+    reuse: bool,
+    reuse_port: bool,
+    multicast_all: bool,
+    recv_buffer_size: Option<usize>,
+    send_buffer_size: Option<usize>,
+    hardware_timestamps: bool,
+}
+
+impl MulticastSocket {
+    pub fn new(group: net::IpAddr, port: u16) -> MulticastSocket {
+        MulticastSocket {
+            group,
+            port,
+            interface: None,
+            source: None,
+            source_filter: None,
+            ttl: DEFAULT_TTL,
+            tos: None,
+            loopback: true,
+            reuse: false,
+            reuse_port: false,
+            multicast_all: true,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            hardware_timestamps: false,
+        }
+    }
+
+    /// Interface to join on and send from, given as a name, index or address.
+    pub fn interface(&mut self, spec: &str) -> &mut MulticastSocket {
+        self.interface = Some(spec.to_owned());
+        self
+    }
+
+    /// Restricts the join to a single sender (source-specific multicast).
+    pub fn source(&mut self, source: net::IpAddr) -> &mut MulticastSocket {
+        self.source = Some(source);
+        self
+    }
+
+    /// Has the kernel drop traffic from senders the filter rejects, using
+    /// `MCAST_MSFILTER` on the membership. Only single-address entries can
+    /// be expressed this way.
+    pub fn source_filter(&mut self, filter: SourceFilter) -> &mut MulticastSocket {
+        self.source_filter = Some(filter);
+        self
+    }
+
+    pub fn ttl(&mut self, ttl: u8) -> &mut MulticastSocket {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Marks sent datagrams with this TOS / traffic class byte; the DSCP is
+    /// the upper six bits.
+    pub fn tos(&mut self, tos: u8) -> &mut MulticastSocket {
+        self.tos = Some(tos);
+        self
+    }
+
+    pub fn loopback(&mut self, loopback: bool) -> &mut MulticastSocket {
+        self.loopback = loopback;
+        self
+    }
+
+    /// Sets `SO_REUSEADDR` so other receivers can bind the same port.
+    pub fn reuse(&mut self, reuse: bool) -> &mut MulticastSocket {
+        self.reuse = reuse;
+        self
+    }
+
+    /// Sets `SO_REUSEPORT`, needed to share a port with receivers that use it
+    /// themselves.
+    pub fn reuse_port(&mut self, reuse_port: bool) -> &mut MulticastSocket {
+        self.reuse_port = reuse_port;
+        self
+    }
+
+    /// Whether the socket also receives traffic for groups joined by other
+    /// sockets bound to the same port (the Linux default). Turn this off
+    /// when several sockets share a port but each should only see its own
+    /// group.
+    pub fn multicast_all(&mut self, all: bool) -> &mut MulticastSocket {
+        self.multicast_all = all;
+        self
+    }
+
+    /// Requests an `SO_RCVBUF` size. The kernel may grant a different size;
+    /// see `recv_buffer_size()`.
+    pub fn recv_buffer_size(&mut self, size: usize) -> &mut MulticastSocket {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Requests an `SO_SNDBUF` size.
+    pub fn send_buffer_size(&mut self, size: usize) -> &mut MulticastSocket {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Stamps received datagrams with the time the NIC took them in rather
+    /// than the time the kernel did, for NICs that can, once hardware
+    /// stamping is enabled on the interface.
+    pub fn hardware_timestamps(&mut self, on: bool) -> &mut MulticastSocket {
+        self.hardware_timestamps = on;
+        self
+    }
+
+    pub fn addr(&self) -> net::SocketAddr {
+        (self.group, self.port).into()
+    }
+
+    /// The destination address for sending, scoped to the chosen interface
+    /// when the group is IPv6.
+    pub fn target(&self) -> io::Result<net::SocketAddr> {
+        match self.group {
+            net::IpAddr::V6(addr) if self.interface.is_some() => {
+                Ok(net::SocketAddrV6::new(addr, self.port, 0, self.interface_v6()?).into())
+            }
+            _ => Ok(self.addr()),
+        }
+    }
+
+    fn check(&self) -> io::Result<()> {
+        if !self.group.is_multicast() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("{} is not a multicast address", self.group)));
+        }
+        if let Some(source) = self.source {
+            if source.is_ipv4() != self.group.is_ipv4() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          format!("source {} and group {} are different address families",
+                                                  source, self.group)));
+            }
+        }
+        Ok(())
+    }
+
+    fn interface_v4(&self) -> io::Result<net::Ipv4Addr> {
+        match self.interface {
+            Some(ref spec) => iface::resolve_v4(spec),
+            None => Ok(net::Ipv4Addr::from(0)),
+        }
+    }
+
+    fn interface_v6(&self) -> io::Result<u32> {
+        match self.interface {
+            Some(ref spec) => iface::resolve_v6(spec),
+            None => Ok(0),
+        }
+    }
+
+    fn set_buffer_sizes(&self, sock: &net::UdpSocket) -> io::Result<()> {
+        if let Some(size) = self.recv_buffer_size {
+            sockopt::set_recv_buffer_size(sock, size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            sockopt::set_send_buffer_size(sock, size)?;
+        }
+        Ok(())
+    }
+
+    /// Binds the group port and joins the group.
+    pub fn join(&self) -> io::Result<net::UdpSocket> {
+        self.check()?;
+        let sock = match self.group {
+            net::IpAddr::V4(addr) => {
+                let ifaddr = self.interface_v4()?;
+                let sock = sockopt::bind_udp((net::Ipv4Addr::from(0), self.port).into(), self.reuse, self.reuse_port)?;
+                if !self.multicast_all {
+                    sockopt::set_multicast_all_v4(&sock, false)?;
+                }
+                if self.interface.is_some() {
+                    // for anything sent back to the group
+                    sockopt::set_multicast_if_v4(&sock, ifaddr)?;
+                }
+                sockopt::set_pktinfo_v4(&sock, true)?;
+                sockopt::set_recv_ttl_tos_v4(&sock, true)?;
+                sockopt::set_rxq_ovfl(&sock, true)?;
+                sockopt::set_recv_timestamps(&sock, true, self.hardware_timestamps)?;
+                self.set_buffer_sizes(&sock)?;
+                match self.source {
+                    Some(net::IpAddr::V4(source)) => sockopt::join_source_v4(&sock, addr, source, ifaddr)?,
+                    _ => sock.join_multicast_v4(&addr, &ifaddr)?,
+                }
+                sock
+            }
+            net::IpAddr::V6(addr) => {
+                let ifindex = self.interface_v6()?;
+                let sock = sockopt::bind_udp((net::Ipv6Addr::from([0u8; 16]), self.port).into(), self.reuse, self.reuse_port)?;
+                if !self.multicast_all {
+                    sockopt::set_multicast_all_v6(&sock, false)?;
+                }
+                if self.interface.is_some() {
+                    sockopt::set_multicast_if_v6(&sock, ifindex)?;
+                }
+                sockopt::set_recv_pktinfo_v6(&sock, true)?;
+                sockopt::set_recv_hoplimit_tclass_v6(&sock, true)?;
+                sockopt::set_rxq_ovfl(&sock, true)?;
+                sockopt::set_recv_timestamps(&sock, true, self.hardware_timestamps)?;
+                self.set_buffer_sizes(&sock)?;
+                match self.source {
+                    Some(net::IpAddr::V6(source)) => sockopt::join_source_v6(&sock, addr, source, ifindex)?,
+                    _ => sock.join_multicast_v6(&addr, ifindex)?,
+                }
+                sock
+            }
+        };
+        self.apply_source_filter(&sock)?;
+        Ok(sock)
+    }
+
+    fn apply_source_filter(&self, sock: &net::UdpSocket) -> io::Result<()> {
+        let filter = match self.source_filter {
+            Some(ref filter) if self.source.is_none() => filter,
+            _ => return Ok(()),
+        };
+        let (mode, sources) = filter.kernel_sources(&self.group)?;
+        let ifindex = self.interface_v6()?;
+        sockopt::set_source_filter(sock, self.group, ifindex, mode == FilterMode::Include, &sources)
+    }
+
+    /// Drops the membership `join` added to `sock`.
+    pub fn leave(&self, sock: &net::UdpSocket) -> io::Result<()> {
+        match self.group {
+            net::IpAddr::V4(addr) => {
+                let ifaddr = self.interface_v4()?;
+                match self.source {
+                    Some(net::IpAddr::V4(source)) => sockopt::leave_source_v4(sock, addr, source, ifaddr),
+                    _ => sock.leave_multicast_v4(&addr, &ifaddr),
+                }
+            }
+            net::IpAddr::V6(addr) => {
+                let ifindex = self.interface_v6()?;
+                match self.source {
+                    Some(net::IpAddr::V6(source)) => sockopt::leave_source_v6(sock, addr, source, ifindex),
+                    _ => sock.leave_multicast_v6(&addr, ifindex),
+                }
+            }
+        }
+    }
+
+    /// Binds an ephemeral port configured for sending to the group.
+    pub fn sender(&self) -> io::Result<net::UdpSocket> {
+        self.check()?;
+        let sock = match self.group {
+            net::IpAddr::V4(_) => {
+                let ifaddr = self.interface_v4()?;
+                let sock = sockopt::bind_udp((ifaddr, 0).into(), self.reuse, self.reuse_port)?;
+                if self.interface.is_some() {
+                    sockopt::set_multicast_if_v4(&sock, ifaddr)?;
+                }
+                sock.set_multicast_ttl_v4(self.ttl.into())?;
+                if let Some(tos) = self.tos {
+                    sockopt::set_tos_v4(&sock, tos)?;
+                }
+                sock.set_multicast_loop_v4(self.loopback)?;
+                self.set_buffer_sizes(&sock)?;
+                sock
+            }
+            net::IpAddr::V6(_) => {
+                let sock = sockopt::bind_udp((net::Ipv6Addr::from([0u8; 16]), 0).into(), self.reuse, self.reuse_port)?;
+                if self.interface.is_some() {
+                    sockopt::set_multicast_if_v6(&sock, self.interface_v6()?)?;
+                }
+                sockopt::set_multicast_hops_v6(&sock, self.ttl)?;
+                if let Some(tclass) = self.tos {
+                    sockopt::set_tclass_v6(&sock, tclass)?;
+                }
+                sock.set_multicast_loop_v6(self.loopback)?;
+                self.set_buffer_sizes(&sock)?;
+                sock
+            }
+        };
+        Ok(sock)
+    }
+}
+
+/// The `SO_RCVBUF` size granted by the kernel.
+pub fn recv_buffer_size(sock: &net::UdpSocket) -> io::Result<usize> {
+    sockopt::recv_buffer_size(sock)
+}
+
+/// The `SO_SNDBUF` size granted by the kernel.
+pub fn send_buffer_size(sock: &net::UdpSocket) -> io::Result<usize> {
+    sockopt::send_buffer_size(sock)
+}
+
+/// Datagrams the kernel dropped on the socket because its receive buffer
+/// was full, as listed in `/proc/net/udp`. Unlike `Datagram::drops`, this
+/// also counts drops after the last datagram received.
+pub fn kernel_drops(sock: &net::UdpSocket) -> io::Result<Option<u64>> {
+    sockopt::proc_drops(sock)
+}
@@ -0,0 +1,568 @@
+use std::{fs, io, mem, net, ptr};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libc;
+
+pub fn set<S: AsRawFd, T>(sock: &S, level: libc::c_int, name: libc::c_int, value: &T) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(sock.as_raw_fd(),
+                         level,
+                         name,
+                         value as *const T as *const libc::c_void,
+                         mem::size_of::<T>() as libc::socklen_t)
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn get<S: AsRawFd, T: Copy>(sock: &S, level: libc::c_int, name: libc::c_int) -> io::Result<T> {
+    let mut value: T = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<T>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(sock.as_raw_fd(),
+                         level,
+                         name,
+                         &mut value as *mut T as *mut libc::c_void,
+                         &mut len)
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// Sets a buffer size option, trying the privileged `*BUFFORCE` variant
+/// first so that root can exceed the sysctl maximum.
+fn set_buffer_size<S: AsRawFd>(sock: &S, force: libc::c_int, name: libc::c_int, size: usize) -> io::Result<()> {
+    let size = size.min(libc::c_int::MAX as usize) as libc::c_int;
+    set(sock, libc::SOL_SOCKET, force, &size).or_else(|_| set(sock, libc::SOL_SOCKET, name, &size))
+}
+
+pub fn set_recv_buffer_size<S: AsRawFd>(sock: &S, size: usize) -> io::Result<()> {
+    set_buffer_size(sock, libc::SO_RCVBUFFORCE, libc::SO_RCVBUF, size)
+}
+
+pub fn set_send_buffer_size<S: AsRawFd>(sock: &S, size: usize) -> io::Result<()> {
+    set_buffer_size(sock, libc::SO_SNDBUFFORCE, libc::SO_SNDBUF, size)
+}
+
+pub fn recv_buffer_size<S: AsRawFd>(sock: &S) -> io::Result<usize> {
+    get::<_, libc::c_int>(sock, libc::SOL_SOCKET, libc::SO_RCVBUF).map(|size| size as usize)
+}
+
+pub fn send_buffer_size<S: AsRawFd>(sock: &S) -> io::Result<usize> {
+    get::<_, libc::c_int>(sock, libc::SOL_SOCKET, libc::SO_SNDBUF).map(|size| size as usize)
+}
+
+pub fn in_addr(addr: net::Ipv4Addr) -> libc::in_addr {
+    libc::in_addr { s_addr: u32::from(addr).to_be() }
+}
+
+pub fn set_multicast_if_v4<S: AsRawFd>(sock: &S, addr: net::Ipv4Addr) -> io::Result<()> {
+    set(sock, libc::IPPROTO_IP, libc::IP_MULTICAST_IF, &in_addr(addr))
+}
+
+pub fn set_multicast_if_v6<S: AsRawFd>(sock: &S, index: u32) -> io::Result<()> {
+    set(sock, libc::IPPROTO_IPV6, libc::IPV6_MULTICAST_IF, &(index as libc::c_int))
+}
+
+pub fn set_multicast_all_v4<S: AsRawFd>(sock: &S, all: bool) -> io::Result<()> {
+    set(sock, libc::IPPROTO_IP, libc::IP_MULTICAST_ALL, &(all as libc::c_int))
+}
+
+pub fn set_multicast_all_v6<S: AsRawFd>(sock: &S, all: bool) -> io::Result<()> {
+    set(sock, libc::IPPROTO_IPV6, libc::IPV6_MULTICAST_ALL, &(all as libc::c_int))
+}
+
+pub fn set_multicast_hops_v6<S: AsRawFd>(sock: &S, hops: u8) -> io::Result<()> {
+    set(sock, libc::IPPROTO_IPV6, libc::IPV6_MULTICAST_HOPS, &libc::c_int::from(hops))
+}
+
+pub fn set_tos_v4<S: AsRawFd>(sock: &S, tos: u8) -> io::Result<()> {
+    set(sock, libc::IPPROTO_IP, libc::IP_TOS, &libc::c_int::from(tos))
+}
+
+pub fn set_tclass_v6<S: AsRawFd>(sock: &S, tclass: u8) -> io::Result<()> {
+    set(sock, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, &libc::c_int::from(tclass))
+}
+
+fn sockaddr_storage(addr: net::SocketAddr) -> libc::sockaddr_storage {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    match addr {
+        net::SocketAddr::V4(addr) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr = in_addr(*addr.ip());
+        }
+        net::SocketAddr::V6(addr) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            sin6.sin6_scope_id = addr.scope_id();
+        }
+    }
+    storage
+}
+
+pub fn join_source_v4<S: AsRawFd>(sock: &S,
+                                  group: net::Ipv4Addr,
+                                  source: net::Ipv4Addr,
+                                  interface: net::Ipv4Addr)
+                                  -> io::Result<()> {
+    let mreq = libc::ip_mreq_source {
+        imr_multiaddr: in_addr(group),
+        imr_interface: in_addr(interface),
+        imr_sourceaddr: in_addr(source),
+    };
+    set(sock, libc::IPPROTO_IP, libc::IP_ADD_SOURCE_MEMBERSHIP, &mreq)
+}
+
+pub fn join_source_v6<S: AsRawFd>(sock: &S,
+                                  group: net::Ipv6Addr,
+                                  source: net::Ipv6Addr,
+                                  interface: u32)
+                                  -> io::Result<()> {
+    let req = libc::group_source_req {
+        gsr_interface: interface,
+        gsr_group: sockaddr_storage((group, 0).into()),
+        gsr_source: sockaddr_storage((source, 0).into()),
+    };
+    set(sock, libc::IPPROTO_IPV6, libc::MCAST_JOIN_SOURCE_GROUP, &req)
+}
+
+pub fn leave_source_v4<S: AsRawFd>(sock: &S,
+                                   group: net::Ipv4Addr,
+                                   source: net::Ipv4Addr,
+                                   interface: net::Ipv4Addr)
+                                   -> io::Result<()> {
+    let mreq = libc::ip_mreq_source {
+        imr_multiaddr: in_addr(group),
+        imr_interface: in_addr(interface),
+        imr_sourceaddr: in_addr(source),
+    };
+    set(sock, libc::IPPROTO_IP, libc::IP_DROP_SOURCE_MEMBERSHIP, &mreq)
+}
+
+pub fn leave_source_v6<S: AsRawFd>(sock: &S,
+                                   group: net::Ipv6Addr,
+                                   source: net::Ipv6Addr,
+                                   interface: u32)
+                                   -> io::Result<()> {
+    let req = libc::group_source_req {
+        gsr_interface: interface,
+        gsr_group: sockaddr_storage((group, 0).into()),
+        gsr_source: sockaddr_storage((source, 0).into()),
+    };
+    set(sock, libc::IPPROTO_IPV6, libc::MCAST_LEAVE_SOURCE_GROUP, &req)
+}
+
+/// Fixed part of `struct group_filter`, which is followed by `numsrc`
+/// source addresses.
+#[repr(C)]
+struct GroupFilter {
+    interface: u32,
+    group: libc::sockaddr_storage,
+    fmode: u32,
+    numsrc: u32,
+}
+
+/// Replaces the membership's source list with `MCAST_MSFILTER`, accepting
+/// only `sources` in include mode or everything but them in exclude mode.
+pub fn set_source_filter<S: AsRawFd>(sock: &S,
+                                     group: net::IpAddr,
+                                     interface: u32,
+                                     include: bool,
+                                     sources: &[net::IpAddr])
+                                     -> io::Result<()> {
+    let header = GroupFilter {
+        interface,
+        group: sockaddr_storage((group, 0).into()),
+        fmode: if include { libc::MCAST_INCLUDE } else { libc::MCAST_EXCLUDE } as u32,
+        numsrc: sources.len() as u32,
+    };
+    let header_len = mem::size_of::<GroupFilter>();
+    let source_len = mem::size_of::<libc::sockaddr_storage>();
+    let mut buf = vec![0u8; header_len + sources.len() * source_len];
+    unsafe {
+        ptr::copy_nonoverlapping(&header as *const _ as *const u8, buf.as_mut_ptr(), header_len);
+        for (i, &source) in sources.iter().enumerate() {
+            let storage = sockaddr_storage((source, 0).into());
+            ptr::copy_nonoverlapping(&storage as *const _ as *const u8,
+                                     buf.as_mut_ptr().add(header_len + i * source_len),
+                                     source_len);
+        }
+    }
+    let level = if group.is_ipv4() { libc::IPPROTO_IP } else { libc::IPPROTO_IPV6 };
+    let ret = unsafe {
+        libc::setsockopt(sock.as_raw_fd(),
+                         level,
+                         libc::MCAST_MSFILTER,
+                         buf.as_ptr() as *const libc::c_void,
+                         buf.len() as libc::socklen_t)
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn to_socket_addr(storage: &libc::sockaddr_storage) -> io::Result<net::SocketAddr> {
+    match i32::from(storage.ss_family) {
+        libc::AF_INET => {
+            let sin = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+            let ip = net::Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+            Ok((ip, u16::from_be(sin.sin_port)).into())
+        }
+        libc::AF_INET6 => {
+            let sin6 = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+            let ip = net::Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+            Ok(net::SocketAddrV6::new(ip, u16::from_be(sin6.sin6_port), sin6.sin6_flowinfo, sin6.sin6_scope_id)
+                .into())
+        }
+        family => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected address family {}", family))),
+    }
+}
+
+/// What the kernel reported alongside a received datagram.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RecvInfo {
+    /// Destination address from the IP header.
+    pub dst: Option<net::IpAddr>,
+    /// Index of the interface the datagram arrived on.
+    pub ifindex: Option<u32>,
+    /// Remaining TTL / hop limit.
+    pub ttl: Option<u8>,
+    /// TOS / traffic class byte.
+    pub tos: Option<u8>,
+    /// Datagrams the socket has dropped so far for want of buffer space
+    /// (`SO_RXQ_OVFL`); only sent once any have been dropped.
+    pub drops: Option<u32>,
+    /// When the datagram was received, per the kernel.
+    pub timestamp: Option<SystemTime>,
+}
+
+pub fn set_pktinfo_v4<S: AsRawFd>(sock: &S, on: bool) -> io::Result<()> {
+    set(sock, libc::IPPROTO_IP, libc::IP_PKTINFO, &(on as libc::c_int))
+}
+
+pub fn set_recv_pktinfo_v6<S: AsRawFd>(sock: &S, on: bool) -> io::Result<()> {
+    set(sock, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO, &(on as libc::c_int))
+}
+
+/// Asks for the socket's running count of dropped datagrams alongside
+/// each received datagram.
+pub fn set_rxq_ovfl<S: AsRawFd>(sock: &S, on: bool) -> io::Result<()> {
+    set(sock, libc::SOL_SOCKET, libc::SO_RXQ_OVFL, &(on as libc::c_int))
+}
+
+/// Asks for kernel receive timestamps, taken as the datagram reached the
+/// network stack. With `hardware`, asks for the NIC's own stamps instead,
+/// which come from its clock rather than the system's and so only agree
+/// with it where that clock is kept in step, e.g. by phc2sys.
+pub fn set_recv_timestamps<S: AsRawFd>(sock: &S, on: bool, hardware: bool) -> io::Result<()> {
+    let flags = match (on, hardware) {
+        (false, _) => 0,
+        (true, false) => libc::SOF_TIMESTAMPING_RX_SOFTWARE | libc::SOF_TIMESTAMPING_SOFTWARE,
+        (true, true) => libc::SOF_TIMESTAMPING_RX_HARDWARE | libc::SOF_TIMESTAMPING_RAW_HARDWARE,
+    };
+    match set(sock, libc::SOL_SOCKET, libc::SO_TIMESTAMPING, &(flags as libc::c_int)) {
+        Ok(()) if on => Ok(()),
+        Err(err) if hardware => Err(err),
+        _ => set(sock, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, &(on as libc::c_int)),
+    }
+}
+
+fn system_time(ts: &libc::timespec) -> Option<SystemTime> {
+    if ts.tv_sec == 0 && ts.tv_nsec == 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+/// The drop count of a UDP socket from `/proc/net/udp` or `udp6`, for
+/// kernels that don't support `SO_RXQ_OVFL`. None if the socket isn't
+/// listed.
+pub fn proc_drops<S: AsRawFd>(sock: &S) -> io::Result<Option<u64>> {
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    if unsafe { libc::fstat(sock.as_raw_fd(), &mut stat) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let inode = stat.st_ino.to_string();
+    for table in &["/proc/net/udp", "/proc/net/udp6"] {
+        let text = match fs::read_to_string(table) {
+            Ok(text) => text,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        // sl local rem st tx:rx tr:when retrnsmt uid timeout inode ref pointer drops
+        for line in text.lines().skip(1) {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            if fields.len() >= 13 && fields[9] == inode {
+                return Ok(fields[12].parse().ok());
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Asks for the TTL and TOS of received IPv4 datagrams.
+pub fn set_recv_ttl_tos_v4<S: AsRawFd>(sock: &S, on: bool) -> io::Result<()> {
+    set(sock, libc::IPPROTO_IP, libc::IP_RECVTTL, &(on as libc::c_int))?;
+    set(sock, libc::IPPROTO_IP, libc::IP_RECVTOS, &(on as libc::c_int))
+}
+
+/// Asks for the hop limit and traffic class of received IPv6 datagrams.
+pub fn set_recv_hoplimit_tclass_v6<S: AsRawFd>(sock: &S, on: bool) -> io::Result<()> {
+    set(sock, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, &(on as libc::c_int))?;
+    set(sock, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, &(on as libc::c_int))
+}
+
+/// Ancillary data buffer for one message, in u64s for `cmsghdr` alignment.
+type Control = [u64; 32];
+
+/// Receives a datagram with `recvmsg`, returning its full length even if it
+/// was truncated to fit `buf`, its source, and any ancillary data enabled on
+/// the socket.
+pub fn recv_msg<S: AsRawFd>(sock: &S, buf: &mut [u8]) -> io::Result<(usize, net::SocketAddr, RecvInfo)> {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut control: Control = [0; 32];
+    let mut msg = msghdr(&mut storage, &mut iov, &mut control);
+    let ret = unsafe { libc::recvmsg(sock.as_raw_fd(), &mut msg, libc::MSG_TRUNC) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((ret as usize, to_socket_addr(&storage)?, recv_info(&msg)))
+}
+
+fn msghdr(storage: &mut libc::sockaddr_storage, iov: &mut libc::iovec, control: &mut Control) -> libc::msghdr {
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = storage as *mut _ as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(control) as _;
+    msg
+}
+
+fn recv_info(msg: &libc::msghdr) -> RecvInfo {
+    let mut info = RecvInfo::default();
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(msg) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+        let data = unsafe { libc::CMSG_DATA(cmsg) };
+        match (header.cmsg_level, header.cmsg_type) {
+            (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                let pktinfo = unsafe { (data as *const libc::in_pktinfo).read_unaligned() };
+                info.dst = Some(net::IpAddr::V4(net::Ipv4Addr::from(u32::from_be(pktinfo.ipi_addr.s_addr))));
+                info.ifindex = Some(pktinfo.ipi_ifindex as u32);
+            }
+            (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                let pktinfo = unsafe { (data as *const libc::in6_pktinfo).read_unaligned() };
+                info.dst = Some(net::IpAddr::V6(net::Ipv6Addr::from(pktinfo.ipi6_addr.s6_addr)));
+                info.ifindex = Some(pktinfo.ipi6_ifindex);
+            }
+            (libc::IPPROTO_IP, libc::IP_TTL) |
+            (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => {
+                info.ttl = Some(unsafe { (data as *const libc::c_int).read_unaligned() } as u8);
+            }
+            // a single byte, unlike the traffic class
+            (libc::IPPROTO_IP, libc::IP_TOS) => info.tos = Some(unsafe { *data }),
+            (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                info.tos = Some(unsafe { (data as *const libc::c_int).read_unaligned() } as u8);
+            }
+            (libc::SOL_SOCKET, libc::SO_RXQ_OVFL) => {
+                info.drops = Some(unsafe { (data as *const u32).read_unaligned() });
+            }
+            (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS) => {
+                info.timestamp = system_time(&unsafe { (data as *const libc::timespec).read_unaligned() });
+            }
+            // software, deprecated and raw hardware stamps, unset ones zero; the
+            // hardware one is only there if asked for instead of the software one
+            (libc::SOL_SOCKET, libc::SCM_TIMESTAMPING) => {
+                let stamps = unsafe { (data as *const [libc::timespec; 3]).read_unaligned() };
+                info.timestamp = system_time(&stamps[0]).or_else(|| system_time(&stamps[2]));
+            }
+            _ => {}
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(msg, cmsg) };
+    }
+    info
+}
+
+/// Receives up to one datagram per buffer in a single `recvmmsg` call,
+/// blocking only until the first arrives.
+#[cfg(target_os = "linux")]
+pub fn recv_mmsg<S: AsRawFd>(sock: &S, bufs: &mut [Vec<u8>]) -> io::Result<Vec<(usize, net::SocketAddr, RecvInfo)>> {
+    let mut storage: Vec<libc::sockaddr_storage> = bufs.iter().map(|_| unsafe { mem::zeroed() }).collect();
+    let mut iovs: Vec<_> = bufs.iter_mut()
+        .map(|buf| {
+            libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            }
+        })
+        .collect();
+    let mut controls: Vec<Control> = vec![[0; 32]; bufs.len()];
+    let mut msgs: Vec<_> = storage.iter_mut()
+        .zip(&mut iovs)
+        .zip(&mut controls)
+        .map(|((storage, iov), control)| {
+            libc::mmsghdr {
+                msg_hdr: msghdr(storage, iov, control),
+                msg_len: 0,
+            }
+        })
+        .collect();
+    let ret = unsafe {
+        libc::recvmmsg(sock.as_raw_fd(),
+                       msgs.as_mut_ptr(),
+                       msgs.len() as _,
+                       (libc::MSG_WAITFORONE | libc::MSG_TRUNC) as _,
+                       ptr::null_mut())
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    msgs[..ret as usize]
+        .iter()
+        .zip(&storage)
+        .map(|(msg, storage)| Ok((msg.msg_len as usize, to_socket_addr(storage)?, recv_info(&msg.msg_hdr))))
+        .collect()
+}
+
+/// Without `recvmmsg`, receives one datagram into the first buffer.
+#[cfg(not(target_os = "linux"))]
+pub fn recv_mmsg<S: AsRawFd>(sock: &S, bufs: &mut [Vec<u8>]) -> io::Result<Vec<(usize, net::SocketAddr, RecvInfo)>> {
+    Ok(vec![recv_msg(sock, &mut bufs[0])?])
+}
+
+/// Sends every buffer as a datagram on a connected socket, batching them
+/// into as few `sendmmsg` calls as the kernel allows.
+#[cfg(target_os = "linux")]
+pub fn send_mmsg<S: AsRawFd>(sock: &S, bufs: &[&[u8]]) -> io::Result<()> {
+    let mut iovs: Vec<_> = bufs.iter()
+        .map(|buf| {
+            libc::iovec {
+                iov_base: buf.as_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            }
+        })
+        .collect();
+    let mut msgs: Vec<_> = iovs.iter_mut()
+        .map(|iov| {
+            let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+            msg.msg_hdr.msg_iov = iov;
+            msg.msg_hdr.msg_iovlen = 1;
+            msg
+        })
+        .collect();
+    let mut sent = 0;
+    while sent < msgs.len() {
+        let ret = unsafe {
+            libc::sendmmsg(sock.as_raw_fd(), msgs[sent..].as_mut_ptr(), (msgs.len() - sent) as _, 0)
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        sent += ret as usize;
+    }
+    Ok(())
+}
+
+/// Without `sendmmsg`, sends the buffers one call at a time.
+#[cfg(not(target_os = "linux"))]
+pub fn send_mmsg<S: AsRawFd>(sock: &S, bufs: &[&[u8]]) -> io::Result<()> {
+    for buf in bufs {
+        let ret = unsafe { libc::send(sock.as_raw_fd(), buf.as_ptr() as *const libc::c_void, buf.len(), 0) };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn sockaddr_len(addr: &net::SocketAddr) -> libc::socklen_t {
+    match *addr {
+        net::SocketAddr::V4(_) => mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        net::SocketAddr::V6(_) => mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+    }
+}
+
+/// Creates a raw IP socket for `protocol`, which takes `CAP_NET_RAW`.
+pub fn raw_socket(family: libc::c_int, protocol: libc::c_int) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::socket(family, libc::SOCK_RAW | libc::SOCK_CLOEXEC, protocol) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+pub fn bind<S: AsRawFd>(sock: &S, addr: net::SocketAddr) -> io::Result<()> {
+    let storage = sockaddr_storage(addr);
+    let ret = unsafe {
+        libc::bind(sock.as_raw_fd(), &storage as *const _ as *const libc::sockaddr, sockaddr_len(&addr))
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn send_to<S: AsRawFd>(sock: &S, buf: &[u8], addr: net::SocketAddr) -> io::Result<usize> {
+    let storage = sockaddr_storage(addr);
+    let ret = unsafe {
+        libc::sendto(sock.as_raw_fd(),
+                     buf.as_ptr() as *const libc::c_void,
+                     buf.len(),
+                     0,
+                     &storage as *const _ as *const libc::sockaddr,
+                     sockaddr_len(&addr))
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret as usize)
+}
+
+/// Creates a UDP socket bound to `addr`, setting `SO_REUSEADDR` (and
+/// `SO_REUSEPORT`) before the bind if requested. std's `UdpSocket::bind`
+/// offers no such hook.
+pub fn bind_udp(addr: net::SocketAddr, reuse: bool, reuse_port: bool) -> io::Result<net::UdpSocket> {
+    let family = match addr {
+        net::SocketAddr::V4(_) => libc::AF_INET,
+        net::SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(family, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let sock = unsafe { net::UdpSocket::from_raw_fd(fd) };
+    if reuse {
+        set(&sock, libc::SOL_SOCKET, libc::SO_REUSEADDR, &(1 as libc::c_int))?;
+    }
+    if reuse_port {
+        set(&sock, libc::SOL_SOCKET, libc::SO_REUSEPORT, &(1 as libc::c_int))?;
+    }
+    if family == libc::AF_INET6 {
+        // IPv4 groups get their own sockets
+        set(&sock, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, &(1 as libc::c_int))?;
+    }
+    let storage = sockaddr_storage(addr);
+    let ret = unsafe {
+        libc::bind(fd, &storage as *const _ as *const libc::sockaddr, sockaddr_len(&addr))
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(sock)
+}
@@ -0,0 +1,108 @@
+use std::{fmt, net, str};
+
+use output::json_string;
+
+/// The multicast destination named in every SSDP request.
+pub const HOST_V4: &str = "239.255.255.250:1900";
+pub const HOST_V6: &str = "[ff02::c]:1900";
+
+/// Search target matching every device and service.
+pub const ALL: &str = "ssdp:all";
+
+/// The start line of an SSDP message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    Notify,
+    Search,
+    /// A reply to M-SEARCH, with its HTTP status code.
+    Response(u16),
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Kind::Notify => write!(f, "NOTIFY"),
+            Kind::Search => write!(f, "M-SEARCH"),
+            Kind::Response(status) => write!(f, "RESPONSE {}", status),
+        }
+    }
+}
+
+/// An HTTP-over-UDP message as used by SSDP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub kind: Kind,
+    /// Headers in the order sent, names as sent.
+    pub headers: Vec<(String, String)>,
+}
+
+/// An M-SEARCH request for `target`, answered within `mx` seconds.
+pub fn search(host: &str, target: &str, mx: u8) -> String {
+    format!("M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: {}\r\nST: {}\r\n\r\n",
+            host,
+            mx,
+            target)
+}
+
+impl Message {
+    pub fn parse(data: &[u8]) -> Result<Message, String> {
+        let text = str::from_utf8(data).map_err(|_| "SSDP message is not valid UTF-8".to_owned())?;
+        let mut lines = text.split('\n').map(|line| line.trim_end_matches('\r'));
+        let start = lines.next().unwrap_or_default();
+        let kind = if start.starts_with("NOTIFY ") {
+            Kind::Notify
+        } else if start.starts_with("M-SEARCH ") {
+            Kind::Search
+        } else if start.starts_with("HTTP/") {
+            let status = start.split_whitespace()
+                .nth(1)
+                .and_then(|status| status.parse().ok())
+                .ok_or_else(|| format!("invalid SSDP status line {:?}", start))?;
+            Kind::Response(status)
+        } else {
+            return Err(format!("not an SSDP message: {:?}", start));
+        };
+        let mut headers = Vec::new();
+        for line in lines {
+            if line.is_empty() {
+                break;
+            }
+            if let Some(colon) = line.find(':') {
+                headers.push((line[..colon].trim().to_owned(), line[colon + 1..].trim().to_owned()));
+            }
+        }
+        Ok(Message { kind, headers })
+    }
+
+    /// The value of a header, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// One JSON object with the sender, message kind and headers, the
+    /// header names lowercased.
+    pub fn to_json(&self, src: net::SocketAddr) -> String {
+        let mut json = format!("{{\"src\":{},\"src_port\":{},\"type\":{}",
+                               json_string(&src.ip().to_string()),
+                               src.port(),
+                               json_string(match self.kind {
+                                   Kind::Notify => "notify",
+                                   Kind::Search => "search",
+                                   Kind::Response(_) => "response",
+                               }));
+        if let Kind::Response(status) = self.kind {
+            json.push_str(&format!(",\"status\":{}", status));
+        }
+        json.push_str(",\"headers\":{");
+        let headers: Vec<_> = self.headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}", json_string(&name.to_ascii_lowercase()), json_string(value)))
+            .collect();
+        json.push_str(&headers.join(","));
+        json.push_str("}}");
+        json
+    }
+}
@@ -0,0 +1,380 @@
+use std::{fmt, net};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::{Duration, Instant, SystemTime};
+
+use listen::Datagram;
+use timestamp;
+
+/// Inter-arrival statistics over some period.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ArrivalReport {
+    pub period: Duration,
+    pub packets: u64,
+    pub bytes: u64,
+    pub min_gap: Duration,
+    pub max_gap: Duration,
+    pub avg_gap: Duration,
+    /// RFC 3550-style jitter: the smoothed variation between successive
+    /// inter-arrival gaps.
+    pub jitter: Duration,
+    /// Datagrams the kernel dropped because the receive buffer was full,
+    /// which is local loss rather than loss in the network.
+    pub kernel_drops: u64,
+}
+
+impl ArrivalReport {
+    pub fn packets_per_second(&self) -> f64 {
+        let secs = self.period.as_secs_f64();
+        if secs > 0.0 { self.packets as f64 / secs } else { 0.0 }
+    }
+
+    pub fn bytes_per_second(&self) -> f64 {
+        let secs = self.period.as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 / secs } else { 0.0 }
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1e3
+}
+
+impl fmt::Display for ArrivalReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{:.1} s: {} packets, {} bytes, {:.1} pps, {:.0} bytes/s ({:.3} Mbit/s), \
+                gap min/avg/max = {:.3}/{:.3}/{:.3} ms, jitter {:.3} ms",
+               self.period.as_secs_f64(),
+               self.packets,
+               self.bytes,
+               self.packets_per_second(),
+               self.bytes_per_second(),
+               self.bytes_per_second() * 8.0 / 1e6,
+               ms(self.min_gap),
+               ms(self.avg_gap),
+               ms(self.max_gap),
+               ms(self.jitter))?;
+        if self.kernel_drops > 0 {
+            write!(f, ", {} dropped by the kernel (receive buffer full)", self.kernel_drops)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counters {
+    start: Option<SystemTime>,
+    packets: u64,
+    bytes: u64,
+    gaps: u64,
+    gap_sum: Duration,
+    min_gap: Option<Duration>,
+    max_gap: Duration,
+}
+
+impl Counters {
+    fn record(&mut self, datagram: &Datagram, gap: Option<Duration>) {
+        self.start.get_or_insert(datagram.received);
+        self.packets += 1;
+        self.bytes += datagram.size as u64;
+        if let Some(gap) = gap {
+            self.gaps += 1;
+            self.gap_sum += gap;
+            self.min_gap = Some(self.min_gap.map_or(gap, |min| min.min(gap)));
+            self.max_gap = self.max_gap.max(gap);
+        }
+    }
+
+    fn report(&self, end: SystemTime, jitter: f64, kernel_drops: u64) -> ArrivalReport {
+        let period = self.start.and_then(|start| end.duration_since(start).ok()).unwrap_or_default();
+        ArrivalReport {
+            period,
+            packets: self.packets,
+            bytes: self.bytes,
+            min_gap: self.min_gap.unwrap_or_default(),
+            max_gap: self.max_gap,
+            avg_gap: if self.gaps > 0 { self.gap_sum / self.gaps as u32 } else { Duration::default() },
+            jitter: Duration::from_secs_f64(jitter),
+            kernel_drops,
+        }
+    }
+}
+
+/// Packet and byte rates, inter-arrival gaps and jitter of everything
+/// received, both in total and per reporting interval.
+#[derive(Debug)]
+pub struct ArrivalStats {
+    last: Option<SystemTime>,
+    last_gap: Option<Duration>,
+    jitter: f64,
+    total: Counters,
+    interval: Counters,
+    /// The latest drop count of each group's socket.
+    drops: HashMap<net::SocketAddr, u64>,
+    drops_at_interval: u64,
+}
+
+impl Default for ArrivalStats {
+    fn default() -> ArrivalStats {
+        ArrivalStats::new()
+    }
+}
+
+impl ArrivalStats {
+    pub fn new() -> ArrivalStats {
+        ArrivalStats {
+            last: None,
+            last_gap: None,
+            jitter: 0.0,
+            total: Counters::default(),
+            interval: Counters { start: Some(SystemTime::now()), ..Counters::default() },
+            drops: HashMap::new(),
+            drops_at_interval: 0,
+        }
+    }
+
+    pub fn record(&mut self, datagram: &Datagram) {
+        let gap = self.last.map(|last| datagram.received.duration_since(last).unwrap_or_default());
+        if let (Some(gap), Some(last_gap)) = (gap, self.last_gap) {
+            let variation = (gap.as_secs_f64() - last_gap.as_secs_f64()).abs();
+            self.jitter += (variation - self.jitter) / 16.0;
+        }
+        self.last = Some(datagram.received);
+        self.last_gap = gap;
+        self.total.record(datagram, gap);
+        self.interval.record(datagram, gap);
+        if let Some(drops) = datagram.drops {
+            self.kernel_drops(datagram.group, u64::from(drops));
+        }
+    }
+
+    /// Updates the drop count of a group's socket from elsewhere, such as
+    /// `kernel_drops()`, to catch drops since its last datagram.
+    pub fn kernel_drops(&mut self, group: net::SocketAddr, drops: u64) {
+        let count = self.drops.entry(group).or_insert(0);
+        *count = (*count).max(drops);
+    }
+
+    fn total_drops(&self) -> u64 {
+        self.drops.values().sum()
+    }
+
+    /// Reports on the time since the previous call and starts a new interval.
+    pub fn interval(&mut self) -> ArrivalReport {
+        let now = SystemTime::now();
+        let counters = self.interval;
+        self.interval = Counters { start: Some(now), ..Counters::default() };
+        let drops = self.total_drops();
+        let report = counters.report(now, self.jitter, drops - self.drops_at_interval);
+        self.drops_at_interval = drops;
+        report
+    }
+
+    /// Reports on everything since the first datagram.
+    pub fn summary(&self) -> ArrivalReport {
+        self.total.report(SystemTime::now(), self.jitter, self.total_drops())
+    }
+}
+
+/// Traffic seen during one rolling report interval.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RateReport {
+    pub period: Duration,
+    pub packets: u64,
+    pub bytes: u64,
+    /// Number of distinct senders.
+    pub sources: usize,
+}
+
+impl fmt::Display for RateReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.period.as_secs_f64().max(1e-9);
+        write!(f,
+               "{} packets, {} bytes, {:.1} pps, {:.3} Mbit/s, {} {}",
+               self.packets,
+               self.bytes,
+               self.packets as f64 / secs,
+               self.bytes as f64 * 8.0 / secs / 1e6,
+               self.sources,
+               if self.sources == 1 { "source" } else { "sources" })
+    }
+}
+
+/// Counts packets, bytes and senders between rolling reports.
+#[derive(Debug)]
+pub struct RateMeter {
+    start: Instant,
+    packets: u64,
+    bytes: u64,
+    sources: HashSet<net::SocketAddr>,
+}
+
+impl Default for RateMeter {
+    fn default() -> RateMeter {
+        RateMeter::new()
+    }
+}
+
+impl RateMeter {
+    pub fn new() -> RateMeter {
+        RateMeter {
+            start: Instant::now(),
+            packets: 0,
+            bytes: 0,
+            sources: HashSet::new(),
+        }
+    }
+
+    pub fn record(&mut self, datagram: &Datagram) {
+        self.packets += 1;
+        self.bytes += datagram.size as u64;
+        self.sources.insert(datagram.src);
+    }
+
+    /// Reports on the time since the previous report without starting over.
+    pub fn peek(&self) -> RateReport {
+        RateReport {
+            period: self.start.elapsed(),
+            packets: self.packets,
+            bytes: self.bytes,
+            sources: self.sources.len(),
+        }
+    }
+
+    /// Reports on the time since the previous report and starts over.
+    pub fn report(&mut self) -> RateReport {
+        let report = self.peek();
+        *self = RateMeter::new();
+        report
+    }
+}
+
+/// Totals for one sender.
+#[derive(Debug, Clone, Copy)]
+pub struct SourceStats {
+    pub src: net::SocketAddr,
+    pub packets: u64,
+    pub bytes: u64,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+impl SourceStats {
+    /// Average rate between the first and last datagram.
+    pub fn bits_per_second(&self) -> f64 {
+        let secs = self.last_seen.duration_since(self.first_seen).unwrap_or_default().as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 * 8.0 / secs } else { 0.0 }
+    }
+}
+
+impl fmt::Display for SourceStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{}: {} packets, {} bytes, {:.3} Mbit/s, last seen {}",
+               self.src,
+               self.packets,
+               self.bytes,
+               self.bits_per_second() / 1e6,
+               timestamp::iso8601(self.last_seen))
+    }
+}
+
+/// Counters per sender.
+#[derive(Debug, Default)]
+pub struct SourceTable {
+    sources: HashMap<net::SocketAddr, SourceStats>,
+}
+
+impl SourceTable {
+    pub fn new() -> SourceTable {
+        SourceTable::default()
+    }
+
+    pub fn record(&mut self, datagram: &Datagram) {
+        let stats = self.sources.entry(datagram.src).or_insert(SourceStats {
+            src: datagram.src,
+            packets: 0,
+            bytes: 0,
+            first_seen: datagram.received,
+            last_seen: datagram.received,
+        });
+        stats.packets += 1;
+        stats.bytes += datagram.size as u64;
+        stats.last_seen = datagram.received;
+    }
+
+    /// Senders ordered by bytes sent, busiest first.
+    pub fn top_talkers(&self) -> Vec<SourceStats> {
+        let mut sources: Vec<_> = self.sources.values().cloned().collect();
+        sources.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(b.packets.cmp(&a.packets)).then(a.src.cmp(&b.src)));
+        sources
+    }
+}
+
+/// Counters for one sender tag, or for untagged datagrams.
+#[derive(Debug, Clone)]
+pub struct TagStats {
+    pub tag: Option<String>,
+    pub packets: u64,
+    pub bytes: u64,
+    /// Where datagrams with the tag came from; several senders behind one
+    /// address tell apart only by their tags.
+    pub senders: BTreeSet<net::SocketAddr>,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+impl TagStats {
+    /// Average rate between the first and last datagram.
+    pub fn bits_per_second(&self) -> f64 {
+        let secs = self.last_seen.duration_since(self.first_seen).unwrap_or_default().as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 * 8.0 / secs } else { 0.0 }
+    }
+}
+
+impl fmt::Display for TagStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let senders: Vec<String> = self.senders.iter().map(ToString::to_string).collect();
+        write!(f,
+               "{}: {} packets, {} bytes, {:.3} Mbit/s from {}",
+               self.tag.as_deref().unwrap_or("untagged"),
+               self.packets,
+               self.bytes,
+               self.bits_per_second() / 1e6,
+               senders.join(", "))
+    }
+}
+
+/// Counters per sender tag, as `send --tag` and `generate --tag` put on
+/// datagrams.
+#[derive(Debug, Default)]
+pub struct TagTable {
+    tags: HashMap<Option<String>, TagStats>,
+}
+
+impl TagTable {
+    pub fn new() -> TagTable {
+        TagTable::default()
+    }
+
+    pub fn record(&mut self, datagram: &Datagram) {
+        let stats = self.tags.entry(datagram.tag.clone()).or_insert_with(|| TagStats {
+            tag: datagram.tag.clone(),
+            packets: 0,
+            bytes: 0,
+            senders: BTreeSet::new(),
+            first_seen: datagram.received,
+            last_seen: datagram.received,
+        });
+        stats.packets += 1;
+        stats.bytes += datagram.size as u64;
+        stats.senders.insert(datagram.src);
+        stats.last_seen = datagram.received;
+    }
+
+    /// Tags in order, with untagged datagrams last.
+    pub fn tags(&self) -> Vec<TagStats> {
+        let mut tags: Vec<_> = self.tags.values().cloned().collect();
+        tags.sort_by(|a, b| (a.tag.is_none(), &a.tag).cmp(&(b.tag.is_none(), &b.tag)));
+        tags
+    }
+}
@@ -0,0 +1,101 @@
+use std::{env, io, net, process};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+use libc;
+
+use event::Timer;
+use sockopt;
+
+/// The first file descriptor systemd passes, after stdin, stdout and stderr.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Whether an environment variable naming a process names this one.
+fn for_us(var: &str) -> bool {
+    env::var(var).ok().and_then(|pid| pid.parse::<u32>().ok()) == Some(process::id())
+}
+
+/// Sockets passed by systemd socket activation (`LISTEN_FDS`), in the
+/// order of the socket unit. Takes them only once, so children don't
+/// inherit them.
+pub fn listen_fds() -> Vec<RawFd> {
+    if !for_us("LISTEN_PID") {
+        return Vec::new();
+    }
+    let count = env::var("LISTEN_FDS").ok().and_then(|count| count.parse::<RawFd>().ok()).unwrap_or(0);
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    let fds: Vec<_> = (LISTEN_FDS_START..LISTEN_FDS_START + count).collect();
+    for &fd in &fds {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    fds
+}
+
+fn socket_type(fd: RawFd) -> io::Result<libc::c_int> {
+    sockopt::get(&fd, libc::SOL_SOCKET, libc::SO_TYPE)
+}
+
+/// The first stream socket passed by socket activation, if any, to serve
+/// on instead of binding one.
+pub fn tcp_listener(fds: &[RawFd]) -> io::Result<Option<net::TcpListener>> {
+    for &fd in fds {
+        if socket_type(fd)? == libc::SOCK_STREAM {
+            return Ok(Some(unsafe { net::TcpListener::from_raw_fd(fd) }));
+        }
+    }
+    Ok(None)
+}
+
+/// The first datagram socket passed by socket activation, if any.
+pub fn udp_socket(fds: &[RawFd]) -> io::Result<Option<net::UdpSocket>> {
+    for &fd in fds {
+        if socket_type(fd)? == libc::SOCK_DGRAM {
+            return Ok(Some(unsafe { net::UdpSocket::from_raw_fd(fd) }));
+        }
+    }
+    Ok(None)
+}
+
+/// Tells systemd about a state change, e.g. `READY=1`, as `sd_notify`
+/// does. Does nothing when not run by systemd with a notify socket.
+pub fn notify(state: &str) -> io::Result<()> {
+    let path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return Ok(()),
+    };
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(&path)?,
+    };
+    let sock = UnixDatagram::unbound()?;
+    sock.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// Keeps the systemd watchdog (`WatchdogSec=`) from restarting us while
+/// the event loop is alive, pinging at half the timeout.
+#[derive(Debug)]
+pub struct Watchdog {
+    timer: Option<Timer>,
+}
+
+impl Watchdog {
+    /// A watchdog per `WATCHDOG_USEC`, inactive when systemd set none.
+    pub fn from_env() -> Watchdog {
+        let usec = env::var("WATCHDOG_USEC").ok().and_then(|usec| usec.parse::<u64>().ok());
+        let ours = env::var("WATCHDOG_PID").is_err() || for_us("WATCHDOG_PID");
+        Watchdog {
+            timer: usec.filter(|&usec| usec > 0 && ours).map(|usec| Timer::every(Duration::from_micros(usec / 2))),
+        }
+    }
+
+    /// Whether to send `WATCHDOG=1` now; ask on every turn of the event
+    /// loop.
+    pub fn due(&mut self) -> bool {
+        self.timer.as_mut().is_some_and(Timer::fired)
+    }
+}
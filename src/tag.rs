@@ -0,0 +1,34 @@
+use std::str;
+
+/// Marks a tagged datagram, followed by the length of the tag and the tag.
+const MAGIC: &[u8] = b"MCT";
+pub const MAX_LEN: usize = 32;
+
+/// Whether `tag` can name a sender: 1 to `MAX_LEN` letters, digits, dots,
+/// dashes or underscores, so that it can also name a file.
+pub fn valid(tag: &str) -> bool {
+    (1..=MAX_LEN).contains(&tag.len()) &&
+    tag.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'-' || b == b'_')
+}
+
+/// The header `tag` puts in front of a payload.
+pub fn header(tag: &str) -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    header.push(tag.len() as u8);
+    header.extend_from_slice(tag.as_bytes());
+    header
+}
+
+/// The tag and the payload behind it, if `data` is tagged.
+pub fn untag(data: &[u8]) -> Option<(&str, &[u8])> {
+    let rest = data.strip_prefix(MAGIC)?;
+    let (&len, rest) = rest.split_first()?;
+    let len = usize::from(len);
+    let tag = str::from_utf8(rest.get(..len)?).ok().filter(|tag| valid(tag))?;
+    Some((tag, &rest[len..]))
+}
+
+/// The payload of `data`, behind its tag if it has one.
+pub fn payload(data: &[u8]) -> &[u8] {
+    untag(data).map_or(data, |(_, payload)| payload)
+}
@@ -0,0 +1,95 @@
+use std::{fmt, str};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// UTC wall clock time, e.g. `2017-03-01T12:00:00.000000Z`.
+    Iso8601,
+    /// Seconds since the Unix epoch.
+    Epoch,
+    /// Seconds since listening started.
+    Relative,
+    /// Seconds since the previous datagram.
+    Delta,
+}
+
+impl str::FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TimestampFormat, String> {
+        match s {
+            "iso8601" => Ok(TimestampFormat::Iso8601),
+            "epoch" => Ok(TimestampFormat::Epoch),
+            "relative" => Ok(TimestampFormat::Relative),
+            "delta" => Ok(TimestampFormat::Delta),
+            _ => Err(format!("unknown timestamp format {}: expected iso8601, epoch, relative or delta", s)),
+        }
+    }
+}
+
+struct Seconds(Duration);
+
+impl fmt::Display for Seconds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:06}", self.0.as_secs(), self.0.subsec_micros())
+    }
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) civil date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+pub fn iso8601(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let secs_of_day = secs.rem_euclid(86_400);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+            year,
+            month,
+            day,
+            secs_of_day / 3600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60,
+            since_epoch.subsec_micros())
+}
+
+/// Formats receive times, keeping the state needed for relative and delta
+/// timestamps.
+pub struct Timestamper {
+    format: TimestampFormat,
+    start: SystemTime,
+    prev: Option<SystemTime>,
+}
+
+impl Timestamper {
+    pub fn new(format: TimestampFormat) -> Timestamper {
+        Timestamper {
+            format,
+            start: SystemTime::now(),
+            prev: None,
+        }
+    }
+
+    pub fn stamp(&mut self, time: SystemTime) -> String {
+        let since = |earlier: SystemTime| Seconds(time.duration_since(earlier).unwrap_or_default());
+        let stamp = match self.format {
+            TimestampFormat::Iso8601 => iso8601(time),
+            TimestampFormat::Epoch => since(UNIX_EPOCH).to_string(),
+            TimestampFormat::Relative => since(self.start).to_string(),
+            TimestampFormat::Delta => since(self.prev.unwrap_or(time)).to_string(),
+        };
+        self.prev = Some(time);
+        stamp
+    }
+}
@@ -0,0 +1,379 @@
+use std::{io, mem, net};
+use std::collections::{HashMap, VecDeque};
+use std::io::prelude::*;
+use std::time::{Duration, Instant, SystemTime};
+
+use libc;
+
+use listen::Datagram;
+use loss::LossCounts;
+use timestamp;
+
+/// Seconds of packet rate history kept for the sparklines.
+const HISTORY: usize = 120;
+/// Datagrams kept for the recent list and hex view.
+const RECENT: usize = 500;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// A key pressed on the dashboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Escape,
+    Backspace,
+    Up,
+    Down,
+}
+
+/// The terminal in raw mode on the alternate screen, as it is put back when
+/// dropped. Signals from ^C and the like still arrive as usual.
+pub struct Terminal {
+    saved: libc::termios,
+    pending: Vec<u8>,
+}
+
+impl Terminal {
+    pub fn enter() -> io::Result<Terminal> {
+        if unsafe { libc::isatty(libc::STDIN_FILENO) != 1 || libc::isatty(libc::STDOUT_FILENO) != 1 } {
+            return Err(io::Error::other("the dashboard needs a terminal"));
+        }
+        let mut saved: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        // reads return at once, with whatever has been typed
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?1049h\x1b[?25l")?;
+        stdout.flush()?;
+        Ok(Terminal { saved, pending: Vec::new() })
+    }
+
+    /// Columns and rows.
+    pub fn size(&self) -> (usize, usize) {
+        let mut size: libc::winsize = unsafe { mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_col == 0 {
+            return (80, 24);
+        }
+        (size.ws_col as usize, size.ws_row as usize)
+    }
+
+    /// The keys typed since the last call.
+    pub fn keys(&mut self) -> io::Result<Vec<Key>> {
+        let mut buf = [0u8; 64];
+        let len = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if len < 0 {
+            let err = io::Error::last_os_error();
+            return if err.kind() == io::ErrorKind::Interrupted { Ok(Vec::new()) } else { Err(err) };
+        }
+        self.pending.extend_from_slice(&buf[..len as usize]);
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+        let mut keys = Vec::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            keys.push(match c {
+                '\x1b' if chars.peek() == Some(&'[') => {
+                    chars.next();
+                    match chars.next() {
+                        Some('A') => Key::Up,
+                        Some('B') => Key::Down,
+                        _ => continue,
+                    }
+                }
+                '\x1b' => Key::Escape,
+                '\r' | '\n' => Key::Enter,
+                '\x7f' | '\x08' => Key::Backspace,
+                c if c.is_control() => continue,
+                c => Key::Char(c),
+            });
+        }
+        Ok(keys)
+    }
+
+    /// Replaces the screen with `lines`.
+    pub fn draw(&mut self, lines: &[String]) -> io::Result<()> {
+        let mut screen = String::from("\x1b[H");
+        for line in lines {
+            screen.push_str(line);
+            screen.push_str("\x1b[K\r\n");
+        }
+        screen.push_str("\x1b[J");
+        let mut stdout = io::stdout();
+        stdout.write_all(screen.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x1b[?25h\x1b[?1049l");
+        let _ = stdout.flush();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+    }
+}
+
+/// Packet counts per second, the latest last.
+#[derive(Debug, Default)]
+struct Rates {
+    history: VecDeque<u64>,
+    current: u64,
+    packets: u64,
+    /// Bytes in the last full second.
+    last_bytes: u64,
+    current_bytes: u64,
+}
+
+impl Rates {
+    fn record(&mut self, size: usize) {
+        self.packets += 1;
+        self.current += 1;
+        self.current_bytes += size as u64;
+    }
+
+    fn tick(&mut self) {
+        self.history.push_back(self.current);
+        if self.history.len() > HISTORY {
+            self.history.pop_front();
+        }
+        self.current = 0;
+        self.last_bytes = self.current_bytes;
+        self.current_bytes = 0;
+    }
+
+    fn pps(&self) -> u64 {
+        self.history.back().copied().unwrap_or(0)
+    }
+
+    fn mbps(&self) -> f64 {
+        self.last_bytes as f64 * 8.0 / 1e6
+    }
+
+    fn sparkline(&self, width: usize) -> String {
+        let start = self.history.len().saturating_sub(width);
+        let shown: Vec<u64> = self.history.iter().skip(start).copied().collect();
+        let peak = shown.iter().copied().max().unwrap_or(0).max(1);
+        shown.iter().map(|&n| if n == 0 { ' ' } else { SPARKS[(n * 7 / peak) as usize] }).collect()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Source {
+    rates: Rates,
+    last: Option<SystemTime>,
+    last_gap: Option<Duration>,
+    /// Smoothed variation in the gaps between datagrams, in seconds, as
+    /// RFC 3550 smooths jitter.
+    jitter: f64,
+    loss: Option<LossCounts>,
+}
+
+/// What the dashboard shows: rates per group and per source, and the
+/// latest datagrams, which can be paused, filtered and viewed in hex.
+#[derive(Debug)]
+pub struct Dashboard {
+    groups: Vec<(net::SocketAddr, Rates)>,
+    sources: HashMap<(net::SocketAddr, net::SocketAddr), Source>,
+    recent: VecDeque<Datagram>,
+    next_tick: Instant,
+    paused: bool,
+    filter: String,
+    editing_filter: bool,
+    /// Index into the filtered recent datagrams, newest first.
+    selected: usize,
+    hex: bool,
+}
+
+impl Dashboard {
+    pub fn new(groups: &[net::SocketAddr]) -> Dashboard {
+        Dashboard {
+            groups: groups.iter().map(|&group| (group, Rates::default())).collect(),
+            sources: HashMap::new(),
+            recent: VecDeque::new(),
+            next_tick: Instant::now() + Duration::from_secs(1),
+            paused: false,
+            filter: String::new(),
+            editing_filter: false,
+            selected: 0,
+            hex: false,
+        }
+    }
+
+    pub fn record(&mut self, datagram: &Datagram, loss: Option<LossCounts>) {
+        self.tick();
+        let index = match self.groups.iter().position(|&(group, _)| group == datagram.group) {
+            Some(index) => index,
+            None => {
+                self.groups.push((datagram.group, Rates::default()));
+                self.groups.len() - 1
+            }
+        };
+        self.groups[index].1.record(datagram.size);
+        let source = self.sources.entry((datagram.src, datagram.group)).or_default();
+        source.rates.record(datagram.size);
+        let gap = source.last.map(|last| datagram.received.duration_since(last).unwrap_or_default());
+        if let (Some(gap), Some(last_gap)) = (gap, source.last_gap) {
+            let variation = (gap.as_secs_f64() - last_gap.as_secs_f64()).abs();
+            source.jitter += (variation - source.jitter) / 16.0;
+        }
+        source.last = Some(datagram.received);
+        source.last_gap = gap;
+        if loss.is_some() {
+            source.loss = loss;
+        }
+        if !self.paused {
+            self.recent.push_front(datagram.clone());
+            self.recent.truncate(RECENT);
+            // keep the same datagram selected as new ones arrive
+            if self.selected > 0 {
+                self.selected = (self.selected + 1).min(self.recent.len() - 1);
+            }
+        }
+    }
+
+    /// Moves the rate history on by the seconds that have passed.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        while now >= self.next_tick {
+            for &mut (_, ref mut rates) in &mut self.groups {
+                rates.tick();
+            }
+            for source in self.sources.values_mut() {
+                source.rates.tick();
+            }
+            self.next_tick += Duration::from_secs(1);
+        }
+    }
+
+    /// Acts on a key, returning false for the one that quits.
+    pub fn key(&mut self, key: Key) -> bool {
+        if self.editing_filter {
+            match key {
+                Key::Char(c) => self.filter.push(c),
+                Key::Backspace => {
+                    self.filter.pop();
+                }
+                Key::Escape => {
+                    self.filter.clear();
+                    self.editing_filter = false;
+                }
+                Key::Enter => self.editing_filter = false,
+                Key::Up | Key::Down => {}
+            }
+            self.selected = 0;
+            return true;
+        }
+        match key {
+            Key::Char('q') => return false,
+            Key::Char('p') | Key::Char(' ') => self.paused = !self.paused,
+            Key::Char('/') => self.editing_filter = true,
+            Key::Char('x') | Key::Enter => self.hex = !self.hex,
+            Key::Escape => {
+                self.hex = false;
+                self.filter.clear();
+            }
+            Key::Up => self.selected = self.selected.saturating_sub(1),
+            Key::Down => self.selected += 1,
+            _ => {}
+        }
+        true
+    }
+
+    fn shown(&self, src: &net::SocketAddr, group: &net::SocketAddr, data: &[u8]) -> bool {
+        self.filter.is_empty() || src.to_string().contains(&self.filter) || group.to_string().contains(&self.filter) ||
+        String::from_utf8_lossy(data).contains(&self.filter)
+    }
+
+    /// The screen, `width` columns by `height` rows.
+    pub fn render(&mut self, width: usize, height: usize) -> Vec<String> {
+        self.tick();
+        let mut lines = Vec::new();
+        let (pps, mbps) = self.groups.iter().fold((0, 0.0), |(pps, mbps), (_, rates)| {
+            (pps + rates.pps(), mbps + rates.mbps())
+        });
+        let mut status = format!("mccat  {} groups  {} pps  {:.3} Mbit/s", self.groups.len(), pps, mbps);
+        if self.paused {
+            status.push_str("  [paused]");
+        }
+        if self.editing_filter || !self.filter.is_empty() {
+            status.push_str(&format!("  filter: {}{}", self.filter, if self.editing_filter { "_" } else { "" }));
+        }
+        lines.push(format!("\x1b[7m{:width$}\x1b[0m", clip(&status, width), width = width));
+        let spark_width = width.saturating_sub(56).clamp(1, HISTORY);
+        lines.push(format!("\x1b[1m{:<28} {:>10} {:>7} {:>9}  PACKETS PER SECOND\x1b[0m", "GROUP", "PACKETS",
+                           "PPS", "MBIT/S"));
+        for &(group, ref rates) in &self.groups {
+            lines.push(clip(&format!("{:<28} {:>10} {:>7} {:>9.3}  {}", group, rates.packets, rates.pps(),
+                                     rates.mbps(), rates.sparkline(spark_width)), width));
+        }
+        lines.push(String::new());
+        lines.push(format!("\x1b[1m{:<28} {:<22} {:>10} {:>7} {:>9} {:>10}  {}\x1b[0m", "SOURCE", "GROUP",
+                           "PACKETS", "PPS", "JITTER", "LOST", ""));
+        let mut sources: Vec<_> = self.sources
+            .iter()
+            .filter(|&(&(src, group), _)| self.shown(&src, &group, &[]))
+            .collect();
+        sources.sort_by_key(|&(_, source)| std::cmp::Reverse(source.rates.packets));
+        // leave room for the recent datagrams
+        let source_rows = height.saturating_sub(lines.len() + 8) / 2;
+        for (&(src, group), source) in sources.iter().take(source_rows.max(1)) {
+            let lost = match source.loss {
+                Some(loss) => loss.missing.to_string(),
+                None => "-".to_owned(),
+            };
+            lines.push(clip(&format!("{:<28} {:<22} {:>10} {:>7} {:>7.2}ms {:>10}  {}", src, group.to_string(),
+                                     source.rates.packets, source.rates.pps(), source.jitter * 1e3, lost,
+                                     source.rates.sparkline(spark_width.min(30))), width));
+        }
+        lines.push(String::new());
+        let recent: Vec<&Datagram> = self.recent.iter().filter(|d| self.shown(&d.src, &d.group, &d.data)).collect();
+        self.selected = self.selected.min(recent.len().saturating_sub(1));
+        let footer = "q quit  p pause  / filter  ↑↓ select  x hex  esc clear";
+        let rows = height.saturating_sub(lines.len() + 2);
+        if self.hex && !recent.is_empty() {
+            let datagram = recent[self.selected];
+            lines.push(format!("\x1b[1m{} bytes from {} to {} at {}\x1b[0m", datagram.size, datagram.src,
+                               datagram.group, timestamp::iso8601(datagram.received)));
+            for (i, chunk) in datagram.data.chunks(16).take(rows.saturating_sub(1)).enumerate() {
+                let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+                let text: String = chunk.iter()
+                    .map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' })
+                    .collect();
+                lines.push(clip(&format!("{:08x}  {:<47}  |{}|", i * 16, hex.join(" "), text), width));
+            }
+        } else {
+            lines.push(format!("\x1b[1mRECENT{}\x1b[0m", if self.paused { " (paused)" } else { "" }));
+            // scroll so the selected datagram stays in view
+            let start = (self.selected + 1).saturating_sub(rows.saturating_sub(1));
+            for (i, datagram) in recent.iter().enumerate().skip(start).take(rows.saturating_sub(1)) {
+                let time = timestamp::iso8601(datagram.received);
+                let preview: String = String::from_utf8_lossy(&datagram.data)
+                    .chars()
+                    .map(|c| if c.is_control() { '.' } else { c })
+                    .collect();
+                let line = clip(&format!("{} {} > {} {:>5}  {}", &time[11..23], datagram.src, datagram.group,
+                                         datagram.size, preview), width);
+                lines.push(if i == self.selected { format!("\x1b[7m{}\x1b[0m", line) } else { line });
+            }
+        }
+        while lines.len() + 1 < height {
+            lines.push(String::new());
+        }
+        lines.truncate(height.saturating_sub(1));
+        lines.push(format!("\x1b[7m{:width$}\x1b[0m", clip(footer, width), width = width));
+        lines
+    }
+}
+
+/// `text` cut to `width` characters.
+fn clip(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
@@ -0,0 +1,114 @@
+use std::net;
+
+/// A well-known multicast group with its IPv4 and IPv6 addresses.
+#[derive(Debug, Clone, Copy)]
+pub struct WellKnownGroup {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub v4: net::Ipv4Addr,
+    pub v6: net::Ipv6Addr,
+    pub port: u16,
+}
+
+pub const GROUPS: &[WellKnownGroup] = &[
+    WellKnownGroup {
+        name: "all-hosts",
+        description: "All hosts on the link",
+        v4: net::Ipv4Addr::new(224, 0, 0, 1),
+        v6: net::Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1),
+        port: 0,
+    },
+    WellKnownGroup {
+        name: "ospf",
+        description: "OSPF all routers",
+        v4: net::Ipv4Addr::new(224, 0, 0, 5),
+        v6: net::Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 5),
+        port: 0,
+    },
+    WellKnownGroup {
+        name: "ospf-dr",
+        description: "OSPF designated routers",
+        v4: net::Ipv4Addr::new(224, 0, 0, 6),
+        v6: net::Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 6),
+        port: 0,
+    },
+    WellKnownGroup {
+        name: "ripv2",
+        description: "RIPv2 / RIPng routers",
+        v4: net::Ipv4Addr::new(224, 0, 0, 9),
+        v6: net::Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 9),
+        port: 520,
+    },
+    WellKnownGroup {
+        name: "vrrp",
+        description: "VRRP routers",
+        v4: net::Ipv4Addr::new(224, 0, 0, 18),
+        v6: net::Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x12),
+        port: 0,
+    },
+    WellKnownGroup {
+        name: "mdns",
+        description: "Multicast DNS",
+        v4: net::Ipv4Addr::new(224, 0, 0, 251),
+        v6: net::Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb),
+        port: 5353,
+    },
+    WellKnownGroup {
+        name: "llmnr",
+        description: "Link-local multicast name resolution",
+        v4: net::Ipv4Addr::new(224, 0, 0, 252),
+        v6: net::Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 3),
+        port: 5355,
+    },
+    WellKnownGroup {
+        name: "ntp",
+        description: "Network time protocol",
+        v4: net::Ipv4Addr::new(224, 0, 1, 1),
+        v6: net::Ipv6Addr::new(0xff0e, 0, 0, 0, 0, 0, 0, 0x101),
+        port: 123,
+    },
+    WellKnownGroup {
+        name: "ptp",
+        description: "Precision time protocol event messages",
+        v4: net::Ipv4Addr::new(224, 0, 1, 129),
+        v6: net::Ipv6Addr::new(0xff0e, 0, 0, 0, 0, 0, 0, 0x181),
+        port: 319,
+    },
+    WellKnownGroup {
+        name: "ptp-general",
+        description: "Precision time protocol general messages",
+        v4: net::Ipv4Addr::new(224, 0, 1, 129),
+        v6: net::Ipv6Addr::new(0xff0e, 0, 0, 0, 0, 0, 0, 0x181),
+        port: 320,
+    },
+    WellKnownGroup {
+        name: "sap",
+        description: "Session announcement protocol",
+        v4: net::Ipv4Addr::new(224, 2, 127, 254),
+        v6: net::Ipv6Addr::new(0xff0e, 0, 0, 0, 0, 0, 2, 0x7ffe),
+        port: 9875,
+    },
+    WellKnownGroup {
+        name: "ssdp",
+        description: "Simple service discovery protocol (UPnP)",
+        v4: net::Ipv4Addr::new(239, 255, 255, 250),
+        v6: net::Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xc),
+        port: 1900,
+    },
+];
+
+pub fn by_name(name: &str) -> Option<&'static WellKnownGroup> {
+    GROUPS.iter().find(|group| group.name == name)
+}
+
+/// The same well-known group in the other address family, e.g. ff02::fb
+/// for 224.0.0.251.
+pub fn counterpart(addr: net::IpAddr) -> Option<net::IpAddr> {
+    GROUPS.iter()
+        .filter_map(|group| match addr {
+            net::IpAddr::V4(v4) if v4 == group.v4 => Some(net::IpAddr::V6(group.v6)),
+            net::IpAddr::V6(v6) if v6 == group.v6 => Some(net::IpAddr::V4(group.v4)),
+            _ => None,
+        })
+        .next()
+}